@@ -1,4 +1,8 @@
-use rust_road_router::{cli::CliErr, datastr::graph::*, io::*};
+use rust_road_router::{
+    cli::CliErr,
+    datastr::{graph::*, road_attributes::*},
+    io::*,
+};
 
 use std::{env, error::Error, path::Path};
 
@@ -51,6 +55,11 @@ fn main() -> Result<(), Box<dyn Error>> {
     exp_graph.weight().write_to(&out_path.join("travel_time"))?;
     new_lat.write_to(&out_path.join("latitude"))?;
     new_lng.write_to(&out_path.join("longitude"))?;
+    if path.join("street_name").exists() {
+        RoadAttributes::reconstruct_from(&path)?
+            .select(&line_graph_edge_origins(&exp_graph))
+            .deconstruct_to(&out_path)?;
+    }
 
     Ok(())
 }
@@ -1,4 +1,3 @@
-#![feature(array_chunks)]
 
 #[macro_use]
 extern crate rust_road_router;
@@ -0,0 +1,60 @@
+// Sample node elevations and the ascent and descent along each edge from local SRTM or GeoTIFF height grids.
+// Edges follow the link geometry if the graph directory contains `first_geometry_point_of_arc`, straight lines between their nodes otherwise.
+// Nodes outside of the height grids get the average elevation of their neighbors, edges between nodes without any elevation are left flat.
+// Writes `elevation`, `ascent` and `descent` into the graph directory.
+//
+// Usage: import_elevation <graph_dir> <elevation data dir> [sample spacing in m]
+
+use std::{env, error::Error, path::Path};
+
+use conversion::elevation::*;
+#[macro_use]
+extern crate rust_road_router;
+use rust_road_router::{
+    cli::CliErr,
+    datastr::{graph::*, link_geometry::LinkGeometry},
+    io::*,
+    report::*,
+};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let _reporter = enable_reporting("import_elevation");
+    let mut args = env::args().skip(1);
+    let arg = &args.next().ok_or(CliErr("No directory arg given"))?;
+    let path = Path::new(arg);
+    let elevation_dir = args.next().ok_or(CliErr("No elevation data directory arg given"))?;
+    let spacing: f64 = args.next().map(|s| s.parse()).transpose()?.unwrap_or(30.0);
+
+    let graph = UnweightedOwnedGraph::reconstruct_from(&path)?;
+    let lat = Vec::<f32>::load_from(path.join("latitude"))?;
+    let lng = Vec::<f32>::load_from(path.join("longitude"))?;
+
+    let elevation_data = report_time("reading height grids", || ElevationData::read_dir(Path::new(&elevation_dir)))?;
+    if elevation_data.num_grids() == 0 {
+        return Err(CliErr("No height grids found").into());
+    }
+    report!("num_height_grids", elevation_data.num_grids());
+    report!("num_nodes", graph.num_nodes());
+    report!("num_arcs", graph.num_arcs());
+    report!("sample_spacing_m", spacing);
+
+    let mut elevation = report_time("node elevation", || elevation_data.node_elevation(&lat, &lng));
+    let (num_interpolated, num_unknown) = report_time("interpolating missing node elevation", || interpolate_missing_elevation(&graph, &mut elevation));
+    report!("num_nodes_elevation_interpolated", num_interpolated);
+    report!("num_nodes_without_elevation", num_unknown);
+    let shapes: Option<Vec<Vec<(f32, f32)>>> = if path.join("first_geometry_point_of_arc").exists() {
+        let geometry = LinkGeometry::reconstruct_from(&path)?;
+        Some((0..geometry.num_arcs()).map(|edge| geometry.shape(edge as EdgeId).collect()).collect())
+    } else {
+        None
+    };
+    let (edge_elevation, num_unknown_arcs) = report_time("edge elevation", || {
+        elevation_data.edge_elevation(&graph, &lat, &lng, &elevation, shapes.as_deref(), spacing)
+    });
+    report!("num_arcs_without_elevation", num_unknown_arcs);
+
+    elevation.write_to(&path.join("elevation"))?;
+    edge_elevation.deconstruct_to(&path)?;
+
+    Ok(())
+}
@@ -0,0 +1,52 @@
+// Import a GTFS timetable for one day and link it to the road graph.
+// Writes the timetable and the walking graph into a `transit` subdirectory of the graph directory.
+// Fails if a stop is further than 10km from the road network.
+//
+// Usage: import_gtfs <graph_dir> <gtfs zip> <date as YYYYMMDD> [max transfer walking time in s] [walking speed in km/h]
+
+use std::{env, error::Error, fs::File, io::BufReader, path::Path};
+
+use chrono::NaiveDate;
+use conversion::gtfs::*;
+#[macro_use]
+extern crate rust_road_router;
+use rust_road_router::{
+    cli::CliErr,
+    datastr::{graph::*, timetable::Timetable},
+    io::*,
+    report::*,
+};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let _reporter = enable_reporting("import_gtfs");
+    let mut args = env::args().skip(1);
+    let arg = &args.next().ok_or(CliErr("No directory arg given"))?;
+    let path = Path::new(arg);
+    let gtfs_file = args.next().ok_or(CliErr("No GTFS zip file arg given"))?;
+    let date = NaiveDate::parse_from_str(&args.next().ok_or(CliErr("No date arg given"))?, "%Y%m%d")?;
+    let max_transfer_time: Weight = args.next().map(|s| s.parse()).transpose()?.unwrap_or(300) * 1000;
+    let walking_speed: f64 = args.next().map(|s| s.parse()).transpose()?.unwrap_or(4.5);
+
+    let graph = UnweightedOwnedGraph::reconstruct_from(&path)?;
+    let geo_distance = Vec::<Weight>::load_from(path.join("geo_distance"))?;
+    let lat = Vec::<f32>::load_from(path.join("latitude"))?;
+    let lng = Vec::<f32>::load_from(path.join("longitude"))?;
+
+    let gtfs = report_time("reading GTFS", || read_gtfs(BufReader::new(File::open(gtfs_file)?), date))?;
+    report!("date", date.to_string());
+    report!("num_stops", gtfs.stop_ids.len());
+    report!("num_trips", gtfs.trip_ids.len());
+    report!("num_connections", gtfs.connections.len());
+
+    let walking = walking_graph(&graph, &geo_distance, walking_speed);
+    let stop_node = nearest_nodes(&lat, &lng, &gtfs.stop_lat, &gtfs.stop_lng, 10)?;
+    let mut timetable = Timetable::new(stop_node, gtfs.connections);
+    report_time("computing footpaths", || timetable.compute_footpaths(&walking, max_transfer_time));
+    report!("num_footpaths", timetable.footpaths().num_arcs());
+
+    let out_dir = path.join("transit");
+    timetable.deconstruct_to(&out_dir)?;
+    walking.deconstruct_to(&out_dir.join("walking"))?;
+
+    Ok(())
+}
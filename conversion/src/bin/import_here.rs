@@ -1,11 +1,23 @@
 // Program to convert map data from HERE into RoutingKit data structures
+//
+// Usage: import_here <input_dir> <output_dir> [min_lat] [min_lon] [max_lat] [max_lon] [elevation data dir]
+// Road attributes (see `road_attributes`) include street names if the optional `rdf_road_link` and `rdf_road_name` tables are present.
+// Official names in the language given by the `NAME_LANGUAGE` environment variable (HERE language codes like `GER`) are preferred.
+// Link shape points are written as `first_geometry_point_of_arc`, `geometry_point_latitude` and `geometry_point_longitude`.
+// With elevation data, heights are sampled along the link geometry, see `import_elevation`.
 
 use std::{env, error::Error, path::Path, str::FromStr};
 
-use conversion::here::{csv_source::CSVSource, read_graph};
-use rust_road_router::{cli::CliErr, io::*};
+use conversion::{
+    elevation::*,
+    here::{csv_source::CSVSource, read_graph},
+};
+#[macro_use]
+extern crate rust_road_router;
+use rust_road_router::{cli::CliErr, datastr::link_geometry::LinkGeometry, io::*, report::*};
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let _reporter = enable_reporting("import_here");
     let mut args = env::args().skip(1);
 
     let in_dir = &args.next().ok_or(CliErr("No input directory arg given"))?;
@@ -15,23 +27,33 @@ fn main() -> Result<(), Box<dyn Error>> {
     let min_lon = (args.next().as_deref().map(f64::from_str).unwrap_or(Ok(-360.0))? * 100_000.) as i64;
     let max_lat = (args.next().as_deref().map(f64::from_str).unwrap_or(Ok(360.0))? * 100_000.) as i64;
     let max_lon = (args.next().as_deref().map(f64::from_str).unwrap_or(Ok(360.0))? * 100_000.) as i64;
+    let elevation_dir = args.next();
 
     let source = CSVSource::new(Path::new(in_dir));
-    let data = read_graph(&source, (min_lat, min_lon), (max_lat, max_lon));
+    let name_language = env::var("NAME_LANGUAGE").ok();
+    let data = read_graph(&source, (min_lat, min_lon), (max_lat, max_lon), name_language.as_deref());
     data.graph.deconstruct_to(out_dir)?;
     let out_dir = Path::new(out_dir);
 
     data.graph.weight().write_to(&out_dir.join("travel_time"))?;
-    data.functional_road_classes.write_to(&out_dir.join("functional_road_classes"))?;
     data.lat.write_to(&out_dir.join("latitude"))?;
     data.lng.write_to(&out_dir.join("longitude"))?;
+    LinkGeometry::from_shapes(&data.link_geometry).deconstruct_to(&out_dir)?;
+    if let Some(elevation_dir) = elevation_dir {
+        let elevation_data = ElevationData::read_dir(Path::new(&elevation_dir))?;
+        let mut elevation = elevation_data.node_elevation(&data.lat, &data.lng);
+        let (num_interpolated, num_unknown) = interpolate_missing_elevation(&data.graph, &mut elevation);
+        report!("num_nodes_elevation_interpolated", num_interpolated);
+        report!("num_nodes_without_elevation", num_unknown);
+        let (edge_elevation, num_unknown_arcs) = elevation_data.edge_elevation(&data.graph, &data.lat, &data.lng, &elevation, Some(&data.link_geometry), 30.0);
+        report!("num_arcs_without_elevation", num_unknown_arcs);
+        elevation.write_to(&out_dir.join("elevation"))?;
+        edge_elevation.deconstruct_to(&out_dir)?;
+    }
     data.link_id_mapping.write_to(&out_dir.join("link_id_mapping"))?;
     data.here_rank_to_link_id.write_to(&out_dir.join("here_rank_to_link_id"))?;
-    data.link_lengths
-        .into_iter()
-        .map(|d| d as u32)
-        .collect::<Box<[_]>>()
-        .write_to(&out_dir.join("geo_distance"))?;
+    // functional_road_classes, link_flags, geo_distance, speed limits and street names
+    data.road_attributes().deconstruct_to(&out_dir)?;
     [1000].write_to(&out_dir.join("tt_units_per_s"))?;
     [1].write_to(&out_dir.join("dist_units_per_m"))?;
 
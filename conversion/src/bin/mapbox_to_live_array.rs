@@ -1,14 +1,14 @@
 // WIP: CH potentials for TD Routing.
 
-use std::{env, error::Error, fs::File, path::Path};
+use std::{env, error::Error, fs::File, io::BufReader, path::Path};
 
 use rust_road_router::{
     cli::CliErr,
-    datastr::{graph::*, rank_select_map::*},
+    datastr::{graph::*, live_traffic::LiveTraffic},
     io::*,
+    live_feed::*,
 };
 
-use csv::ReaderBuilder;
 use glob::glob;
 
 fn main() -> Result<(), Box<dyn Error>> {
@@ -17,55 +17,25 @@ fn main() -> Result<(), Box<dyn Error>> {
     let path = Path::new(arg);
 
     let graph = UnweightedOwnedGraph::reconstruct_from(&path)?;
-
-    let geo_distance = Vec::<EdgeId>::load_from(path.join("geo_distance"))?;
-    let osm_node_ids = Vec::<u64>::load_from(path.join("osm_node_ids"))?;
-
-    let mut osm_ids_present = BitVec::new(*osm_node_ids.last().unwrap() as usize + 1);
-    for osm_id in osm_node_ids {
-        osm_ids_present.set(osm_id as usize);
-    }
-    let id_map = RankSelectMap::new(osm_ids_present);
+    let mapper = FeedMapper::new(graph)
+        .with_osm_node_ids(&Vec::<u64>::load_from(path.join("osm_node_ids"))?)
+        .with_geo_distance(Vec::load_from(path.join("geo_distance"))?);
 
     let arg = &args.next().ok_or(CliErr("No live data directory arg given"))?;
     let live_dir = Path::new(arg);
 
-    let mut live = Vec::<(EdgeId, Weight, Weight)>::new();
-
-    let mut total = 0;
-    let mut found = 0;
-
+    let mut records = Vec::new();
     for live_file in glob(live_dir.join("*").to_str().unwrap()).unwrap() {
-        let file = File::open(live_file?.clone()).unwrap();
-        let mut reader = ReaderBuilder::new()
-            .has_headers(false)
-            .delimiter(b',')
-            .quoting(false)
-            .double_quote(false)
-            .escape(None)
-            .from_reader(file);
-
-        for line in reader.records() {
-            total += 1;
-
-            let record = line?;
-            let from = record[0].parse()?;
-            let to = record[1].parse()?;
-            let speed = record[2].parse::<u32>()?;
-
-            if let (Some(from), Some(to)) = (id_map.get(from), id_map.get(to)) {
-                if let Some(EdgeIdT(edge_idx)) = graph.edge_indices(from as NodeId, to as NodeId).next() {
-                    found += 1;
-                    let new_tt = 100 * 36 * geo_distance[edge_idx as usize] / speed;
-                    live.push((edge_idx, new_tt, 3600 * 1000));
-                }
-            }
-        }
+        records.extend(read_feed(BufReader::new(File::open(live_file?)?), FeedFormat::Mapbox)?);
     }
+    // all reports are valid for the default duration of an hour from now on
+    let updates = mapper.map_all(&records, 0);
 
-    dbg!(total, found);
+    dbg!(records.len(), updates.len());
 
-    live.write_to(&path.join(args.next().as_deref().unwrap_or("live_data")))?;
+    let mut live = LiveTraffic::new();
+    live.ingest(updates);
+    live.live_data(0).write_to(&path.join(args.next().as_deref().unwrap_or("live_data")))?;
 
     Ok(())
 }
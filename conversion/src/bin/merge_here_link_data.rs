@@ -12,7 +12,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     let in_dir = &env::args().skip(1).next().ok_or(CliErr("No input directory arg given"))?;
 
     let source = CSVSource::new(Path::new(in_dir));
-    let data = read_graph(&source, (-360 * 100_000, -360 * 100_000), (360 * 100_000, 360 * 100_000));
+    let data = read_graph(&source, (-360 * 100_000, -360 * 100_000), (360 * 100_000, 360 * 100_000), None);
 
     let graph = data.graph;
     let link_id_mapper = LinkIdMapper::new(
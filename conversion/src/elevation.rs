@@ -0,0 +1,321 @@
+//! Elevation from local height grids.
+//!
+//! Supported are SRTM `.hgt` tiles, named after their south west corner like `N49E008.hgt`,
+//! and GeoTIFFs in WGS84 geographic coordinates with a single band.
+//! Heights are interpolated bilinearly between the grid samples, voids are ignored.
+//! Everything is read from disk, no downloads.
+
+use rust_road_router::datastr::{elevation::EdgeElevation, graph::*};
+use std::{
+    error::Error,
+    fs::File,
+    io::{BufReader, Read},
+    path::Path,
+};
+use tiff::{
+    decoder::{Decoder, DecodingResult, Limits},
+    tags::Tag,
+};
+
+/// A regular grid of height samples in m, rows from north to south.
+#[derive(Debug, Clone)]
+pub struct HeightGrid {
+    // coordinates of the first sample
+    north: f64,
+    west: f64,
+    lat_step: f64,
+    lng_step: f64,
+    rows: usize,
+    cols: usize,
+    // NaN for voids
+    heights: Vec<f32>,
+}
+
+impl HeightGrid {
+    /// `north` and `west` are the coordinates of the first sample, `heights` are given row by row.
+    pub fn new(north: f64, west: f64, lat_step: f64, lng_step: f64, cols: usize, heights: Vec<f32>) -> Self {
+        assert!(cols > 0);
+        assert_eq!(heights.len() % cols, 0, "incomplete last row");
+        assert!(lat_step > 0.0 && lng_step > 0.0);
+        Self {
+            north,
+            west,
+            lat_step,
+            lng_step,
+            rows: heights.len() / cols,
+            cols,
+            heights,
+        }
+    }
+
+    /// Read an SRTM tile, the resolution is derived from the file size.
+    pub fn read_hgt(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let name = path.file_stem().and_then(|name| name.to_str()).ok_or("invalid hgt file name")?.to_uppercase();
+        let invalid_name = || format!("hgt file name {} not like N49E008", name);
+        if name.len() != 7 {
+            return Err(invalid_name().into());
+        }
+        let lat: f64 = name[1..3].parse().map_err(|_| invalid_name())?;
+        let lng: f64 = name[4..7].parse().map_err(|_| invalid_name())?;
+        let lat = match &name[0..1] {
+            "N" => lat,
+            "S" => -lat,
+            _ => return Err(invalid_name().into()),
+        };
+        let lng = match &name[3..4] {
+            "E" => lng,
+            "W" => -lng,
+            _ => return Err(invalid_name().into()),
+        };
+
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        let side = ((bytes.len() / 2) as f64).sqrt() as usize;
+        if side < 2 || side * side * 2 != bytes.len() {
+            return Err(format!("{} is not a square grid of 16 bit samples", path.display()).into());
+        }
+        let heights = bytes
+            .chunks_exact(2)
+            .map(|sample| match i16::from_be_bytes([sample[0], sample[1]]) {
+                i16::MIN => f32::NAN,
+                height => f32::from(height),
+            })
+            .collect();
+        // samples are on the tile borders, so neighboring tiles overlap by one row or column
+        let step = 1.0 / (side - 1) as f64;
+        Ok(Self::new(lat + 1.0, lng, step, step, side, heights))
+    }
+
+    /// Read the first band of a GeoTIFF.
+    /// Georeferencing is taken from the model tiepoint and pixel scale tags, coordinates are assumed to be WGS84 degrees.
+    pub fn read_geotiff(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut decoder = Decoder::new(BufReader::new(File::open(path)?))?.with_limits(Limits::unlimited());
+        let (cols, _rows) = decoder.dimensions()?;
+        let scale = decoder.get_tag(Tag::ModelPixelScaleTag)?.into_f64_vec()?;
+        let tiepoint = decoder.get_tag(Tag::ModelTiepointTag)?.into_f64_vec()?;
+        if scale.len() < 2 || tiepoint.len() < 6 {
+            return Err(format!("{}: invalid georeferencing", path.display()).into());
+        }
+        let nodata: Option<f64> = decoder
+            .find_tag(Tag::GdalNodata)?
+            .map(|value| value.into_string())
+            .transpose()?
+            .and_then(|nodata| nodata.trim_end_matches('\0').trim().parse().ok());
+
+        let samples_per_pixel = decoder.find_tag_unsigned::<usize>(Tag::SamplesPerPixel)?.unwrap_or(1);
+        let to_f32 = |values: Vec<f64>| -> Vec<f32> {
+            values
+                .into_iter()
+                .step_by(samples_per_pixel)
+                .map(|height| if Some(height) == nodata { f32::NAN } else { height as f32 })
+                .collect()
+        };
+        let heights = match decoder.read_image()? {
+            DecodingResult::U8(values) => to_f32(values.into_iter().map(f64::from).collect()),
+            DecodingResult::U16(values) => to_f32(values.into_iter().map(f64::from).collect()),
+            DecodingResult::U32(values) => to_f32(values.into_iter().map(f64::from).collect()),
+            DecodingResult::U64(values) => to_f32(values.into_iter().map(|v| v as f64).collect()),
+            DecodingResult::I8(values) => to_f32(values.into_iter().map(f64::from).collect()),
+            DecodingResult::I16(values) => to_f32(values.into_iter().map(f64::from).collect()),
+            DecodingResult::I32(values) => to_f32(values.into_iter().map(f64::from).collect()),
+            DecodingResult::I64(values) => to_f32(values.into_iter().map(|v| v as f64).collect()),
+            DecodingResult::F32(values) => to_f32(values.into_iter().map(f64::from).collect()),
+            DecodingResult::F64(values) => to_f32(values),
+        };
+
+        // raster point (i, j) is at (x, y), samples are at pixel centers
+        let (i, j, x, y) = (tiepoint[0], tiepoint[1], tiepoint[3], tiepoint[4]);
+        let (lng_step, lat_step) = (scale[0], scale[1]);
+        let west = x + (0.5 - i) * lng_step;
+        let north = y - (0.5 - j) * lat_step;
+        Ok(Self::new(north, west, lat_step, lng_step, cols as usize, heights))
+    }
+
+    /// Interpolated height, `None` outside of the grid or in voids.
+    pub fn height(&self, lat: f64, lng: f64) -> Option<f32> {
+        let row = (self.north - lat) / self.lat_step;
+        let col = (lng - self.west) / self.lng_step;
+        if !(0.0..=(self.rows - 1) as f64).contains(&row) || !(0.0..=(self.cols - 1) as f64).contains(&col) {
+            return None;
+        }
+        let (row0, col0) = (row.floor() as usize, col.floor() as usize);
+        let (row1, col1) = (std::cmp::min(row0 + 1, self.rows - 1), std::cmp::min(col0 + 1, self.cols - 1));
+        let (row_frac, col_frac) = (row - row0 as f64, col - col0 as f64);
+
+        let mut weighted_sum = 0.0;
+        let mut weight_sum = 0.0;
+        for (r, c, weight) in [
+            (row0, col0, (1.0 - row_frac) * (1.0 - col_frac)),
+            (row0, col1, (1.0 - row_frac) * col_frac),
+            (row1, col0, row_frac * (1.0 - col_frac)),
+            (row1, col1, row_frac * col_frac),
+        ] {
+            let height = self.heights[r * self.cols + c];
+            if !height.is_nan() {
+                weighted_sum += f64::from(height) * weight;
+                weight_sum += weight;
+            }
+        }
+        if weight_sum > 0.0 {
+            Some((weighted_sum / weight_sum) as f32)
+        } else {
+            None
+        }
+    }
+}
+
+/// All height grids of a directory.
+#[derive(Debug, Clone, Default)]
+pub struct ElevationData {
+    grids: Vec<HeightGrid>,
+}
+
+impl ElevationData {
+    /// Read all `.hgt`, `.tif` and `.tiff` files in `dir`.
+    pub fn read_dir(dir: &Path) -> Result<Self, Box<dyn Error>> {
+        let mut paths: Vec<_> = std::fs::read_dir(dir)?.map(|entry| entry.map(|entry| entry.path())).collect::<Result<_, _>>()?;
+        paths.sort();
+        let mut grids = Vec::new();
+        for path in paths {
+            match path.extension().and_then(|ext| ext.to_str()).map(str::to_lowercase).as_deref() {
+                Some("hgt") => grids.push(HeightGrid::read_hgt(&path)?),
+                Some("tif") | Some("tiff") => grids.push(HeightGrid::read_geotiff(&path)?),
+                _ => (),
+            }
+        }
+        Ok(Self { grids })
+    }
+
+    pub fn add(&mut self, grid: HeightGrid) {
+        self.grids.push(grid);
+    }
+
+    pub fn num_grids(&self) -> usize {
+        self.grids.len()
+    }
+
+    /// Height from the first grid which covers the point.
+    pub fn height(&self, lat: f64, lng: f64) -> Option<f32> {
+        self.grids.iter().find_map(|grid| grid.height(lat, lng))
+    }
+
+    /// Elevation of all nodes, `NaN` for nodes without elevation data.
+    pub fn node_elevation(&self, lat: &[f32], lng: &[f32]) -> Vec<f32> {
+        lat.iter()
+            .zip(lng)
+            .map(|(&lat, &lng)| self.height(f64::from(lat), f64::from(lng)).unwrap_or(f32::NAN))
+            .collect()
+    }
+
+    /// Ascent and descent along all edges, sampling heights every `spacing` meters.
+    /// Edges follow `shape` points where given (in driving direction), straight lines between their nodes otherwise.
+    /// The ends of each edge get the node elevation, so ascent minus descent always is the elevation difference of the nodes.
+    /// Edges with an end without elevation (`NaN`) are unknown and get neither ascent nor descent.
+    /// Also returns the number of such edges.
+    pub fn edge_elevation<G: LinkIterable<(NodeIdT, EdgeIdT)>>(
+        &self,
+        graph: &G,
+        lat: &[f32],
+        lng: &[f32],
+        elevation: &[f32],
+        shape: Option<&[Vec<(f32, f32)>]>,
+        spacing: f64,
+    ) -> (EdgeElevation, usize) {
+        assert!(spacing > 0.0);
+        let mut ascent = vec![0.0; graph.num_arcs()];
+        let mut descent = vec![0.0; graph.num_arcs()];
+        let mut unknown = 0;
+
+        for tail in 0..graph.num_nodes() {
+            for (NodeIdT(head), EdgeIdT(edge)) in graph.link_iter(tail as NodeId) {
+                let head = head as usize;
+                if elevation[tail].is_nan() || elevation[head].is_nan() {
+                    unknown += 1;
+                    continue;
+                }
+                let mut points = vec![(f64::from(lat[tail]), f64::from(lng[tail]))];
+                if let Some(shape) = shape {
+                    points.extend(shape[edge as usize].iter().map(|&(lat, lng)| (f64::from(lat), f64::from(lng))));
+                }
+                points.push((f64::from(lat[head]), f64::from(lng[head])));
+
+                let mut heights = vec![elevation[tail]];
+                for (idx, segment) in points.windows(2).enumerate() {
+                    let ((lat0, lng0), (lat1, lng1)) = (segment[0], segment[1]);
+                    let num_samples = (approx_distance(segment[0], segment[1]) / spacing).ceil() as usize;
+                    // the end of each segment is the start of the next, the end of the last one is the head
+                    heights.extend((1..num_samples).filter_map(|k| {
+                        let frac = k as f64 / num_samples as f64;
+                        self.height(lat0 + frac * (lat1 - lat0), lng0 + frac * (lng1 - lng0))
+                    }));
+                    if idx + 2 < points.len() {
+                        heights.extend(self.height(lat1, lng1));
+                    }
+                }
+                heights.push(elevation[head]);
+
+                for pair in heights.windows(2) {
+                    let delta = pair[1] - pair[0];
+                    if delta > 0.0 {
+                        ascent[edge as usize] += delta;
+                    } else {
+                        descent[edge as usize] -= delta;
+                    }
+                }
+            }
+        }
+
+        (EdgeElevation::new(ascent, descent), unknown)
+    }
+}
+
+/// Fill in nodes without elevation data (`NaN`) from their neighbors, ignoring edge directions.
+/// Starting from the nodes with data, each node gets the average of its neighbors which already have an elevation,
+/// so gaps are filled from their borders inwards.
+/// Returns the number of filled in nodes and of nodes which remain without elevation because their component has no data at all.
+pub fn interpolate_missing_elevation<G: LinkIterable<NodeIdT>>(graph: &G, elevation: &mut [f32]) -> (usize, usize) {
+    let reversed = UnweightedOwnedGraph::reversed(graph);
+    let neighbors = |node: usize| {
+        graph
+            .link_iter(node as NodeId)
+            .chain(LinkIterable::<NodeIdT>::link_iter(&reversed, node as NodeId))
+            .map(|NodeIdT(neighbor)| neighbor as usize)
+    };
+
+    let mut missing: Vec<usize> = (0..graph.num_nodes()).filter(|&node| elevation[node].is_nan()).collect();
+    let num_missing = missing.len();
+    loop {
+        // only use elevations known before this round, so the result does not depend on the node order
+        let filled: Vec<(usize, f32)> = missing
+            .iter()
+            .filter_map(|&node| {
+                let (sum, count) = neighbors(node)
+                    .map(|neighbor| elevation[neighbor])
+                    .filter(|height| !height.is_nan())
+                    .fold((0.0, 0), |(sum, count), height| (sum + height, count + 1));
+                if count > 0 {
+                    Some((node, sum / count as f32))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        if filled.is_empty() {
+            break;
+        }
+        for &(node, height) in &filled {
+            elevation[node] = height;
+        }
+        missing.retain(|&node| elevation[node].is_nan());
+    }
+
+    (num_missing - missing.len(), missing.len())
+}
+
+// equirectangular approximation in m, good enough for short distances
+fn approx_distance((lat0, lng0): (f64, f64), (lat1, lng1): (f64, f64)) -> f64 {
+    const EARTH_RADIUS: f64 = 6_371_000.0;
+    let x = (lng1 - lng0).to_radians() * ((lat0 + lat1) / 2.0).to_radians().cos();
+    let y = (lat1 - lat0).to_radians();
+    (x * x + y * y).sqrt() * EARTH_RADIUS
+}
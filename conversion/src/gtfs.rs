@@ -0,0 +1,346 @@
+//! Import of public transit timetables from GTFS zip files.
+//!
+//! Only what is needed for earliest arrival routing on a single day is read:
+//! `stops.txt`, `trips.txt`, `stop_times.txt` and the service calendar from `calendar.txt` and `calendar_dates.txt`.
+//! Trips of the previous service day which are still running after midnight are included as well.
+
+use chrono::{Datelike, NaiveDate, Weekday};
+use csv::{ReaderBuilder, StringRecord};
+use rust_road_router::datastr::{
+    graph::{time_dependent::Timestamp, *},
+    timetable::{Connection, StopId, TripId},
+};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    io::{Read, Seek},
+};
+use zip::{result::ZipError, ZipArchive};
+
+pub struct Gtfs {
+    pub stop_ids: Vec<String>,
+    pub stop_lat: Vec<f32>,
+    pub stop_lng: Vec<f32>,
+    pub trip_ids: Vec<String>,
+    pub connections: Vec<Connection>,
+}
+
+struct Table {
+    columns: HashMap<String, usize>,
+    records: Vec<StringRecord>,
+}
+
+impl Table {
+    fn column(&self, name: &str) -> Result<usize, Box<dyn Error>> {
+        self.columns.get(name).copied().ok_or_else(|| format!("missing column {}", name).into())
+    }
+}
+
+// Missing optional files yield an empty table.
+fn read_table<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str, optional: bool) -> Result<Table, Box<dyn Error>> {
+    let file = match archive.by_name(name) {
+        Ok(file) => file,
+        Err(ZipError::FileNotFound) if optional => {
+            return Ok(Table {
+                columns: HashMap::new(),
+                records: Vec::new(),
+            })
+        }
+        Err(e) => return Err(format!("{}: {}", name, e).into()),
+    };
+    let mut reader = ReaderBuilder::new().has_headers(true).flexible(true).from_reader(file);
+    let columns = reader
+        .headers()?
+        .iter()
+        .enumerate()
+        // strip byte order marks
+        .map(|(idx, column)| (column.trim_start_matches('\u{feff}').trim().to_string(), idx))
+        .collect();
+    let records = reader.records().collect::<Result<_, _>>()?;
+    Ok(Table { columns, records })
+}
+
+const DAY: Timestamp = 24 * 60 * 60 * 1000;
+
+/// Parse a GTFS time `H:MM:SS` to milliseconds after midnight, hours may exceed 24.
+/// Empty fields yield `None`, they are allowed for stops which are not a timepoint.
+pub fn parse_time(time: &str) -> Result<Option<Timestamp>, Box<dyn Error>> {
+    let time = time.trim();
+    if time.is_empty() {
+        return Ok(None);
+    }
+    let parts = time.split(':').map(|part| part.parse::<u32>().ok()).collect::<Vec<_>>();
+    match parts[..] {
+        [Some(h), Some(m), Some(s)] if m < 60 && s < 60 => h
+            .checked_mul(3600)
+            .and_then(|secs| secs.checked_add(m * 60 + s))
+            .and_then(|secs| secs.checked_mul(1000))
+            .map(Some)
+            .ok_or_else(|| format!("time out of range: {}", time).into()),
+        _ => Err(format!("invalid time: {}", time).into()),
+    }
+}
+
+// Distance traveled and arrival and departure of a stop time, times may be missing.
+type UntimedStopTime = (Option<f64>, Option<(Timestamp, Timestamp)>);
+
+// Times of all stops of a trip, stops which are not a timepoint are interpolated linearly between the surrounding timed stops,
+// by `shape_dist_traveled` if available for all involved stops, otherwise spaced evenly.
+// Takes the distance traveled and arrival and departure of each stop, returns arrival and departure.
+fn interpolate_times(stops: &[UntimedStopTime]) -> Result<Vec<(Timestamp, Timestamp)>, &'static str> {
+    if stops.is_empty() {
+        return Ok(Vec::new());
+    }
+    let timed: Vec<_> = stops
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, &(dist, times))| times.map(|times| (idx, dist, times)))
+        .collect();
+    match (timed.first(), timed.last()) {
+        (Some(&(first, ..)), Some(&(last, ..))) if first == 0 && last == stops.len() - 1 => (),
+        _ => return Err("first and last stop of a trip need times"),
+    }
+
+    let mut times = vec![timed[0].2];
+    for pair in timed.windows(2) {
+        let ((prev, prev_dist, (_, departure)), (next, next_dist, next_times)) = (pair[0], pair[1]);
+        let between = &stops[prev + 1..next];
+        let by_distance = matches!((prev_dist, next_dist), (Some(from), Some(to)) if to > from) && between.iter().all(|(dist, _)| dist.is_some());
+        for (offset, &(dist, _)) in between.iter().enumerate() {
+            let fraction = match (by_distance, prev_dist, next_dist, dist) {
+                (true, Some(from), Some(to), Some(at)) => (at - from) / (to - from),
+                _ => (offset + 1) as f64 / (next - prev) as f64,
+            };
+            let time = departure + (f64::from(next_times.0.saturating_sub(departure)) * fraction.clamp(0.0, 1.0)).round() as Timestamp;
+            times.push((time, time));
+        }
+        times.push(next_times);
+    }
+    Ok(times)
+}
+
+// Service ids running on `date`.
+fn active_services(calendar: &Table, calendar_dates: &Table, date: NaiveDate) -> Result<HashSet<String>, Box<dyn Error>> {
+    let mut active = HashSet::new();
+    let date_str = date.format("%Y%m%d").to_string();
+
+    if !calendar.records.is_empty() {
+        let weekday_column = calendar.column(match date.weekday() {
+            Weekday::Mon => "monday",
+            Weekday::Tue => "tuesday",
+            Weekday::Wed => "wednesday",
+            Weekday::Thu => "thursday",
+            Weekday::Fri => "friday",
+            Weekday::Sat => "saturday",
+            Weekday::Sun => "sunday",
+        })?;
+        let (service_id, start_date, end_date) = (calendar.column("service_id")?, calendar.column("start_date")?, calendar.column("end_date")?);
+        for record in &calendar.records {
+            // dates as YYYYMMDD compare correctly as strings
+            if &record[weekday_column] == "1" && record[start_date] <= *date_str && *date_str <= record[end_date] {
+                active.insert(record[service_id].to_string());
+            }
+        }
+    }
+
+    if !calendar_dates.records.is_empty() {
+        let (service_id, date_column, exception_type) = (
+            calendar_dates.column("service_id")?,
+            calendar_dates.column("date")?,
+            calendar_dates.column("exception_type")?,
+        );
+        for record in calendar_dates.records.iter().filter(|record| record[date_column] == *date_str) {
+            match &record[exception_type] {
+                "1" => {
+                    active.insert(record[service_id].to_string());
+                }
+                "2" => {
+                    active.remove(&record[service_id]);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    Ok(active)
+}
+
+/// Read all connections departing on `date`, including those of trips of the previous service day which depart after midnight.
+/// Stop times without arrival and departure (not a timepoint) get interpolated times.
+/// Trip ids of the previous day get a `@-1` suffix, since the same trip may run on both days.
+pub fn read_gtfs<R: Read + Seek>(archive: R, date: NaiveDate) -> Result<Gtfs, Box<dyn Error>> {
+    let mut archive = ZipArchive::new(archive)?;
+
+    let stops = read_table(&mut archive, "stops.txt", false)?;
+    let (stop_id, stop_lat, stop_lon) = (stops.column("stop_id")?, stops.column("stop_lat")?, stops.column("stop_lon")?);
+    let location_type = stops.columns.get("location_type").copied();
+    let mut stop_ids = Vec::new();
+    let mut lat = Vec::new();
+    let mut lng = Vec::new();
+    let mut stop_idx = HashMap::new();
+    // stations, entrances and so on are not served by vehicles
+    for record in stops.records.iter().filter(|record| {
+        location_type
+            .and_then(|column| record.get(column))
+            .map(|t| t.is_empty() || t == "0")
+            .unwrap_or(true)
+    }) {
+        stop_idx.insert(record[stop_id].to_string(), stop_ids.len() as StopId);
+        stop_ids.push(record[stop_id].to_string());
+        lat.push(record[stop_lat].parse()?);
+        lng.push(record[stop_lon].parse()?);
+    }
+
+    let calendar = read_table(&mut archive, "calendar.txt", true)?;
+    let calendar_dates = read_table(&mut archive, "calendar_dates.txt", true)?;
+    let services = active_services(&calendar, &calendar_dates, date)?;
+    let previous_day = date.pred_opt().ok_or("no day before the given date")?;
+    let previous_services = active_services(&calendar, &calendar_dates, previous_day)?;
+
+    let trips = read_table(&mut archive, "trips.txt", false)?;
+    let (trip_id, service_id) = (trips.column("trip_id")?, trips.column("service_id")?);
+    let mut trip_ids = Vec::new();
+    // times of trips of the previous day are shifted by a day
+    let mut trip_shifted = Vec::new();
+    let mut trip_idx: HashMap<String, Vec<TripId>> = HashMap::new();
+    for (services, shifted) in [(&services, false), (&previous_services, true)] {
+        for record in trips.records.iter().filter(|record| services.contains(&record[service_id])) {
+            trip_idx.entry(record[trip_id].to_string()).or_default().push(trip_ids.len() as TripId);
+            trip_ids.push(if shifted {
+                format!("{}@-1", &record[trip_id])
+            } else {
+                record[trip_id].to_string()
+            });
+            trip_shifted.push(shifted);
+        }
+    }
+
+    let stop_times = read_table(&mut archive, "stop_times.txt", false)?;
+    let (trip_id, arrival_time, departure_time, stop_id, stop_sequence) = (
+        stop_times.column("trip_id")?,
+        stop_times.column("arrival_time")?,
+        stop_times.column("departure_time")?,
+        stop_times.column("stop_id")?,
+        stop_times.column("stop_sequence")?,
+    );
+    let shape_dist_traveled = stop_times.columns.get("shape_dist_traveled").copied();
+    // (sequence, stop, distance traveled, arrival and departure) for each trip
+    let mut trip_stop_times = vec![Vec::new(); trip_ids.len()];
+    for record in &stop_times.records {
+        let trips = match trip_idx.get(&record[trip_id]) {
+            Some(trips) => trips,
+            None => continue,
+        };
+        let stop = *stop_idx.get(&record[stop_id]).ok_or_else(|| format!("unknown stop {}", &record[stop_id]))?;
+        let invalid_time = |e: Box<dyn Error>| format!("trip {}: {}", &record[trip_id], e);
+        let times = match (
+            parse_time(&record[arrival_time]).map_err(invalid_time)?,
+            parse_time(&record[departure_time]).map_err(invalid_time)?,
+        ) {
+            (Some(arrival), Some(departure)) => Some((arrival, departure)),
+            (Some(time), None) | (None, Some(time)) => Some((time, time)),
+            (None, None) => None,
+        };
+        let dist = shape_dist_traveled
+            .and_then(|column| record.get(column))
+            .filter(|dist| !dist.trim().is_empty())
+            .map(|dist| dist.trim().parse::<f64>())
+            .transpose()?;
+        let sequence: u32 = record[stop_sequence].parse()?;
+        for &trip in trips {
+            trip_stop_times[trip as usize].push((sequence, stop, dist, times));
+        }
+    }
+
+    let mut connections = Vec::new();
+    for (trip, mut stop_times) in trip_stop_times.into_iter().enumerate() {
+        stop_times.sort_unstable_by_key(|&(sequence, ..)| sequence);
+        let times = interpolate_times(&stop_times.iter().map(|&(_, _, dist, times)| (dist, times)).collect::<Vec<_>>())
+            .map_err(|e| format!("trip {}: {}", trip_ids[trip], e))?;
+        let shift = if trip_shifted[trip] { DAY } else { 0 };
+
+        for (pair, times) in stop_times.windows(2).zip(times.windows(2)) {
+            let ((_, dep_stop, ..), (_, arr_stop, ..)) = (pair[0], pair[1]);
+            let ((_, departure), (arrival, _)) = (times[0], times[1]);
+            if arrival < departure {
+                return Err(format!("trip {} arrives at a stop before it departs at the previous one", trip_ids[trip]).into());
+            }
+            // connections of the previous day before midnight can not be reached
+            if departure < shift {
+                continue;
+            }
+            connections.push(Connection {
+                dep_stop,
+                arr_stop,
+                departure: departure - shift,
+                arrival: arrival - shift,
+                trip: trip as TripId,
+            });
+        }
+    }
+
+    Ok(Gtfs {
+        stop_ids,
+        stop_lat: lat,
+        stop_lng: lng,
+        trip_ids,
+        connections,
+    })
+}
+
+/// The closest node for each point, using a uniform grid over the node coordinates.
+/// Fails for points which are further than about `max_radius_km` from all nodes, e.g. stops outside of the road network.
+pub fn nearest_nodes(lat: &[f32], lng: &[f32], point_lat: &[f32], point_lng: &[f32], max_radius_km: i32) -> Result<Vec<NodeId>, Box<dyn Error>> {
+    // roughly 1km
+    const CELL: f32 = 0.01;
+    let cell = |lat: f32, lng: f32| ((lat / CELL).floor() as i32, (lng / CELL).floor() as i32);
+    let mut grid: HashMap<(i32, i32), Vec<NodeId>> = HashMap::new();
+    for node in 0..lat.len() {
+        grid.entry(cell(lat[node], lng[node])).or_default().push(node as NodeId);
+    }
+    let sq_dist = |node: NodeId, point_lat: f32, point_lng: f32| {
+        let (d_lat, d_lng) = (lat[node as usize] - point_lat, lng[node as usize] - point_lng);
+        d_lat * d_lat + d_lng * d_lng
+    };
+
+    point_lat
+        .iter()
+        .zip(point_lng)
+        .map(|(&point_lat, &point_lng)| {
+            let (cell_lat, cell_lng) = cell(point_lat, point_lng);
+            let closest_within = |radius: i32| {
+                (cell_lat - radius..=cell_lat + radius)
+                    .flat_map(|x| (cell_lng - radius..=cell_lng + radius).map(move |y| (x, y)))
+                    .filter_map(|key| grid.get(&key))
+                    .flatten()
+                    .copied()
+                    .min_by(|&a, &b| sq_dist(a, point_lat, point_lng).partial_cmp(&sq_dist(b, point_lat, point_lng)).unwrap())
+            };
+            // grow the search radius until something is found,
+            // then search one ring further because a closer node might be in a neighboring cell
+            let mut radius = 0;
+            while closest_within(radius).is_none() {
+                radius += 1;
+                if radius > max_radius_km {
+                    return Err(format!("no node within {}km of {} {}", max_radius_km, point_lat, point_lng).into());
+                }
+            }
+            Ok(closest_within(radius + 1).unwrap())
+        })
+        .collect()
+}
+
+/// Walking graph for the road network, pedestrians may use every road in both directions.
+/// `walking_speed` in km/h, the result contains walking times in ms.
+pub fn walking_graph(graph: &UnweightedOwnedGraph, geo_distance: &[Weight], walking_speed: f64) -> OwnedGraph {
+    let ms_per_meter = 3600.0 / walking_speed;
+    let mut adjacency_lists = vec![Vec::new(); graph.num_nodes()];
+    for tail in 0..graph.num_nodes() as NodeId {
+        for (NodeIdT(head), EdgeIdT(edge)) in LinkIterable::<(NodeIdT, EdgeIdT)>::link_iter(graph, tail) {
+            let weight = (f64::from(geo_distance[edge as usize]) * ms_per_meter).round() as Weight;
+            adjacency_lists[tail as usize].push(Link { node: head, weight });
+            adjacency_lists[head as usize].push(Link { node: tail, weight });
+        }
+    }
+    OwnedGraph::from_adjancecy_lists(adjacency_lists)
+}
@@ -86,6 +86,9 @@ impl<'a> RdfDataSource for CSVSource<'a> {
                                 .unwrap_or_else(|_| panic!("could not parse {:?} as speed_category in line {} of {:?}", &record[19], i, path)),
                             from_ref_speed_limit: record[25].parse().ok(),
                             to_ref_speed_limit: record[26].parse().ok(),
+                            controlled_access: &record[5] == "Y",
+                            ferry: &record[7] == "Y" || &record[8] == "Y",
+                            tollway: &record[16] == "Y",
                         }
                     }));
                 }
@@ -176,4 +179,89 @@ impl<'a> RdfDataSource for CSVSource<'a> {
 
         elements
     }
+
+    fn road_names(&self) -> Vec<RdfRoadName> {
+        // road_link_id, link_id, road_name_id, ...
+        let mut link_road_names = vec![];
+        for entry in glob(self.directory.join("rdf_road_link/rdf_road_link.txt*").to_str().unwrap()).unwrap() {
+            match entry {
+                Ok(path) => {
+                    let file = File::open(path.clone()).unwrap();
+                    let mut reader = ReaderBuilder::new()
+                        .has_headers(false)
+                        .delimiter(b'\t')
+                        .quoting(false)
+                        .double_quote(false)
+                        .escape(None)
+                        .from_reader(file);
+
+                    link_road_names.extend(reader.records().enumerate().map(|(i, line)| {
+                        let record = line.unwrap();
+                        let link_id: i64 = record[1]
+                            .parse()
+                            .unwrap_or_else(|_| panic!("could not parse {:?} as link_id in line {} of {:?}", &record[1], i, path));
+                        let road_name_id: i64 = record[2]
+                            .parse()
+                            .unwrap_or_else(|_| panic!("could not parse {:?} as road_name_id in line {} of {:?}", &record[2], i, path));
+                        (link_id, road_name_id)
+                    }));
+                }
+                Err(e) => println!("{:?}", e),
+            }
+        }
+
+        let mut names = std::collections::HashMap::new();
+        for entry in glob(self.directory.join("rdf_road_name/rdf_road_name.txt*").to_str().unwrap()).unwrap() {
+            match entry {
+                Ok(path) => {
+                    let file = File::open(path.clone()).unwrap();
+                    let mut reader = ReaderBuilder::new()
+                        .has_headers(false)
+                        .delimiter(b'\t')
+                        .quoting(false)
+                        .double_quote(false)
+                        .escape(None)
+                        .flexible(true)
+                        .from_reader(file);
+
+                    for (i, line) in reader.records().enumerate() {
+                        let record = line.unwrap();
+                        let road_name_id: i64 = record[0]
+                            .parse()
+                            .unwrap_or_else(|_| panic!("could not parse {:?} as road_name_id in line {} of {:?}", &record[0], i, path));
+                        if let Some(name) = record.get(ROAD_NAME_STREET_NAME_COLUMN) {
+                            let column = |idx| record.get(idx).unwrap_or("");
+                            let official = column(ROAD_NAME_NAME_TYPE_COLUMN) == "B"
+                                && column(ROAD_NAME_ROUTE_TYPE_COLUMN).is_empty()
+                                && column(ROAD_NAME_IS_EXONYM_COLUMN) != "Y";
+                            names.insert(road_name_id, (name.to_string(), column(ROAD_NAME_LANGUAGE_CODE_COLUMN).to_string(), official));
+                        }
+                    }
+                }
+                Err(e) => println!("{:?}", e),
+            }
+        }
+
+        link_road_names
+            .into_iter()
+            .filter_map(|(link_id, road_name_id)| {
+                names.get(&road_name_id).map(|(name, language_code, official)| RdfRoadName {
+                    link_id,
+                    name: name.clone(),
+                    language_code: language_code.clone(),
+                    official: *official,
+                })
+            })
+            .collect()
+    }
 }
+
+// columns of rdf_road_name
+const ROAD_NAME_LANGUAGE_CODE_COLUMN: usize = 1;
+// `B` for base names, `A` for alternative names
+const ROAD_NAME_NAME_TYPE_COLUMN: usize = 2;
+// only set for route numbers
+const ROAD_NAME_ROUTE_TYPE_COLUMN: usize = 3;
+const ROAD_NAME_IS_EXONYM_COLUMN: usize = 4;
+// full street name
+const ROAD_NAME_STREET_NAME_COLUMN: usize = 12;
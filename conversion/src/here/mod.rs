@@ -1,5 +1,7 @@
+use rust_road_router::algo::route_preferences::LinkFlags;
 use rust_road_router::datastr::graph::*;
 use rust_road_router::datastr::rank_select_map::{BitVec, RankSelectMap};
+use rust_road_router::datastr::road_attributes::*;
 use rust_road_router::util::in_range_option::*;
 use std::error::Error;
 use std::fmt;
@@ -67,9 +69,36 @@ pub struct RdfNavLink {
     speed_category: i32,
     from_ref_speed_limit: Option<i32>,
     to_ref_speed_limit: Option<i32>,
+    controlled_access: bool,
+    ferry: bool,
+    tollway: bool,
 }
 
 impl RdfNavLink {
+    fn flags(&self) -> LinkFlags {
+        let mut flags = LinkFlags::NONE;
+        if self.tollway {
+            flags = flags | LinkFlags::TOLL;
+        }
+        if self.ferry {
+            flags = flags | LinkFlags::FERRY;
+        }
+        if self.controlled_access {
+            flags = flags | LinkFlags::HIGHWAY;
+        }
+        flags
+    }
+
+    /// In km/h, `0` if unknown.
+    fn speed_limit(&self, direction: RdfLinkDirection) -> u8 {
+        let limit = match direction {
+            RdfLinkDirection::FromRef => self.from_ref_speed_limit,
+            RdfLinkDirection::ToRef => self.to_ref_speed_limit,
+            _ => panic!("invalid argument"),
+        };
+        limit.map(|limit| limit.clamp(0, u8::MAX as i32) as u8).unwrap_or(0)
+    }
+
     fn speed_in_m_per_s(&self, direction: RdfLinkDirection) -> f64 {
         let link_speed = match self.speed_category {
             1 => 36.11,
@@ -117,6 +146,17 @@ impl RdfNode {
     }
 }
 
+/// A name of a link, links may have several in different languages or as route numbers.
+#[derive(Debug, Clone)]
+pub struct RdfRoadName {
+    link_id: i64,
+    name: String,
+    /// HERE language code like `GER` or `ENG`.
+    language_code: String,
+    /// Base name in the local language, not an alternative name, translation or route number.
+    official: bool,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct RdfLinkGeometry {
     link_id: i64,
@@ -140,20 +180,47 @@ pub struct HereData {
     pub graph: OwnedGraph,
     pub link_lengths: Vec<f64>,
     pub functional_road_classes: Vec<u8>,
+    pub link_flags: Vec<u8>,
     pub lat: Vec<f32>,
     pub lng: Vec<f32>,
+    /// Shape points of each edge in driving direction, empty if the link has no geometry.
+    pub link_geometry: Vec<Vec<(f32, f32)>>,
+    /// In km/h, `0` if unknown.
+    pub speed_limits: Vec<u8>,
+    /// Index into `street_name_table` or `NO_NAME`.
+    pub street_names: Vec<u32>,
+    pub street_name_table: StringTable,
     pub link_id_mapping: RankSelectMap,
     pub here_rank_to_link_id: Vec<(InRangeOption<EdgeId>, InRangeOption<EdgeId>)>,
 }
 
+impl HereData {
+    /// Attributes of all edges, lengths rounded down to whole meters like `geo_distance`.
+    pub fn road_attributes(&self) -> RoadAttributes {
+        RoadAttributes::new(
+            self.functional_road_classes.clone(),
+            self.link_flags.clone(),
+            self.link_lengths.iter().map(|&length| length as Weight).collect(),
+            self.speed_limits.clone(),
+            self.street_names.clone(),
+            self.street_name_table.clone(),
+        )
+    }
+}
+
 pub trait RdfDataSource {
     fn links(&self) -> Vec<RdfLink>;
     fn nav_links(&self) -> Vec<RdfNavLink>;
     fn nodes(&self) -> Vec<RdfNode>;
     fn link_geometries(&self) -> Vec<RdfLinkGeometry>;
+    /// Street names are optional.
+    fn road_names(&self) -> Vec<RdfRoadName> {
+        Vec::new()
+    }
 }
 
-pub fn read_graph(source: &dyn RdfDataSource, (min_lat, min_lon): (i64, i64), (max_lat, max_lon): (i64, i64)) -> HereData {
+/// Links with several names get the official one, in `name_language` if there is one in this language, see `choose_street_name`.
+pub fn read_graph(source: &dyn RdfDataSource, (min_lat, min_lon): (i64, i64), (max_lat, max_lon): (i64, i64), name_language: Option<&str>) -> HereData {
     let included = |node: &RdfNode| node.lat >= min_lat && node.lat <= max_lat && node.lon >= min_lon && node.lon <= max_lon;
 
     eprintln!("read nodes");
@@ -258,6 +325,25 @@ pub fn read_graph(source: &dyn RdfDataSource, (min_lat, min_lon): (i64, i64), (m
         geometries.sort_by_key(|geometry| geometry.seq_num);
     }
 
+    eprintln!("read road names");
+    let mut street_name_table = StringTableBuilder::new();
+    let mut link_names = vec![NO_NAME; link_id_mapping.len()];
+    let mut road_names = source.road_names();
+    // stable, so ties between equally good names keep the input order
+    road_names.sort_by_key(|road_name| road_name.link_id);
+    for names in road_names.chunk_by(|a, b| a.link_id == b.link_id) {
+        if let Some(link_index) = link_id_mapping.get(names[0].link_id as usize) {
+            let candidates = names.iter().map(|road_name| NameCandidate {
+                name: &road_name.name,
+                language: &road_name.language_code,
+                official: road_name.official,
+            });
+            if let Some(name) = choose_street_name(candidates, name_language) {
+                link_names[link_index] = street_name_table.intern(name);
+            }
+        }
+    }
+
     eprintln!("sort nodes");
     let mut nodes: Vec<RdfNode> = vec![
         RdfNode {
@@ -279,6 +365,10 @@ pub fn read_graph(source: &dyn RdfDataSource, (min_lat, min_lon): (i64, i64), (m
     let mut travel_times: Vec<Weight> = vec![0; m as usize];
     let mut link_lengths: Vec<f64> = vec![0.0; m as usize];
     let mut functional_road_classes: Vec<u8> = vec![0; m as usize];
+    let mut link_flags: Vec<u8> = vec![0; m as usize];
+    let mut link_geometry: Vec<Vec<(f32, f32)>> = vec![Vec::new(); m as usize];
+    let mut speed_limits: Vec<u8> = vec![0; m as usize];
+    let mut street_names: Vec<u32> = vec![NO_NAME; m as usize];
     let mut here_rank_to_link_id: Vec<(InRangeOption<EdgeId>, InRangeOption<EdgeId>)> = vec![(InRangeOption::NONE, InRangeOption::NONE); links.len()];
 
     eprintln!("calculate weights");
@@ -306,6 +396,10 @@ pub fn read_graph(source: &dyn RdfDataSource, (min_lat, min_lon): (i64, i64), (m
                     travel_times[first_out[from_node] as usize] = from_weight;
                     link_lengths[first_out[from_node] as usize] = length;
                     functional_road_classes[first_out[from_node] as usize] = nav_link.functional_class;
+                    link_flags[first_out[from_node] as usize] = nav_link.flags().0;
+                    link_geometry[first_out[from_node] as usize] = shape(&link_geometries[link_index], false);
+                    speed_limits[first_out[from_node] as usize] = nav_link.speed_limit(RdfLinkDirection::FromRef);
+                    street_names[first_out[from_node] as usize] = link_names[link_index];
                     here_rank_to_link_id[link_index].0 = InRangeOption::some(first_out[from_node]);
                     first_out[from_node] += 1;
                 }
@@ -314,6 +408,10 @@ pub fn read_graph(source: &dyn RdfDataSource, (min_lat, min_lon): (i64, i64), (m
                     travel_times[first_out[to_node] as usize] = to_weight;
                     link_lengths[first_out[to_node] as usize] = length;
                     functional_road_classes[first_out[to_node] as usize] = nav_link.functional_class;
+                    link_flags[first_out[to_node] as usize] = nav_link.flags().0;
+                    link_geometry[first_out[to_node] as usize] = shape(&link_geometries[link_index], true);
+                    speed_limits[first_out[to_node] as usize] = nav_link.speed_limit(RdfLinkDirection::ToRef);
+                    street_names[first_out[to_node] as usize] = link_names[link_index];
                     here_rank_to_link_id[link_index].1 = InRangeOption::some(first_out[to_node]);
                     first_out[to_node] += 1;
                 }
@@ -322,6 +420,10 @@ pub fn read_graph(source: &dyn RdfDataSource, (min_lat, min_lon): (i64, i64), (m
                     travel_times[first_out[from_node] as usize] = from_weight;
                     link_lengths[first_out[from_node] as usize] = length;
                     functional_road_classes[first_out[from_node] as usize] = nav_link.functional_class;
+                    link_flags[first_out[from_node] as usize] = nav_link.flags().0;
+                    link_geometry[first_out[from_node] as usize] = shape(&link_geometries[link_index], false);
+                    speed_limits[first_out[from_node] as usize] = nav_link.speed_limit(RdfLinkDirection::FromRef);
+                    street_names[first_out[from_node] as usize] = link_names[link_index];
                     here_rank_to_link_id[link_index].0 = InRangeOption::some(first_out[from_node]);
                     first_out[from_node] += 1;
 
@@ -329,6 +431,10 @@ pub fn read_graph(source: &dyn RdfDataSource, (min_lat, min_lon): (i64, i64), (m
                     travel_times[first_out[to_node] as usize] = to_weight;
                     link_lengths[first_out[to_node] as usize] = length;
                     functional_road_classes[first_out[to_node] as usize] = nav_link.functional_class;
+                    link_flags[first_out[to_node] as usize] = nav_link.flags().0;
+                    link_geometry[first_out[to_node] as usize] = shape(&link_geometries[link_index], true);
+                    speed_limits[first_out[to_node] as usize] = nav_link.speed_limit(RdfLinkDirection::ToRef);
+                    street_names[first_out[to_node] as usize] = link_names[link_index];
                     here_rank_to_link_id[link_index].1 = InRangeOption::some(first_out[to_node]);
                     first_out[to_node] += 1;
                 }
@@ -349,13 +455,29 @@ pub fn read_graph(source: &dyn RdfDataSource, (min_lat, min_lon): (i64, i64), (m
         graph,
         link_lengths,
         functional_road_classes,
+        link_flags,
         lat,
         lng,
+        link_geometry,
+        speed_limits,
+        street_names,
+        street_name_table: street_name_table.build(),
         link_id_mapping,
         here_rank_to_link_id,
     }
 }
 
+fn shape(geometries: &[RdfLinkGeometry], reversed: bool) -> Vec<(f32, f32)> {
+    let mut shape: Vec<_> = geometries
+        .iter()
+        .map(|geometry| (((geometry.lat as f64) / 10_000_000.) as f32, ((geometry.lon as f64) / 10_000_000.) as f32))
+        .collect();
+    if reversed {
+        shape.reverse();
+    }
+    shape
+}
+
 fn calculate_length_in_m(geometries: &[RdfLinkGeometry]) -> f64 {
     geometries.windows(2).map(|pair| pair[0].as_wgs84().distance(&pair[1].as_wgs84())).sum()
 }
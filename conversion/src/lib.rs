@@ -1,5 +1,7 @@
 use rust_road_router::datastr::graph::{time_dependent::*, *};
 
+pub mod elevation;
+pub mod gtfs;
 pub mod here;
 
 pub fn speed_profile_to_tt_profile(speeds: &[(Timestamp, u32)], edge_len: u32) -> Vec<(Timestamp, Weight)> {
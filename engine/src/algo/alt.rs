@@ -6,15 +6,54 @@ use crate::{
         dijkstra::{query::dijkstra::ServerWrapper, *},
     },
     datastr::rank_select_map::BitVec,
+    io::*,
 };
 use rand::prelude::*;
+use std::cmp::{max, min, Reverse};
+
+/// Number of candidates generated with avoid per landmark for the maxCover selection.
+pub const MAX_COVER_CANDIDATES_PER_LANDMARK: usize = 4;
 
 pub struct ALTPotData {
+    landmarks: Vec<NodeId>,
     landmark_forward_distances: Vec<Weight>,
     landmark_backward_distances: Vec<Weight>,
     num_landmarks: usize,
 }
 
+impl Deconstruct for ALTPotData {
+    fn save_each(&self, store: &dyn Fn(&str, &dyn Save) -> std::io::Result<()>) -> std::io::Result<()> {
+        store("landmarks", &self.landmarks)?;
+        store("landmark_forward_distances", &self.landmark_forward_distances)?;
+        store("landmark_backward_distances", &self.landmark_backward_distances)?;
+        Ok(())
+    }
+}
+
+impl Reconstruct for ALTPotData {
+    fn reconstruct_with(loader: Loader) -> std::io::Result<Self> {
+        let landmarks: Vec<NodeId> = loader.load("landmarks")?;
+        let landmark_forward_distances: Vec<Weight> = loader.load("landmark_forward_distances")?;
+        let landmark_backward_distances: Vec<Weight> = loader.load("landmark_backward_distances")?;
+        let num_landmarks = landmarks.len();
+        if num_landmarks == 0 {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "no landmarks"));
+        }
+        if landmark_forward_distances.len() % num_landmarks != 0 || landmark_forward_distances.len() != landmark_backward_distances.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "landmark distances do not match the number of landmarks",
+            ));
+        }
+        Ok(Self {
+            landmarks,
+            landmark_forward_distances,
+            landmark_backward_distances,
+            num_landmarks,
+        })
+    }
+}
+
 impl ALTPotData {
     pub fn new<G>(graph: &G, landmarks: Vec<NodeId>) -> Self
     where
@@ -41,10 +80,10 @@ impl ALTPotData {
             })
             .unzip();
 
-        Self::new_with_landmark_distances(landmark_forward_distances, landmark_backward_distances)
+        Self::new_with_landmark_distances(landmarks, landmark_forward_distances, landmark_backward_distances)
     }
 
-    fn new_with_landmark_distances(forward: Vec<Vec<Weight>>, backward: Vec<Vec<Weight>>) -> Self {
+    fn new_with_landmark_distances(landmarks: Vec<NodeId>, forward: Vec<Vec<Weight>>, backward: Vec<Vec<Weight>>) -> Self {
         let n = forward[0].len();
         let k = forward.len();
 
@@ -59,6 +98,7 @@ impl ALTPotData {
         }
 
         Self {
+            landmarks,
             landmark_forward_distances,
             landmark_backward_distances,
             num_landmarks: k,
@@ -72,6 +112,139 @@ impl ALTPotData {
     {
         report!("algo", "Avoid Landmarks");
         report!("num_landmarks", num_landmarks);
+        let (landmarks, landmark_forward_distances, landmark_backward_distances) = Self::avoid_landmarks(graph, num_landmarks, rng);
+        Self::new_with_landmark_distances(landmarks, landmark_forward_distances, landmark_backward_distances)
+    }
+
+    /// maxCover selection: generate `MAX_COVER_CANDIDATES_PER_LANDMARK` times as many candidates with avoid,
+    /// then select the subset which covers the most arcs.
+    /// An arc is covered by a landmark if its reduced cost with respect to the landmark is zero,
+    /// that is the arc lies on a shortest path from or to the landmark.
+    /// The subset is first chosen greedily and then improved by a local search with random swaps.
+    pub fn new_with_max_cover<G>(graph: &G, num_landmarks: usize, rng: &mut StdRng) -> Self
+    where
+        G: LinkIterable<Link>,
+        OwnedGraph: BuildReversed<G>,
+    {
+        assert!(num_landmarks > 0, "maxCover needs at least one landmark");
+        report!("algo", "maxCover Landmarks");
+        report!("num_landmarks", num_landmarks);
+        let num_candidates = min(num_landmarks * MAX_COVER_CANDIDATES_PER_LANDMARK, graph.num_nodes());
+        report!("num_landmark_candidates", num_candidates);
+        let (candidates, candidate_forward_distances, candidate_backward_distances) = Self::avoid_landmarks(graph, num_candidates, rng);
+
+        let coverage: Vec<BitVec> = candidate_forward_distances
+            .iter()
+            .zip(candidate_backward_distances.iter())
+            .map(|(forward, backward)| Self::covered_arcs(graph, forward, backward))
+            .collect();
+
+        let mut selected: Vec<usize> = Vec::with_capacity(num_landmarks);
+        let mut is_selected = BitVec::new(num_candidates);
+        // arcs covered by at least one and by at least two of the selected landmarks, as words of the coverage bit vectors
+        let (mut covered, mut covered_multiple) = Self::cover_masks(&coverage, &selected);
+        let newly_covered = |candidate: usize, covered: &[u64]| -> usize {
+            coverage[candidate]
+                .words()
+                .iter()
+                .zip(covered)
+                .map(|(by_candidate, covered)| (by_candidate & !covered).count_ones() as usize)
+                .sum()
+        };
+
+        report_time("landmark_selection", || {
+            while selected.len() < min(num_landmarks, num_candidates) {
+                let best = (0..num_candidates)
+                    .filter(|&candidate| !is_selected.get(candidate))
+                    .max_by_key(|&candidate| newly_covered(candidate, &covered))
+                    .unwrap();
+                Self::add_cover(&mut covered, &mut covered_multiple, &coverage[best]);
+                selected.push(best);
+                is_selected.set(best);
+            }
+
+            if selected.len() < num_candidates {
+                for _ in 0..num_candidates {
+                    let removed_idx = rng.gen_range(0..selected.len());
+                    let removed = selected[removed_idx];
+                    let added = rng.gen_range(0..num_candidates);
+                    if is_selected.get(added) {
+                        continue;
+                    }
+
+                    // arcs covered only by the removed landmark get lost unless the added one covers them too
+                    let lost: usize = coverage[removed]
+                        .words()
+                        .iter()
+                        .zip(coverage[added].words())
+                        .zip(covered.iter().zip(&covered_multiple))
+                        .map(|((by_removed, by_added), (covered, covered_multiple))| {
+                            (by_removed & covered & !covered_multiple & !by_added).count_ones() as usize
+                        })
+                        .sum();
+
+                    if newly_covered(added, &covered) > lost {
+                        selected[removed_idx] = added;
+                        is_selected.unset(removed);
+                        is_selected.set(added);
+                        (covered, covered_multiple) = Self::cover_masks(&coverage, &selected);
+                    }
+                }
+            }
+        });
+
+        report!("num_covered_arcs", covered.iter().map(|words| words.count_ones() as usize).sum::<usize>());
+
+        let landmarks = selected.iter().map(|&candidate| candidates[candidate]).collect();
+        let landmark_forward_distances = selected.iter().map(|&candidate| candidate_forward_distances[candidate].clone()).collect();
+        let landmark_backward_distances = selected.iter().map(|&candidate| candidate_backward_distances[candidate].clone()).collect();
+        Self::new_with_landmark_distances(landmarks, landmark_forward_distances, landmark_backward_distances)
+    }
+
+    // Words of the arcs covered by at least one and by at least two of the selected candidates.
+    fn cover_masks(coverage: &[BitVec], selected: &[usize]) -> (Vec<u64>, Vec<u64>) {
+        let num_words = coverage[0].words().len();
+        let (mut covered, mut covered_multiple) = (vec![0u64; num_words], vec![0u64; num_words]);
+        for &candidate in selected {
+            Self::add_cover(&mut covered, &mut covered_multiple, &coverage[candidate]);
+        }
+        (covered, covered_multiple)
+    }
+
+    fn add_cover(covered: &mut [u64], covered_multiple: &mut [u64], by_candidate: &BitVec) {
+        for ((covered, covered_multiple), by_candidate) in covered.iter_mut().zip(covered_multiple.iter_mut()).zip(by_candidate.words()) {
+            *covered_multiple |= *covered & by_candidate;
+            *covered |= by_candidate;
+        }
+    }
+
+    // Arcs with reduced cost zero with respect to a landmark, given distances from and to the landmark.
+    fn covered_arcs<G>(graph: &G, from_landmark: &[Weight], to_landmark: &[Weight]) -> BitVec
+    where
+        G: LinkIterable<Link>,
+    {
+        let mut covered = BitVec::new(graph.num_arcs());
+        let mut arc = 0;
+        for tail in 0..graph.num_nodes() {
+            for link in graph.link_iter(tail as NodeId) {
+                let head = link.head() as usize;
+                if (from_landmark[tail] < INFINITY && from_landmark[tail] + link.weight == from_landmark[head])
+                    || (to_landmark[head] < INFINITY && link.weight + to_landmark[head] == to_landmark[tail])
+                {
+                    covered.set(arc);
+                }
+                arc += 1;
+            }
+        }
+        covered
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn avoid_landmarks<G>(graph: &G, num_landmarks: usize, rng: &mut StdRng) -> (Vec<NodeId>, Vec<Vec<Weight>>, Vec<Vec<Weight>>)
+    where
+        G: LinkIterable<Link>,
+        OwnedGraph: BuildReversed<G>,
+    {
         let mut landmark_runs_ctxt = push_collection_context("landmark_dijkstras");
         let n = graph.num_nodes() as NodeId;
         let reversed = OwnedGraph::reversed(graph);
@@ -96,8 +269,13 @@ impl ALTPotData {
 
             let mut weights: Vec<u64> = (0..n)
                 .map(|node| {
-                    (dists.distance(node)
-                        - landmark_forward_distances
+                    let dist = dists.distance(node);
+                    // nodes unreachable from the root are not part of the shortest path tree
+                    if dist >= INFINITY {
+                        return 0;
+                    }
+                    dist.saturating_sub(
+                        landmark_forward_distances
                             .iter()
                             .zip(root_forward_distances.iter())
                             .map(|(distances, root_dist)| distances[node as usize].saturating_sub(*root_dist))
@@ -108,7 +286,8 @@ impl ALTPotData {
                                     .map(|(distances, root_dist)| root_dist.saturating_sub(distances[node as usize])),
                             )
                             .max()
-                            .unwrap_or(0)) as u64
+                            .unwrap_or(0),
+                    ) as u64
                 })
                 .inspect(|&delta| debug_assert!(delta < INFINITY as u64))
                 .collect();
@@ -140,7 +319,7 @@ impl ALTPotData {
             landmark_backward_distances.push(backward_distances);
         }
 
-        Self::new_with_landmark_distances(landmark_forward_distances, landmark_backward_distances)
+        (landmarks, landmark_forward_distances, landmark_backward_distances)
     }
 
     fn dfs_set_sizes<G>(
@@ -208,9 +387,62 @@ impl ALTPotData {
         landmarks
     }
 
+    /// Planar selection based on node coordinates.
+    /// The plane is divided into `num_landmarks` equally sized sectors around the center of all nodes.
+    /// In each sector, the node farthest away from the center becomes a landmark.
+    /// Empty sectors are compensated with the farthest remaining nodes.
+    pub fn planar_landmarks(latitude: &[f32], longitude: &[f32], num_landmarks: usize) -> Vec<NodeId> {
+        assert!(num_landmarks > 0, "planar landmark selection needs at least one landmark");
+        report!("algo", "Planar Landmarks");
+        report!("num_landmarks", num_landmarks);
+        let n = latitude.len();
+        assert_eq!(n, longitude.len());
+        let center_lat = latitude.iter().map(|&lat| f64::from(lat)).sum::<f64>() / n as f64;
+        let center_lng = longitude.iter().map(|&lng| f64::from(lng)).sum::<f64>() / n as f64;
+        // account for meridians converging
+        let lng_scale = center_lat.to_radians().cos();
+
+        let polar = |node: usize| {
+            let y = f64::from(latitude[node]) - center_lat;
+            let x = (f64::from(longitude[node]) - center_lng) * lng_scale;
+            (y.atan2(x), x * x + y * y)
+        };
+
+        let mut farthest_in_sector: Vec<Option<(f64, NodeId)>> = vec![None; num_landmarks];
+        for node in 0..n {
+            let (angle, dist) = polar(node);
+            let sector = min(
+                ((angle + std::f64::consts::PI) / (2.0 * std::f64::consts::PI) * num_landmarks as f64) as usize,
+                num_landmarks - 1,
+            );
+            if farthest_in_sector[sector].map(|(max_dist, _)| dist > max_dist).unwrap_or(true) {
+                farthest_in_sector[sector] = Some((dist, node as NodeId));
+            }
+        }
+
+        let mut landmarks: Vec<NodeId> = farthest_in_sector.iter().flatten().map(|&(_, node)| node).collect();
+        if landmarks.len() < num_landmarks {
+            let mut is_landmark = BitVec::new(n);
+            for &landmark in &landmarks {
+                is_landmark.set(landmark as usize);
+            }
+            let mut by_dist: Vec<NodeId> = (0..n as NodeId).filter(|&node| !is_landmark.get(node as usize)).collect();
+            by_dist.sort_unstable_by(|&a, &b| polar(b as usize).1.partial_cmp(&polar(a as usize).1).unwrap());
+            let missing = num_landmarks - landmarks.len();
+            landmarks.extend(by_dist.into_iter().take(missing));
+        }
+
+        landmarks
+    }
+
+    pub fn landmarks(&self) -> &[NodeId] {
+        &self.landmarks
+    }
+
     pub fn forward_potential(&self) -> ALTPotential {
         ALTPotential {
             num_landmarks: self.num_landmarks,
+            active_landmarks: (0..self.num_landmarks).collect(),
             target: u32::MAX as NodeId,
             landmark_forward_distances: &self.landmark_forward_distances,
             landmark_backward_distances: &self.landmark_backward_distances,
@@ -220,6 +452,7 @@ impl ALTPotData {
     pub fn backward_potential(&self) -> ALTPotential {
         ALTPotential {
             num_landmarks: self.num_landmarks,
+            active_landmarks: (0..self.num_landmarks).collect(),
             target: u32::MAX as NodeId,
             landmark_forward_distances: &self.landmark_backward_distances,
             landmark_backward_distances: &self.landmark_forward_distances,
@@ -231,6 +464,8 @@ pub struct ALTPotential<'a> {
     landmark_forward_distances: &'a [Weight],
     landmark_backward_distances: &'a [Weight],
     num_landmarks: usize,
+    // indices of the landmarks used for the potential, initially all
+    active_landmarks: Vec<usize>,
     target: NodeId,
 }
 
@@ -244,6 +479,34 @@ impl ALTPotential<'_> {
         let begin = node as usize * self.num_landmarks;
         &self.landmark_backward_distances[begin..begin + self.num_landmarks]
     }
+
+    // Lower bound for the distance from `node` to the target using a single landmark.
+    fn landmark_bound(&self, landmark: usize, node: NodeId) -> Weight {
+        let to_target = self.landmark_dists_to(self.target)[landmark].saturating_sub(self.landmark_dists_to(node)[landmark]);
+        let from_node = self.landmark_dists_from(node)[landmark].saturating_sub(self.landmark_dists_from(self.target)[landmark]);
+        max(to_target, from_node)
+    }
+
+    /// Only use the `num_active` landmarks which yield the best lower bounds between `source` and `target`.
+    /// Fewer landmarks make each potential evaluation cheaper.
+    /// Any subset of landmarks yields a feasible potential, so the active set stays valid when the potential gets initialized for another target,
+    /// but then the bounds may be worse.
+    pub fn activate_best_landmarks(&mut self, source: NodeId, target: NodeId, num_active: usize) {
+        self.target = target;
+        let mut landmarks: Vec<usize> = (0..self.num_landmarks).collect();
+        landmarks.sort_by_cached_key(|&landmark| Reverse(self.landmark_bound(landmark, source)));
+        landmarks.truncate(max(num_active, 1));
+        self.active_landmarks = landmarks;
+    }
+
+    /// Use all landmarks again.
+    pub fn activate_all_landmarks(&mut self) {
+        self.active_landmarks = (0..self.num_landmarks).collect();
+    }
+
+    pub fn active_landmarks(&self) -> &[usize] {
+        &self.active_landmarks
+    }
 }
 
 impl Potential for ALTPotential<'_> {
@@ -252,19 +515,7 @@ impl Potential for ALTPotential<'_> {
     }
 
     fn potential(&mut self, node: NodeId) -> Option<Weight> {
-        let max_pot = self
-            .landmark_dists_to(node)
-            .iter()
-            .zip(self.landmark_dists_to(self.target).iter())
-            .map(|(node_dist, target_dist)| target_dist.saturating_sub(*node_dist))
-            .chain(
-                self.landmark_dists_from(node)
-                    .iter()
-                    .zip(self.landmark_dists_from(self.target).iter())
-                    .map(|(node_dist, target_dist)| node_dist.saturating_sub(*target_dist)),
-            )
-            .max()
-            .unwrap();
+        let max_pot = self.active_landmarks.iter().map(|&landmark| self.landmark_bound(landmark, node)).max().unwrap();
 
         // TODO mh...........
         if max_pot < INFINITY {
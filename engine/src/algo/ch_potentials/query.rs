@@ -1092,110 +1092,132 @@ impl<P: BiDirPotential + Clone + Send> MultiThreadedBiDirSkipLowDegServer<P> {
         let fw_potential = &mut self.fw_potential;
         let bw_potential = &mut self.bw_potential;
 
+        // searches run on pool threads, they report into their own contexts
+        let fw_recorder = current_recorder().map(|recorder| recorder.object("forward"));
+        let bw_recorder = current_recorder().map(|recorder| recorder.object("backward"));
+
         let ((fw_meeting, fw_num_queue_pops), (bw_meeting, bw_num_queue_pops)) = self.thread_pool.join(
             || {
-                let mut fw_potential = RefCell::new(fw_potential);
-                let mut num_queue_pops = 0;
-                let mut meeting_node = None;
-                let mut fw_tentative_distance = INFINITY;
-                let mut stop_dist = cap;
-
-                while !fw_potential.get_mut().stop_forward(
-                    forward_dijkstra.queue().peek().map(|q| q.key),
-                    Some(bw_progress.load(std::sync::atomic::Ordering::Relaxed)),
-                    stop_dist,
-                ) {
-                    if let Some(node) = forward_dijkstra.next_with_improve_callback_and_potential(
-                        |head, &dist| {
-                            let mut pot = fw_potential.borrow_mut();
-                            if let Some(pot_cap) = pot_cap {
-                                if pot.forward_potential_raw(head).unwrap_or(INFINITY) + pot.backward_potential_raw(head).unwrap_or(INFINITY) > pot_cap {
+                let _recorder = fw_recorder.map(Recorder::attach);
+                let (meeting_node, num_queue_pops) = (|| {
+                    let mut fw_potential = RefCell::new(fw_potential);
+                    let mut num_queue_pops = 0;
+                    let mut meeting_node = None;
+                    let mut fw_tentative_distance = INFINITY;
+                    let mut stop_dist = cap;
+
+                    while !fw_potential.get_mut().stop_forward(
+                        forward_dijkstra.queue().peek().map(|q| q.key),
+                        Some(bw_progress.load(std::sync::atomic::Ordering::Relaxed)),
+                        stop_dist,
+                    ) {
+                        if let Some(node) = forward_dijkstra.next_with_improve_callback_and_potential(
+                            |head, &dist| {
+                                let mut pot = fw_potential.borrow_mut();
+                                if let Some(pot_cap) = pot_cap {
+                                    if pot.forward_potential_raw(head).unwrap_or(INFINITY) + pot.backward_potential_raw(head).unwrap_or(INFINITY) > pot_cap {
+                                        return false;
+                                    }
+                                }
+                                if pot.prune_forward(NodeIdT(head), dist, bw_progress.load(std::sync::atomic::Ordering::Relaxed), stop_dist) {
                                     return false;
                                 }
+                                fw_tentative_distance = min(fw_tentative_distance, dist + fw_reverse_dist.get(head as usize));
+                                stop_dist = min(fw_tentative_distance, cap);
+                                if tentative_distance.fetch_min(fw_tentative_distance, std::sync::atomic::Ordering::Relaxed) > fw_tentative_distance {
+                                    meeting_node = Some(head);
+                                }
+                                true
+                            },
+                            |node| fw_potential.borrow_mut().forward_potential(node),
+                        ) {
+                            num_queue_pops += 1;
+                            let prog = forward_dijkstra.queue().peek().map_or(INFINITY, |p| p.key);
+                            fw_progress.store(prog, std::sync::atomic::Ordering::Relaxed);
+                            if node == query.to() {
+                                fw_progress.store(INFINITY, std::sync::atomic::Ordering::Relaxed);
+                                return (Some(query.to()), num_queue_pops);
                             }
-                            if pot.prune_forward(NodeIdT(head), dist, bw_progress.load(std::sync::atomic::Ordering::Relaxed), stop_dist) {
-                                return false;
-                            }
-                            fw_tentative_distance = min(fw_tentative_distance, dist + fw_reverse_dist.get(head as usize));
-                            stop_dist = min(fw_tentative_distance, cap);
-                            if tentative_distance.fetch_min(fw_tentative_distance, std::sync::atomic::Ordering::Relaxed) > fw_tentative_distance {
-                                meeting_node = Some(head);
-                            }
-                            true
-                        },
-                        |node| fw_potential.borrow_mut().forward_potential(node),
-                    ) {
-                        num_queue_pops += 1;
-                        let prog = forward_dijkstra.queue().peek().map_or(INFINITY, |p| p.key);
-                        fw_progress.store(prog, std::sync::atomic::Ordering::Relaxed);
-                        if node == query.to() {
-                            fw_progress.store(INFINITY, std::sync::atomic::Ordering::Relaxed);
-                            return (Some(query.to()), num_queue_pops);
                         }
                     }
-                }
 
-                if meeting_node.is_some() {
-                    (meeting_node, num_queue_pops)
-                } else {
-                    if forward_dijkstra.tentative_distance(query.to()) < INFINITY {
-                        (Some(query.to()), num_queue_pops)
+                    if meeting_node.is_some() {
+                        (meeting_node, num_queue_pops)
                     } else {
-                        (None, num_queue_pops)
+                        if forward_dijkstra.tentative_distance(query.to()) < INFINITY {
+                            (Some(query.to()), num_queue_pops)
+                        } else {
+                            (None, num_queue_pops)
+                        }
                     }
-                }
+                })();
+
+                report!("num_queue_pops", num_queue_pops);
+                report!("num_queue_pushs", forward_dijkstra.num_queue_pushs());
+                report!("num_relaxed_arcs", forward_dijkstra.num_relaxed_arcs());
+
+                (meeting_node, num_queue_pops)
             },
             || {
-                let mut bw_potential = RefCell::new(bw_potential);
-                let mut num_queue_pops = 0;
-                let mut meeting_node = None;
-                let mut bw_tentative_distance = INFINITY;
-                let mut stop_dist = cap;
-
-                while !bw_potential.get_mut().stop_backward(
-                    Some(fw_progress.load(std::sync::atomic::Ordering::Relaxed)),
-                    backward_dijkstra.queue().peek().map(|q| q.key),
-                    stop_dist,
-                ) {
-                    if let Some(node) = backward_dijkstra.next_with_improve_callback_and_potential(
-                        |head, &dist| {
-                            let mut pot = bw_potential.borrow_mut();
-                            if let Some(pot_cap) = pot_cap {
-                                if pot.forward_potential_raw(head).unwrap_or(INFINITY) + pot.backward_potential_raw(head).unwrap_or(INFINITY) > pot_cap {
+                let _recorder = bw_recorder.map(Recorder::attach);
+                let (meeting_node, num_queue_pops) = (|| {
+                    let mut bw_potential = RefCell::new(bw_potential);
+                    let mut num_queue_pops = 0;
+                    let mut meeting_node = None;
+                    let mut bw_tentative_distance = INFINITY;
+                    let mut stop_dist = cap;
+
+                    while !bw_potential.get_mut().stop_backward(
+                        Some(fw_progress.load(std::sync::atomic::Ordering::Relaxed)),
+                        backward_dijkstra.queue().peek().map(|q| q.key),
+                        stop_dist,
+                    ) {
+                        if let Some(node) = backward_dijkstra.next_with_improve_callback_and_potential(
+                            |head, &dist| {
+                                let mut pot = bw_potential.borrow_mut();
+                                if let Some(pot_cap) = pot_cap {
+                                    if pot.forward_potential_raw(head).unwrap_or(INFINITY) + pot.backward_potential_raw(head).unwrap_or(INFINITY) > pot_cap {
+                                        return false;
+                                    }
+                                }
+                                if pot.prune_backward(NodeIdT(head), dist, fw_progress.load(std::sync::atomic::Ordering::Relaxed), stop_dist) {
                                     return false;
                                 }
+                                bw_tentative_distance = min(bw_tentative_distance, dist + bw_reverse_dist.get(head as usize));
+                                stop_dist = min(bw_tentative_distance, cap);
+                                if tentative_distance.fetch_min(bw_tentative_distance, std::sync::atomic::Ordering::Relaxed) > bw_tentative_distance {
+                                    meeting_node = Some(head);
+                                }
+                                true
+                            },
+                            |node| bw_potential.borrow_mut().backward_potential(node),
+                        ) {
+                            num_queue_pops += 1;
+                            let prog = backward_dijkstra.queue().peek().map_or(INFINITY, |p| p.key);
+                            bw_progress.store(prog, std::sync::atomic::Ordering::Relaxed);
+                            if node == query.from() {
+                                bw_progress.store(INFINITY, std::sync::atomic::Ordering::Relaxed);
+                                return (Some(query.from()), num_queue_pops);
                             }
-                            if pot.prune_backward(NodeIdT(head), dist, fw_progress.load(std::sync::atomic::Ordering::Relaxed), stop_dist) {
-                                return false;
-                            }
-                            bw_tentative_distance = min(bw_tentative_distance, dist + bw_reverse_dist.get(head as usize));
-                            stop_dist = min(bw_tentative_distance, cap);
-                            if tentative_distance.fetch_min(bw_tentative_distance, std::sync::atomic::Ordering::Relaxed) > bw_tentative_distance {
-                                meeting_node = Some(head);
-                            }
-                            true
-                        },
-                        |node| bw_potential.borrow_mut().backward_potential(node),
-                    ) {
-                        num_queue_pops += 1;
-                        let prog = backward_dijkstra.queue().peek().map_or(INFINITY, |p| p.key);
-                        bw_progress.store(prog, std::sync::atomic::Ordering::Relaxed);
-                        if node == query.from() {
-                            bw_progress.store(INFINITY, std::sync::atomic::Ordering::Relaxed);
-                            return (Some(query.from()), num_queue_pops);
                         }
                     }
-                }
 
-                if meeting_node.is_some() {
-                    (meeting_node, num_queue_pops)
-                } else {
-                    if backward_dijkstra.tentative_distance(query.from()) < INFINITY {
-                        (Some(query.from()), num_queue_pops)
+                    if meeting_node.is_some() {
+                        (meeting_node, num_queue_pops)
                     } else {
-                        (None, num_queue_pops)
+                        if backward_dijkstra.tentative_distance(query.from()) < INFINITY {
+                            (Some(query.from()), num_queue_pops)
+                        } else {
+                            (None, num_queue_pops)
+                        }
                     }
-                }
+                })();
+
+                report!("num_queue_pops", num_queue_pops);
+                report!("num_queue_pushs", backward_dijkstra.num_queue_pushs());
+                report!("num_relaxed_arcs", backward_dijkstra.num_relaxed_arcs());
+
+                (meeting_node, num_queue_pops)
             },
         );
 
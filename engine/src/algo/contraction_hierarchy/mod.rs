@@ -1,35 +1,104 @@
-//! Experimental prototype implementation of Contraction Hierarchies in rust.
+//! Implementation of Contraction Hierarchies in rust.
 //!
-//! Not tuned for performance yet.
 //! No node ordering implemented yet, depends on getting a precalculated order.
+//! Contraction can be performed sequentially or in parallel by contracting independent sets of consecutive nodes at once.
 
-use std::marker::PhantomData;
+use std::{cell::RefCell, marker::PhantomData};
 
 use super::*;
 use crate::algo::{a_star::*, dijkstra::*};
 use crate::datastr::node_order::NodeOrder;
+use crate::io::*;
+use crate::util::in_range_option::InRangeOption;
+use rayon::prelude::*;
 
 pub mod query;
 
+/// Unpacking information for a single CH arc.
+/// For shortcuts, the first element contains the (rank of the) middle node,
+/// for original arcs, the second element contains the id of the original edge.
+pub type ArcUnpacking = (InRangeOption<NodeId>, InRangeOption<EdgeId>);
+
+// One pair of witness search buffers for each thread during the scope of a parallel contraction.
+scoped_thread_local!(static WITNESS_WORKSPACE: RefCell<(DijkstraData<Weight>, DijkstraData<Weight>)>);
+
 /// Struct for a Contraction Hierarchy, that is the completely preprocessed
 /// graph augmented by shortcuts and split in an upwards and downward part,
 /// optionally with unpacking info.
 pub struct ContractionHierarchy {
     forward: OwnedGraph,
     backward: OwnedGraph,
-    middle_nodes: Option<(Vec<NodeId>, Vec<NodeId>)>,
+    unpacking: Option<(Vec<ArcUnpacking>, Vec<ArcUnpacking>)>,
 }
 
 impl ContractionHierarchy {
     /// Create CH struct from augmented graph and node order.
+    /// The resulting CH will not contain any unpacking information, so path retrieval is not possible.
     pub fn from_contracted_graph(graph: OwnedGraph, order: &NodeOrder) -> ContractionHierarchy {
         let (forward, backward) = graph.ch_split(order);
         ContractionHierarchy {
             forward,
             backward,
-            middle_nodes: None,
+            unpacking: None,
         }
     }
+
+    /// Borrow the upward graph.
+    pub fn forward(&self) -> &OwnedGraph {
+        &self.forward
+    }
+
+    /// Borrow the downward graph, that is the reversed downward arcs stored at their lower ranked node.
+    pub fn backward(&self) -> &OwnedGraph {
+        &self.backward
+    }
+
+    /// Does this CH contain the information necessary to unpack shortcuts?
+    pub fn has_unpacking(&self) -> bool {
+        self.unpacking.is_some()
+    }
+}
+
+// Stored in the RoutingKit CH layout, so directories can also be loaded with `ch_potentials::CHPotLoader` once the `order` is stored next to it.
+// The unpacking data comes as additional files, CHs from RoutingKit do not have them.
+impl Deconstruct for ContractionHierarchy {
+    fn save_each(&self, store: &dyn Fn(&str, &dyn Save) -> std::io::Result<()>) -> std::io::Result<()> {
+        store("forward_first_out", &self.forward.first_out())?;
+        store("forward_head", &self.forward.head())?;
+        store("forward_weight", &self.forward.weight())?;
+        store("backward_first_out", &self.backward.first_out())?;
+        store("backward_head", &self.backward.head())?;
+        store("backward_weight", &self.backward.weight())?;
+        if let Some((forward_unpacking, backward_unpacking)) = &self.unpacking {
+            store("forward_unpacking", forward_unpacking)?;
+            store("backward_unpacking", backward_unpacking)?;
+        }
+        Ok(())
+    }
+}
+
+impl Reconstruct for ContractionHierarchy {
+    fn reconstruct_with(loader: Loader) -> std::io::Result<Self> {
+        let forward = OwnedGraph::new(loader.load("forward_first_out")?, loader.load("forward_head")?, loader.load("forward_weight")?);
+        let backward = OwnedGraph::new(
+            loader.load("backward_first_out")?,
+            loader.load("backward_head")?,
+            loader.load("backward_weight")?,
+        );
+        // unpacking data is optional, CHs without it can still answer distance queries
+        let unpacking = if loader.path().join("forward_unpacking").exists() {
+            let forward_unpacking: Vec<ArcUnpacking> = loader.load("forward_unpacking")?;
+            let backward_unpacking: Vec<ArcUnpacking> = loader.load("backward_unpacking")?;
+            assert_eq!(forward_unpacking.len(), forward.num_arcs());
+            assert_eq!(backward_unpacking.len(), backward.num_arcs());
+            Some((forward_unpacking, backward_unpacking))
+        } else {
+            None
+        };
+        assert_eq!(forward.num_nodes(), backward.num_nodes());
+
+        Ok(ContractionHierarchy { forward, backward, unpacking })
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -42,31 +111,31 @@ enum ShortcutResult {
 /// Struct for nodes during contraction.
 /// Allows adding, removing or updating edges.
 struct Node {
-    outgoing: Vec<(Link, NodeId)>,
-    incoming: Vec<(Link, NodeId)>,
+    outgoing: Vec<(Link, ArcUnpacking)>,
+    incoming: Vec<(Link, ArcUnpacking)>,
 }
 
 impl Node {
-    fn insert_or_decrease_outgoing(&mut self, to: NodeId, weight: Weight, over: NodeId) -> ShortcutResult {
-        Node::insert_or_decrease(&mut self.outgoing, to, weight, over)
+    fn insert_or_decrease_outgoing(&mut self, to: NodeId, weight: Weight, unpacking: ArcUnpacking) -> ShortcutResult {
+        Node::insert_or_decrease(&mut self.outgoing, to, weight, unpacking)
     }
 
-    fn insert_or_decrease_incoming(&mut self, from: NodeId, weight: Weight, over: NodeId) -> ShortcutResult {
-        Node::insert_or_decrease(&mut self.incoming, from, weight, over)
+    fn insert_or_decrease_incoming(&mut self, from: NodeId, weight: Weight, unpacking: ArcUnpacking) -> ShortcutResult {
+        Node::insert_or_decrease(&mut self.incoming, from, weight, unpacking)
     }
 
-    fn insert_or_decrease(links: &mut Vec<(Link, NodeId)>, node: NodeId, weight: Weight, over: NodeId) -> ShortcutResult {
+    fn insert_or_decrease(links: &mut Vec<(Link, ArcUnpacking)>, node: NodeId, weight: Weight, unpacking: ArcUnpacking) -> ShortcutResult {
         for &mut (
             Link {
                 node: other,
                 weight: ref mut other_weight,
             },
-            ref mut shortcut_middle,
+            ref mut other_unpacking,
         ) in links.iter_mut()
         {
             if node == other {
                 if weight < *other_weight {
-                    *shortcut_middle = over;
+                    *other_unpacking = unpacking;
                     *other_weight = weight;
                     return ShortcutResult::ShortenedExisting;
                 } else {
@@ -75,7 +144,7 @@ impl Node {
             }
         }
 
-        links.push((Link { node, weight }, over));
+        links.push((Link { node, weight }, unpacking));
         ShortcutResult::NewShortcut
     }
 
@@ -90,6 +159,14 @@ impl Node {
         let pos = self.incoming.iter().position(|&(Link { node, .. }, _)| from == node).unwrap();
         self.incoming.swap_remove(pos);
     }
+
+    // are there any links to or from nodes in the given id range
+    fn has_neighbor_in(&self, nodes: std::ops::Range<NodeId>) -> bool {
+        self.outgoing
+            .iter()
+            .chain(self.incoming.iter())
+            .any(|&(Link { node, .. }, _)| nodes.contains(&node))
+    }
 }
 
 /// Intermediate graph representation for during the preprocessing.
@@ -100,46 +177,33 @@ struct ContractionGraph {
 
 impl ContractionGraph {
     // Create a ContractionGraph from a regular graph and an order.
-    fn new<Graph: LinkIterGraph>(graph: &Graph, order: NodeOrder) -> ContractionGraph {
+    fn new<Graph: EdgeRandomAccessGraph<Link>>(graph: &Graph, order: NodeOrder) -> ContractionGraph {
         let n = graph.num_nodes();
 
         // We need to:
         // - filter out loops
         // - translate the node ids
+        // - keep only the lightest of several parallel edges
         // - create the struct we use during preprocessing
-        let nodes = {
-            let outs = (0..n).map(|node| {
-                graph
-                    .link_iter(order.node(node as NodeId))
-                    .filter(|&Link { node: head, .. }| head != order.node(node as NodeId))
-                    .map(|Link { node, weight }| {
-                        (
-                            Link {
-                                node: order.rank(node),
-                                weight,
-                            },
-                            n as NodeId,
-                        )
-                    })
-                    .collect()
-            });
-            let reversed = OwnedGraph::reversed(graph);
-            let ins = (0..n).map(|node| {
-                LinkIterable::<Link>::link_iter(&reversed, order.node(node as NodeId))
-                    .filter(|&Link { node: head, .. }| head != order.node(node as NodeId))
-                    .map(|Link { node, weight }| {
-                        (
-                            Link {
-                                node: order.rank(node),
-                                weight,
-                            },
-                            n as NodeId,
-                        )
-                    })
-                    .collect()
-            });
-            outs.zip(ins).map(|(outgoing, incoming)| Node { outgoing, incoming }).collect()
-        };
+        let mut nodes: Vec<Node> = (0..n)
+            .map(|_| Node {
+                outgoing: Vec::new(),
+                incoming: Vec::new(),
+            })
+            .collect();
+
+        for node in 0..n as NodeId {
+            for edge_id in graph.neighbor_edge_indices(node) {
+                let Link { node: head, weight } = graph.link(edge_id);
+                if head == node {
+                    continue;
+                }
+                let unpacking = (InRangeOption::NONE, InRangeOption::some(edge_id));
+                let (tail_rank, head_rank) = (order.rank(node), order.rank(head));
+                nodes[tail_rank as usize].insert_or_decrease_outgoing(head_rank, weight, unpacking);
+                nodes[head_rank as usize].insert_or_decrease_incoming(tail_rank, weight, unpacking);
+            }
+        }
 
         ContractionGraph { nodes }
     }
@@ -170,18 +234,9 @@ impl ContractionGraph {
             } else {
                 contraction_count -= 1;
             }
-            // for all pairs of neighbors
-            for &(Link { node: from, weight: from_wght }, _) in &node.incoming {
-                for &(Link { node: to, weight: to_wght }, _) in &node.outgoing {
-                    // do witness search to check if we need the shortcut
-                    let (shortcut_required, new_data) = subgraph.shortcut_required(from, to, from_wght + to_wght, data);
-                    data = new_data;
-                    if shortcut_required {
-                        let middle_node_id = subgraph.id_offset - 1;
-                        // insert shortcut
-                        subgraph.insert_or_decrease(from, to, from_wght + to_wght, middle_node_id);
-                    }
-                }
+            let middle_node_id = subgraph.id_offset - 1;
+            for (from, to, weight) in subgraph.required_shortcuts(node, &mut data) {
+                subgraph.insert_or_decrease(from, to, weight, middle_node_id);
             }
 
             // set graph to subgraph, so we continue with the next node in the next iteration
@@ -189,6 +244,54 @@ impl ContractionGraph {
         }
     }
 
+    // contract all nodes, but process sets of consecutive independent nodes in parallel
+    fn contract_parallel(&mut self) {
+        let n = self.nodes.len();
+
+        rayon::ThreadPoolBuilder::new()
+            .build_scoped(
+                |thread| WITNESS_WORKSPACE.set(&RefCell::new((DijkstraData::new(n), DijkstraData::new(n))), || thread.run()),
+                |pool| {
+                    pool.install(|| {
+                        let mut graph = self.partial_graph();
+
+                        loop {
+                            let batch_size = graph.independent_prefix_len();
+                            if batch_size == 0 {
+                                break;
+                            }
+                            let batch_offset = graph.id_offset;
+                            let (batch, mut subgraph) = graph.remove_lowest_batch(batch_size);
+
+                            // Witness searches of the batch are independent of each other:
+                            // No batch node is part of the subgraph anymore and the subgraph is not modified until all searches are done.
+                            let shortcuts: Vec<(NodeId, NodeId, Weight, NodeId)> = {
+                                let subgraph = &subgraph;
+                                batch
+                                    .par_iter()
+                                    .enumerate()
+                                    .flat_map_iter(|(idx, node)| {
+                                        let middle_node_id = batch_offset + idx as NodeId;
+                                        WITNESS_WORKSPACE
+                                            .with(|data| subgraph.required_shortcuts(node, &mut data.borrow_mut()))
+                                            .into_iter()
+                                            .map(move |(from, to, weight)| (from, to, weight, middle_node_id))
+                                    })
+                                    .collect()
+                            };
+
+                            for (from, to, weight, middle_node_id) in shortcuts {
+                                subgraph.insert_or_decrease(from, to, weight, middle_node_id);
+                            }
+
+                            graph = subgraph;
+                        }
+                    })
+                },
+            )
+            .unwrap();
+    }
+
     // create partial graph with all nodes
     fn partial_graph(&mut self) -> PartialContractionGraph {
         PartialContractionGraph {
@@ -205,15 +308,15 @@ impl ContractionGraph {
             .map(|node| (node.outgoing.into_iter().unzip(), node.incoming.into_iter().unzip()))
             .unzip();
 
-        let (outgoing, forward_shortcut_middles): (Vec<Vec<Link>>, Vec<Vec<NodeId>>) = outgoing.into_iter().unzip();
-        let (incoming, backward_shortcut_middles): (Vec<Vec<Link>>, Vec<Vec<NodeId>>) = incoming.into_iter().unzip();
-        let forward_shortcut_middles = forward_shortcut_middles.into_iter().flat_map(|data| data.into_iter()).collect();
-        let backward_shortcut_middles = backward_shortcut_middles.into_iter().flat_map(|data| data.into_iter()).collect();
+        let (outgoing, forward_unpacking): (Vec<Vec<Link>>, Vec<Vec<ArcUnpacking>>) = outgoing.into_iter().unzip();
+        let (incoming, backward_unpacking): (Vec<Vec<Link>>, Vec<Vec<ArcUnpacking>>) = incoming.into_iter().unzip();
+        let forward_unpacking = forward_unpacking.into_iter().flat_map(|data| data.into_iter()).collect();
+        let backward_unpacking = backward_unpacking.into_iter().flat_map(|data| data.into_iter()).collect();
 
         ContractionHierarchy {
             forward: OwnedGraph::from_adjancecy_lists(outgoing),
             backward: OwnedGraph::from_adjancecy_lists(incoming),
-            middle_nodes: Some((forward_shortcut_middles, backward_shortcut_middles)),
+            unpacking: Some((forward_unpacking, backward_unpacking)),
         }
     }
 }
@@ -230,54 +333,86 @@ struct PartialContractionGraph<'a> {
 impl<'a> PartialContractionGraph<'a> {
     // split of the lowest node and remove any edges from higher ranked nodes to this one
     fn remove_lowest(self) -> Option<(&'a Node, PartialContractionGraph<'a>)> {
-        if let Some((node, other_nodes)) = self.nodes.split_first_mut() {
-            let mut subgraph = PartialContractionGraph {
-                nodes: other_nodes,
-                id_offset: self.id_offset + 1,
-            };
-            subgraph.remove_edges_to_removed(&node);
-            Some((node, subgraph))
-        } else {
-            None
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let (nodes, subgraph) = self.remove_lowest_batch(1);
+        Some((&nodes[0], subgraph))
+    }
+
+    // split of the `count` lowest nodes and remove any edges from higher ranked nodes to these
+    fn remove_lowest_batch(self, count: usize) -> (&'a [Node], PartialContractionGraph<'a>) {
+        let (nodes, other_nodes) = self.nodes.split_at_mut(count);
+        let mut subgraph = PartialContractionGraph {
+            nodes: other_nodes,
+            id_offset: self.id_offset + count as NodeId,
+        };
+        for (idx, node) in nodes.iter().enumerate() {
+            subgraph.remove_edges_to_removed(node, self.id_offset + idx as NodeId);
         }
+        (nodes, subgraph)
     }
 
-    fn remove_edges_to_removed(&mut self, node: &Node) {
+    // number of lowest nodes which are pairwise not adjacent and can thus be contracted independently
+    fn independent_prefix_len(&self) -> usize {
+        self.nodes
+            .iter()
+            .enumerate()
+            .position(|(idx, node)| idx > 0 && node.has_neighbor_in(self.id_offset..self.id_offset + idx as NodeId))
+            .unwrap_or(self.nodes.len())
+    }
+
+    fn remove_edges_to_removed(&mut self, node: &Node, node_id: NodeId) {
         for &(Link { node: from, .. }, _) in &node.incoming {
             debug_assert!(from >= self.id_offset, "{}, {}", from, self.id_offset);
-            self.nodes[(from - self.id_offset) as usize].remove_outgoing(self.id_offset - 1);
+            self.nodes[(from - self.id_offset) as usize].remove_outgoing(node_id);
         }
         for &(Link { node: to, .. }, _) in &node.outgoing {
-            self.nodes[(to - self.id_offset) as usize].remove_incmoing(self.id_offset - 1);
+            self.nodes[(to - self.id_offset) as usize].remove_incmoing(node_id);
         }
     }
 
     fn insert_or_decrease(&mut self, from: NodeId, to: NodeId, weight: Weight, over: NodeId) -> ShortcutResult {
-        let out_result = self.nodes[(from - self.id_offset) as usize].insert_or_decrease_outgoing(to, weight, over);
-        let in_result = self.nodes[(to - self.id_offset) as usize].insert_or_decrease_incoming(from, weight, over);
+        let unpacking = (InRangeOption::some(over), InRangeOption::NONE);
+        let out_result = self.nodes[(from - self.id_offset) as usize].insert_or_decrease_outgoing(to, weight, unpacking);
+        let in_result = self.nodes[(to - self.id_offset) as usize].insert_or_decrease_incoming(from, weight, unpacking);
 
         assert!(out_result == in_result);
         out_result
     }
 
-    fn shortcut_required(
-        &self,
-        from: NodeId,
-        to: NodeId,
-        shortcut_weight: Weight,
-        recycled: (DijkstraData<Weight>, DijkstraData<Weight>),
-    ) -> (bool, (DijkstraData<Weight>, DijkstraData<Weight>)) {
+    // all shortcuts necessary to contract the given node, which must already have been removed from this graph
+    fn required_shortcuts(&self, node: &Node, data: &mut (DijkstraData<Weight>, DijkstraData<Weight>)) -> Vec<(NodeId, NodeId, Weight)> {
+        let mut shortcuts = Vec::new();
+        // for all pairs of neighbors
+        for &(Link { node: from, weight: from_wght }, _) in &node.incoming {
+            for &(Link { node: to, weight: to_wght }, _) in &node.outgoing {
+                // paths of length INFINITY or more are never shortest paths, so skipping them also keeps weights from overflowing
+                if from_wght + to_wght >= INFINITY {
+                    continue;
+                }
+                // do witness search to check if we need the shortcut
+                if self.shortcut_required(from, to, from_wght + to_wght, data) {
+                    shortcuts.push((from, to, from_wght + to_wght));
+                }
+            }
+        }
+        shortcuts
+    }
+
+    fn shortcut_required(&self, from: NodeId, to: NodeId, shortcut_weight: Weight, data: &mut (DijkstraData<Weight>, DijkstraData<Weight>)) -> bool {
         // no loop shortcuts ever required
         if from == to {
-            return (false, recycled);
+            return false;
         }
 
         // create server from recycled stuff
+        let (forward_data, backward_data) = std::mem::replace(data, (DijkstraData::new(0), DijkstraData::new(0)));
         let mut server = crate::algo::dijkstra::query::bidirectional_dijkstra::Server {
-            forward: ForwardWrapper { graph: &self },
-            backward: BackwardWrapper { graph: &self },
-            forward_data: recycled.0,
-            backward_data: recycled.1,
+            forward: ForwardWrapper { graph: self },
+            backward: BackwardWrapper { graph: self },
+            forward_data,
+            backward_data,
             meeting_node: 0,
             potential: BiDirZeroPot,
             dir_chooser: PhantomData::<ChooseMinKeyDir>::default(),
@@ -290,12 +425,13 @@ impl<'a> PartialContractionGraph<'a> {
             None => true,
         };
 
-        (res, (server.forward_data, server.backward_data))
+        *data = (server.forward_data, server.backward_data);
+        res
     }
 }
 
 /// Create an overlay graph by contracting a fixed number of nodes
-pub fn overlay<Graph: LinkIterGraph>(graph: &Graph, order: NodeOrder, contraction_count: usize) -> (OwnedGraph, OwnedGraph) {
+pub fn overlay<Graph: EdgeRandomAccessGraph<Link>>(graph: &Graph, order: NodeOrder, contraction_count: usize) -> (OwnedGraph, OwnedGraph) {
     let mut graph = ContractionGraph::new(graph, order);
     graph.contract_partially(contraction_count);
     let ch = graph.into_first_out_graphs();
@@ -303,12 +439,21 @@ pub fn overlay<Graph: LinkIterGraph>(graph: &Graph, order: NodeOrder, contractio
 }
 
 /// Perform CH Preprocessing
-pub fn contract<Graph: LinkIterGraph>(graph: &Graph, order: NodeOrder) -> ContractionHierarchy {
+pub fn contract<Graph: EdgeRandomAccessGraph<Link>>(graph: &Graph, order: NodeOrder) -> ContractionHierarchy {
     let mut graph = ContractionGraph::new(graph, order);
     graph.contract();
     graph.into_first_out_graphs()
 }
 
+/// Perform CH Preprocessing in parallel.
+/// Sets of consecutive nodes in the order which are not adjacent in the remaining graph are contracted simultaneously.
+/// Witness searches never use any node of the current set, so the result may contain slightly more shortcuts than with [contract].
+pub fn contract_parallel<Graph: EdgeRandomAccessGraph<Link>>(graph: &Graph, order: NodeOrder) -> ContractionHierarchy {
+    let mut graph = ContractionGraph::new(graph, order);
+    graph.contract_parallel();
+    graph.into_first_out_graphs()
+}
+
 // Utilities for witness search
 
 struct ForwardWrapper<'a> {
@@ -349,7 +494,7 @@ impl<'a> Graph for BackwardWrapper<'a> {
 
 // workaround until we get an implementation of https://github.com/rust-lang/rfcs/pull/2071
 struct LinkMappingIterator<'a> {
-    iter: std::slice::Iter<'a, (Link, NodeId)>,
+    iter: std::slice::Iter<'a, (Link, ArcUnpacking)>,
     offset: NodeId,
 }
 
@@ -4,17 +4,21 @@
 //! And more complicated path unpacking.
 //! This works because the augmented graph was split into an upward and an downward part.
 //! This implicitly makes sure, that both searches only go to higher ranked nodes.
+//! Both searches use stall-on-demand to prune nodes which can be reached on a shorter path through a higher ranked node.
 
 use super::*;
 
 pub struct Server {
     forward: OwnedGraph,
     backward: OwnedGraph,
-    forward_data: DijkstraData<Weight>,
-    backward_data: DijkstraData<Weight>,
+    forward_data: DijkstraData<Weight, EdgeIdT>,
+    backward_data: DijkstraData<Weight, EdgeIdT>,
     meeting_node: NodeId,
-    shortcut_middle_nodes: Option<(Vec<NodeId>, Vec<NodeId>)>,
+    unpacking: Option<(Vec<ArcUnpacking>, Vec<ArcUnpacking>)>,
     order: NodeOrder,
+    num_settled_nodes: usize,
+    num_stalled_nodes: usize,
+    num_relaxed_edges: usize,
 }
 
 impl Server {
@@ -26,20 +30,26 @@ impl Server {
             forward_data: DijkstraData::new(n),
             backward_data: DijkstraData::new(n),
             meeting_node: 0,
-            shortcut_middle_nodes: ch.middle_nodes,
+            unpacking: ch.unpacking,
             order,
+            num_settled_nodes: 0,
+            num_stalled_nodes: 0,
+            num_relaxed_edges: 0,
         }
     }
 
     fn distance(&mut self, from: NodeId, to: NodeId) -> Option<Weight> {
+        self.num_settled_nodes = 0;
+        self.num_stalled_nodes = 0;
         let from = self.order.rank(from);
         let to = self.order.rank(to);
 
         // initialize
         let mut tentative_distance = INFINITY;
+        self.meeting_node = from;
 
-        let mut fw_ops = DefaultOps();
-        let mut bw_ops = DefaultOps();
+        let mut fw_ops = DefaultOpsWithLinkPath();
+        let mut bw_ops = DefaultOpsWithLinkPath();
         let mut forward_dijkstra = DijkstraRun::query(&self.forward, &mut self.forward_data, &mut fw_ops, DijkstraInit::from(from));
         let mut backward_dijkstra = DijkstraRun::query(&self.backward, &mut self.backward_data, &mut bw_ops, DijkstraInit::from(to));
 
@@ -51,7 +61,21 @@ impl Server {
         // compare tentative distance to both directions progress individually rather than the sum!
         while (tentative_distance > forward_progress || tentative_distance > backward_progress) && !(forward_done && backward_done) {
             if backward_done || (forward_progress <= backward_progress && !forward_done) {
-                if let Some(node) = forward_dijkstra.next() {
+                // Stall on demand: if the next node can be reached on a shorter path through a higher ranked node,
+                // the current distance cannot be part of a shortest up-down path and we don't need to relax its edges.
+                // The higher ranked nodes with arcs to the current one are exactly the heads of its arcs in the backward graph.
+                let stalled = if let Some(&State { node, .. }) = forward_dijkstra.queue().peek() {
+                    let dist = *forward_dijkstra.tentative_distance(node);
+                    LinkIterable::<Link>::link_iter(&self.backward, node).any(|l| *forward_dijkstra.tentative_distance(l.node) + l.weight < dist)
+                } else {
+                    false
+                };
+                if stalled {
+                    self.num_stalled_nodes += 1;
+                }
+
+                if let Some(node) = forward_dijkstra.next_filtered_edges(|_| !stalled) {
+                    self.num_settled_nodes += 1;
                     let distance = *forward_dijkstra.tentative_distance(node);
                     forward_progress = distance;
 
@@ -67,7 +91,19 @@ impl Server {
                     forward_done = true;
                 }
             } else {
-                if let Some(node) = backward_dijkstra.next() {
+                // same for the backward search, where the higher ranked nodes are the heads of the arcs in the forward graph
+                let stalled = if let Some(&State { node, .. }) = backward_dijkstra.queue().peek() {
+                    let dist = *backward_dijkstra.tentative_distance(node);
+                    LinkIterable::<Link>::link_iter(&self.forward, node).any(|l| *backward_dijkstra.tentative_distance(l.node) + l.weight < dist)
+                } else {
+                    false
+                };
+                if stalled {
+                    self.num_stalled_nodes += 1;
+                }
+
+                if let Some(node) = backward_dijkstra.next_filtered_edges(|_| !stalled) {
+                    self.num_settled_nodes += 1;
                     let distance = *backward_dijkstra.tentative_distance(node);
                     backward_progress = distance;
 
@@ -85,78 +121,97 @@ impl Server {
             }
         }
 
+        self.num_relaxed_edges = forward_dijkstra.num_relaxed_arcs() + backward_dijkstra.num_relaxed_arcs();
+
         match tentative_distance {
             INFINITY => None,
             dist => Some(dist),
         }
     }
 
-    // Ugly path unpacking.
-    // Should probably switch to something similar as in CCH query, which utilizes the parent pointers in the dijkstra struct.
-    fn path(&self, query: Query) -> Vec<NodeId> {
-        use std::collections::LinkedList;
-
-        let &(ref forward_middle_nodes, ref backward_middle_nodes) = self.shortcut_middle_nodes.as_ref().unwrap();
-        let mut forwad_path = LinkedList::new();
-        forwad_path.push_front(self.meeting_node);
-
-        while *forwad_path.front().unwrap() != query.from {
-            let next = self.forward_data.predecessors[*forwad_path.front().unwrap() as usize].0;
-            let mut shortcut_stack = vec![next];
-
-            while let Some(node) = shortcut_stack.pop() {
-                let next = self.forward_data.predecessors[*forwad_path.front().unwrap() as usize].0;
-                let middle = forward_middle_nodes[self.forward.edge_indices(node, next).next().unwrap().0 as usize];
-                if middle < self.forward.num_nodes() as NodeId {
-                    shortcut_stack.push(node);
-                    shortcut_stack.push(middle);
-                } else {
-                    forwad_path.push_front(node);
-                }
-            }
+    // The up-down path as a sequence of CH arcs: (tail rank, head rank, unpacking info) in path order.
+    fn ch_path(&self, from: NodeId, to: NodeId) -> Vec<(NodeId, NodeId, ArcUnpacking)> {
+        let (forward_unpacking, backward_unpacking) = self.unpacking.as_ref().expect("CH contains no unpacking information");
+
+        let mut path = Vec::new();
+        let mut node = self.meeting_node;
+        while node != from {
+            let (pred, EdgeIdT(edge)) = self.forward_data.predecessors[node as usize];
+            path.push((pred, node, forward_unpacking[edge as usize]));
+            node = pred;
         }
+        path.reverse();
 
-        let mut backward_path = LinkedList::new();
-        backward_path.push_back(self.meeting_node);
+        let mut node = self.meeting_node;
+        while node != to {
+            let (pred, EdgeIdT(edge)) = self.backward_data.predecessors[node as usize];
+            path.push((node, pred, backward_unpacking[edge as usize]));
+            node = pred;
+        }
 
-        while *backward_path.back().unwrap() != query.to {
-            let next = self.backward_data.predecessors[*backward_path.back().unwrap() as usize].0;
-            let mut shortcut_stack = vec![next];
+        path
+    }
 
-            while let Some(node) = shortcut_stack.pop() {
-                let next = self.backward_data.predecessors[*backward_path.back().unwrap() as usize].0;
-                let middle = backward_middle_nodes[self.backward.edge_indices(node, next).next().unwrap().0 as usize];
-                if middle < self.backward.num_nodes() as NodeId {
-                    shortcut_stack.push(node);
-                    shortcut_stack.push(middle);
-                } else {
-                    backward_path.push_back(node);
-                }
+    // Recursively unpack the up-down path into original edges, as (head rank, original edge id) pairs.
+    fn unpacked_path(&self, query: Query) -> Vec<(NodeId, EdgeIdT)> {
+        let (forward_unpacking, backward_unpacking) = self.unpacking.as_ref().expect("CH contains no unpacking information");
+        let from = self.order.rank(query.from);
+        let to = self.order.rank(query.to);
+
+        let mut path = Vec::new();
+        let mut stack = self.ch_path(from, to);
+        stack.reverse();
+
+        while let Some((tail, head, (middle, orig_edge))) = stack.pop() {
+            if let Some(middle) = middle.value() {
+                // A shortcut `tail -> head` over `middle` consists of the arc `tail -> middle`,
+                // which is stored in the backward graph at `middle` and the arc `middle -> head` stored in the forward graph.
+                let EdgeIdT(down) = self.backward.edge_indices(middle, tail).next().unwrap();
+                let EdgeIdT(up) = self.forward.edge_indices(middle, head).next().unwrap();
+                stack.push((middle, head, forward_unpacking[up as usize]));
+                stack.push((tail, middle, backward_unpacking[down as usize]));
+            } else {
+                path.push((head, EdgeIdT(orig_edge.value().unwrap())));
             }
         }
 
-        forwad_path.pop_back();
-        forwad_path.append(&mut backward_path);
+        path
+    }
 
-        for node in &mut forwad_path {
-            *node = self.order.node(*node);
-        }
+    fn node_path(&self, query: Query) -> Vec<NodeId> {
+        std::iter::once(query.from)
+            .chain(self.unpacked_path(query).into_iter().map(|(head, _)| self.order.node(head)))
+            .collect()
+    }
 
-        forwad_path.into_iter().collect()
+    fn edge_path(&self, query: Query) -> Vec<EdgeIdT> {
+        self.unpacked_path(query).into_iter().map(|(_, edge)| edge).collect()
     }
 }
 
 pub struct PathServerWrapper<'s>(&'s Server, Query);
 
+impl<'s> PathServerWrapper<'s> {
+    pub fn num_nodes_in_searchspace(&self) -> usize {
+        self.0.num_settled_nodes
+    }
+    pub fn num_stalled_nodes(&self) -> usize {
+        self.0.num_stalled_nodes
+    }
+    pub fn num_relaxed_edges(&self) -> usize {
+        self.0.num_relaxed_edges
+    }
+}
+
 impl<'s> PathServer for PathServerWrapper<'s> {
     type NodeInfo = NodeId;
-    type EdgeInfo = ();
+    type EdgeInfo = EdgeIdT;
 
     fn reconstruct_node_path(&mut self) -> Vec<Self::NodeInfo> {
-        Server::path(self.0, self.1)
+        Server::node_path(self.0, self.1)
     }
     fn reconstruct_edge_path(&mut self) -> Vec<Self::EdgeInfo> {
-        vec![(); self.reconstruct_node_path().len() - 1]
+        Server::edge_path(self.0, self.1)
     }
 }
 
@@ -8,6 +8,7 @@ use parallelization::*;
 pub mod directed;
 pub mod ftd;
 pub mod ftd_for_pot;
+pub mod multi_metric;
 pub mod validity;
 
 // One mapping of node id to weight for each thread during the scope of the customization.
@@ -230,7 +231,7 @@ fn customize_basic(cch: &CCH, mut upward_weights: Vec<Weight>, mut downward_weig
     };
 
     // setup customization for parallization
-    let customization = SeperatorBasedParallelCustomization::new_with_aux(cch, customize, customize);
+    let customization = SeperatorBasedParallelCustomization::new_with_aux(cch, customize, customize).with_subgraph_reports("basic_customization_subgraphs");
 
     let mut upward_unpack = vec![(InRangeOption::NONE, InRangeOption::NONE); m as usize];
     let mut downward_unpack = vec![(InRangeOption::NONE, InRangeOption::NONE); m as usize];
@@ -325,10 +326,12 @@ pub fn customize_perfect_without_rebuild(customized: &mut CustomizedBasic<CCH>)
             }
         });
 
+        report!("num_triangles", num_cell_triangles);
         num_triangles.fetch_add(num_cell_triangles, std::sync::atomic::Ordering::SeqCst);
     };
 
-    let static_perfect_customization = SeperatorBasedPerfectParallelCustomization::new_with_aux(cch, customize_perfect, customize_perfect);
+    let static_perfect_customization = SeperatorBasedPerfectParallelCustomization::new_with_aux(cch, customize_perfect, customize_perfect)
+        .with_subgraph_reports("perfect_customization_subgraphs");
 
     let mut upward_modified = vec![false; customized.upward.len()];
     let mut downward_modified = vec![false; customized.downward.len()];
@@ -457,13 +460,17 @@ pub fn rebuild_customized_perfect<'c, C: CCHT + Sync>(
 
             let mut edges_of_each_thread = vec![(0, 0); k + 1];
             let mut local_edge_counts = &mut edges_of_each_thread[1..];
-            let target_edges_per_thread = (m_fw + k - 1) / k;
-            let first_node_of_chunk: Vec<_> = cch
+            let target_edges_per_thread = std::cmp::max(m_fw.div_ceil(k), 1);
+            let mut first_node_of_chunk: Vec<_> = cch
                 .forward_tail()
                 .chunks(target_edges_per_thread)
                 .map(|chunk| chunk[0] as usize)
                 .chain(std::iter::once(n))
                 .collect();
+            // nodes without forward edges (possible in directed CCHs) before the first tail still need to be processed for their backward edges
+            first_node_of_chunk[0] = 0;
+            // with only few edges, there may be less than k chunks
+            first_node_of_chunk.resize(k + 1, n);
 
             // let nodes_per_thread = (n + k - 1) / k;
             // let first_node_of_chunk: Vec<_> = (0..=k).map(|i| min(n, i * nodes_per_thread)).collect();
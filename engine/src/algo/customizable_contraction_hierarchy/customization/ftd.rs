@@ -257,6 +257,8 @@ pub fn customize_internal<'a, 'b: 'a>(cch: &'a CCH, metric: &'b TDGraph) -> (Vec
 
         report_time("TD-CCH Customization", || {
             // spawn of a thread, which periodically reports the state of things
+            // it has no recorder attached on purpose, it reports the same keys over and over, which only goes to stderr with `report-to-stderr`
+            // the events it collects are reported into the `main` context below
             thread::spawn(move || {
                 let timer = Timer::new();
 
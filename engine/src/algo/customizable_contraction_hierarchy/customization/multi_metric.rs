@@ -0,0 +1,375 @@
+//! Batched customization of several metrics at once.
+//!
+//! Instead of one `Weight` per CCH edge, each edge carries a `[Weight; K]` with one lane per metric.
+//! All metrics are customized in a single pass over the lower triangles,
+//! so the memory accesses and the triangle enumeration are shared between all metrics.
+//! The lane wise relaxations use SSE2 instructions for each group of four lanes, including branchless updates of the unpacking info.
+//! SSE2 is part of every x86_64 CPU, so no runtime detection is necessary. Remaining lanes and other architectures take a scalar loop.
+//! `customize_batched` customizes any number of metrics in batches of four, the `cch_multi_metric` binary compares it against separate customizations.
+
+use super::*;
+
+// Flat workspaces with `K` consecutive entries for each node.
+// The number of lanes is a const generic, so we can't have the lane arrays in the type of the static.
+scoped_thread_local!(static UPWARD_WEIGHTS: RefCell<Vec<Weight>>);
+scoped_thread_local!(static DOWNWARD_WEIGHTS: RefCell<Vec<Weight>>);
+// `2 * K` entries for each node, first the `K` first edges of the unpacking info, then the `K` second edges.
+// `EdgeId::MAX` marks edges without unpacking info, the same sentinel as in `InRangeOption`.
+scoped_thread_local!(static UPWARD_UNPACK: RefCell<Vec<EdgeId>>);
+scoped_thread_local!(static DOWNWARD_UNPACK: RefCell<Vec<EdgeId>>);
+
+/// Number of metrics customized together by `customize_batched`, that is the number of weights in one SSE register.
+pub const BATCH_SIZE: usize = 4;
+
+#[cfg(target_arch = "x86_64")]
+mod simd {
+    use super::*;
+    use std::arch::x86_64::*;
+
+    const WIDTH: usize = 4;
+
+    #[inline(always)]
+    unsafe fn load(lanes: &[u32], i: usize) -> __m128i {
+        _mm_loadu_si128(lanes.as_ptr().add(i) as *const __m128i)
+    }
+
+    #[inline(always)]
+    unsafe fn store(lanes: &mut [u32], i: usize, values: __m128i) {
+        _mm_storeu_si128(lanes.as_mut_ptr().add(i) as *mut __m128i, values)
+    }
+
+    // All bits set in lanes where `a < b`.
+    // SSE2 only compares signed integers, flipping the sign bits maps the unsigned order onto the signed one.
+    #[inline(always)]
+    unsafe fn less(a: __m128i, b: __m128i) -> __m128i {
+        let sign = _mm_set1_epi32(i32::MIN);
+        _mm_cmplt_epi32(_mm_xor_si128(a, sign), _mm_xor_si128(b, sign))
+    }
+
+    // `if_set` in lanes where `mask` is set, `otherwise` in the others.
+    #[inline(always)]
+    unsafe fn select(mask: __m128i, if_set: __m128i, otherwise: __m128i) -> __m128i {
+        _mm_or_si128(_mm_and_si128(mask, if_set), _mm_andnot_si128(mask, otherwise))
+    }
+
+    /// Relax all full groups of four lanes. Returns the number of lanes handled.
+    #[inline(always)]
+    pub fn relax<const K: usize>(target: &mut [Weight; K], first: &[Weight; K], second: &[Weight; K]) -> usize {
+        let mut i = 0;
+        while i + WIDTH <= K {
+            // Safety: the bounds are checked by the loop condition, SSE2 is available on all x86_64 CPUs
+            unsafe {
+                let current = load(target, i);
+                let sum = _mm_add_epi32(load(first, i), load(second, i));
+                store(target, i, select(less(sum, current), sum, current));
+            }
+            i += WIDTH;
+        }
+        i
+    }
+
+    /// Relax all full groups of four lanes and set the unpacking info of improved lanes. Returns the number of lanes handled.
+    #[inline(always)]
+    pub fn relax_with_unpacking<const K: usize>(
+        target: &mut [Weight; K],
+        first: &[Weight; K],
+        second: &[Weight; K],
+        unpack_first: &mut [EdgeId],
+        unpack_second: &mut [EdgeId],
+        edges: (EdgeId, EdgeId),
+    ) -> usize {
+        assert!(unpack_first.len() == K && unpack_second.len() == K);
+        let mut i = 0;
+        while i + WIDTH <= K {
+            // Safety: the bounds are checked by the loop condition and the length assertion, SSE2 is available on all x86_64 CPUs
+            unsafe {
+                let current = load(target, i);
+                let sum = _mm_add_epi32(load(first, i), load(second, i));
+                let improved = less(sum, current);
+                store(target, i, select(improved, sum, current));
+                store(unpack_first, i, select(improved, _mm_set1_epi32(edges.0 as i32), load(unpack_first, i)));
+                store(unpack_second, i, select(improved, _mm_set1_epi32(edges.1 as i32), load(unpack_second, i)));
+            }
+            i += WIDTH;
+        }
+        i
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+mod simd {
+    use super::*;
+
+    #[inline(always)]
+    pub fn relax<const K: usize>(_target: &mut [Weight; K], _first: &[Weight; K], _second: &[Weight; K]) -> usize {
+        0
+    }
+
+    #[inline(always)]
+    pub fn relax_with_unpacking<const K: usize>(
+        _target: &mut [Weight; K],
+        _first: &[Weight; K],
+        _second: &[Weight; K],
+        _unpack_first: &mut [EdgeId],
+        _unpack_second: &mut [EdgeId],
+        _edges: (EdgeId, EdgeId),
+    ) -> usize {
+        0
+    }
+}
+
+/// Lane wise `target = min(target, first + second)`.
+#[inline(always)]
+pub fn relax_lanes<const K: usize>(target: &mut [Weight; K], first: &[Weight; K], second: &[Weight; K]) {
+    for i in simd::relax(target, first, second)..K {
+        target[i] = min(target[i], first[i] + second[i]);
+    }
+}
+
+// Lane wise `target = min(target, first + second)`, lanes which improve get `edges` as their unpacking info.
+#[inline(always)]
+fn relax_lanes_with_unpacking<const K: usize>(
+    target: &mut [Weight; K],
+    first: &[Weight; K],
+    second: &[Weight; K],
+    unpack_first: &mut [EdgeId],
+    unpack_second: &mut [EdgeId],
+    edges: (EdgeId, EdgeId),
+) {
+    for i in simd::relax_with_unpacking(target, first, second, unpack_first, unpack_second, edges)..K {
+        let relaxed = first[i] + second[i];
+        if relaxed < target[i] {
+            target[i] = relaxed;
+            unpack_first[i] = edges.0;
+            unpack_second[i] = edges.1;
+        }
+    }
+}
+
+#[inline(always)]
+fn lanes<const K: usize>(flat: &[Weight], node: NodeId) -> &[Weight; K] {
+    flat[node as usize * K..(node as usize + 1) * K].try_into().unwrap()
+}
+
+#[inline(always)]
+fn lanes_mut<const K: usize>(flat: &mut [Weight], node: NodeId) -> &mut [Weight; K] {
+    (&mut flat[node as usize * K..(node as usize + 1) * K]).try_into().unwrap()
+}
+
+// The first and the second edges of the unpacking info of `node` in a flat unpacking workspace.
+#[inline(always)]
+fn unpack_lanes_mut<const K: usize>(flat: &mut [EdgeId], node: NodeId) -> (&mut [EdgeId], &mut [EdgeId]) {
+    flat[node as usize * 2 * K..(node as usize + 1) * 2 * K].split_at_mut(K)
+}
+
+fn to_unpacking_info(edge: EdgeId) -> InRangeOption<EdgeId> {
+    InRangeOption::new(if edge == EdgeId::MAX { None } else { Some(edge) })
+}
+
+/// Result of a batched customization of `K` metrics.
+pub struct CustomizedMultiMetric<'a, const K: usize> {
+    pub cch: &'a CCH,
+    upward: Vec<[Weight; K]>,
+    downward: Vec<[Weight; K]>,
+    up_unpacking: Vec<[(InRangeOption<EdgeId>, InRangeOption<EdgeId>); K]>,
+    down_unpacking: Vec<[(InRangeOption<EdgeId>, InRangeOption<EdgeId>); K]>,
+}
+
+impl<'a, const K: usize> CustomizedMultiMetric<'a, K> {
+    /// Customized weights of the upward edges, one lane per metric.
+    pub fn upward(&self) -> &[[Weight; K]] {
+        &self.upward
+    }
+
+    /// Customized weights of the downward edges, one lane per metric.
+    pub fn downward(&self) -> &[[Weight; K]] {
+        &self.downward
+    }
+
+    /// Split into one regular customization per metric, for example to use the regular query servers and path unpacking.
+    pub fn into_metrics(self) -> [CustomizedBasic<'a, CCH>; K] {
+        std::array::from_fn(|i| {
+            CustomizedBasic::new(
+                self.cch,
+                self.upward.iter().map(|weights| weights[i]).collect(),
+                self.downward.iter().map(|weights| weights[i]).collect(),
+                self.up_unpacking.iter().map(|unpacking| unpacking[i]).collect(),
+                self.down_unpacking.iter().map(|unpacking| unpacking[i]).collect(),
+            )
+        })
+    }
+}
+
+/// Customize `K` metrics at once.
+/// Each metric is given as a weight slice for the arcs of the graph used for the first phase preprocessing.
+pub fn customize_multi_metric<'c, const K: usize>(cch: &'c CCH, metrics: [&[Weight]; K]) -> CustomizedMultiMetric<'c, K> {
+    assert!(K > 0);
+    let n = cch.num_nodes();
+    let m = cch.num_arcs();
+    report!("num_cch_edges", m);
+    report!("num_metrics", K);
+
+    let mut upward_weights = vec![[INFINITY; K]; m];
+    let mut downward_weights = vec![[INFINITY; K]; m];
+
+    // respecting phase
+    report_time_with_key("CCH apply weights", "respecting_running_time_ms", || {
+        let apply = |(weights, arcs): (&mut [Weight; K], &[EdgeIdT])| {
+            for &EdgeIdT(arc) in arcs {
+                for (weight, metric) in weights.iter_mut().zip(metrics.iter()) {
+                    *weight = min(*weight, metric[arc as usize]);
+                }
+            }
+        };
+        #[cfg(not(feature = "cch-disable-par"))]
+        {
+            upward_weights.par_iter_mut().zip(cch.forward_cch_edge_to_orig_arc().par_iter()).for_each(apply);
+            downward_weights
+                .par_iter_mut()
+                .zip(cch.backward_cch_edge_to_orig_arc().par_iter())
+                .for_each(apply);
+        }
+        #[cfg(feature = "cch-disable-par")]
+        {
+            upward_weights.iter_mut().zip(cch.forward_cch_edge_to_orig_arc().iter()).for_each(apply);
+            downward_weights.iter_mut().zip(cch.backward_cch_edge_to_orig_arc().iter()).for_each(apply);
+        }
+    });
+
+    // Same as the routine in `customize_basic`, just with `K` lanes for each weight.
+    let customize = |nodes: Range<usize>,
+                     offset: usize,
+                     _offset_down: usize,
+                     upward_weights: &mut [[Weight; K]],
+                     downward_weights: &mut [[Weight; K]],
+                     upward_unpack: &mut [[(InRangeOption<EdgeId>, InRangeOption<EdgeId>); K]],
+                     downward_unpack: &mut [[(InRangeOption<EdgeId>, InRangeOption<EdgeId>); K]]| {
+        UPWARD_WEIGHTS.with(|node_outgoing_weights| {
+            let mut node_outgoing_weights = node_outgoing_weights.borrow_mut();
+            DOWNWARD_WEIGHTS.with(|node_incoming_weights| {
+                let mut node_incoming_weights = node_incoming_weights.borrow_mut();
+                UPWARD_UNPACK.with(|node_outgoing_unpack| {
+                    let mut node_outgoing_unpack = node_outgoing_unpack.borrow_mut();
+                    DOWNWARD_UNPACK.with(|node_incoming_unpack| {
+                        let mut node_incoming_unpack = node_incoming_unpack.borrow_mut();
+
+                        for current_node in nodes {
+                            let current_node = current_node as NodeId;
+                            let mut edges = cch.neighbor_edge_indices_usize(current_node);
+                            edges.start -= offset;
+                            edges.end -= offset;
+                            for ((node, down_weight), up_weight) in cch
+                                .neighbor_iter(current_node)
+                                .zip(&downward_weights[edges.clone()])
+                                .zip(&upward_weights[edges.clone()])
+                            {
+                                *lanes_mut(&mut node_incoming_weights, node) = *down_weight;
+                                *lanes_mut(&mut node_outgoing_weights, node) = *up_weight;
+                                let node = node as usize;
+                                node_incoming_unpack[node * 2 * K..(node + 1) * 2 * K].fill(EdgeId::MAX);
+                                node_outgoing_unpack[node * 2 * K..(node + 1) * 2 * K].fill(EdgeId::MAX);
+                            }
+
+                            for (NodeIdT(low_node), Reversed(EdgeIdT(first_edge_id))) in cch.inverted.link_iter(current_node) {
+                                let first_down_weight = downward_weights[first_edge_id as usize - offset];
+                                let first_up_weight = upward_weights[first_edge_id as usize - offset];
+                                let mut low_up_edges = cch.neighbor_edge_indices_usize(low_node);
+                                let low_up_edges_orig = low_up_edges.clone();
+                                low_up_edges.start -= offset;
+                                low_up_edges.end -= offset;
+                                for (((node, upward_weight), downward_weight), second_edge_id) in cch
+                                    .neighbor_iter(low_node)
+                                    .rev()
+                                    .zip(upward_weights[low_up_edges.clone()].iter().rev())
+                                    .zip(downward_weights[low_up_edges].iter().rev())
+                                    .zip(low_up_edges_orig.rev())
+                                {
+                                    if node <= current_node {
+                                        break;
+                                    }
+                                    let second_edge_id = second_edge_id as EdgeId;
+
+                                    let (unpack_first, unpack_second) = unpack_lanes_mut::<K>(&mut node_outgoing_unpack, node);
+                                    relax_lanes_with_unpacking(
+                                        lanes_mut(&mut node_outgoing_weights, node),
+                                        upward_weight,
+                                        &first_down_weight,
+                                        unpack_first,
+                                        unpack_second,
+                                        (first_edge_id, second_edge_id),
+                                    );
+                                    let (unpack_first, unpack_second) = unpack_lanes_mut::<K>(&mut node_incoming_unpack, node);
+                                    relax_lanes_with_unpacking(
+                                        lanes_mut(&mut node_incoming_weights, node),
+                                        downward_weight,
+                                        &first_up_weight,
+                                        unpack_first,
+                                        unpack_second,
+                                        (second_edge_id, first_edge_id),
+                                    );
+                                }
+                            }
+
+                            for ((((node, downward_weight), upward_weight), downward_unpack), upward_unpack) in cch
+                                .neighbor_iter(current_node)
+                                .zip(&mut downward_weights[edges.clone()])
+                                .zip(&mut upward_weights[edges.clone()])
+                                .zip(&mut downward_unpack[edges.clone()])
+                                .zip(&mut upward_unpack[edges])
+                            {
+                                *downward_weight = *lanes(&node_incoming_weights, node);
+                                *upward_weight = *lanes(&node_outgoing_weights, node);
+                                let (first, second) = unpack_lanes_mut::<K>(&mut node_incoming_unpack, node);
+                                for (unpack, (&first, &second)) in downward_unpack.iter_mut().zip(first.iter().zip(second.iter())) {
+                                    *unpack = (to_unpacking_info(first), to_unpacking_info(second));
+                                }
+                                let (first, second) = unpack_lanes_mut::<K>(&mut node_outgoing_unpack, node);
+                                for (unpack, (&first, &second)) in upward_unpack.iter_mut().zip(first.iter().zip(second.iter())) {
+                                    *unpack = (to_unpacking_info(first), to_unpacking_info(second));
+                                }
+                            }
+                        }
+                    });
+                });
+            });
+        });
+    };
+
+    let customization = SeperatorBasedParallelCustomization::new_with_aux(cch, customize, customize);
+
+    let mut upward_unpack = vec![[(InRangeOption::NONE, InRangeOption::NONE); K]; m];
+    let mut downward_unpack = vec![[(InRangeOption::NONE, InRangeOption::NONE); K]; m];
+
+    report_time_with_key("CCH Multi Metric Customization", "basic_customization_running_time_ms", || {
+        customization.customize_with_aux(&mut upward_weights, &mut downward_weights, &mut upward_unpack, &mut downward_unpack, |cb| {
+            UPWARD_WEIGHTS.set(&RefCell::new(vec![INFINITY; n * K]), || {
+                DOWNWARD_WEIGHTS.set(&RefCell::new(vec![INFINITY; n * K]), || {
+                    UPWARD_UNPACK.set(&RefCell::new(vec![EdgeId::MAX; n * 2 * K]), || {
+                        DOWNWARD_UNPACK.set(&RefCell::new(vec![EdgeId::MAX; n * 2 * K]), cb);
+                    });
+                });
+            });
+        });
+    });
+
+    CustomizedMultiMetric {
+        cch,
+        upward: upward_weights,
+        downward: downward_weights,
+        up_unpacking: upward_unpack,
+        down_unpacking: downward_unpack,
+    }
+}
+
+/// Customize any number of metrics, `BATCH_SIZE` at a time.
+/// The last batch is padded by repeating its last metric, so every pass uses the full SIMD width.
+pub fn customize_batched<'c>(cch: &'c CCH, metrics: &[&[Weight]]) -> Vec<CustomizedBasic<'c, CCH>> {
+    let mut customized = Vec::with_capacity(metrics.len());
+    let mut batches_ctxt = push_collection_context("batches");
+    for batch in metrics.chunks(BATCH_SIZE) {
+        let _batch_ctxt = batches_ctxt.push_collection_item();
+        let lanes: [&[Weight]; BATCH_SIZE] = std::array::from_fn(|i| batch[min(i, batch.len() - 1)]);
+        customized.extend(customize_multi_metric(cch, lanes).into_metrics().into_iter().take(batch.len()));
+    }
+    customized
+}
@@ -1,8 +1,32 @@
 //! Separator based parallelization of CCH customization.
 //! Utilizes that disconnected cells (by removing a separator) can be processed independently.
+//! Cells are processed on rayon pool threads, which only report if subgraph reports are enabled with `with_subgraph_reports`.
+//! Then each processed cell and separator gets an item in a report collection, which is attached to the thread processing it,
+//! so the customization routines can `report!` per subgraph stats and their running time is recorded.
+//! Items are indexed by the first node of their subgraph, so the merged report does not depend on the scheduling.
 
 use super::*;
 
+// Process `nodes` with a new item of `subgraphs` attached to the current thread, if subgraph reports are enabled.
+fn exec_reported(subgraphs: Option<&CollectionRecorder>, kind: &str, nodes: Range<usize>, exec: impl FnOnce(Range<usize>)) {
+    match subgraphs {
+        Some(subgraphs) if !nodes.is_empty() => {
+            let mut item = subgraphs.item(nodes.start);
+            item.record("kind", json!(kind));
+            item.record("first_node", json!(nodes.start));
+            item.record("num_nodes", json!(nodes.len()));
+            let _recorder = item.attach();
+            silent_report_time(|| exec(nodes));
+        }
+        _ => exec(nodes),
+    }
+}
+
+// Collection for the subgraph items under `key` in the current context of the calling thread.
+fn subgraph_collection(key: Option<&'static str>) -> Option<CollectionRecorder> {
+    key.and_then(|key| current_recorder().map(|recorder| recorder.collection(key)))
+}
+
 pub trait SubgraphCustomization<T, E>: Sync {
     fn exec(
         &self,
@@ -70,6 +94,7 @@ pub struct SeperatorBasedParallelCustomization<'a, T, F, G, C, E = ()> {
     cch: &'a C,
     customize_cell: F,
     customize_separator: G,
+    subgraph_report_key: Option<&'static str>,
     _t: std::marker::PhantomData<T>,
     _e: std::marker::PhantomData<E>,
 }
@@ -132,11 +157,20 @@ where
             cch,
             customize_cell,
             customize_separator,
+            subgraph_report_key: None,
             _t: std::marker::PhantomData::<T>,
             _e: std::marker::PhantomData::<E>,
         }
     }
 
+    /// Report each processed cell and separator as an item of a collection under `key` in the current context.
+    pub fn with_subgraph_reports(self, key: &'static str) -> Self {
+        Self {
+            subgraph_report_key: Some(key),
+            ..self
+        }
+    }
+
     pub fn customize_with_aux(
         &self,
         upward: &'a mut [T],
@@ -145,10 +179,13 @@ where
         down_aux: &'a mut [E],
         setup: impl Fn(Box<dyn FnOnce() + '_>) + Sync,
     ) {
+        let subgraphs = subgraph_collection(self.subgraph_report_key);
+        let subgraphs = subgraphs.as_ref();
         if cfg!(feature = "cch-disable-par") {
             setup(Box::new(|| {
-                self.customize_cell
-                    .exec(0..self.cch.forward_first_out().len() - 1, 0, 0, upward, downward, up_aux, down_aux)
+                exec_reported(subgraphs, "cell", 0..self.cch.forward_first_out().len() - 1, |nodes| {
+                    self.customize_cell.exec(nodes, 0, 0, upward, downward, up_aux, down_aux)
+                })
             }));
         } else {
             let available_cpus = affinity::get_thread_affinity().unwrap();
@@ -158,28 +195,33 @@ where
                         affinity::set_thread_affinity(&[available_cpus[thread.index()]]).unwrap();
                         setup(Box::new(|| thread.run()));
                     },
-                    |pool| pool.install(|| self.customize_tree(self.cch.separators(), 0, upward, downward, up_aux, down_aux)),
+                    |pool| pool.install(|| self.customize_tree(self.cch.separators(), 0, upward, downward, up_aux, down_aux, subgraphs)),
                 )
                 .unwrap();
         }
     }
 
-    fn customize_tree(&self, sep_tree: &SeparatorTree, offset: usize, upward: &'a mut [T], downward: &'a mut [T], up_aux: &'a mut [E], down_aux: &'a mut [E]) {
+    #[allow(clippy::too_many_arguments)]
+    fn customize_tree(
+        &self,
+        sep_tree: &SeparatorTree,
+        offset: usize,
+        upward: &'a mut [T],
+        downward: &'a mut [T],
+        up_aux: &'a mut [E],
+        down_aux: &'a mut [E],
+        subgraphs: Option<&CollectionRecorder>,
+    ) {
         let n = self.cch.forward_first_out().len() - 1;
         let forward_edge_offset = self.cch.forward_first_out()[offset] as usize;
         let backward_edge_offset = self.cch.backward_first_out()[offset] as usize;
 
         if sep_tree.num_nodes < n / (32 * rayon::current_num_threads()) {
             // if the current cell is small enough (load balancing parameters) run the customize_cell routine on it
-            self.customize_cell.exec(
-                offset..offset + sep_tree.num_nodes,
-                forward_edge_offset,
-                backward_edge_offset,
-                upward,
-                downward,
-                up_aux,
-                down_aux,
-            );
+            exec_reported(subgraphs, "cell", offset..offset + sep_tree.num_nodes, |nodes| {
+                self.customize_cell
+                    .exec(nodes, forward_edge_offset, backward_edge_offset, upward, downward, up_aux, down_aux)
+            });
         } else {
             // if not, split at the separator, process all subcells independently in parallel and the separator afterwards
             let mut sub_offset = offset;
@@ -207,9 +249,9 @@ where
                     // this catches the case of very small cell at high levels which may sometime occur
                     // subcells are ordered descending by their size, so we will always first spawn of tasks for the big ones
                     if sub.num_nodes < n / (32 * rayon::current_num_threads()) {
-                        self.customize_tree(sub, sub_offset, this_sub_up, this_sub_down, this_aux_up, this_aux_down);
+                        self.customize_tree(sub, sub_offset, this_sub_up, this_sub_down, this_aux_up, this_aux_down, subgraphs);
                     } else {
-                        s.spawn(move |_| self.customize_tree(sub, sub_offset, this_sub_up, this_sub_down, this_aux_up, this_aux_down));
+                        s.spawn(move |_| self.customize_tree(sub, sub_offset, this_sub_up, this_sub_down, this_aux_up, this_aux_down, subgraphs));
                     }
                     sub_offset += sub.num_nodes;
                     sub_upward = rest_up;
@@ -220,15 +262,10 @@ where
             });
 
             // once all subcells are processed, process the separator itself
-            self.customize_separator.exec(
-                sub_offset..offset + sep_tree.num_nodes,
-                forward_edge_offset,
-                backward_edge_offset,
-                upward,
-                downward,
-                up_aux,
-                down_aux,
-            )
+            exec_reported(subgraphs, "separator", sub_offset..offset + sep_tree.num_nodes, |nodes| {
+                self.customize_separator
+                    .exec(nodes, forward_edge_offset, backward_edge_offset, upward, downward, up_aux, down_aux)
+            })
         }
     }
 }
@@ -270,6 +307,7 @@ pub struct SeperatorBasedPerfectParallelCustomization<'a, T, F, G, C, E = ()> {
     cch: &'a C,
     customize_cell: F,
     customize_separator: G,
+    subgraph_report_key: Option<&'static str>,
     _t: std::marker::PhantomData<T>,
     _e: std::marker::PhantomData<E>,
 }
@@ -324,11 +362,20 @@ where
             cch,
             customize_cell,
             customize_separator,
+            subgraph_report_key: None,
             _t: std::marker::PhantomData::<T>,
             _e: std::marker::PhantomData::<E>,
         }
     }
 
+    /// Report each processed cell and separator as an item of a collection under `key` in the current context.
+    pub fn with_subgraph_reports(self, key: &'static str) -> Self {
+        Self {
+            subgraph_report_key: Some(key),
+            ..self
+        }
+    }
+
     /// Execute customization. Takes a mut slice to the full memory where weights that should be customized are stored.
     /// The setup callback can be used to perform additional scoped setup work.
     /// It has to call the callback that gets passed to it in turn.
@@ -341,15 +388,14 @@ where
         down_aux: &'a mut [E],
         setup: impl Fn(Box<dyn FnOnce() + '_>) + Sync,
     ) {
+        let subgraphs = subgraph_collection(self.subgraph_report_key);
+        let subgraphs = subgraphs.as_ref();
         if cfg!(feature = "cch-disable-par") {
             setup(Box::new(|| {
-                self.customize_cell.exec(
-                    0..self.cch.num_cch_nodes(),
-                    upward.as_mut_ptr(),
-                    downward.as_mut_ptr(),
-                    up_aux.as_mut_ptr(),
-                    down_aux.as_mut_ptr(),
-                )
+                exec_reported(subgraphs, "cell", 0..self.cch.num_cch_nodes(), |nodes| {
+                    self.customize_cell
+                        .exec(nodes, upward.as_mut_ptr(), downward.as_mut_ptr(), up_aux.as_mut_ptr(), down_aux.as_mut_ptr())
+                })
             }));
         } else {
             let available_cpus = affinity::get_thread_affinity().unwrap();
@@ -368,6 +414,7 @@ where
                                 downward.as_mut_ptr(),
                                 up_aux.as_mut_ptr(),
                                 down_aux.as_mut_ptr(),
+                                subgraphs,
                             )
                         })
                     },
@@ -376,15 +423,28 @@ where
         }
     }
 
-    fn customize_tree(&self, sep_tree: &SeparatorTree, offset: usize, upward: *mut T, downward: *mut T, up_aux: *mut E, down_aux: *mut E) {
+    #[allow(clippy::too_many_arguments)]
+    fn customize_tree(
+        &self,
+        sep_tree: &SeparatorTree,
+        offset: usize,
+        upward: *mut T,
+        downward: *mut T,
+        up_aux: *mut E,
+        down_aux: *mut E,
+        subgraphs: Option<&CollectionRecorder>,
+    ) {
         if sep_tree.num_nodes < self.cch.num_cch_nodes() / (32 * rayon::current_num_threads()) {
             // if the current cell is small enough (load balancing parameters) run the customize_cell routine on it
-            self.customize_cell
-                .exec(offset - sep_tree.num_nodes..offset, upward, downward, up_aux, down_aux);
+            exec_reported(subgraphs, "cell", offset - sep_tree.num_nodes..offset, |nodes| {
+                self.customize_cell.exec(nodes, upward, downward, up_aux, down_aux)
+            });
         } else {
             // if not, process separator, then split into subcells and process them independently in parallel.
             let mut end_next = offset - sep_tree.nodes.len();
-            self.customize_separator.exec(end_next..offset, upward, downward, up_aux, down_aux);
+            exec_reported(subgraphs, "separator", end_next..offset, |nodes| {
+                self.customize_separator.exec(nodes, upward, downward, up_aux, down_aux)
+            });
             let upward = PtrWrapper(upward);
             let downward = PtrWrapper(downward);
             let up_aux = PtrWrapper(up_aux);
@@ -398,7 +458,7 @@ where
                         let _ = &downward;
                         let _ = &up_aux;
                         let _ = &down_aux;
-                        self.customize_tree(sub, end_next, upward.0, downward.0, up_aux.0, down_aux.0)
+                        self.customize_tree(sub, end_next, upward.0, downward.0, up_aux.0, down_aux.0, subgraphs)
                     });
                     end_next -= sub.num_nodes;
                 }
@@ -13,6 +13,7 @@ mod contraction;
 use contraction::*;
 pub mod customization;
 pub use customization::ftd as ftd_cch;
+pub use customization::multi_metric::{customize_batched, customize_multi_metric, CustomizedMultiMetric};
 pub use customization::{customize, customize_directed, customize_directed_perfect, customize_perfect};
 pub mod separator_decomposition;
 use separator_decomposition::*;
@@ -496,6 +497,28 @@ impl<'a, C: CCHT> Customized for CustomizedBasic<'a, C> {
     }
 }
 
+impl<C> crate::io::Deconstruct for CustomizedBasic<'_, C> {
+    fn save_each(&self, store: &dyn Fn(&str, &dyn crate::io::Save) -> std::io::Result<()>) -> std::io::Result<()> {
+        store("upward", &self.upward)?;
+        store("downward", &self.downward)?;
+        store("up_unpacking", &self.up_unpacking)?;
+        store("down_unpacking", &self.down_unpacking)?;
+        Ok(())
+    }
+}
+
+impl<'a, C: CCHT> crate::io::ReconstructPrepared<CustomizedBasic<'a, C>> for &'a C {
+    fn reconstruct_with(self, loader: Loader) -> std::io::Result<CustomizedBasic<'a, C>> {
+        Ok(CustomizedBasic::new(
+            self,
+            loader.load("upward")?,
+            loader.load("downward")?,
+            loader.load("up_unpacking")?,
+            loader.load("down_unpacking")?,
+        ))
+    }
+}
+
 pub struct CustomizedPerfect<'a, CCH> {
     pub cch: &'a CCH,
     upward: OwnedGraph,
@@ -1,6 +1,7 @@
 //! CCH query based on elimination tree
 
 use super::*;
+pub mod multi_metric;
 pub mod nearest_neighbor;
 pub mod stepped_elimination_tree;
 use stepped_elimination_tree::EliminationTreeWalk;
@@ -41,6 +42,16 @@ impl<C: Customized> Server<C> {
         &self.customized
     }
 
+    /// Nodes walked by the last query.
+    pub fn num_nodes_in_searchspace(&self) -> usize {
+        self.walked_nodes
+    }
+
+    /// Edges relaxed by the last query.
+    pub fn num_relaxed_edges(&self) -> usize {
+        self.relaxed_edges
+    }
+
     fn distance(&mut self, from: NodeId, to: NodeId) -> Option<Weight> {
         self.walked_nodes = 0;
         self.relaxed_edges = 0;
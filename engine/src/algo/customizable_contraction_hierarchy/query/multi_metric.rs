@@ -0,0 +1,97 @@
+//! Elimination tree query on a batched multi metric customization.
+//! Answers one query for all `K` metrics at once.
+
+use super::*;
+use crate::algo::customizable_contraction_hierarchy::customization::multi_metric::*;
+
+pub struct Server<'a, const K: usize> {
+    customized: CustomizedMultiMetric<'a, K>,
+    fw_distances: Vec<[Weight; K]>,
+    bw_distances: Vec<[Weight; K]>,
+}
+
+impl<'a, const K: usize> Server<'a, K> {
+    pub fn new(customized: CustomizedMultiMetric<'a, K>) -> Self {
+        let n = customized.cch.num_nodes();
+        Server {
+            customized,
+            fw_distances: vec![[INFINITY; K]; n],
+            bw_distances: vec![[INFINITY; K]; n],
+        }
+    }
+
+    // Update the metrics using a new customization result
+    pub fn update(&mut self, customized: CustomizedMultiMetric<'a, K>) {
+        self.customized = customized;
+    }
+
+    pub fn customized(&self) -> &CustomizedMultiMetric<'a, K> {
+        &self.customized
+    }
+
+    /// Shortest distances from `query.from` to `query.to` for each of the `K` metrics.
+    pub fn query(&mut self, query: Query) -> [Option<Weight>; K] {
+        let cch = self.customized.cch;
+        let elimination_tree = cch.elimination_tree();
+        let from = cch.node_order().rank(query.from);
+        let to = cch.node_order().rank(query.to);
+
+        let mut tentative_distances = [INFINITY; K];
+        self.fw_distances[from as usize] = [0; K];
+        self.bw_distances[to as usize] = [0; K];
+
+        let mut fw_node = Some(from);
+        let mut bw_node = Some(to);
+
+        // Walk up the elimination tree from both endpoints.
+        // Once both walks arrive at the same node, they will stay together.
+        // Distances are reset right after a node was settled, so everything is clean for the next query.
+        loop {
+            match (fw_node, bw_node) {
+                (Some(fw), Some(bw)) if fw < bw => {
+                    Self::relax_upward(cch, self.customized.upward(), &mut self.fw_distances, fw);
+                    self.fw_distances[fw as usize] = [INFINITY; K];
+                    fw_node = elimination_tree[fw as usize].value();
+                }
+                (Some(fw), Some(bw)) if fw > bw => {
+                    Self::relax_upward(cch, self.customized.downward(), &mut self.bw_distances, bw);
+                    self.bw_distances[bw as usize] = [INFINITY; K];
+                    bw_node = elimination_tree[bw as usize].value();
+                }
+                (Some(node), Some(_)) => {
+                    relax_lanes(&mut tentative_distances, &self.fw_distances[node as usize], &self.bw_distances[node as usize]);
+                    // prune if no lane can be improved anymore
+                    if (0..K).any(|i| self.fw_distances[node as usize][i] < tentative_distances[i]) {
+                        Self::relax_upward(cch, self.customized.upward(), &mut self.fw_distances, node);
+                    }
+                    if (0..K).any(|i| self.bw_distances[node as usize][i] < tentative_distances[i]) {
+                        Self::relax_upward(cch, self.customized.downward(), &mut self.bw_distances, node);
+                    }
+                    self.fw_distances[node as usize] = [INFINITY; K];
+                    self.bw_distances[node as usize] = [INFINITY; K];
+                    fw_node = elimination_tree[node as usize].value();
+                    bw_node = fw_node;
+                }
+                // no common ancestors, so there is no path, but we still need to reset the distances
+                (Some(fw), None) => {
+                    self.fw_distances[fw as usize] = [INFINITY; K];
+                    fw_node = elimination_tree[fw as usize].value();
+                }
+                (None, Some(bw)) => {
+                    self.bw_distances[bw as usize] = [INFINITY; K];
+                    bw_node = elimination_tree[bw as usize].value();
+                }
+                (None, None) => break,
+            }
+        }
+
+        tentative_distances.map(|dist| if dist < INFINITY { Some(dist) } else { None })
+    }
+
+    fn relax_upward(cch: &CCH, weights: &[[Weight; K]], distances: &mut [[Weight; K]], node: NodeId) {
+        let node_dist = distances[node as usize];
+        for (head, edge) in cch.neighbor_iter(node).zip(cch.neighbor_edge_indices_usize(node)) {
+            relax_lanes(&mut distances[head as usize], &node_dist, &weights[edge]);
+        }
+    }
+}
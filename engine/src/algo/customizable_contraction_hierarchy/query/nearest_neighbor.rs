@@ -256,3 +256,164 @@ impl<'s, 'a: 's> BCCHNearestNeighborSelectedTargets<'s, 'a> {
         self.0.query(source, self.1, k)
     }
 }
+
+/// Bucket based nearest neighbor index for moving targets like vehicles.
+/// Each point stores its upward search spaces in both directions as buckets,
+/// so points can be inserted, moved and removed without rebuilding the index.
+pub struct DynamicBCCHNearestNeighbor<'a> {
+    customized: &'a CustomizedPerfect<'a, CCH>,
+    // (point, distance from the point to the node)
+    out_buckets: Vec<Vec<(u32, Weight)>>,
+    // (point, distance from the node to the point)
+    in_buckets: Vec<Vec<(u32, Weight)>>,
+    // rank of each point
+    points: Vec<Option<NodeId>>,
+    distances: Vec<Weight>,
+    bw_distances: Vec<Weight>,
+    point_distances: Vec<Weight>,
+    reached_points: Vec<u32>,
+}
+
+impl<'a> DynamicBCCHNearestNeighbor<'a> {
+    pub fn new(customized: &'a CustomizedPerfect<'a, CCH>) -> Self {
+        let n = customized.cch().num_nodes();
+        Self {
+            customized,
+            out_buckets: vec![Vec::new(); n],
+            in_buckets: vec![Vec::new(); n],
+            points: Vec::new(),
+            distances: vec![INFINITY; n],
+            bw_distances: vec![INFINITY; n],
+            point_distances: Vec::new(),
+            reached_points: Vec::new(),
+        }
+    }
+
+    /// Point ids are chosen by the caller and should be dense.
+    pub fn insert(&mut self, point: u32, node: NodeId) {
+        if self.points.len() <= point as usize {
+            self.points.resize(point as usize + 1, None);
+            self.point_distances.resize(point as usize + 1, INFINITY);
+        }
+        assert!(self.points[point as usize].is_none(), "point {} already inserted", point);
+        let rank = self.customized.cch().node_order().rank(node);
+        self.points[point as usize] = Some(rank);
+
+        let fw_graph = self.customized.forward_graph();
+        let bw_graph = self.customized.backward_graph();
+        for (node, dist) in Self::upward_distances(&fw_graph, self.customized.cch(), &mut self.distances, rank) {
+            self.out_buckets[node as usize].push((point, dist));
+        }
+        for (node, dist) in Self::upward_distances(&bw_graph, self.customized.cch(), &mut self.distances, rank) {
+            self.in_buckets[node as usize].push((point, dist));
+        }
+    }
+
+    /// Returns the node the point was at.
+    pub fn remove(&mut self, point: u32) -> Option<NodeId> {
+        let rank = self.points.get_mut(point as usize)?.take()?;
+        let mut cur_node = Some(rank);
+        while let Some(node) = cur_node {
+            self.out_buckets[node as usize].retain(|&(other, _)| other != point);
+            self.in_buckets[node as usize].retain(|&(other, _)| other != point);
+            cur_node = self.customized.cch().elimination_tree()[node as usize].value();
+        }
+        Some(self.customized.cch().node_order().node(rank))
+    }
+
+    pub fn move_point(&mut self, point: u32, node: NodeId) {
+        self.remove(point);
+        self.insert(point, node);
+    }
+
+    pub fn node(&self, point: u32) -> Option<NodeId> {
+        self.points
+            .get(point as usize)
+            .copied()
+            .flatten()
+            .map(|rank| self.customized.cch().node_order().node(rank))
+    }
+
+    /// The `k` points with the shortest distance from the point to `node`, sorted by distance.
+    /// Pass `usize::MAX` as `k` for all points which can reach `node`.
+    pub fn closest_from(&mut self, node: NodeId, k: usize) -> Vec<(Weight, u32)> {
+        let bw_graph = self.customized.backward_graph();
+        self.closest(&bw_graph, node, k, true)
+    }
+
+    /// The `k` points with the shortest distance from `node` to the point, sorted by distance.
+    /// Pass `usize::MAX` as `k` for all points reachable from `node`.
+    pub fn closest_to(&mut self, node: NodeId, k: usize) -> Vec<(Weight, u32)> {
+        let fw_graph = self.customized.forward_graph();
+        self.closest(&fw_graph, node, k, false)
+    }
+
+    /// Point to point distance on the same metric, independent of the inserted points.
+    pub fn distance(&mut self, from: NodeId, to: NodeId) -> Option<Weight> {
+        let cch = self.customized.cch();
+        let fw_graph = self.customized.forward_graph();
+        let bw_graph = self.customized.backward_graph();
+        let fw_search_space = Self::upward_distances(&fw_graph, cch, &mut self.distances, cch.node_order().rank(from));
+        for (node, dist) in Self::upward_distances(&bw_graph, cch, &mut self.distances, cch.node_order().rank(to)) {
+            self.bw_distances[node as usize] = dist;
+        }
+        let dist = fw_search_space
+            .iter()
+            .map(|&(node, dist)| dist.saturating_add(self.bw_distances[node as usize]))
+            .min()
+            .unwrap_or(INFINITY);
+        // the backward search space consists of the elimination tree ancestors of `to`
+        let mut cur_node = Some(cch.node_order().rank(to));
+        while let Some(node) = cur_node {
+            self.bw_distances[node as usize] = INFINITY;
+            cur_node = cch.elimination_tree()[node as usize].value();
+        }
+        if dist < INFINITY {
+            Some(dist)
+        } else {
+            None
+        }
+    }
+
+    // `graph` is the direction of the search from `node`, the buckets of the other direction are scanned.
+    fn closest(&mut self, graph: &BorrowedGraph, node: NodeId, k: usize, scan_out_buckets: bool) -> Vec<(Weight, u32)> {
+        let rank = self.customized.cch().node_order().rank(node);
+        for (node, dist) in Self::upward_distances(graph, self.customized.cch(), &mut self.distances, rank) {
+            let buckets = if scan_out_buckets { &self.out_buckets } else { &self.in_buckets };
+            for &(point, bucket_dist) in &buckets[node as usize] {
+                let point_dist = &mut self.point_distances[point as usize];
+                if *point_dist == INFINITY {
+                    self.reached_points.push(point);
+                }
+                *point_dist = std::cmp::min(*point_dist, dist + bucket_dist);
+            }
+        }
+
+        let mut closest: Vec<(Weight, u32)> = self
+            .reached_points
+            .drain(..)
+            .map(|point| (std::mem::replace(&mut self.point_distances[point as usize], INFINITY), point))
+            .collect();
+        if closest.len() > k {
+            closest.select_nth_unstable(k);
+            closest.truncate(k);
+        }
+        closest.sort_unstable();
+        closest
+    }
+
+    // Finite distances of the upward search space of `rank` in elimination tree order.
+    fn upward_distances(graph: &BorrowedGraph, cch: &CCH, distances: &mut Vec<Weight>, rank: NodeId) -> Vec<(NodeId, Weight)> {
+        let mut search_space = Vec::new();
+        let mut parent_info = stepped_elimination_tree::ForgetParentInfo();
+        let mut walk = EliminationTreeWalk::query_with_resetted(graph, cch.elimination_tree(), distances, &mut parent_info, rank);
+        while let Some(node) = walk.next() {
+            let dist = walk.tentative_distance(node);
+            if dist < INFINITY {
+                search_space.push((node, dist));
+            }
+            walk.reset_distance(node);
+        }
+        search_space
+    }
+}
@@ -1,5 +1,5 @@
 use super::*;
-use crate::datastr::graph::time_dependent::*;
+use crate::datastr::{graph::time_dependent::*, live_traffic::WallClock, road_closures::RoadClosures};
 
 #[derive(Default)]
 pub struct TDDijkstraOps();
@@ -95,3 +95,32 @@ impl DijkstraOps<PessimisticLiveTDGraph> for PessimisticLiveTDDijkstraOps {
     #[inline(always)]
     fn predecessor_link(&self, _link: &Self::Arc) -> Self::PredecessorLink {}
 }
+
+/// Like `TDDijkstraOps`, but departures on closed edges wait until the closure ends.
+/// The second member is the wall clock time of `t == 0`, usually the last midnight.
+pub struct ClosureAwareTDDijkstraOps<'c>(pub &'c RoadClosures, pub WallClock);
+
+impl DijkstraOps<TDGraph> for ClosureAwareTDDijkstraOps<'_> {
+    type Label = Weight;
+    type LinkResult = Weight;
+    type Arc = (NodeIdT, EdgeIdT);
+    type PredecessorLink = ();
+
+    #[inline(always)]
+    fn link(&mut self, graph: &TDGraph, _parents: &[(NodeId, Self::PredecessorLink)], _tail: NodeIdT, label: &Weight, link: &Self::Arc) -> Self::LinkResult {
+        let ttf = graph.travel_time_function(link.1 .0);
+        label + self.0.eval_td(link.1 .0, self.1, *label, |t| ttf.eval(t))
+    }
+
+    #[inline(always)]
+    fn merge(&mut self, label: &mut Weight, linked: Self::LinkResult) -> bool {
+        if linked < *label {
+            *label = linked;
+            return true;
+        }
+        false
+    }
+
+    #[inline(always)]
+    fn predecessor_link(&self, _link: &Self::Arc) -> Self::PredecessorLink {}
+}
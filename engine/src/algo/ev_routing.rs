@@ -0,0 +1,642 @@
+//! Routing for electric vehicles with a limited battery.
+//!
+//! Each edge consumes energy, derived from its length and the elevation along it.
+//! Going downhill recuperates energy, so consumption may be negative.
+//! The state of charge (SoC) is bounded by the battery capacity: recuperated energy beyond a full battery is lost
+//! and edges which would drain the battery below zero cannot be used.
+//! Vehicles may stop at charging stations, where the charging time depends on the SoC through a charging function.
+//!
+//! Queries minimize the travel time including charging with a multi criteria label search over pareto sets of (time, SoC).
+//! The search is guided by a CCH potential on travel times.
+//! Labels are pruned when their SoC is below a lower bound of the energy needed to reach the target or any charging station.
+//! These bounds come from a CCH potential on a reduced consumption metric, which is made nonnegative
+//! by subtracting the potential energy gained along each edge.
+//! Charging amounts are restricted to the breakpoints of the charging functions,
+//! so results are exact for routes without charging and heuristic otherwise.
+
+use super::*;
+use crate::{
+    algo::{
+        a_star::Potential,
+        ch_potentials::{BorrowedCCHPot, CCHPotData},
+        dijkstra::{generic_dijkstra::*, *},
+    },
+    datastr::{elevation::EdgeElevation, timestamped_vector::*},
+    report::*,
+};
+use std::collections::HashMap;
+
+/// Energy in mWh.
+pub type Energy = i32;
+
+/// Parameters to derive the energy consumption of edges from distances and elevation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConsumptionModel {
+    /// mWh per meter on flat roads.
+    pub per_meter: f64,
+    /// mWh per meter of elevation gain.
+    pub per_meter_ascent: f64,
+    /// Share of the potential energy which is recuperated when going downhill, in `[0, 1]`.
+    pub recuperation: f64,
+}
+
+impl Default for ConsumptionModel {
+    fn default() -> Self {
+        // roughly a compact car of 1.8t
+        Self {
+            per_meter: 150.0,
+            per_meter_ascent: 5500.0,
+            recuperation: 0.6,
+        }
+    }
+}
+
+impl ConsumptionModel {
+    /// Consumption of all edges of `graph`, `geo_distance` in meters, `elevation` of each node in meters.
+    /// Edges are assumed to go straight from one node elevation to the other.
+    pub fn metric<G: LinkIterable<(NodeIdT, EdgeIdT)>>(&self, graph: &G, geo_distance: &[Weight], elevation: &[f32]) -> ConsumptionMetric {
+        self.metric_with_edge_elevation(graph, geo_distance, elevation, &EdgeElevation::from_node_elevation(graph, elevation))
+    }
+
+    /// Consumption of all edges of `graph`, taking the ascent and descent along the edges into account.
+    pub fn metric_with_edge_elevation<G: LinkIterable<(NodeIdT, EdgeIdT)>>(
+        &self,
+        graph: &G,
+        geo_distance: &[Weight],
+        elevation: &[f32],
+        edge_elevation: &EdgeElevation,
+    ) -> ConsumptionMetric {
+        assert!((0.0..=1.0).contains(&self.recuperation));
+        assert_eq!(geo_distance.len(), graph.num_arcs());
+        assert_eq!(edge_elevation.num_arcs(), graph.num_arcs());
+        assert_eq!(elevation.len(), graph.num_nodes());
+
+        let height_energy: Vec<Energy> = elevation.iter().map(|&h| (f64::from(h) * self.per_meter_ascent).round() as Energy).collect();
+        let mut consumption = vec![0; graph.num_arcs()];
+        let mut reduced = vec![0; graph.num_arcs()];
+        for tail in 0..graph.num_nodes() {
+            for (NodeIdT(head), EdgeIdT(edge)) in graph.link_iter(tail as NodeId) {
+                let flat = (f64::from(geo_distance[edge as usize]) * self.per_meter).round() as Energy;
+                let gained = height_energy[head as usize] - height_energy[tail];
+                // ascent minus descent has to match the potential energy difference of the nodes exactly
+                let ascent = std::cmp::max(
+                    (f64::from(edge_elevation.ascent()[edge as usize]) * self.per_meter_ascent).round() as Energy,
+                    gained,
+                );
+                let descent = ascent - gained;
+                // rounding towards less recuperation keeps the reduced consumption nonnegative
+                let recuperated = (f64::from(descent) * self.recuperation).floor() as Energy;
+                consumption[edge as usize] = flat + ascent - recuperated;
+                reduced[edge as usize] = (flat + descent - recuperated) as Weight;
+            }
+        }
+
+        ConsumptionMetric {
+            consumption,
+            reduced,
+            height_energy,
+        }
+    }
+}
+
+/// Edge consumption together with a nonnegative reduced metric for lower bounds.
+#[derive(Debug, Clone)]
+pub struct ConsumptionMetric {
+    consumption: Vec<Energy>,
+    // consumption minus the potential energy gained along the edge
+    reduced: Vec<Weight>,
+    // potential energy of each node
+    height_energy: Vec<Energy>,
+}
+
+impl ConsumptionMetric {
+    pub fn consumption(&self) -> &[Energy] {
+        &self.consumption
+    }
+
+    /// Nonnegative metric for CCH potentials.
+    /// The consumption of a path is its reduced length plus the difference in potential energy between its endpoints.
+    pub fn reduced(&self) -> &[Weight] {
+        &self.reduced
+    }
+
+    /// Lower bound for the consumption from `from` to `to`, given a lower bound of the reduced distance between them.
+    pub fn lower_bound(&self, from: NodeId, to: NodeId, reduced_distance: Weight) -> Energy {
+        let bound = i64::from(reduced_distance) + i64::from(self.height_energy[to as usize]) - i64::from(self.height_energy[from as usize]);
+        std::cmp::min(bound, i64::from(Energy::MAX)) as Energy
+    }
+}
+
+/// SoC over charging time, piecewise linear and concave, starting with an empty battery.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChargingFunction {
+    // (time in ms, SoC), strictly increasing in both
+    points: Vec<(Weight, Energy)>,
+}
+
+impl ChargingFunction {
+    pub fn new(points: Vec<(Weight, Energy)>) -> Self {
+        assert_eq!(points.first(), Some(&(0, 0)), "charging has to start with an empty battery");
+        assert!(points.len() > 1);
+        for w in points.windows(2) {
+            assert!(w[0].0 < w[1].0 && w[0].1 < w[1].1, "charging function not strictly increasing");
+        }
+        for w in points.windows(3) {
+            let slope = |a: (Weight, Energy), b: (Weight, Energy)| (i64::from(b.1 - a.1), i64::from(b.0 - a.0));
+            let ((de1, dt1), (de2, dt2)) = (slope(w[0], w[1]), slope(w[1], w[2]));
+            assert!(de1 * dt2 >= de2 * dt1, "charging function not concave");
+        }
+        Self { points }
+    }
+
+    /// Typical constant current constant voltage charging with `power` in W:
+    /// full power up to 80%, half power up to 90% and a quarter of the power for the rest.
+    pub fn cc_cv(capacity: Energy, power: f64) -> Self {
+        let mut points = vec![(0, 0)];
+        let mut time = 0.0;
+        let mut soc = 0;
+        for tenth in 1..=10 {
+            let share = match tenth {
+                9 => 0.5,
+                10 => 0.25,
+                _ => 1.0,
+            };
+            let next_soc = (i64::from(capacity) * tenth / 10) as Energy;
+            // mWh / W = 3.6s
+            time += f64::from(next_soc - soc) * 3600.0 / (power * share);
+            soc = next_soc;
+            points.push((time.round() as Weight, soc));
+        }
+        points.dedup_by_key(|&mut (_, soc)| soc);
+        Self::new(points)
+    }
+
+    pub fn capacity(&self) -> Energy {
+        self.points.last().unwrap().1
+    }
+
+    /// Time to charge from SoC `from` to `to`.
+    pub fn charging_time(&self, from: Energy, to: Energy) -> Weight {
+        debug_assert!(from <= to);
+        self.time_at(to) - self.time_at(from)
+    }
+
+    /// SoC after charging for `time` starting at SoC `from`.
+    pub fn soc_after(&self, from: Energy, time: Weight) -> Energy {
+        self.soc_at(self.time_at(from) + time)
+    }
+
+    /// SoC levels worth to charge to.
+    pub fn levels(&self) -> impl Iterator<Item = Energy> + '_ {
+        self.points[1..].iter().map(|&(_, soc)| soc)
+    }
+
+    fn time_at(&self, soc: Energy) -> Weight {
+        let soc = soc.clamp(0, self.capacity());
+        let idx = self.points.partition_point(|&(_, s)| s < soc).clamp(1, self.points.len() - 1);
+        let ((t0, s0), (t1, s1)) = (self.points[idx - 1], self.points[idx]);
+        t0 + (i64::from(soc - s0) * i64::from(t1 - t0) / i64::from(s1 - s0)) as Weight
+    }
+
+    fn soc_at(&self, time: Weight) -> Energy {
+        let idx = self.points.partition_point(|&(t, _)| t < time);
+        if idx == self.points.len() {
+            return self.capacity();
+        }
+        let idx = std::cmp::max(idx, 1);
+        let ((t0, s0), (t1, s1)) = (self.points[idx - 1], self.points[idx]);
+        s0 + (i64::from(time - t0) * i64::from(s1 - s0) / i64::from(t1 - t0)) as Energy
+    }
+}
+
+/// Label of the EV search: arrival time and SoC, together with what is needed to find the label it was derived from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvLabel {
+    pub time: Weight,
+    pub soc: Energy,
+    parent: NodeIdT,
+    // `INFINITY` for the initial label
+    parent_time: Weight,
+    parent_soc: Energy,
+    // SoC when leaving the parent, differs from `parent_soc` when charging there
+    departure_soc: Energy,
+}
+
+impl Reset for EvLabel {
+    const DEFAULT: Self = EvLabel {
+        time: INFINITY,
+        soc: 0,
+        parent: NodeIdT(0),
+        parent_time: INFINITY,
+        parent_soc: 0,
+        departure_soc: 0,
+    };
+}
+
+impl Label for EvLabel {
+    type Key = Weight;
+    fn neutral() -> Self {
+        Self::DEFAULT
+    }
+    fn key(&self) -> Self::Key {
+        self.time
+    }
+}
+
+impl PartialEq for NodeQueueLabelOrder<EvLabel> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.time == other.0.time && self.0.soc == other.0.soc
+    }
+}
+impl Eq for NodeQueueLabelOrder<EvLabel> {}
+impl PartialOrd for NodeQueueLabelOrder<EvLabel> {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for NodeQueueLabelOrder<EvLabel> {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        // switched for reversing, among equal times higher SoCs first
+        other.0.time.cmp(&self.0.time).then_with(|| self.0.soc.cmp(&other.0.soc))
+    }
+}
+
+/// Ops for the multi criteria search with bounded SoC propagation, charging and lower bound pruning.
+pub struct EvDijkstra<'a> {
+    travel_time: Vec<Weight>,
+    consumption: ConsumptionMetric,
+    capacity: Energy,
+    stations: HashMap<NodeId, ChargingFunction>,
+    consumption_pot: BorrowedCCHPot<'a>,
+    // lower bound of the consumption to the closest charging station
+    station_bound: Vec<Energy>,
+    target: NodeId,
+    num_labels_pushed: usize,
+}
+
+impl<'a> EvDijkstra<'a> {
+    fn init(&mut self, target: NodeId) {
+        self.consumption_pot.init(target);
+        self.target = target;
+        self.num_labels_pushed = 0;
+    }
+
+    // SoC needed at `node` to reach either the target or a charging station
+    fn required_soc(&mut self, node: NodeId) -> Energy {
+        let to_target = self
+            .consumption_pot
+            .potential(node)
+            .map(|reduced_distance| self.consumption.lower_bound(node, self.target, reduced_distance))
+            .unwrap_or(Energy::MAX);
+        std::cmp::min(to_target, self.station_bound[node as usize])
+    }
+}
+
+impl<'a, G> MultiCritDijkstraOps<G> for EvDijkstra<'a> {
+    type Label = EvLabel;
+    type Arc = (NodeIdT, EdgeIdT);
+    type LinkResult = Vec<EvLabel>;
+    type PredecessorLink = ();
+
+    fn link(
+        &mut self,
+        _graph: &G,
+        _labels: &TimestampedVector<MultiCritNodeData<Self::Label>>,
+        _parents: &[(NodeId, Self::PredecessorLink)],
+        NodeIdT(tail): NodeIdT,
+        _key: Weight,
+        label: &Self::Label,
+        &(NodeIdT(head), EdgeIdT(edge)): &Self::Arc,
+    ) -> Self::LinkResult {
+        let travel_time = self.travel_time[edge as usize];
+        if travel_time >= INFINITY {
+            return Vec::new();
+        }
+        let required = self.required_soc(head);
+        let consumption = self.consumption.consumption[edge as usize];
+        let capacity = self.capacity;
+        let arrive = |departure_time: Weight, departure_soc: Energy| {
+            let soc = std::cmp::min(capacity, departure_soc - consumption);
+            if soc < 0 || soc < required {
+                None
+            } else {
+                Some(EvLabel {
+                    time: departure_time + travel_time,
+                    soc,
+                    parent: NodeIdT(tail),
+                    parent_time: label.time,
+                    parent_soc: label.soc,
+                    departure_soc,
+                })
+            }
+        };
+
+        let mut linked: Vec<_> = arrive(label.time, label.soc).into_iter().collect();
+        if let Some(charging) = self.stations.get(&tail) {
+            let mut levels: Vec<_> = charging
+                .levels()
+                .map(|level| std::cmp::min(level, capacity))
+                .filter(|&level| level > label.soc)
+                .collect();
+            levels.dedup();
+            linked.extend(
+                levels
+                    .into_iter()
+                    .filter_map(|level| arrive(label.time + charging.charging_time(label.soc, level), level)),
+            );
+        }
+        linked
+    }
+
+    fn merge(&mut self, label: &mut MultiCritNodeData<Self::Label>, linked: Self::LinkResult) -> Option<Weight> {
+        let mut improved = None;
+        for new in linked {
+            let mut dominated = false;
+            // With a consistent potential, settled labels are never later than new ones,
+            // so only removing strictly later labels keeps all settled labels for path unpacking.
+            label.retain(|NodeQueueLabelOrder(old)| {
+                if old.time <= new.time && old.soc >= new.soc {
+                    dominated = true;
+                }
+                dominated || !(new.time < old.time && new.soc >= old.soc)
+            });
+
+            if !dominated {
+                self.num_labels_pushed += 1;
+                improved = Some(std::cmp::min(improved.unwrap_or(INFINITY), new.time));
+                label.push(NodeQueueLabelOrder(new));
+            }
+        }
+        improved
+    }
+
+    fn predecessor_link(&self, _link: &Self::Arc) -> Self::PredecessorLink {}
+}
+
+/// A stop at a charging station along a route.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChargingStop {
+    pub node: NodeId,
+    pub arrival_soc: Energy,
+    pub departure_soc: Energy,
+    pub charging_time: Weight,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EvRoute {
+    /// Including charging time.
+    pub travel_time: Weight,
+    pub path: Vec<NodeId>,
+    /// SoC when arriving at each node of `path`.
+    pub soc: Vec<Energy>,
+    pub charging_stops: Vec<ChargingStop>,
+}
+
+pub struct Server<'a> {
+    graph: UnweightedOwnedGraph,
+    time_pot: BorrowedCCHPot<'a>,
+    dijkstra_data: DijkstraData<EvLabel, (), MultiCritNodeData<EvLabel>>,
+    ops: EvDijkstra<'a>,
+}
+
+impl<'a> Server<'a> {
+    /// `time_pot_data` has to be customized with lower bounds of the travel times of `graph`,
+    /// `consumption_pot_data` with `consumption.reduced()`.
+    /// `capacity` of the battery in mWh.
+    pub fn new(
+        graph: OwnedGraph,
+        consumption: ConsumptionMetric,
+        capacity: Energy,
+        stations: Vec<(NodeId, ChargingFunction)>,
+        time_pot_data: &'a CCHPotData<'a>,
+        consumption_pot_data: &'a CCHPotData<'a>,
+    ) -> Self {
+        assert_eq!(consumption.consumption.len(), graph.num_arcs());
+        assert!(capacity >= 0);
+        let n = graph.num_nodes();
+        let stations: HashMap<_, _> = stations.into_iter().collect();
+        let station_bound = station_consumption_bounds(&graph, &consumption, &stations);
+        let (first_out, head, travel_time) = graph.decompose();
+
+        Self {
+            graph: UnweightedOwnedGraph::new(first_out, head),
+            time_pot: time_pot_data.forward_potential(),
+            dijkstra_data: DijkstraData::new(n),
+            ops: EvDijkstra {
+                travel_time,
+                consumption,
+                capacity,
+                stations,
+                consumption_pot: consumption_pot_data.forward_potential(),
+                station_bound,
+                target: 0,
+                num_labels_pushed: 0,
+            },
+        }
+    }
+
+    /// Fastest route including charging stops, starting with `initial_soc` in mWh.
+    /// `None` when the target cannot be reached with the battery.
+    pub fn query(&mut self, query: Query, initial_soc: Energy) -> Option<EvRoute> {
+        report!("algo", "EV Multi Criteria A*");
+        assert!((0..=self.ops.capacity).contains(&initial_soc));
+        self.time_pot.init(query.to);
+        self.ops.init(query.to);
+
+        let time_pot = &mut self.time_pot;
+        let mut run = MultiCritDijkstraRun::query(
+            &self.graph,
+            &mut self.dijkstra_data,
+            &mut self.ops,
+            DijkstraInit {
+                source: NodeIdT(query.from),
+                initial_state: EvLabel {
+                    time: 0,
+                    soc: initial_soc,
+                    parent: NodeIdT(query.from),
+                    parent_time: INFINITY,
+                    parent_soc: initial_soc,
+                    departure_soc: initial_soc,
+                },
+            },
+            |node| time_pot.potential(node),
+        );
+
+        let mut num_queue_pops: usize = 0;
+        while let Some(node) = run.next_step_with_potential(|node| time_pot.potential(node)) {
+            num_queue_pops += 1;
+            if node == query.to {
+                break;
+            }
+        }
+        report!("num_queue_pops", num_queue_pops);
+        report!("num_relaxed_arcs", run.num_relaxed_arcs());
+        report!("num_labels_pushed", self.ops.num_labels_pushed);
+
+        let distances = &self.dijkstra_data.distances;
+        let NodeQueueLabelOrder(mut label) = *distances[query.to as usize].popped().first()?;
+        let travel_time = label.time;
+        let mut path = vec![query.to];
+        let mut soc = vec![label.soc];
+        let mut charging_stops = Vec::new();
+
+        while label.parent_time != INFINITY {
+            let NodeIdT(parent) = label.parent;
+            if label.departure_soc != label.parent_soc {
+                charging_stops.push(ChargingStop {
+                    node: parent,
+                    arrival_soc: label.parent_soc,
+                    departure_soc: label.departure_soc,
+                    charging_time: self.ops.stations[&parent].charging_time(label.parent_soc, label.departure_soc),
+                });
+            }
+            label = distances[parent as usize]
+                .popped()
+                .iter()
+                .find(|NodeQueueLabelOrder(l)| l.time == label.parent_time && l.soc == label.parent_soc)
+                .unwrap()
+                .0;
+            path.push(parent);
+            soc.push(label.soc);
+        }
+
+        path.reverse();
+        soc.reverse();
+        charging_stops.reverse();
+        Some(EvRoute {
+            travel_time,
+            path,
+            soc,
+            charging_stops,
+        })
+    }
+}
+
+// Lower bound of the consumption from each node to the closest station, by a backward search from all stations on the reduced metric.
+fn station_consumption_bounds(graph: &OwnedGraph, consumption: &ConsumptionMetric, stations: &HashMap<NodeId, ChargingFunction>) -> Vec<Energy> {
+    let mut bounds = vec![Energy::MAX; graph.num_nodes()];
+    let height_energy = &consumption.height_energy;
+    let min_height_energy = match stations.keys().map(|&station| height_energy[station as usize]).min() {
+        Some(min) => min,
+        None => return bounds,
+    };
+
+    let reversed = OwnedGraph::reversed(&FirstOutGraph::new(graph.first_out(), graph.head(), consumption.reduced()));
+    let mut data = DijkstraData::new(graph.num_nodes());
+    let mut ops = DefaultOps::default();
+    // start labels shifted by the potential energy of the stations, so they can be compared
+    let mut starts = stations.keys().map(|&station| DijkstraInit {
+        source: NodeIdT(station),
+        initial_state: (height_energy[station as usize] - min_height_energy) as Weight,
+    });
+    let mut run = DijkstraRun::query(&reversed, &mut data, &mut ops, starts.next().unwrap());
+    for init in starts {
+        run.add_start_node(init);
+    }
+    while let Some(node) = run.next() {
+        let bound = i64::from(*run.tentative_distance(node)) + i64::from(min_height_energy) - i64::from(height_energy[node as usize]);
+        bounds[node as usize] = std::cmp::min(bound, i64::from(Energy::MAX)) as Energy;
+    }
+    bounds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{algo::customizable_contraction_hierarchy::CCH, datastr::node_order::NodeOrder};
+
+    fn route(
+        graph: &OwnedGraph,
+        consumption: &ConsumptionMetric,
+        capacity: Energy,
+        stations: &[(NodeId, ChargingFunction)],
+        query: Query,
+        initial_soc: Energy,
+    ) -> Option<EvRoute> {
+        let cch = CCH::fix_order_and_build(graph, NodeOrder::identity(graph.num_nodes()));
+        let time_pot_data = CCHPotData::new(&cch, graph);
+        let reduced = FirstOutGraph::new(graph.first_out(), graph.head(), consumption.reduced());
+        let consumption_pot_data = CCHPotData::new(&cch, &reduced);
+        let mut server = Server::new(
+            graph.clone(),
+            consumption.clone(),
+            capacity,
+            stations.to_vec(),
+            &time_pot_data,
+            &consumption_pot_data,
+        );
+        server.query(query, initial_soc)
+    }
+
+    #[test]
+    fn bounded_soc_with_recuperation() {
+        // 0 -> 1 downhill, 1 -> 2 uphill
+        let graph = OwnedGraph::new(vec![0, 1, 2, 2], vec![1, 2], vec![10, 10]);
+        let model = ConsumptionModel {
+            per_meter: 1.0,
+            per_meter_ascent: 10.0,
+            recuperation: 0.5,
+        };
+        let consumption = model.metric(&graph, &[10, 10], &[10.0, 0.0, 10.0]);
+        assert_eq!(consumption.consumption(), &[-40, 110]);
+        assert_eq!(consumption.reduced(), &[60, 10]);
+        assert_eq!(consumption.lower_bound(0, 2, 70), 70);
+        // climbing on the way down and descending more than the node elevations tell
+        let hilly = model.metric_with_edge_elevation(&graph, &[10, 10], &[10.0, 0.0, 10.0], &EdgeElevation::new(vec![10.0, 10.0], vec![20.0, 0.0]));
+        assert_eq!(hilly.consumption(), &[10, 110]);
+        assert_eq!(hilly.reduced(), &[110, 10]);
+
+        let query = Query { from: 0, to: 2 };
+        // recuperated energy is lost with a full battery
+        assert_eq!(route(&graph, &consumption, 200, &[], query, 200).unwrap().soc, vec![200, 200, 90]);
+        assert_eq!(route(&graph, &consumption, 200, &[], query, 100).unwrap().soc, vec![100, 140, 30]);
+        assert_eq!(route(&graph, &consumption, 200, &[], query, 69), None);
+    }
+
+    #[test]
+    fn fastest_feasible_route_with_charging() {
+        // 0 -> 1 -> 3 fast but expensive, 0 -> 2 -> 3 slow but efficient, 0 -> 4 -> 3 past a charging station
+        let graph = OwnedGraph::new(vec![0, 3, 4, 5, 5, 6], vec![1, 2, 4, 3, 3, 3], vec![10, 20, 5, 10, 20, 30]);
+        let model = ConsumptionModel {
+            per_meter: 1.0,
+            per_meter_ascent: 0.0,
+            recuperation: 0.0,
+        };
+        let consumption = model.metric(&graph, &[100, 50, 50, 100, 50, 100], &[0.0; 5]);
+        let stations = [(4, ChargingFunction::new(vec![(0, 0), (40, 100), (120, 200)]))];
+        let query = Query { from: 0, to: 3 };
+
+        let result = |initial_soc| route(&graph, &consumption, 200, &stations, query, initial_soc);
+        assert_eq!(result(200).map(|route| (route.travel_time, route.path)), Some((20, vec![0, 1, 3])));
+        assert_eq!(result(150).map(|route| (route.travel_time, route.path)), Some((35, vec![0, 4, 3])));
+        assert_eq!(result(100).map(|route| (route.travel_time, route.path)), Some((40, vec![0, 2, 3])));
+        assert_eq!(
+            result(60),
+            Some(EvRoute {
+                travel_time: 71,
+                path: vec![0, 4, 3],
+                soc: vec![60, 10, 0],
+                charging_stops: vec![ChargingStop {
+                    node: 4,
+                    arrival_soc: 10,
+                    departure_soc: 100,
+                    charging_time: 36,
+                }],
+            })
+        );
+        assert_eq!(result(40), None);
+    }
+
+    #[test]
+    fn charging_functions() {
+        let charging = ChargingFunction::new(vec![(0, 0), (40, 100), (120, 200)]);
+        assert_eq!(charging.charging_time(50, 150), 60);
+        assert_eq!(charging.soc_after(50, 60), 150);
+        assert_eq!(charging.soc_after(150, 1000), 200);
+
+        let cc_cv = ChargingFunction::cc_cv(50_000_000, 50_000.0);
+        assert_eq!(cc_cv.capacity(), 50_000_000);
+        // 80% of 50kWh with 50kW take 48 minutes
+        assert_eq!(cc_cv.charging_time(0, 40_000_000), 48 * 60 * 1000);
+    }
+}
@@ -30,18 +30,28 @@ impl HubLabels {
             for link in LinkIterable::<Link>::link_iter(up, node as NodeId) {
                 let (out_remaining, out_done) = self.outgoing.split_at_mut(node + 1);
                 let cur_out = out_remaining.last_mut().unwrap();
-                cur_out.extend(out_done[link.node as usize - node - 1].iter().map(|&(hub, weight)| (hub, weight + link.weight)));
+                cur_out.extend(
+                    out_done[link.node as usize - node - 1]
+                        .iter()
+                        .map(|&(hub, weight)| (hub, weight + link.weight))
+                        .filter(|&(_, weight)| weight < INFINITY),
+                );
             }
 
             for link in LinkIterable::<Link>::link_iter(down, node as NodeId) {
                 let (in_remaining, in_done) = self.incoming.split_at_mut(node + 1);
                 let cur_in = in_remaining.last_mut().unwrap();
-                cur_in.extend(in_done[link.node as usize - node - 1].iter().map(|&(hub, weight)| (hub, weight + link.weight)));
+                cur_in.extend(
+                    in_done[link.node as usize - node - 1]
+                        .iter()
+                        .map(|&(hub, weight)| (hub, weight + link.weight))
+                        .filter(|&(_, weight)| weight < INFINITY),
+                );
             }
 
             for dir in [&mut self.outgoing[node], &mut self.incoming[node]] {
                 dir.sort_unstable();
-                for same_hub in dir.group_by_mut(|a, b| a.0 == b.0) {
+                for same_hub in dir.chunk_by_mut(|a, b| a.0 == b.0) {
                     let min_dist = same_hub.iter().map(|&(_, w)| w).min().unwrap();
                     for label in same_hub {
                         label.1 = min_dist;
@@ -114,7 +124,7 @@ impl HubLabels {
             }
         }
 
-        return result;
+        result.filter(|&(_, dist)| dist < INFINITY)
     }
 
     pub fn num_labels(&self) -> usize {
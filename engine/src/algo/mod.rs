@@ -11,14 +11,24 @@ pub mod ch_potentials;
 pub mod contraction_hierarchy;
 pub mod customizable_contraction_hierarchy;
 pub mod dijkstra;
+pub mod ev_routing;
 pub mod hl;
 pub mod metric_merging;
 pub mod minimal_nonshortest_subpaths;
+pub mod path_statistics;
+pub mod ridesharing;
+pub mod route_preferences;
+pub mod router;
 pub mod rphast;
+pub mod scc;
 pub mod td_astar;
+pub mod time_dependent_contraction_hierarchy;
 pub mod time_dependent_sampling;
 pub mod topocore;
 pub mod traffic_aware;
+pub mod transit;
+pub mod vrp;
+pub mod waypoints;
 
 pub trait GenQuery<Label> {
     fn new(from: NodeId, to: NodeId, initial_state: Label) -> Self;
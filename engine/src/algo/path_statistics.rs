@@ -0,0 +1,242 @@
+//! Evaluation of paths under additional metrics, for example length, fuel consumption or toll costs of a route optimized for travel time.
+//!
+//! Query servers only return node or edge paths in their own graph representation.
+//! Here, paths given as node sequences are mapped back to edges of the original graph.
+//! Between two nodes, the parallel edge with the lowest primary weight is taken, as the query would have.
+//! Time-dependent paths are evaluated at the actual time each edge is entered, so secondary metrics may depend on time, too.
+
+use super::*;
+use crate::{algo::time_dependent_contraction_hierarchy::TDMetric, datastr::graph::time_dependent::*};
+
+/// A per edge metric, optionally depending on the time the edge is entered.
+pub trait EdgeMetric {
+    fn cost(&self, edge: EdgeId, t: Timestamp) -> Weight;
+}
+
+impl EdgeMetric for [Weight] {
+    fn cost(&self, edge: EdgeId, _t: Timestamp) -> Weight {
+        self[edge as usize]
+    }
+}
+
+impl EdgeMetric for Vec<Weight> {
+    fn cost(&self, edge: EdgeId, _t: Timestamp) -> Weight {
+        self[edge as usize]
+    }
+}
+
+impl<F: Fn(EdgeId, Timestamp) -> Weight> EdgeMetric for F {
+    fn cost(&self, edge: EdgeId, t: Timestamp) -> Weight {
+        self(edge, t)
+    }
+}
+
+impl EdgeMetric for TDGraph {
+    fn cost(&self, edge: EdgeId, t: Timestamp) -> Weight {
+        TDMetric::eval(self, edge, t)
+    }
+}
+
+impl EdgeMetric for LiveTDGraph {
+    fn cost(&self, edge: EdgeId, t: Timestamp) -> Weight {
+        TDMetric::eval(self, edge, t)
+    }
+}
+
+impl EdgeMetric for PessimisticLiveTDGraph {
+    fn cost(&self, edge: EdgeId, t: Timestamp) -> Weight {
+        TDMetric::eval(self, edge, t)
+    }
+}
+
+/// Per edge breakdown and totals of a path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathStatistics {
+    /// Edges of the path in the original graph.
+    pub edges: Vec<EdgeId>,
+    /// Time at which each edge is entered.
+    pub entry_times: Vec<Timestamp>,
+    /// Primary weight (travel time) of each edge.
+    pub weights: Vec<Weight>,
+    /// For each secondary metric the value of each edge.
+    pub metric_values: Vec<Vec<Weight>>,
+}
+
+impl PathStatistics {
+    /// Statistics of an empty path.
+    pub fn new(num_metrics: usize) -> Self {
+        Self {
+            edges: Vec::new(),
+            entry_times: Vec::new(),
+            weights: Vec::new(),
+            metric_values: vec![Vec::new(); num_metrics],
+        }
+    }
+
+    fn push(&mut self, edge: EdgeId, t: Timestamp, weight: Weight, metrics: &[&dyn EdgeMetric]) {
+        self.edges.push(edge);
+        self.entry_times.push(t);
+        self.weights.push(weight);
+        for (values, metric) in self.metric_values.iter_mut().zip(metrics) {
+            values.push(metric.cost(edge, t));
+        }
+    }
+
+    /// Evaluate a path of a static graph, entering the first edge at `departure`.
+    /// Panics if two consecutive nodes are not connected by an edge.
+    pub fn evaluate<G: EdgeIdGraph + EdgeRandomAccessGraph<Link>>(graph: &G, departure: Timestamp, node_path: &[NodeId], metrics: &[&dyn EdgeMetric]) -> Self {
+        Self::evaluate_with(graph, departure, node_path, |_, weight| weight, metrics)
+    }
+
+    /// Like `evaluate`, for paths found on a modified metric, for example with route preferences.
+    /// Parallel edges are chosen by their `modified` weight, the statistics contain the weights of `graph`.
+    pub fn evaluate_with<G: EdgeIdGraph + EdgeRandomAccessGraph<Link>>(
+        graph: &G,
+        departure: Timestamp,
+        node_path: &[NodeId],
+        modified: impl Fn(EdgeId, Weight) -> Weight,
+        metrics: &[&dyn EdgeMetric],
+    ) -> Self {
+        let mut statistics = Self::new(metrics.len());
+        let mut t = departure;
+        for pair in node_path.windows(2) {
+            let (_, weight, edge) = graph
+                .edge_indices(pair[0], pair[1])
+                .map(|EdgeIdT(edge)| {
+                    let weight = graph.link(edge).weight;
+                    (modified(edge, weight), weight, edge)
+                })
+                .min()
+                .unwrap_or_else(|| panic!("no edge from {} to {}", pair[0], pair[1]));
+            statistics.push(edge, t, weight, metrics);
+            t += weight;
+        }
+        statistics
+    }
+
+    /// Evaluate a path of a time-dependent graph, entering the first edge at `departure`.
+    /// Panics if two consecutive nodes are not connected by an edge.
+    pub fn evaluate_td<G: TDMetric>(graph: &G, departure: Timestamp, node_path: &[NodeId], metrics: &[&dyn EdgeMetric]) -> Self {
+        let mut statistics = Self::new(metrics.len());
+        let mut t = departure;
+        for pair in node_path.windows(2) {
+            let (weight, edge) = graph
+                .link_iter(pair[0])
+                .filter(|&(NodeIdT(head), _)| head == pair[1])
+                .map(|(_, EdgeIdT(edge))| (graph.eval(edge, t), edge))
+                .min()
+                .unwrap_or_else(|| panic!("no edge from {} to {}", pair[0], pair[1]));
+            statistics.push(edge, t, weight, metrics);
+            t += weight;
+        }
+        statistics
+    }
+
+    /// Concatenate the statistics of a path starting where this one ends, leaving `other` empty.
+    pub fn append(&mut self, other: &mut Self) {
+        assert_eq!(self.metric_values.len(), other.metric_values.len());
+        self.edges.append(&mut other.edges);
+        self.entry_times.append(&mut other.entry_times);
+        self.weights.append(&mut other.weights);
+        for (values, other_values) in self.metric_values.iter_mut().zip(&mut other.metric_values) {
+            values.append(other_values);
+        }
+    }
+
+    pub fn num_edges(&self) -> usize {
+        self.edges.len()
+    }
+
+    /// Sum of the primary weights.
+    pub fn total_weight(&self) -> u64 {
+        self.weights.iter().map(|&weight| u64::from(weight)).sum()
+    }
+
+    /// Sum of the secondary metric with index `metric`.
+    pub fn metric_total(&self, metric: usize) -> u64 {
+        self.metric_values[metric].iter().map(|&value| u64::from(value)).sum()
+    }
+
+    pub fn metric_totals(&self) -> Vec<u64> {
+        (0..self.metric_values.len()).map(|metric| self.metric_total(metric)).collect()
+    }
+}
+
+impl<P, W: Copy> ConnectedQueryResult<P, W>
+where
+    P: PathServer<NodeInfo = NodeId>,
+{
+    /// Evaluate the path of a static query in `graph`.
+    pub fn statistics<G: EdgeIdGraph + EdgeRandomAccessGraph<Link>>(&mut self, graph: &G, metrics: &[&dyn EdgeMetric]) -> PathStatistics {
+        PathStatistics::evaluate(graph, 0, &self.node_path(), metrics)
+    }
+}
+
+impl<P, W: Copy> ConnectedQueryResult<P, W>
+where
+    P: PathServer<NodeInfo = (NodeId, Timestamp)>,
+{
+    /// Evaluate the path of a time-dependent query in `graph`, starting at the departure time of the query.
+    pub fn td_statistics<G: TDMetric>(&mut self, graph: &G, metrics: &[&dyn EdgeMetric]) -> PathStatistics {
+        let path = self.node_path();
+        let departure = path[0].1;
+        let nodes: Vec<NodeId> = path.iter().map(|&(node, _)| node).collect();
+        PathStatistics::evaluate_td(graph, departure, &nodes, metrics)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algo::dijkstra::{query::dijkstra::Server as DijkServer, *};
+
+    #[test]
+    fn parallel_edges_and_secondary_metrics() {
+        // 0 -> 1 twice, the second edge is faster but longer, 1 -> 2
+        let graph = OwnedGraph::new(vec![0, 2, 3, 3], vec![1, 1, 2], vec![5, 3, 4]);
+        let length = vec![100, 200, 50];
+        let toll = |edge: EdgeId, t: Timestamp| if edge == 2 && t >= 3 { 7 } else { 0 };
+
+        let mut server = DijkServer::<_, DefaultOps>::new(graph.clone());
+        let mut result = server.query(Query { from: 0, to: 2 }).found().unwrap();
+        let statistics = result.statistics(&graph, &[&length, &toll]);
+        assert_eq!(statistics.edges, vec![1, 2]);
+        assert_eq!(statistics.entry_times, vec![0, 3]);
+        assert_eq!(statistics.total_weight(), u64::from(result.distance()));
+        assert_eq!(statistics.metric_values, vec![vec![200, 50], vec![0, 7]]);
+        assert_eq!(statistics.metric_totals(), vec![250, 7]);
+    }
+
+    #[test]
+    fn td_metrics_at_traversal_time() {
+        run_test_with_periodicity(1000, || {
+            // 0 -> 1 twice, 1 -> 2, the faster parallel edge changes over time
+            let graph = TDGraph::new(
+                vec![0, 2, 3, 3],
+                vec![1, 1, 2],
+                vec![0, 2, 3, 6],
+                vec![0, 100, 0, 0, 100, 200],
+                vec![10, 30, 20, 10, 50, 50],
+            );
+            let fuel = TDGraph::new(
+                vec![0, 2, 3, 3],
+                vec![1, 1, 2],
+                vec![0, 1, 2, 5],
+                vec![0, 0, 0, 100, 200],
+                vec![1, 2, 3, 13, 13],
+            );
+
+            let statistics = PathStatistics::evaluate_td(&graph, 0, &[0, 1, 2], &[&fuel]);
+            assert_eq!(statistics.edges, vec![0, 2]);
+            assert_eq!(statistics.entry_times, vec![0, 10]);
+            assert_eq!(statistics.weights, vec![10, 14]);
+            assert_eq!(statistics.metric_values, vec![vec![1, 4]]);
+
+            let statistics = PathStatistics::evaluate_td(&graph, 100, &[0, 1, 2], &[&fuel]);
+            assert_eq!(statistics.edges, vec![1, 2]);
+            assert_eq!(statistics.entry_times, vec![100, 120]);
+            assert_eq!(statistics.total_weight(), 20 + 50);
+            assert_eq!(statistics.metric_total(0), 2 + 13);
+        });
+    }
+}
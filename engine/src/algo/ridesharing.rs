@@ -0,0 +1,286 @@
+//! Dynamic vehicle assignment for on-demand dispatching.
+//!
+//! A `Fleet` tracks the position and the remaining stops of each vehicle in `DynamicBCCHNearestNeighbor` indices,
+//! one for positions and one for stops, so vehicles can move and change their routes cheaply.
+//! Requests are matched either to the closest vehicles by road distance or to the cheapest insertion of the pickup into a route.
+
+use super::*;
+use crate::algo::customizable_contraction_hierarchy::{query::nearest_neighbor::DynamicBCCHNearestNeighbor, *};
+
+pub type VehicleId = u32;
+
+/// Inserting a pickup into the route of a vehicle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Insertion {
+    pub vehicle: VehicleId,
+    /// Number of stops served before the pickup.
+    pub position: usize,
+    /// Distance along the route from the current position to the pickup.
+    pub pickup_distance: Weight,
+    /// Additional distance for the vehicle.
+    pub detour: Weight,
+}
+
+struct VehicleState {
+    position: NodeId,
+    stops: Vec<NodeId>,
+    // point ids of the stops
+    stop_points: Vec<u32>,
+    // distance of the legs to each stop from the previous stop or the current position
+    legs: Vec<Weight>,
+}
+
+pub struct Fleet<'a> {
+    positions: DynamicBCCHNearestNeighbor<'a>,
+    stops: DynamicBCCHNearestNeighbor<'a>,
+    vehicles: Vec<Option<VehicleState>>,
+    num_stop_points: u32,
+    free_stop_points: Vec<u32>,
+}
+
+impl<'a> Fleet<'a> {
+    pub fn new(customized: &'a CustomizedPerfect<'a, CCH>) -> Self {
+        Self {
+            positions: DynamicBCCHNearestNeighbor::new(customized),
+            stops: DynamicBCCHNearestNeighbor::new(customized),
+            vehicles: Vec::new(),
+            num_stop_points: 0,
+            free_stop_points: Vec::new(),
+        }
+    }
+
+    pub fn add_vehicle(&mut self, position: NodeId) -> VehicleId {
+        let vehicle = self.vehicles.len() as VehicleId;
+        self.positions.insert(vehicle, position);
+        self.vehicles.push(Some(VehicleState {
+            position,
+            stops: Vec::new(),
+            stop_points: Vec::new(),
+            legs: Vec::new(),
+        }));
+        vehicle
+    }
+
+    pub fn remove_vehicle(&mut self, vehicle: VehicleId) {
+        self.set_stops(vehicle, Vec::new());
+        self.positions.remove(vehicle);
+        self.vehicles[vehicle as usize] = None;
+    }
+
+    pub fn position(&self, vehicle: VehicleId) -> NodeId {
+        self.state(vehicle).position
+    }
+
+    pub fn stops(&self, vehicle: VehicleId) -> &[NodeId] {
+        &self.state(vehicle).stops
+    }
+
+    pub fn move_vehicle(&mut self, vehicle: VehicleId, position: NodeId) {
+        self.positions.move_point(vehicle, position);
+        let first_stop = self.state(vehicle).stops.first().copied();
+        let first_leg = first_stop.map(|stop| self.positions.distance(position, stop).unwrap_or(INFINITY));
+        let state = self.vehicles[vehicle as usize].as_mut().unwrap();
+        state.position = position;
+        if let Some(leg) = first_leg {
+            state.legs[0] = leg;
+        }
+    }
+
+    /// Replace the remaining stops of a vehicle.
+    pub fn set_stops(&mut self, vehicle: VehicleId, stops: Vec<NodeId>) {
+        let state = self.vehicles[vehicle as usize].as_mut().expect("unknown vehicle");
+        for point in state.stop_points.drain(..) {
+            self.stops.remove(point);
+            self.free_stop_points.push(point);
+        }
+
+        let mut legs = Vec::with_capacity(stops.len());
+        let mut prev = state.position;
+        for &stop in &stops {
+            legs.push(self.stops.distance(prev, stop).unwrap_or(INFINITY));
+            prev = stop;
+        }
+
+        let mut stop_points = Vec::with_capacity(stops.len());
+        for &stop in &stops {
+            let point = self.free_stop_points.pop().unwrap_or_else(|| {
+                self.num_stop_points += 1;
+                self.num_stop_points - 1
+            });
+            self.stops.insert(point, stop);
+            stop_points.push(point);
+        }
+
+        let state = self.vehicles[vehicle as usize].as_mut().unwrap();
+        state.stops = stops;
+        state.stop_points = stop_points;
+        state.legs = legs;
+    }
+
+    /// Add a stop after the first `position` stops, for example the pickup of an accepted `Insertion`.
+    pub fn insert_stop(&mut self, vehicle: VehicleId, position: usize, stop: NodeId) {
+        let mut stops = self.stops(vehicle).to_vec();
+        stops.insert(position, stop);
+        self.set_stops(vehicle, stops);
+    }
+
+    /// The vehicle reached its next stop.
+    pub fn arrive(&mut self, vehicle: VehicleId) {
+        let mut stops = self.stops(vehicle).to_vec();
+        assert!(!stops.is_empty(), "vehicle {} has no stops", vehicle);
+        let stop = stops.remove(0);
+        self.move_vehicle(vehicle, stop);
+        self.set_stops(vehicle, stops);
+    }
+
+    /// The `k` vehicles closest to `node` by distance from their current position, sorted by distance.
+    pub fn closest_vehicles(&mut self, node: NodeId, k: usize) -> Vec<(Weight, VehicleId)> {
+        self.positions.closest_from(node, k)
+    }
+
+    /// The `k` cheapest insertions of a pickup at `node`, at most one per vehicle, sorted by detour.
+    /// Evaluates all vehicles which can reach `node`.
+    pub fn insertions(&mut self, node: NodeId, k: usize) -> Vec<Insertion> {
+        let mut from_position = vec![INFINITY; self.vehicles.len()];
+        for (dist, vehicle) in self.positions.closest_from(node, usize::MAX) {
+            from_position[vehicle as usize] = dist;
+        }
+        let mut from_stop = vec![INFINITY; self.num_stop_points as usize];
+        for (dist, point) in self.stops.closest_from(node, usize::MAX) {
+            from_stop[point as usize] = dist;
+        }
+        let mut to_stop = vec![INFINITY; self.num_stop_points as usize];
+        for (dist, point) in self.stops.closest_to(node, usize::MAX) {
+            to_stop[point as usize] = dist;
+        }
+
+        let mut insertions = Vec::new();
+        for (vehicle, state) in self.vehicles.iter().enumerate() {
+            let state = match state {
+                Some(state) => state,
+                None => continue,
+            };
+            let mut best: Option<Insertion> = None;
+            let mut route_dist: Weight = 0;
+            for position in 0..=state.stops.len() {
+                let to_pickup = if position == 0 {
+                    from_position[vehicle]
+                } else {
+                    from_stop[state.stop_points[position - 1] as usize]
+                };
+                let detour = if position < state.stops.len() {
+                    (to_pickup.saturating_add(to_stop[state.stop_points[position] as usize])).saturating_sub(state.legs[position])
+                } else {
+                    to_pickup
+                };
+                if to_pickup < INFINITY && best.map(|best| detour < best.detour).unwrap_or(true) {
+                    best = Some(Insertion {
+                        vehicle: vehicle as VehicleId,
+                        position,
+                        pickup_distance: route_dist.saturating_add(to_pickup),
+                        detour,
+                    });
+                }
+                if position < state.stops.len() {
+                    route_dist = route_dist.saturating_add(state.legs[position]);
+                }
+            }
+            insertions.extend(best);
+        }
+
+        insertions.sort_unstable_by_key(|insertion| (insertion.detour, insertion.vehicle));
+        insertions.truncate(k);
+        insertions
+    }
+
+    fn state(&self, vehicle: VehicleId) -> &VehicleState {
+        self.vehicles[vehicle as usize].as_ref().expect("unknown vehicle")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        algo::dijkstra::{query::dijkstra::Server as DijkServer, *},
+        datastr::node_order::NodeOrder,
+    };
+
+    // 4x4 grid, some streets are one-way
+    fn graph() -> OwnedGraph {
+        let mut adjacency = vec![Vec::new(); 16];
+        for node in 0..16u32 {
+            if node % 4 < 3 {
+                adjacency[node as usize].push(Link {
+                    node: node + 1,
+                    weight: 2 + node % 3,
+                });
+                if node % 5 != 0 {
+                    adjacency[node as usize + 1].push(Link { node, weight: 2 + node % 3 });
+                }
+            }
+            if node < 12 {
+                adjacency[node as usize].push(Link {
+                    node: node + 4,
+                    weight: 3 + node % 2,
+                });
+                adjacency[node as usize + 4].push(Link { node, weight: 3 + node % 2 });
+            }
+        }
+        OwnedGraph::from_adjancecy_lists(adjacency)
+    }
+
+    #[test]
+    fn dispatch_matches_dijkstra() {
+        let graph = graph();
+        let order = NodeOrder::from_node_order(vec![5, 0, 3, 12, 15, 10, 1, 2, 4, 8, 7, 11, 13, 14, 6, 9]);
+        let cch = CCH::fix_order_and_build(&graph, order);
+        let customized = customize_perfect(customize(&cch, &graph));
+        let mut dijkstra = DijkServer::<_, DefaultOps>::new(graph);
+        let mut dist = |from, to| dijkstra.query(Query { from, to }).distance().unwrap();
+
+        let mut fleet = Fleet::new(&customized);
+        let a = fleet.add_vehicle(0);
+        let b = fleet.add_vehicle(15);
+        let c = fleet.add_vehicle(6);
+        fleet.move_vehicle(c, 9);
+        fleet.set_stops(b, vec![3, 12]);
+        fleet.remove_vehicle(a);
+        let d = fleet.add_vehicle(1);
+        fleet.insert_stop(d, 0, 14);
+
+        for node in 0..16 {
+            let mut expected: Vec<(Weight, VehicleId)> = [b, c, d].iter().map(|&v| (dist(fleet.position(v), node), v)).collect();
+            expected.sort_unstable();
+            assert_eq!(fleet.closest_vehicles(node, 2), expected[..2]);
+
+            let insertions = fleet.insertions(node, 3);
+            assert_eq!(insertions.len(), 3);
+            for insertion in insertions {
+                let mut route = vec![fleet.position(insertion.vehicle)];
+                route.extend_from_slice(fleet.stops(insertion.vehicle));
+                let length =
+                    |route: &[NodeId], dist: &mut dyn FnMut(NodeId, NodeId) -> Weight| route.windows(2).map(|leg| dist(leg[0], leg[1])).sum::<Weight>();
+                let before = length(&route, &mut dist);
+                let best = (1..=route.len())
+                    .map(|position| {
+                        let mut with_pickup = route.clone();
+                        with_pickup.insert(position, node);
+                        length(&with_pickup, &mut dist) - before
+                    })
+                    .min()
+                    .unwrap();
+                assert_eq!(insertion.detour, best);
+                assert_eq!(
+                    insertion.pickup_distance,
+                    length(&route[..=insertion.position], &mut dist) + dist(route[insertion.position], node)
+                );
+            }
+        }
+
+        fleet.arrive(b);
+        assert_eq!(fleet.position(b), 3);
+        assert_eq!(fleet.stops(b), &[12]);
+        assert_eq!(fleet.closest_vehicles(3, 1), vec![(0, b)]);
+    }
+}
@@ -0,0 +1,282 @@
+//! Query time route preferences: avoiding tolls, ferries or highways and penalizing road classes or single edges.
+//!
+//! Preferences only ever make edges more expensive, so distances of the unmodified metric are lower bounds for any preferences.
+//! Arbitrary preferences are answered exactly by A* on the modified metric, guided by a CCH potential of the unmodified metric.
+//! For common combinations of avoidance flags, dedicated CCH customizations can be added.
+//! Queries which only avoid exactly such a combination are answered by a plain CCH query.
+
+use super::*;
+use crate::{
+    algo::{
+        a_star::Potential,
+        ch_potentials::{BorrowedCCHPot, CCHPotData},
+        customizable_contraction_hierarchy::{query::Server as CCHServer, *},
+        dijkstra::{generic_dijkstra::*, *},
+    },
+    io::*,
+    report::*,
+};
+use std::{collections::HashMap, error::Error, fmt};
+
+/// Bitset of link attributes relevant for avoidance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct LinkFlags(pub u8);
+
+impl LinkFlags {
+    pub const NONE: LinkFlags = LinkFlags(0);
+    pub const TOLL: LinkFlags = LinkFlags(1);
+    pub const FERRY: LinkFlags = LinkFlags(1 << 1);
+    /// Controlled access roads, that is motorways and similar.
+    pub const HIGHWAY: LinkFlags = LinkFlags(1 << 2);
+
+    pub fn contains(self, other: LinkFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(self, other: LinkFlags) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl std::ops::BitOr for LinkFlags {
+    type Output = LinkFlags;
+
+    fn bitor(self, rhs: LinkFlags) -> LinkFlags {
+        LinkFlags(self.0 | rhs.0)
+    }
+}
+
+/// Per arc attributes as kept by the importers.
+/// Stored in the graph directory as `link_flags` (one `LinkFlags` byte per arc) and `functional_road_classes`.
+pub struct LinkAttributes {
+    flags: Vec<u8>,
+    road_classes: Vec<u8>,
+}
+
+impl LinkAttributes {
+    pub fn new(flags: Vec<u8>, road_classes: Vec<u8>) -> Self {
+        assert_eq!(flags.len(), road_classes.len());
+        Self { flags, road_classes }
+    }
+
+    pub fn num_arcs(&self) -> usize {
+        self.flags.len()
+    }
+
+    pub fn flags(&self, edge: EdgeId) -> LinkFlags {
+        LinkFlags(self.flags[edge as usize])
+    }
+
+    pub fn road_class(&self, edge: EdgeId) -> u8 {
+        self.road_classes[edge as usize]
+    }
+}
+
+impl Deconstruct for LinkAttributes {
+    fn save_each(&self, store: &dyn Fn(&str, &dyn Save) -> std::io::Result<()>) -> std::io::Result<()> {
+        store("link_flags", &self.flags)?;
+        store("functional_road_classes", &self.road_classes)?;
+        Ok(())
+    }
+}
+
+impl Reconstruct for LinkAttributes {
+    fn reconstruct_with(loader: Loader) -> std::io::Result<Self> {
+        let flags: Vec<u8> = loader.load("link_flags")?;
+        let road_classes: Vec<u8> = loader.load("functional_road_classes")?;
+        if flags.len() != road_classes.len() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "link_flags and functional_road_classes differ in length",
+            ));
+        }
+        Ok(Self { flags, road_classes })
+    }
+}
+
+/// Error for preferences which would make the unmodified metric an invalid lower bound.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutePreferencesErr(pub String);
+
+impl fmt::Display for RoutePreferencesErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid route preferences: {}", self.0)
+    }
+}
+
+impl Error for RoutePreferencesErr {}
+
+/// Preferences for a single query.
+#[derive(Debug, Clone, Default)]
+pub struct RoutePreferences {
+    /// Links with any of these flags are not used at all.
+    pub avoid: LinkFlags,
+    // Travel time multipliers indexed by road class, only set through `set_road_class_factors` which checks them.
+    road_class_factors: Vec<f64>,
+    /// Additional costs for single edges.
+    pub edge_penalties: HashMap<EdgeId, Weight>,
+}
+
+impl RoutePreferences {
+    pub fn avoiding(avoid: LinkFlags) -> Self {
+        Self { avoid, ..Default::default() }
+    }
+
+    /// Travel time multipliers indexed by road class.
+    pub fn road_class_factors(&self) -> &[f64] {
+        &self.road_class_factors
+    }
+
+    /// Set travel time multipliers indexed by road class, classes without an entry are not penalized.
+    /// Factors must be at least `1.0`, otherwise the unmodified metric is no lower bound anymore and A* may return suboptimal routes.
+    pub fn set_road_class_factors(&mut self, factors: Vec<f64>) -> Result<(), RoutePreferencesErr> {
+        if let Some((road_class, factor)) = factors.iter().enumerate().find(|(_, factor)| factor.is_nan() || **factor < 1.0) {
+            return Err(RoutePreferencesErr(format!("factor {} of road class {} is less than 1.0", factor, road_class)));
+        }
+        self.road_class_factors = factors;
+        Ok(())
+    }
+
+    // Only avoidance flags, so dedicated customizations can be used.
+    fn is_avoid_only(&self) -> bool {
+        self.road_class_factors.iter().all(|&factor| factor == 1.0) && self.edge_penalties.is_empty()
+    }
+
+    /// Modified weight of `edge` with unmodified weight `weight`.
+    pub fn apply(&self, attributes: &LinkAttributes, edge: EdgeId, weight: Weight) -> Weight {
+        if attributes.flags(edge).intersects(self.avoid) || weight >= INFINITY {
+            return INFINITY;
+        }
+        let factor = self.road_class_factors.get(attributes.road_class(edge) as usize).copied().unwrap_or(1.0);
+        let penalty = self.edge_penalties.get(&edge).copied().unwrap_or(0);
+        std::cmp::min((f64::from(weight) * factor).round() as u64 + u64::from(penalty), u64::from(INFINITY)) as Weight
+    }
+
+    /// The complete modified metric, for example for a dedicated customization.
+    pub fn metric(&self, attributes: &LinkAttributes, weights: &[Weight]) -> Vec<Weight> {
+        assert_eq!(attributes.num_arcs(), weights.len());
+        weights
+            .iter()
+            .enumerate()
+            .map(|(edge, &weight)| self.apply(attributes, edge as EdgeId, weight))
+            .collect()
+    }
+}
+
+/// Dijkstra ops which apply the preferences while relaxing arcs.
+pub struct PreferenceOps<'p> {
+    attributes: &'p LinkAttributes,
+    preferences: &'p RoutePreferences,
+}
+
+impl<G> DijkstraOps<G> for PreferenceOps<'_> {
+    type Label = Weight;
+    type Arc = (NodeIdT, Weight, EdgeIdT);
+    type LinkResult = Weight;
+    type PredecessorLink = EdgeIdT;
+
+    #[inline(always)]
+    fn link(
+        &mut self,
+        _graph: &G,
+        _parents: &[(NodeId, Self::PredecessorLink)],
+        _tail: NodeIdT,
+        label: &Weight,
+        &(_, weight, EdgeIdT(edge)): &Self::Arc,
+    ) -> Weight {
+        label + self.preferences.apply(self.attributes, edge, weight)
+    }
+
+    #[inline(always)]
+    fn merge(&mut self, label: &mut Weight, linked: Weight) -> bool {
+        if linked < *label {
+            *label = linked;
+            return true;
+        }
+        false
+    }
+
+    #[inline(always)]
+    fn predecessor_link(&self, &(_, _, edge_id): &Self::Arc) -> Self::PredecessorLink {
+        edge_id
+    }
+}
+
+/// Query server for shortest paths with preferences.
+pub struct Server<'a> {
+    graph: OwnedGraph,
+    attributes: LinkAttributes,
+    cch: &'a CCH,
+    potential: BorrowedCCHPot<'a>,
+    dijkstra_data: DijkstraData<Weight, EdgeIdT>,
+    dedicated: Vec<(LinkFlags, CCHServer<CustomizedBasic<'a, CCH>>)>,
+}
+
+impl<'a> Server<'a> {
+    /// `pot_data` has to be customized with the weights of `graph`.
+    pub fn new(graph: OwnedGraph, attributes: LinkAttributes, pot_data: &'a CCHPotData<'a>) -> Self {
+        assert_eq!(graph.num_arcs(), attributes.num_arcs());
+        Self {
+            dijkstra_data: DijkstraData::new(graph.num_nodes()),
+            cch: pot_data.customized().cch(),
+            potential: pot_data.forward_potential(),
+            graph,
+            attributes,
+            dedicated: Vec::new(),
+        }
+    }
+
+    /// The graph with the unmodified weights.
+    pub fn graph(&self) -> &OwnedGraph {
+        &self.graph
+    }
+
+    pub fn attributes(&self) -> &LinkAttributes {
+        &self.attributes
+    }
+
+    /// Customize the CCH for queries avoiding exactly `avoid`.
+    pub fn add_dedicated_customization(&mut self, avoid: LinkFlags) {
+        if self.dedicated.iter().any(|(flags, _)| *flags == avoid) {
+            return;
+        }
+        let metric = RoutePreferences::avoiding(avoid).metric(&self.attributes, self.graph.weight());
+        let customized = customize(self.cch, &FirstOutGraph::new(self.graph.first_out(), self.graph.head(), &metric[..]));
+        self.dedicated.push((avoid, CCHServer::new(customized)));
+    }
+
+    /// Distance and node path with the given preferences.
+    pub fn query(&mut self, query: Query, preferences: &RoutePreferences) -> Option<(Weight, Vec<NodeId>)> {
+        if preferences.is_avoid_only() {
+            if let Some((_, server)) = self.dedicated.iter_mut().find(|(flags, _)| *flags == preferences.avoid) {
+                report!("algo", "Dedicated CCH Query");
+                let mut result = server.query(query);
+                let distance = result.distance()?;
+                return Some((distance, result.node_path().unwrap()));
+            }
+        }
+
+        report!("algo", "CCH Potentials Preferences Query");
+        let mut ops = PreferenceOps {
+            attributes: &self.attributes,
+            preferences,
+        };
+        let mut dijkstra = DijkstraRun::query(&self.graph, &mut self.dijkstra_data, &mut ops, DijkstraInit::from(query.from));
+        self.potential.init(query.to);
+        let potential = &mut self.potential;
+
+        let mut num_queue_pops: usize = 0;
+        let mut result = None;
+        while let Some(node) = dijkstra.next_step_with_potential(|node| potential.potential(node)) {
+            num_queue_pops += 1;
+            if node == query.to {
+                result = Some(*dijkstra.tentative_distance(node));
+                break;
+            }
+        }
+        report!("num_queue_pops", num_queue_pops);
+        report!("num_relaxed_arcs", dijkstra.num_relaxed_arcs());
+
+        result.map(|distance| (distance, self.dijkstra_data.node_path(query.from, query.to)))
+    }
+}
@@ -0,0 +1,489 @@
+//! A uniform interface over static and time-dependent query servers.
+//!
+//! Services which accept queries from outside should not need to know which algorithm answers them.
+//! `Router` wraps a `QueryServer` or `TDQueryServer<Timestamp, Weight>` together with the graph its paths refer to.
+//! Routes are always evaluated on that graph, so travel times and edges are consistent across algorithms,
+//! no matter if the server reports distances, travel times or arrival times.
+
+use super::{path_statistics::*, *};
+use crate::{
+    algo::{
+        customizable_contraction_hierarchy::query::nearest_neighbor::BCCHNearestNeighbor,
+        route_preferences::{self, LinkFlags, RoutePreferences},
+        time_dependent_contraction_hierarchy::TDMetric,
+        vrp::DistanceTable,
+    },
+    datastr::graph::time_dependent::Timestamp,
+};
+use std::ops::Deref;
+
+/// Node of a path as returned by a `PathServer`.
+pub trait PathNode {
+    fn node(&self) -> NodeId;
+}
+
+impl PathNode for NodeId {
+    fn node(&self) -> NodeId {
+        *self
+    }
+}
+
+impl PathNode for (NodeId, Timestamp) {
+    fn node(&self) -> NodeId {
+        self.0
+    }
+}
+
+/// Node path of the shortest path of a query result.
+pub fn node_ids<P: PathServer, W: Copy>(result: QueryResult<P, W>) -> Option<Vec<NodeId>>
+where
+    P::NodeInfo: PathNode,
+{
+    let mut result = result.found()?;
+    Some(result.node_path().iter().map(PathNode::node).collect())
+}
+
+/// Query servers usable by a `StaticRouter`.
+///
+/// A blanket implementation for all `QueryServer`s would have to bound `for<'s> <S::P<'s> as PathServer>::NodeInfo: PathNode`,
+/// which only holds for `S: 'static` and thus rules out all servers borrowing their preprocessing.
+/// So each server implements this separately, usually just as `node_ids(self.query(query))`.
+pub trait NodePathServer {
+    fn node_path_query(&mut self, query: Query) -> Option<Vec<NodeId>>;
+
+    /// Size of the search space of the last query, `None` if the server does not keep track of it.
+    fn search_space(&self) -> Option<SearchSpace> {
+        None
+    }
+}
+
+/// Time-dependent query servers usable by a `TDRouter`, see `NodePathServer`.
+pub trait TDNodePathServer {
+    fn node_path_td_query(&mut self, query: TDQuery<Timestamp>) -> Option<Vec<NodeId>>;
+}
+
+impl<C: customizable_contraction_hierarchy::Customized> NodePathServer for customizable_contraction_hierarchy::query::Server<C> {
+    fn node_path_query(&mut self, query: Query) -> Option<Vec<NodeId>> {
+        node_ids(self.query(query))
+    }
+
+    fn search_space(&self) -> Option<SearchSpace> {
+        Some(SearchSpace {
+            nodes: self.num_nodes_in_searchspace(),
+            relaxed_edges: self.num_relaxed_edges(),
+        })
+    }
+}
+
+impl NodePathServer for contraction_hierarchy::query::Server {
+    fn node_path_query(&mut self, query: Query) -> Option<Vec<NodeId>> {
+        node_ids(self.query(query))
+    }
+}
+
+impl<G, O, P, B> NodePathServer for dijkstra::query::dijkstra::Server<G, O, P, B>
+where
+    G: LinkIterable<O::Arc>,
+    O: dijkstra::DijkstraOps<G, Label = Weight> + Default,
+    P: a_star::Potential,
+    B: std::borrow::Borrow<G>,
+{
+    fn node_path_query(&mut self, query: Query) -> Option<Vec<NodeId>> {
+        node_ids(self.query(query))
+    }
+}
+
+impl<G, O, P, B> TDNodePathServer for dijkstra::query::dijkstra::Server<G, O, P, B>
+where
+    G: LinkIterable<O::Arc>,
+    O: dijkstra::DijkstraOps<G, Label = Weight> + Default,
+    P: a_star::Potential,
+    B: std::borrow::Borrow<G>,
+{
+    fn node_path_td_query(&mut self, query: TDQuery<Timestamp>) -> Option<Vec<NodeId>> {
+        node_ids(self.td_query(query))
+    }
+}
+
+impl<'a, M: TDMetric> TDNodePathServer for time_dependent_contraction_hierarchy::query::Server<'a, M> {
+    fn node_path_td_query(&mut self, query: TDQuery<Timestamp>) -> Option<Vec<NodeId>> {
+        node_ids(self.td_query(query))
+    }
+}
+
+/// Nodes and edges touched by a query.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchSpace {
+    pub nodes: usize,
+    pub relaxed_edges: usize,
+}
+
+impl std::ops::Add for SearchSpace {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            nodes: self.nodes + other.nodes,
+            relaxed_edges: self.relaxed_edges + other.relaxed_edges,
+        }
+    }
+}
+
+/// A route through a sequence of nodes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Route {
+    pub departure: Timestamp,
+    pub travel_time: Weight,
+    pub node_path: Vec<NodeId>,
+    /// Edges with their entry times and travel times, see `PathStatistics`.
+    pub statistics: PathStatistics,
+    /// Search space of the queries for this route, `None` if the server does not keep track of it.
+    pub search_space: Option<SearchSpace>,
+}
+
+impl Route {
+    pub fn arrival(&self) -> Timestamp {
+        self.departure + self.travel_time
+    }
+}
+
+pub trait Router {
+    /// Whether the travel times depend on the departure.
+    fn is_time_dependent(&self) -> bool;
+    /// Shortest route from `from` to `to` when leaving at `departure`, `None` if `to` is unreachable.
+    fn route(&mut self, from: NodeId, to: NodeId, departure: Timestamp) -> Option<Route>;
+    /// Travel time of `edge` when entering it at `entry`, on the same graph routes are evaluated on.
+    fn edge_weight(&self, edge: EdgeId, entry: Timestamp) -> Weight;
+
+    /// Avoid links with any of the flags in `avoid` in all following queries.
+    /// Returns `false` and leaves the router unchanged if it does not support avoidance.
+    fn set_avoid(&mut self, avoid: LinkFlags) -> bool {
+        avoid == LinkFlags::NONE
+    }
+
+    /// Shortest route visiting all `waypoints` in order, each leg starting when the previous one ends.
+    fn route_via(&mut self, waypoints: &[NodeId], departure: Timestamp) -> Option<Route> {
+        assert!(!waypoints.is_empty());
+        let mut route = Route {
+            departure,
+            travel_time: 0,
+            node_path: vec![waypoints[0]],
+            statistics: PathStatistics::new(0),
+            search_space: Some(SearchSpace::default()),
+        };
+        for leg in waypoints.windows(2) {
+            let mut leg = self.route(leg[0], leg[1], route.arrival())?;
+            route.travel_time += leg.travel_time;
+            route.node_path.extend_from_slice(&leg.node_path[1..]);
+            route.statistics.append(&mut leg.statistics);
+            route.search_space = route.search_space.zip(leg.search_space).map(|(route, leg)| route + leg);
+        }
+        Some(route)
+    }
+
+    /// Travel times from `from_nodes[i]` to `to_nodes[j]` for all pairs of indices, `INFINITY` if unreachable and `0` on the diagonal.
+    /// All queries depart at `departure`. Separate sources and targets allow waypoints which are entered and left at different nodes.
+    /// By default one query per pair, see `BucketTableRouter` for a faster way on static CCHs.
+    fn distance_table(&mut self, from_nodes: &[NodeId], to_nodes: &[NodeId], departure: Timestamp) -> DistanceTable {
+        assert_eq!(from_nodes.len(), to_nodes.len());
+        let n = from_nodes.len();
+        let mut distances = vec![0; n * n];
+        for (from_idx, &from) in from_nodes.iter().enumerate() {
+            for (to_idx, &to) in to_nodes.iter().enumerate() {
+                if from_idx != to_idx {
+                    distances[from_idx * n + to_idx] = self.route(from, to, departure).map(|route| route.travel_time).unwrap_or(INFINITY);
+                }
+            }
+        }
+        DistanceTable::from_matrix(n, distances)
+    }
+}
+
+/// `Router` for a `QueryServer`, `graph` has to be the graph the server was built for.
+pub struct StaticRouter<'g, S, G> {
+    server: S,
+    graph: &'g G,
+}
+
+impl<'g, S, G> StaticRouter<'g, S, G> {
+    pub fn new(server: S, graph: &'g G) -> Self {
+        Self { server, graph }
+    }
+
+    pub fn server(&mut self) -> &mut S {
+        &mut self.server
+    }
+}
+
+impl<'g, S, G> Router for StaticRouter<'g, S, G>
+where
+    S: NodePathServer,
+    G: EdgeIdGraph + EdgeRandomAccessGraph<Link>,
+{
+    fn is_time_dependent(&self) -> bool {
+        false
+    }
+
+    fn route(&mut self, from: NodeId, to: NodeId, departure: Timestamp) -> Option<Route> {
+        let node_path = self.server.node_path_query(Query { from, to })?;
+        let statistics = PathStatistics::evaluate(self.graph, departure, &node_path, &[]);
+        Some(Route {
+            departure,
+            travel_time: statistics.total_weight() as Weight,
+            node_path,
+            statistics,
+            search_space: self.server.search_space(),
+        })
+    }
+
+    fn edge_weight(&self, edge: EdgeId, _entry: Timestamp) -> Weight {
+        self.graph.link(edge).weight
+    }
+}
+
+/// `Router` for a `TDQueryServer`, `graph` has to be the graph the server was built for.
+/// `graph` may be a reference or a shared pointer like `Rc`, the latter allows replacing graph and server together.
+pub struct TDRouter<S, G> {
+    server: S,
+    graph: G,
+}
+
+impl<S, G> TDRouter<S, G> {
+    pub fn new(server: S, graph: G) -> Self {
+        Self { server, graph }
+    }
+
+    pub fn server(&mut self) -> &mut S {
+        &mut self.server
+    }
+}
+
+impl<S, G> Router for TDRouter<S, G>
+where
+    S: TDNodePathServer,
+    G: Deref,
+    G::Target: TDMetric + Sized,
+{
+    fn is_time_dependent(&self) -> bool {
+        true
+    }
+
+    fn route(&mut self, from: NodeId, to: NodeId, departure: Timestamp) -> Option<Route> {
+        let node_path = self.server.node_path_td_query(TDQuery { from, to, departure })?;
+        let statistics = PathStatistics::evaluate_td(&*self.graph, departure, &node_path, &[]);
+        Some(Route {
+            departure,
+            travel_time: statistics.total_weight() as Weight,
+            node_path,
+            statistics,
+            search_space: None,
+        })
+    }
+
+    fn edge_weight(&self, edge: EdgeId, entry: Timestamp) -> Weight {
+        self.graph.eval(edge, entry)
+    }
+}
+
+/// `Router` with `RoutePreferences`, the route statistics contain the unmodified weights.
+pub struct PreferenceRouter<'a> {
+    server: route_preferences::Server<'a>,
+    preferences: RoutePreferences,
+}
+
+impl<'a> PreferenceRouter<'a> {
+    pub fn new(server: route_preferences::Server<'a>, preferences: RoutePreferences) -> Self {
+        Self { server, preferences }
+    }
+
+    pub fn preferences(&mut self) -> &mut RoutePreferences {
+        &mut self.preferences
+    }
+}
+
+impl<'a> Router for PreferenceRouter<'a> {
+    fn is_time_dependent(&self) -> bool {
+        false
+    }
+
+    fn set_avoid(&mut self, avoid: LinkFlags) -> bool {
+        self.preferences.avoid = avoid;
+        true
+    }
+
+    fn route(&mut self, from: NodeId, to: NodeId, departure: Timestamp) -> Option<Route> {
+        let (_, node_path) = self.server.query(Query { from, to }, &self.preferences)?;
+        let (attributes, preferences) = (self.server.attributes(), &self.preferences);
+        let statistics = PathStatistics::evaluate_with(
+            self.server.graph(),
+            departure,
+            &node_path,
+            |edge, weight| preferences.apply(attributes, edge, weight),
+            &[],
+        );
+        Some(Route {
+            departure,
+            travel_time: statistics.total_weight() as Weight,
+            node_path,
+            statistics,
+            search_space: None,
+        })
+    }
+
+    fn edge_weight(&self, edge: EdgeId, _entry: Timestamp) -> Weight {
+        self.server.graph().link(edge).weight
+    }
+}
+
+/// Wraps a static `Router` and answers distance tables with bucket many-to-many queries instead of one query per pair.
+/// `nn` has to be based on a customization with the same weights as the graph of `router`.
+pub struct BucketTableRouter<'a, R> {
+    router: R,
+    nn: BCCHNearestNeighbor<'a>,
+}
+
+impl<'a, R: Router> BucketTableRouter<'a, R> {
+    pub fn new(router: R, nn: BCCHNearestNeighbor<'a>) -> Self {
+        assert!(!router.is_time_dependent());
+        Self { router, nn }
+    }
+}
+
+impl<'a, R: Router> Router for BucketTableRouter<'a, R> {
+    fn is_time_dependent(&self) -> bool {
+        false
+    }
+
+    fn route(&mut self, from: NodeId, to: NodeId, departure: Timestamp) -> Option<Route> {
+        self.router.route(from, to, departure)
+    }
+
+    fn edge_weight(&self, edge: EdgeId, entry: Timestamp) -> Weight {
+        self.router.edge_weight(edge, entry)
+    }
+
+    fn set_avoid(&mut self, avoid: LinkFlags) -> bool {
+        // the buckets know nothing about link flags
+        avoid == LinkFlags::NONE && self.router.set_avoid(avoid)
+    }
+
+    fn distance_table(&mut self, from_nodes: &[NodeId], to_nodes: &[NodeId], _departure: Timestamp) -> DistanceTable {
+        let table = DistanceTable::between(&mut self.nn, from_nodes, to_nodes);
+        let n = table.num_locations();
+        let distances = (0..n * n).map(|idx| if idx / n == idx % n { 0 } else { table.get(idx / n, idx % n) }).collect();
+        DistanceTable::from_matrix(n, distances)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        algo::{
+            ch_potentials::CCHPotData,
+            customizable_contraction_hierarchy::{query::Server as CCHServer, *},
+            dijkstra::{
+                query::{dijkstra::Server as DijkServer, td_dijkstra::TDDijkstraOps},
+                *,
+            },
+            route_preferences::LinkAttributes,
+            time_dependent_contraction_hierarchy::{self, query::Server as TCHServer},
+        },
+        datastr::{graph::time_dependent::*, node_order::NodeOrder},
+    };
+
+    #[test]
+    fn static_routers_agree() {
+        // a ring 0 -> 1 -> 2 -> 3 -> 0 with a parallel edge and a shortcut 0 -> 2
+        let graph = OwnedGraph::new(vec![0, 3, 4, 5, 6], vec![1, 1, 2, 2, 3, 0], vec![4, 2, 7, 3, 1, 5]);
+        let cch = CCH::fix_order_and_build(&graph, NodeOrder::from_node_order(vec![1, 3, 0, 2]));
+        let mut routers: Vec<Box<dyn Router + '_>> = vec![
+            Box::new(StaticRouter::new(CCHServer::new(customize(&cch, &graph)), &graph)),
+            Box::new(StaticRouter::new(DijkServer::<_, DefaultOps>::new(graph.clone()), &graph)),
+        ];
+        for router in &mut routers {
+            assert!(!router.is_time_dependent());
+            let route = router.route_via(&[0, 3, 1], 10).unwrap();
+            assert_eq!(route.node_path, vec![0, 1, 2, 3, 0, 1]);
+            assert_eq!(route.statistics.edges, vec![1, 3, 4, 5, 1]);
+            assert_eq!(route.statistics.entry_times, vec![10, 12, 15, 16, 21]);
+            assert_eq!(route.travel_time, 13);
+            assert_eq!(route.arrival(), 23);
+            assert_eq!(router.edge_weight(2, 0), 7);
+        }
+        assert!(routers[0].route(0, 2, 0).unwrap().search_space.unwrap().nodes > 0);
+        assert_eq!(routers[1].route(0, 2, 0).unwrap().search_space, None);
+    }
+
+    #[test]
+    fn bucket_table_matches_queries() {
+        let graph = OwnedGraph::new(vec![0, 3, 4, 5, 6], vec![1, 1, 2, 2, 3, 0], vec![4, 2, 7, 3, 1, 5]);
+        let cch = CCH::fix_order_and_build(&graph, NodeOrder::from_node_order(vec![1, 3, 0, 2]));
+        let customized = customize_perfect(customize(&cch, &graph));
+        let mut router = StaticRouter::new(DijkServer::<_, DefaultOps>::new(graph.clone()), &graph);
+        let mut bucket_router = BucketTableRouter::new(
+            StaticRouter::new(DijkServer::<_, DefaultOps>::new(graph.clone()), &graph),
+            BCCHNearestNeighbor::new(&customized),
+        );
+        let (from_nodes, to_nodes) = ([0, 2, 3, 0], [1, 2, 3, 2]);
+        assert_eq!(
+            bucket_router.distance_table(&from_nodes, &to_nodes, 0),
+            router.distance_table(&from_nodes, &to_nodes, 0)
+        );
+        assert_eq!(bucket_router.distance_table(&from_nodes, &to_nodes, 0).get(0, 1), 5);
+        assert!(!bucket_router.set_avoid(LinkFlags::TOLL));
+    }
+
+    #[test]
+    fn avoiding_tolled_parallel_edge() {
+        // like above, but the direct edge 0 -> 2 is slower than both paths over 1
+        let graph = OwnedGraph::new(vec![0, 3, 4, 5, 6], vec![1, 1, 2, 2, 3, 0], vec![4, 2, 8, 3, 1, 5]);
+        let attributes = LinkAttributes::new(vec![0, LinkFlags::TOLL.0, 0, 0, 0, 0], vec![0; 6]);
+        let cch = CCH::fix_order_and_build(&graph, NodeOrder::from_node_order(vec![1, 3, 0, 2]));
+        let pot_data = CCHPotData::new(&cch, &graph);
+        let mut router = PreferenceRouter::new(
+            route_preferences::Server::new(graph.clone(), attributes, &pot_data),
+            RoutePreferences::default(),
+        );
+        assert_eq!(router.route(0, 2, 0).unwrap().statistics.edges, vec![1, 3]);
+        assert!(router.set_avoid(LinkFlags::TOLL));
+        let route = router.route(0, 2, 0).unwrap();
+        assert_eq!(route.statistics.edges, vec![0, 3]);
+        assert_eq!(route.travel_time, 7);
+
+        let mut static_router = StaticRouter::new(DijkServer::<_, DefaultOps>::new(graph.clone()), &graph);
+        assert!(!static_router.set_avoid(LinkFlags::TOLL));
+        assert!(static_router.set_avoid(LinkFlags::NONE));
+    }
+
+    #[test]
+    fn td_routers_agree() {
+        run_test_with_periodicity(1000, || {
+            // 0 -> 1 -> 2 or directly 0 -> 2, which is only fast early
+            let graph = TDGraph::new(
+                vec![0, 2, 3, 3],
+                vec![1, 2, 2],
+                vec![0, 1, 4, 5],
+                vec![0, 0, 100, 500, 0],
+                vec![10, 5, 100, 5, 10],
+            );
+            let tch = time_dependent_contraction_hierarchy::contract(&graph, NodeOrder::from_node_order(vec![1, 0, 2]));
+            let mut routers: Vec<Box<dyn Router + '_>> = vec![
+                Box::new(TDRouter::new(TCHServer::new(&tch, &graph), &graph)),
+                Box::new(TDRouter::new(DijkServer::<_, TDDijkstraOps>::new(graph.clone()), &graph)),
+            ];
+            for router in &mut routers {
+                assert!(router.is_time_dependent());
+                let early = router.route(0, 2, 0).unwrap();
+                assert_eq!(early.node_path, vec![0, 2]);
+                assert_eq!(early.travel_time, 5);
+                let late = router.route(0, 2, 100).unwrap();
+                assert_eq!(late.node_path, vec![0, 1, 2]);
+                assert_eq!(late.statistics.entry_times, vec![100, 110]);
+                assert_eq!(late.arrival(), 120);
+                assert_eq!(router.edge_weight(1, 0), 5);
+                assert_eq!(router.edge_weight(1, 100), 100);
+            }
+        });
+    }
+}
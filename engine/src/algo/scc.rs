@@ -0,0 +1,174 @@
+//! Strongly connected components with Tarjan's algorithm.
+//!
+//! Imported road networks usually contain small islands, e.g. parking lots only connected through filtered private roads.
+//! Queries between different components have no result, so benchmarks and services should restrict themselves to the largest component.
+
+use super::*;
+use crate::datastr::rank_select_map::BitVec;
+use crate::report::*;
+use std::cmp::{min, Reverse};
+
+/// The strongly connected components of a graph.
+/// Components are numbered in reverse topological order of the condensed graph, i.e. no arc leads from a component to one with a larger id.
+#[derive(Debug, Clone)]
+pub struct StronglyConnectedComponents {
+    component: Vec<u32>,
+    sizes: Vec<usize>,
+}
+
+const UNVISITED: u32 = u32::MAX;
+
+/// Calculate the strongly connected components of `graph`.
+/// The DFS is iterative, so long paths in road networks do not overflow the stack.
+pub fn strongly_connected_components<G: LinkIterable<NodeIdT>>(graph: &G) -> StronglyConnectedComponents {
+    let n = graph.num_nodes();
+    let mut dfs_num = vec![UNVISITED; n];
+    let mut dfs_low = vec![0; n];
+    let mut on_stack = BitVec::new(n);
+    let mut component_stack = Vec::new();
+    let mut dfs_stack = Vec::new();
+    let mut dfs_num_counter = 0;
+
+    let mut component = vec![UNVISITED; n];
+    let mut sizes = Vec::new();
+
+    for root in 0..n {
+        if dfs_num[root] != UNVISITED {
+            continue;
+        }
+        dfs_num[root] = dfs_num_counter;
+        dfs_low[root] = dfs_num_counter;
+        dfs_num_counter += 1;
+        component_stack.push(root);
+        on_stack.set(root);
+        dfs_stack.push((root, graph.link_iter(root as NodeId)));
+
+        while let Some((node, neighbors)) = dfs_stack.last_mut() {
+            let node = *node;
+            match neighbors.next() {
+                Some(NodeIdT(neighbor)) => {
+                    let neighbor = neighbor as usize;
+                    if dfs_num[neighbor] == UNVISITED {
+                        dfs_num[neighbor] = dfs_num_counter;
+                        dfs_low[neighbor] = dfs_num_counter;
+                        dfs_num_counter += 1;
+                        component_stack.push(neighbor);
+                        on_stack.set(neighbor);
+                        dfs_stack.push((neighbor, graph.link_iter(neighbor as NodeId)));
+                    } else if on_stack.get(neighbor) {
+                        dfs_low[node] = min(dfs_low[node], dfs_num[neighbor]);
+                    }
+                }
+                None => {
+                    dfs_stack.pop();
+                    if let Some(&(parent, _)) = dfs_stack.last() {
+                        dfs_low[parent] = min(dfs_low[parent], dfs_low[node]);
+                    }
+                    if dfs_low[node] == dfs_num[node] {
+                        let id = sizes.len() as u32;
+                        let mut size = 0;
+                        while let Some(member) = component_stack.pop() {
+                            on_stack.unset(member);
+                            component[member] = id;
+                            size += 1;
+                            if member == node {
+                                break;
+                            }
+                        }
+                        sizes.push(size);
+                    }
+                }
+            }
+        }
+    }
+
+    StronglyConnectedComponents { component, sizes }
+}
+
+impl StronglyConnectedComponents {
+    pub fn num_components(&self) -> usize {
+        self.sizes.len()
+    }
+
+    /// The id of the component of `node`.
+    pub fn component(&self, node: NodeId) -> u32 {
+        self.component[node as usize]
+    }
+
+    pub fn size(&self, component: u32) -> usize {
+        self.sizes[component as usize]
+    }
+
+    /// The id of the component with the most nodes, the first one for ties. `None` for empty graphs.
+    pub fn largest(&self) -> Option<u32> {
+        self.sizes
+            .iter()
+            .enumerate()
+            .max_by_key(|&(id, &size)| (size, Reverse(id)))
+            .map(|(id, _)| id as u32)
+    }
+
+    /// A mask with the nodes of the largest component set.
+    pub fn largest_mask(&self) -> BitVec {
+        let mut mask = BitVec::new(self.component.len());
+        if let Some(largest) = self.largest() {
+            for (node, &component) in self.component.iter().enumerate() {
+                if component == largest {
+                    mask.set(node);
+                }
+            }
+        }
+        mask
+    }
+
+    /// Component sizes, largest first.
+    pub fn sorted_sizes(&self) -> Vec<usize> {
+        let mut sizes = self.sizes.clone();
+        sizes.sort_unstable_by(|a, b| b.cmp(a));
+        sizes
+    }
+
+    /// Report the number and sizes of the components.
+    pub fn report_connectivity(&self) {
+        let n = self.component.len();
+        let sizes = self.sorted_sizes();
+        let largest = sizes.first().copied().unwrap_or(0);
+        report!("num_nodes", n);
+        report!("num_components", sizes.len());
+        report!("largest_component_size", largest);
+        report!("share_in_largest_component", if n > 0 { largest as f64 / n as f64 } else { 0.0 });
+        report!("num_single_node_components", sizes.iter().filter(|&&size| size == 1).count());
+        report!("largest_component_sizes", &sizes[..min(sizes.len(), 10)]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn components() {
+        // 0 <-> 1 <-> 2 is a cycle, 3 -> 0 can not be reached back, 4 -> 5 -> 4 is an island, 6 is isolated
+        let graph = UnweightedOwnedGraph::from_adjancecy_lists(vec![vec![1], vec![0, 2], vec![1], vec![0], vec![5], vec![4], vec![]]);
+        let components = strongly_connected_components(&graph);
+        assert_eq!(components.num_components(), 4);
+        assert_eq!(components.component(0), components.component(2));
+        assert_ne!(components.component(0), components.component(3));
+        assert_ne!(components.component(4), components.component(0));
+        // reverse topological order
+        assert!(components.component(3) > components.component(0));
+        assert_eq!(components.largest(), Some(components.component(1)));
+        assert_eq!(components.sorted_sizes(), vec![3, 2, 1, 1]);
+        let mask = components.largest_mask();
+        assert_eq!((0..7).filter(|&node| mask.get(node)).collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn long_path_does_not_overflow() {
+        let n = 1_000_000;
+        let graph = UnweightedOwnedGraph::from_adjancecy_lists((0..n).map(|node| vec![((node + 1) % n) as NodeId]).collect());
+        let components = strongly_connected_components(&graph);
+        assert_eq!(components.num_components(), 1);
+        assert_eq!(components.size(0), n);
+    }
+}
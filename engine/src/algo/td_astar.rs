@@ -207,11 +207,12 @@ impl<'a> MultiMetricPreprocessed<'a> {
         let mut upper_bound_customized = customize(cch, &BorrowedGraph::new(graph.first_out(), graph.head(), &upper_bound));
         let modified = customization::customize_perfect_without_rebuild(&mut upper_bound_customized);
 
-        let (fw_metrics, bw_metrics): (Vec<_>, Vec<_>) = metrics
-            .iter()
-            .flat_map(|m| {
-                let (fw, bw) = customize(cch, &BorrowedGraph::new(graph.first_out(), graph.head(), m)).into_weights();
-                fw.into_iter().zip(bw.into_iter())
+        let refs: Box<[_]> = metrics.iter().map(|m| &m[..]).collect();
+        let (fw_metrics, bw_metrics): (Vec<_>, Vec<_>) = customize_batched(cch, &refs)
+            .into_iter()
+            .flat_map(|customized| {
+                let (fw, bw) = customized.into_weights();
+                fw.into_iter().zip(bw)
             })
             .unzip();
 
@@ -0,0 +1,495 @@
+//! Time-dependent Contraction Hierarchies (TCH) for graphs with integer travel time functions.
+//!
+//! Nodes are contracted in a precalculated order, just like in the static CH.
+//! During contraction, each shortcut keeps an approximated travel time profile, obtained by linking and merging the integer PLFs.
+//! Witness searches and the decision which sources of a shortcut can be dropped only use global lower and upper bounds though.
+//! These are always valid, regardless of approximation and rounding errors in the profiles.
+//! The profiles only determine which source is tried first when evaluating a single shortcut.
+//! Since the query unpacks all sources which may be optimal down to the original edges,
+//! the results are exact with respect to TD-Dijkstra on the original graph (given that the travel time functions are FIFO).
+//!
+//! The preprocessing works with all integer TD graph types through the `TDMetric` trait,
+//! so live traffic (`LiveTDGraph`, `PessimisticLiveTDGraph`) can be used directly.
+
+use super::*;
+use crate::algo::dijkstra::*;
+use crate::datastr::graph::time_dependent::*;
+use crate::report::*;
+use std::cmp::min;
+
+pub mod query;
+
+/// Maximum deviation of approximated shortcut profiles in ms.
+pub const APPROX: Weight = 1000;
+/// Profiles with more interpolation points than this will be approximated.
+pub const APPROX_THRESHOLD: usize = 64;
+
+/// Integer time-dependent graphs which can be used for TCH preprocessing and queries.
+pub trait TDMetric: Graph + LinkIterable<(NodeIdT, EdgeIdT)> {
+    /// Evaluate the travel time of an edge for a given departure time.
+    fn eval(&self, edge_id: EdgeId, t: Timestamp) -> Weight;
+    /// Lower bound of the travel time of an edge for all points in time.
+    fn lower_bound(&self, edge_id: EdgeId) -> Weight;
+    /// Upper bound of the travel time of an edge for all points in time.
+    fn upper_bound(&self, edge_id: EdgeId) -> Weight;
+    /// The travel time profile used for shortcut profiles.
+    /// Only used as a heuristic, so this does not have to be exact.
+    fn profile(&self, edge_id: EdgeId) -> PiecewiseLinearFunction<'_>;
+}
+
+impl TDMetric for TDGraph {
+    fn eval(&self, edge_id: EdgeId, t: Timestamp) -> Weight {
+        self.travel_time_function(edge_id).eval(t)
+    }
+    fn lower_bound(&self, edge_id: EdgeId) -> Weight {
+        self.travel_time_function(edge_id).lower_bound()
+    }
+    fn upper_bound(&self, edge_id: EdgeId) -> Weight {
+        self.travel_time_function(edge_id).upper_bound()
+    }
+    fn profile(&self, edge_id: EdgeId) -> PiecewiseLinearFunction<'_> {
+        self.travel_time_function(edge_id)
+    }
+}
+
+impl TDMetric for LiveTDGraph {
+    fn eval(&self, edge_id: EdgeId, t: Timestamp) -> Weight {
+        LiveTDGraph::eval(self, edge_id, t)
+    }
+    fn lower_bound(&self, edge_id: EdgeId) -> Weight {
+        self.global_lower_bound(edge_id)
+    }
+    fn upper_bound(&self, edge_id: EdgeId) -> Weight {
+        self.global_upper_bound(edge_id)
+    }
+    fn profile(&self, edge_id: EdgeId) -> PiecewiseLinearFunction<'_> {
+        self.graph().travel_time_function(edge_id)
+    }
+}
+
+impl TDMetric for PessimisticLiveTDGraph {
+    fn eval(&self, edge_id: EdgeId, t: Timestamp) -> Weight {
+        PessimisticLiveTDGraph::eval(self, edge_id, t)
+    }
+    fn lower_bound(&self, edge_id: EdgeId) -> Weight {
+        // live values only ever make things slower
+        self.graph().travel_time_function(edge_id).lower_bound()
+    }
+    fn upper_bound(&self, edge_id: EdgeId) -> Weight {
+        self.global_upper_bound(edge_id)
+    }
+    fn profile(&self, edge_id: EdgeId) -> PiecewiseLinearFunction<'_> {
+        self.graph().travel_time_function(edge_id)
+    }
+}
+
+/// Where the travel time of a TCH arc comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShortcutSource {
+    /// An edge of the original graph, by original edge id.
+    OriginalEdge(EdgeId),
+    /// A lower triangle, consisting of the id of the downward arc to the middle node (in the backward graph)
+    /// and the id of the upward arc from the middle node (in the forward graph).
+    Shortcut(EdgeId, EdgeId),
+}
+
+/// Data of a single TCH arc.
+#[derive(Debug, Clone)]
+pub struct Shortcut {
+    // all sources which might be part of a shortest path, with lower bounds for each source
+    sources: Vec<(ShortcutSource, Weight)>,
+    // the source which the approximated profile considered best, starting at each timestamp
+    preferred_sources: Vec<(Timestamp, ShortcutSource)>,
+    lower_bound: Weight,
+    upper_bound: Weight,
+}
+
+impl Shortcut {
+    pub fn lower_bound(&self) -> Weight {
+        self.lower_bound
+    }
+
+    pub fn upper_bound(&self) -> Weight {
+        self.upper_bound
+    }
+
+    pub fn sources(&self) -> impl Iterator<Item = ShortcutSource> + '_ {
+        self.sources.iter().map(|&(source, _)| source)
+    }
+
+    fn preferred_source(&self, t: Timestamp) -> ShortcutSource {
+        let t = t % period();
+        let idx = self.preferred_sources.partition_point(|&(start, _)| start <= t);
+        self.preferred_sources[idx - 1].1
+    }
+}
+
+/// A fully preprocessed TCH.
+/// Node ids are ranks, arcs are split into an upward (forward) and a downward part,
+/// the latter stored reversed at the lower ranked node (backward).
+pub struct TCH {
+    forward: UnweightedOwnedGraph,
+    backward: UnweightedOwnedGraph,
+    forward_shortcuts: Vec<Shortcut>,
+    backward_shortcuts: Vec<Shortcut>,
+    order: NodeOrder,
+}
+
+impl TCH {
+    pub fn forward(&self) -> &UnweightedOwnedGraph {
+        &self.forward
+    }
+
+    pub fn backward(&self) -> &UnweightedOwnedGraph {
+        &self.backward
+    }
+
+    pub fn forward_shortcuts(&self) -> &[Shortcut] {
+        &self.forward_shortcuts
+    }
+
+    pub fn backward_shortcuts(&self) -> &[Shortcut] {
+        &self.backward_shortcuts
+    }
+
+    pub fn order(&self) -> &NodeOrder {
+        &self.order
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.forward.num_nodes()
+    }
+
+    /// Evaluate the travel time of an upward arc for a given departure time.
+    pub fn evaluate_forward<M: TDMetric>(&self, metric: &M, edge_id: EdgeId, t: Timestamp) -> Weight {
+        self.evaluate(metric, &self.forward_shortcuts[edge_id as usize], t).0
+    }
+
+    /// Evaluate the travel time of a downward arc for a given departure time.
+    pub fn evaluate_backward<M: TDMetric>(&self, metric: &M, edge_id: EdgeId, t: Timestamp) -> Weight {
+        self.evaluate(metric, &self.backward_shortcuts[edge_id as usize], t).0
+    }
+
+    // Evaluate a shortcut and also return the source which yielded the travel time.
+    // The preferred source is evaluated first, its result is used to skip all sources whose lower bound is not smaller.
+    fn evaluate<M: TDMetric>(&self, metric: &M, shortcut: &Shortcut, t: Timestamp) -> (Weight, ShortcutSource) {
+        let preferred = shortcut.preferred_source(t);
+        let mut best = (self.evaluate_source(metric, preferred, t), preferred);
+        for &(source, lower) in &shortcut.sources {
+            if source != preferred && lower < best.0 {
+                let tt = self.evaluate_source(metric, source, t);
+                if tt < best.0 {
+                    best = (tt, source);
+                }
+            }
+        }
+        best
+    }
+
+    fn evaluate_source<M: TDMetric>(&self, metric: &M, source: ShortcutSource, t: Timestamp) -> Weight {
+        match source {
+            ShortcutSource::OriginalEdge(edge_id) => metric.eval(edge_id, t),
+            ShortcutSource::Shortcut(down, up) => {
+                let first = self.evaluate_backward(metric, down, t);
+                first.saturating_add(self.evaluate_forward(metric, up, t.saturating_add(first)))
+            }
+        }
+    }
+
+    // The middle node of lower triangles is the tail of the upward arc
+    fn forward_tail(&self, edge_id: EdgeId) -> NodeId {
+        (self.forward.first_out().partition_point(|&first| first <= edge_id) - 1) as NodeId
+    }
+}
+
+// Shortcut data during contraction, including the approximated profile.
+struct ContractionShortcut {
+    // sources reference other contraction shortcuts by index
+    sources: Vec<(ShortcutSource, Weight)>,
+    preferred_sources: Vec<(Timestamp, ShortcutSource)>,
+    profile: OwnedPLF,
+    lower_bound: Weight,
+    upper_bound: Weight,
+}
+
+impl ContractionShortcut {
+    fn new(source: ShortcutSource, profile: OwnedPLF, lower_bound: Weight, upper_bound: Weight) -> Self {
+        Self {
+            sources: vec![(source, lower_bound)],
+            preferred_sources: vec![(0, source)],
+            profile,
+            lower_bound,
+            upper_bound,
+        }
+    }
+
+    // Add another source to this shortcut, unless it can never be better than the existing ones.
+    fn merge(&mut self, source: ShortcutSource, profile: OwnedPLF, lower_bound: Weight, upper_bound: Weight) {
+        if lower_bound > self.upper_bound {
+            return;
+        }
+
+        let (merged, better) = self.profile.as_plf().merge(&profile.as_plf());
+        let mut preferred_sources = Vec::with_capacity(self.preferred_sources.len() + better.len());
+        for (idx, &(start, self_better)) in better.iter().enumerate() {
+            if self_better {
+                let end = better.get(idx + 1).map(|&(end, _)| end).unwrap_or(period());
+                let first = self.preferred_sources.partition_point(|&(t, _)| t <= start) - 1;
+                preferred_sources.push((start, self.preferred_sources[first].1));
+                preferred_sources.extend(self.preferred_sources[first + 1..].iter().take_while(|&&(t, _)| t < end));
+            } else {
+                preferred_sources.push((start, source));
+            }
+        }
+        preferred_sources.dedup_by_key(|&mut (_, source)| source);
+
+        self.preferred_sources = preferred_sources;
+        self.profile = approximate(merged);
+        self.sources.push((source, lower_bound));
+        self.lower_bound = min(self.lower_bound, lower_bound);
+        self.upper_bound = min(self.upper_bound, upper_bound);
+        let upper_bound = self.upper_bound;
+        self.sources.retain(|&(_, lower)| lower <= upper_bound);
+    }
+}
+
+fn approximate(profile: OwnedPLF) -> OwnedPLF {
+    if profile.num_ipps() > APPROX_THRESHOLD {
+        profile.as_plf().approximate(APPROX)
+    } else {
+        profile
+    }
+}
+
+fn add_bounds(first: Weight, second: Weight) -> Weight {
+    min(first.saturating_add(second), INFINITY)
+}
+
+// Adjacency of a node during contraction, (rank of neighbor, contraction shortcut id) pairs.
+#[derive(Default)]
+struct Node {
+    outgoing: Vec<(NodeId, usize)>,
+    incoming: Vec<(NodeId, usize)>,
+}
+
+struct ContractionGraph {
+    nodes: Vec<Node>,
+    shortcuts: Vec<ContractionShortcut>,
+}
+
+impl ContractionGraph {
+    fn new<M: TDMetric>(metric: &M, order: &NodeOrder) -> Self {
+        let mut graph = ContractionGraph {
+            nodes: (0..metric.num_nodes()).map(|_| Node::default()).collect(),
+            shortcuts: Vec::with_capacity(metric.num_arcs()),
+        };
+
+        for node in 0..metric.num_nodes() as NodeId {
+            for (NodeIdT(head), EdgeIdT(edge_id)) in LinkIterable::<(NodeIdT, EdgeIdT)>::link_iter(metric, node) {
+                if head == node {
+                    continue;
+                }
+                graph.insert_or_merge(
+                    order.rank(node),
+                    order.rank(head),
+                    ShortcutSource::OriginalEdge(edge_id),
+                    OwnedPLF::from(metric.profile(edge_id)),
+                    metric.lower_bound(edge_id),
+                    metric.upper_bound(edge_id),
+                );
+            }
+        }
+
+        graph
+    }
+
+    fn insert_or_merge(&mut self, from: NodeId, to: NodeId, source: ShortcutSource, profile: OwnedPLF, lower_bound: Weight, upper_bound: Weight) {
+        if let Some(&(_, existing)) = self.nodes[from as usize].outgoing.iter().find(|&&(head, _)| head == to) {
+            self.shortcuts[existing].merge(source, profile, lower_bound, upper_bound);
+        } else {
+            let id = self.shortcuts.len();
+            self.shortcuts.push(ContractionShortcut::new(source, profile, lower_bound, upper_bound));
+            self.nodes[from as usize].outgoing.push((to, id));
+            self.nodes[to as usize].incoming.push((from, id));
+        }
+    }
+
+    fn contract(&mut self) {
+        let n = self.nodes.len();
+        let mut witness_data = DijkstraData::new(n);
+        let mut num_shortcuts_inserted: usize = 0;
+
+        for node in 0..n {
+            // remove the node from the remaining graph, so witness searches will not use it
+            let (lower, higher) = self.nodes.split_at_mut(node + 1);
+            let current = &lower[node];
+            for &(from, _) in &current.incoming {
+                let from = &mut higher[from as usize - node - 1];
+                let pos = from.outgoing.iter().position(|&(head, _)| head == node as NodeId).unwrap();
+                from.outgoing.swap_remove(pos);
+            }
+            for &(to, _) in &current.outgoing {
+                let to = &mut higher[to as usize - node - 1];
+                let pos = to.incoming.iter().position(|&(tail, _)| tail == node as NodeId).unwrap();
+                to.incoming.swap_remove(pos);
+            }
+
+            let required = self.required_shortcuts(node as NodeId, &mut witness_data);
+            num_shortcuts_inserted += required.len();
+
+            for (from, to, down, up) in required {
+                let profile = approximate(self.shortcuts[down].profile.as_plf().link(&self.shortcuts[up].profile.as_plf()));
+                let lower_bound = add_bounds(self.shortcuts[down].lower_bound, self.shortcuts[up].lower_bound);
+                let upper_bound = add_bounds(self.shortcuts[down].upper_bound, self.shortcuts[up].upper_bound);
+                // ids of contraction shortcuts for now, will be translated when building the final TCH
+                let source = ShortcutSource::Shortcut(down as EdgeId, up as EdgeId);
+                self.insert_or_merge(from, to, source, profile, lower_bound, upper_bound);
+            }
+        }
+
+        report!("num_shortcut_candidates_inserted", num_shortcuts_inserted);
+    }
+
+    // All triangles `from -> node -> to` which have no witness, as (from, to, down contraction shortcut id, up contraction shortcut id).
+    fn required_shortcuts(&self, node: NodeId, witness_data: &mut DijkstraData<Weight>) -> Vec<(NodeId, NodeId, usize, usize)> {
+        let current = &self.nodes[node as usize];
+        let mut required = Vec::new();
+
+        for &(from, down) in &current.incoming {
+            let max_lower = current
+                .outgoing
+                .iter()
+                .filter(|&&(to, _)| to != from)
+                .map(|&(_, up)| add_bounds(self.shortcuts[down].lower_bound, self.shortcuts[up].lower_bound))
+                .max();
+            let max_lower = if let Some(max_lower) = max_lower { max_lower } else { continue };
+
+            // Witness search on the upper bounds in the remaining graph.
+            // A triangle is not needed if there is a path which is never slower, that is has an upper bound not greater than the lower bound of the triangle.
+            let witness_graph = UpperBoundGraph { graph: self };
+            let mut ops = DefaultOps::default();
+            let mut witness_search = DijkstraRun::query(&witness_graph, witness_data, &mut ops, DijkstraInit::from(from));
+            while let Some(State { key, .. }) = witness_search.queue().peek() {
+                if *key > max_lower {
+                    break;
+                }
+                witness_search.next();
+            }
+
+            for &(to, up) in &current.outgoing {
+                if to == from {
+                    continue;
+                }
+                let lower = add_bounds(self.shortcuts[down].lower_bound, self.shortcuts[up].lower_bound);
+                if *witness_search.tentative_distance(to) > lower {
+                    required.push((from, to, down, up));
+                }
+            }
+        }
+
+        required
+    }
+
+    // Build the final TCH, translating the ids in shortcut sources from contraction shortcut ids to forward and backward arc ids.
+    fn into_tch(self, order: NodeOrder) -> TCH {
+        let ContractionGraph { nodes, shortcuts } = self;
+
+        // sort adjacency lists by head, so the structure is deterministic
+        let mut outgoing: Vec<Vec<(NodeId, usize)>> = Vec::with_capacity(nodes.len());
+        let mut incoming: Vec<Vec<(NodeId, usize)>> = Vec::with_capacity(nodes.len());
+        for Node {
+            outgoing: mut out,
+            incoming: mut inc,
+        } in nodes
+        {
+            out.sort_unstable();
+            inc.sort_unstable();
+            outgoing.push(out);
+            incoming.push(inc);
+        }
+
+        let mut forward_id = vec![EdgeId::MAX; shortcuts.len()];
+        let mut backward_id = vec![EdgeId::MAX; shortcuts.len()];
+        for (edge_id, &(_, shortcut)) in outgoing.iter().flatten().enumerate() {
+            forward_id[shortcut] = edge_id as EdgeId;
+        }
+        for (edge_id, &(_, shortcut)) in incoming.iter().flatten().enumerate() {
+            backward_id[shortcut] = edge_id as EdgeId;
+        }
+
+        let translate = |shortcut: &ContractionShortcut| {
+            let translate_source = |source: ShortcutSource| match source {
+                ShortcutSource::OriginalEdge(edge_id) => ShortcutSource::OriginalEdge(edge_id),
+                ShortcutSource::Shortcut(down, up) => ShortcutSource::Shortcut(backward_id[down as usize], forward_id[up as usize]),
+            };
+            Shortcut {
+                sources: shortcut.sources.iter().map(|&(source, lower)| (translate_source(source), lower)).collect(),
+                preferred_sources: shortcut.preferred_sources.iter().map(|&(t, source)| (t, translate_source(source))).collect(),
+                lower_bound: shortcut.lower_bound,
+                upper_bound: shortcut.upper_bound,
+            }
+        };
+
+        let forward_shortcuts = outgoing.iter().flatten().map(|&(_, shortcut)| translate(&shortcuts[shortcut])).collect();
+        let backward_shortcuts = incoming.iter().flatten().map(|&(_, shortcut)| translate(&shortcuts[shortcut])).collect();
+
+        let tch = TCH {
+            forward: UnweightedOwnedGraph::from_adjancecy_lists(outgoing.into_iter().map(|links| links.into_iter().map(|(head, _)| head).collect()).collect()),
+            backward: UnweightedOwnedGraph::from_adjancecy_lists(incoming.into_iter().map(|links| links.into_iter().map(|(head, _)| head).collect()).collect()),
+            forward_shortcuts,
+            backward_shortcuts,
+            order,
+        };
+
+        report!("num_forward_arcs", tch.forward.num_arcs());
+        report!("num_backward_arcs", tch.backward.num_arcs());
+        report!(
+            "num_sources",
+            tch.forward_shortcuts
+                .iter()
+                .chain(tch.backward_shortcuts.iter())
+                .map(|s| s.sources.len())
+                .sum::<usize>()
+        );
+
+        tch
+    }
+}
+
+/// Perform TCH preprocessing for the given metric and node order.
+pub fn contract<M: TDMetric>(metric: &M, order: NodeOrder) -> TCH {
+    report!("algo", "TCH Preprocessing");
+    let mut graph = ContractionGraph::new(metric, &order);
+    report_time_with_key("TCH Contraction", "contraction_running_time_ms", || graph.contract());
+    graph.into_tch(order)
+}
+
+// Remaining graph during contraction with the upper bounds of the arcs as weights, for witness searches.
+struct UpperBoundGraph<'a> {
+    graph: &'a ContractionGraph,
+}
+
+impl<'a> Graph for UpperBoundGraph<'a> {
+    fn num_nodes(&self) -> usize {
+        self.graph.nodes.len()
+    }
+
+    fn num_arcs(&self) -> usize {
+        self.graph.nodes.iter().map(|node| node.outgoing.len()).sum()
+    }
+
+    fn degree(&self, node: NodeId) -> usize {
+        self.graph.nodes[node as usize].outgoing.len()
+    }
+}
+
+impl<'a> LinkIterable<Link> for UpperBoundGraph<'a> {
+    type Iter<'b> = impl Iterator<Item = Link> + 'b where Self: 'b;
+
+    fn link_iter(&self, node: NodeId) -> Self::Iter<'_> {
+        self.graph.nodes[node as usize].outgoing.iter().map(move |&(head, shortcut)| Link {
+            node: head,
+            weight: self.graph.shortcuts[shortcut].upper_bound,
+        })
+    }
+}
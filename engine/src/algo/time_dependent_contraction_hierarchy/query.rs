@@ -0,0 +1,339 @@
+//! Corridor based TCH query.
+//!
+//! First, the upward search spaces of source and target are explored completely with lower and upper bounds.
+//! All arcs which may be part of a shortest up-down path according to these bounds form the corridor.
+//! The corridor is then unpacked into original edges by following all remaining sources of the shortcuts.
+//! Finally, a time-dependent Dijkstra restricted to these original edges determines the exact earliest arrival.
+//! Unpacking the entire corridor once is linear in its size, while evaluating shortcuts recursively for each relaxation
+//! may take time exponential in the depth of the hierarchy, since we have to consider all sources which may be optimal.
+
+use super::*;
+use crate::datastr::clearlist_vector::ClearlistVector;
+use crate::datastr::index_heap::IndexdMinHeap;
+use crate::datastr::rank_select_map::FastClearBitVec;
+
+// A corridor arc, either an upward arc (forward graph) or a downward arc (backward graph) by its id.
+#[derive(Debug, Clone, Copy)]
+enum CorridorArc {
+    Up(EdgeId),
+    Down(EdgeId),
+}
+
+pub struct Server<'a, M> {
+    tch: &'a TCH,
+    metric: &'a M,
+
+    // bounds of the upward searches
+    forward_bounds: ClearlistVector<(Weight, Weight)>,
+    backward_bounds: ClearlistVector<(Weight, Weight)>,
+    forward_search_space: Vec<NodeId>,
+    backward_search_space: Vec<NodeId>,
+    // lower bounds to the target (for forward search space nodes) and from the source (for backward search space nodes) within the corridor
+    corridor_bounds: ClearlistVector<Weight>,
+    // (tail, head, arc)
+    corridor: Vec<(NodeId, NodeId, CorridorArc)>,
+    // original edges of the unpacked corridor as (tail, head, original edge id), sorted by tail
+    unpacked_corridor: Vec<(NodeId, NodeId, EdgeId)>,
+    forward_unpacked: FastClearBitVec,
+    backward_unpacked: FastClearBitVec,
+
+    // time-dependent Dijkstra in the unpacked corridor
+    distances: ClearlistVector<Timestamp>,
+    parents: Vec<(NodeId, EdgeId)>,
+    queue: IndexdMinHeap<State<Timestamp>>,
+
+    num_settled_nodes: usize,
+}
+
+impl<'a, M: TDMetric> Server<'a, M> {
+    pub fn new(tch: &'a TCH, metric: &'a M) -> Self {
+        let n = tch.num_nodes();
+        Server {
+            tch,
+            metric,
+            forward_bounds: ClearlistVector::new(n, (INFINITY, INFINITY)),
+            backward_bounds: ClearlistVector::new(n, (INFINITY, INFINITY)),
+            forward_search_space: Vec::new(),
+            backward_search_space: Vec::new(),
+            corridor_bounds: ClearlistVector::new(n, INFINITY),
+            corridor: Vec::new(),
+            unpacked_corridor: Vec::new(),
+            forward_unpacked: FastClearBitVec::new(tch.forward().num_arcs()),
+            backward_unpacked: FastClearBitVec::new(tch.backward().num_arcs()),
+            distances: ClearlistVector::new(n, INFINITY),
+            parents: vec![(n as NodeId, EdgeId::MAX); n],
+            queue: IndexdMinHeap::new(n),
+            num_settled_nodes: 0,
+        }
+    }
+
+    fn distance(&mut self, from: NodeId, to: NodeId, departure: Timestamp) -> Option<Weight> {
+        let from = self.tch.order.rank(from);
+        let to = self.tch.order.rank(to);
+
+        self.num_settled_nodes = 0;
+        self.distances.reset();
+        self.queue.clear();
+
+        let upper_bound = report_time_with_key("corridor selection", "corridor_selection_running_time_ms", || self.corridor(from, to));
+        report!("num_corridor_arcs", self.corridor.len());
+        report!("distance_upper_bound", upper_bound);
+        self.unpack_corridor();
+        report!("num_unpacked_corridor_edges", self.unpacked_corridor.len());
+
+        self.distances[from as usize] = departure;
+        self.queue.push(State { key: departure, node: from });
+
+        while let Some(State { key: t, node }) = self.queue.pop() {
+            self.num_settled_nodes += 1;
+            if node == to {
+                return Some(t - departure);
+            }
+
+            let first = self.unpacked_corridor.partition_point(|&(tail, _, _)| tail < node);
+            for &(_, head, edge_id) in self.unpacked_corridor[first..].iter().take_while(|&&(tail, _, _)| tail == node) {
+                let arrival = t.saturating_add(self.metric.eval(edge_id, t));
+                if arrival < self.distances[head as usize] {
+                    self.distances[head as usize] = arrival;
+                    self.parents[head as usize] = (node, edge_id);
+                    if self.queue.contains_index(head as usize) {
+                        self.queue.decrease_key(State { key: arrival, node: head });
+                    } else {
+                        self.queue.push(State { key: arrival, node: head });
+                    }
+                }
+            }
+        }
+
+        None
+    }
+
+    // Collect all original edges reachable through the sources of the corridor arcs.
+    fn unpack_corridor(&mut self) {
+        self.unpacked_corridor.clear();
+        self.forward_unpacked.clear();
+        self.backward_unpacked.clear();
+
+        let mut stack = self.corridor.clone();
+        while let Some((tail, head, arc)) = stack.pop() {
+            let shortcut = match arc {
+                CorridorArc::Up(edge_id) if !self.forward_unpacked.get(edge_id as usize) => {
+                    self.forward_unpacked.set(edge_id as usize);
+                    &self.tch.forward_shortcuts()[edge_id as usize]
+                }
+                CorridorArc::Down(edge_id) if !self.backward_unpacked.get(edge_id as usize) => {
+                    self.backward_unpacked.set(edge_id as usize);
+                    &self.tch.backward_shortcuts()[edge_id as usize]
+                }
+                _ => continue,
+            };
+            for source in shortcut.sources() {
+                match source {
+                    ShortcutSource::OriginalEdge(edge_id) => self.unpacked_corridor.push((tail, head, edge_id)),
+                    ShortcutSource::Shortcut(down, up) => {
+                        let middle = self.tch.forward_tail(up);
+                        stack.push((tail, middle, CorridorArc::Down(down)));
+                        stack.push((middle, head, CorridorArc::Up(up)));
+                    }
+                }
+            }
+        }
+
+        self.unpacked_corridor.sort_unstable();
+    }
+
+    // Determine the corridor and return the upper bound of the distance.
+    fn corridor(&mut self, from: NodeId, to: NodeId) -> Weight {
+        self.forward_bounds.reset();
+        self.backward_bounds.reset();
+        self.corridor_bounds.reset();
+        self.corridor.clear();
+
+        Self::upward_search(
+            self.tch.forward(),
+            self.tch.forward_shortcuts(),
+            from,
+            &mut self.forward_bounds,
+            &mut self.forward_search_space,
+        );
+        Self::upward_search(
+            self.tch.backward(),
+            self.tch.backward_shortcuts(),
+            to,
+            &mut self.backward_bounds,
+            &mut self.backward_search_space,
+        );
+
+        let mut upper_bound = INFINITY;
+        for &node in &self.forward_search_space {
+            let (_, forward_upper) = self.forward_bounds[node as usize];
+            let (_, backward_upper) = self.backward_bounds[node as usize];
+            upper_bound = min(upper_bound, add_bounds(forward_upper, backward_upper));
+        }
+
+        let is_meeting_node = |node: NodeId| {
+            let (forward_lower, _) = self.forward_bounds[node as usize];
+            let (backward_lower, _) = self.backward_bounds[node as usize];
+            forward_lower < INFINITY && backward_lower < INFINITY && forward_lower + backward_lower <= upper_bound
+        };
+
+        // Upward part of the corridor, processed top down.
+        // For each node, we calculate a lower bound to the target through the corridor and keep all arcs which may be on a shortest path.
+        for &node in self.forward_search_space.iter().rev() {
+            let mut lower_to_target = if is_meeting_node(node) {
+                self.backward_bounds[node as usize].0
+            } else {
+                INFINITY
+            };
+            let lower_from_source = self.forward_bounds[node as usize].0;
+            for (NodeIdT(head), EdgeIdT(edge_id)) in LinkIterable::<(NodeIdT, EdgeIdT)>::link_iter(self.tch.forward(), node) {
+                let lower = add_bounds(
+                    self.tch.forward_shortcuts()[edge_id as usize].lower_bound(),
+                    self.corridor_bounds[head as usize],
+                );
+                if lower < INFINITY && lower_from_source + lower <= upper_bound {
+                    self.corridor.push((node, head, CorridorArc::Up(edge_id)));
+                    lower_to_target = min(lower_to_target, lower);
+                }
+            }
+            if lower_to_target < INFINITY {
+                self.corridor_bounds[node as usize] = lower_to_target;
+            }
+        }
+
+        // Downward part of the corridor, same but with lower bounds from the source.
+        // The bounds of forward search space nodes are not needed anymore.
+        for &node in &self.forward_search_space {
+            self.corridor_bounds[node as usize] = INFINITY;
+        }
+        for &node in self.backward_search_space.iter().rev() {
+            let mut lower_from_source = if is_meeting_node(node) {
+                self.forward_bounds[node as usize].0
+            } else {
+                INFINITY
+            };
+            let lower_to_target = self.backward_bounds[node as usize].0;
+            for (NodeIdT(tail), EdgeIdT(edge_id)) in LinkIterable::<(NodeIdT, EdgeIdT)>::link_iter(self.tch.backward(), node) {
+                let lower = add_bounds(
+                    self.tch.backward_shortcuts()[edge_id as usize].lower_bound(),
+                    self.corridor_bounds[tail as usize],
+                );
+                if lower < INFINITY && lower_to_target + lower <= upper_bound {
+                    self.corridor.push((tail, node, CorridorArc::Down(edge_id)));
+                    lower_from_source = min(lower_from_source, lower);
+                }
+            }
+            if lower_from_source < INFINITY {
+                self.corridor_bounds[node as usize] = lower_from_source;
+            }
+        }
+
+        upper_bound
+    }
+
+    // Explore the complete upward search space.
+    // Since arcs always lead to higher ranked nodes, processing the nodes by increasing rank yields exact bounds.
+    fn upward_search(
+        graph: &UnweightedOwnedGraph,
+        shortcuts: &[Shortcut],
+        start: NodeId,
+        bounds: &mut ClearlistVector<(Weight, Weight)>,
+        search_space: &mut Vec<NodeId>,
+    ) {
+        search_space.clear();
+        search_space.push(start);
+        bounds[start as usize] = (0, 0);
+        let mut stack = vec![start];
+        while let Some(node) = stack.pop() {
+            for NodeIdT(head) in LinkIterable::<NodeIdT>::link_iter(graph, node) {
+                // we use the upper bound as a visited marker for now
+                if bounds[head as usize].1 == INFINITY {
+                    bounds[head as usize].1 = 0;
+                    search_space.push(head);
+                    stack.push(head);
+                }
+            }
+        }
+
+        search_space.sort_unstable();
+        for &node in &search_space[1..] {
+            bounds[node as usize] = (INFINITY, INFINITY);
+        }
+
+        for &node in search_space.iter() {
+            let (lower, upper) = bounds[node as usize];
+            for (NodeIdT(head), EdgeIdT(edge_id)) in LinkIterable::<(NodeIdT, EdgeIdT)>::link_iter(graph, node) {
+                let shortcut = &shortcuts[edge_id as usize];
+                let head_bounds = &mut bounds[head as usize];
+                head_bounds.0 = min(head_bounds.0, add_bounds(lower, shortcut.lower_bound()));
+                head_bounds.1 = min(head_bounds.1, add_bounds(upper, shortcut.upper_bound()));
+            }
+        }
+    }
+
+    // The path as (head rank, original edge id) pairs
+    fn unpacked_path(&self, query: TDQuery<Timestamp>) -> Vec<(NodeId, EdgeIdT)> {
+        let from = self.tch.order.rank(query.from);
+        let mut node = self.tch.order.rank(query.to);
+
+        let mut path = Vec::new();
+        while node != from {
+            let (pred, edge_id) = self.parents[node as usize];
+            path.push((node, EdgeIdT(edge_id)));
+            node = pred;
+        }
+        path.reverse();
+
+        path
+    }
+
+    fn node_path(&self, query: TDQuery<Timestamp>) -> Vec<(NodeId, Timestamp)> {
+        let mut t = query.departure;
+        let mut path = vec![(query.from, t)];
+        for (head, EdgeIdT(edge_id)) in self.unpacked_path(query) {
+            t += self.metric.eval(edge_id, t);
+            path.push((self.tch.order.node(head), t));
+        }
+        path
+    }
+
+    fn edge_path(&self, query: TDQuery<Timestamp>) -> Vec<EdgeIdT> {
+        self.unpacked_path(query).into_iter().map(|(_, edge)| edge).collect()
+    }
+}
+
+pub struct PathServerWrapper<'s, 'a, M>(&'s Server<'a, M>, TDQuery<Timestamp>);
+
+impl<'s, 'a, M> PathServerWrapper<'s, 'a, M> {
+    pub fn num_nodes_in_searchspace(&self) -> usize {
+        self.0.num_settled_nodes
+    }
+
+    pub fn num_corridor_arcs(&self) -> usize {
+        self.0.corridor.len()
+    }
+
+    pub fn num_unpacked_corridor_edges(&self) -> usize {
+        self.0.unpacked_corridor.len()
+    }
+}
+
+impl<'s, 'a, M: TDMetric> PathServer for PathServerWrapper<'s, 'a, M> {
+    type NodeInfo = (NodeId, Timestamp);
+    type EdgeInfo = EdgeIdT;
+
+    fn reconstruct_node_path(&mut self) -> Vec<Self::NodeInfo> {
+        Server::node_path(self.0, self.1)
+    }
+    fn reconstruct_edge_path(&mut self) -> Vec<Self::EdgeInfo> {
+        Server::edge_path(self.0, self.1)
+    }
+}
+
+impl<'a, M: TDMetric> TDQueryServer<Timestamp, Weight> for Server<'a, M> {
+    type P<'s> = PathServerWrapper<'s, 'a, M> where Self: 's;
+
+    fn td_query(&mut self, query: TDQuery<Timestamp>) -> QueryResult<Self::P<'_>, Weight> {
+        QueryResult::new(self.distance(query.from, query.to, query.departure), PathServerWrapper(self, query))
+    }
+}
@@ -3,10 +3,11 @@
 //! TD-S is a simple heuristic for time-dependent routing.
 //! During preprocessing the time-dependent functions are split into a couple of windows.
 //! For each window one static travel time is determined for each link, i.E. by taking the average of the travel times in that window.
+//! Windows, aggregation and the number of samples can be configured with a `SamplingConfig`.
 //! The query algorithm is to run independent fast shortest path queries on each window and combine the resulting optimal paths into a subgraph of the original graph.
 //! On this subgraph, a standard time-dependent dijkstra is performed.
 //!
-//! Preprocessing is completely done in CCHs, so this module is actually just the query algorithm.
+//! Preprocessing is completely done in CCHs, so this module only determines the static weights of the samples and implements the query algorithm.
 
 use super::*;
 use crate::{
@@ -15,9 +16,217 @@ use crate::{
         dijkstra::{generic_dijkstra::*, query::td_dijkstra::TDDijkstraOps, *},
     },
     datastr::{graph::time_dependent::*, timestamped_vector::TimestampedVector},
+    io::*,
+    report::*,
 };
 
-use std::ops::Range;
+use std::{error::Error, fmt, ops::Range};
+
+/// How the travel time function of a link is condensed into a single static weight for one time window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+    /// Average travel time over the window
+    Average,
+    /// Minimum travel time within the window
+    Min,
+    /// The given percentile of `num_points` travel times evaluated at evenly spaced departures within the window
+    Percentile { percentile: u8, num_points: usize },
+    /// Travel time when departing at the start of the window
+    Instant,
+}
+
+impl Aggregation {
+    /// Static weight of `plf` for the (possibly wrapping) window `window`.
+    pub fn aggregate(&self, plf: &PiecewiseLinearFunction, window: Range<Timestamp>) -> Weight {
+        let window = WrappingRange::new(window);
+        match *self {
+            Aggregation::Average => plf.average(window),
+            Aggregation::Min => plf.lower_bound_in_range(window.monotonize()),
+            Aggregation::Percentile { percentile, num_points } => {
+                assert!(
+                    percentile <= 100 && num_points > 0,
+                    "invalid percentile aggregation, use SamplingConfig::new to validate"
+                );
+                let window = window.monotonize();
+                let step = (window.end - window.start) as u64;
+                let mut travel_times: Vec<Weight> = (0..num_points as u64)
+                    .map(|i| plf.eval(window.start + (i * step / num_points as u64) as Timestamp))
+                    .collect();
+                travel_times.sort_unstable();
+                travel_times[(num_points - 1) * percentile as usize / 100]
+            }
+            Aggregation::Instant => plf.eval(window.start()),
+        }
+    }
+}
+
+/// Error for sampling parameters which TD-S cannot work with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamplingConfigErr(pub String);
+
+impl fmt::Display for SamplingConfigErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid sampling config: {}", self.0)
+    }
+}
+
+impl Error for SamplingConfigErr {}
+
+/// Preprocessing parameters for TD-S.
+/// Build configs from user input with `new` or `uniform`, which reject invalid parameters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamplingConfig {
+    /// Time windows to sample, each with start and end in `0..period()`.
+    /// Windows with `start >= end` wrap around midnight.
+    pub windows: Vec<Range<Timestamp>>,
+    pub aggregation: Aggregation,
+    /// If set, the windows are merged with `metric_merging::merge` until only this many samples remain.
+    /// Windows with similar metrics get merged first, so the remaining samples have the most diverse paths.
+    /// The weight of a merged sample is the edge-wise minimum of the merged windows.
+    pub num_samples: Option<usize>,
+}
+
+impl SamplingConfig {
+    pub fn new(windows: Vec<Range<Timestamp>>, aggregation: Aggregation, num_samples: Option<usize>) -> Result<Self, SamplingConfigErr> {
+        if windows.is_empty() {
+            return Err(SamplingConfigErr("at least one window is required".to_string()));
+        }
+        if let Some(window) = windows.iter().find(|window| window.start >= period() || window.end >= period()) {
+            return Err(SamplingConfigErr(format!("window {:?} exceeds the period of {}", window, period())));
+        }
+        if let Aggregation::Percentile { percentile, num_points } = aggregation {
+            if percentile > 100 {
+                return Err(SamplingConfigErr(format!("percentile {} is larger than 100", percentile)));
+            }
+            if num_points == 0 {
+                return Err(SamplingConfigErr("percentile aggregation needs at least one point".to_string()));
+            }
+        }
+        if num_samples == Some(0) {
+            return Err(SamplingConfigErr("at least one sample is required".to_string()));
+        }
+        Ok(SamplingConfig {
+            windows,
+            aggregation,
+            num_samples,
+        })
+    }
+
+    /// Split the period into `num_windows` windows of equal length.
+    pub fn uniform(num_windows: usize, aggregation: Aggregation) -> Result<Self, SamplingConfigErr> {
+        let num_windows = num_windows as u64;
+        let boundary = |i: u64| (i * period() as u64 / num_windows) as Timestamp;
+        Self::new((0..num_windows).map(|i| boundary(i)..boundary(i + 1) % period()).collect(), aggregation, None)
+    }
+}
+
+impl Default for SamplingConfig {
+    /// The four windows of the original TD-S evaluation: night, morning rush hour, midday and afternoon rush hour.
+    /// Here, we assume, that our travel time functions cover one day.
+    fn default() -> Self {
+        let hour = period() / 24;
+        SamplingConfig {
+            windows: vec![22 * hour..5 * hour, 7 * hour..10 * hour, 11 * hour..15 * hour, 16 * hour..19 * hour],
+            aggregation: Aggregation::Average,
+            num_samples: None,
+        }
+    }
+}
+
+/// Customized CCHs for all samples of TD-S together with the time windows each sample was built from.
+pub struct Samples<'a> {
+    windows: Vec<Vec<Range<Timestamp>>>,
+    customized: Vec<CustomizedBasic<'a, CCH>>,
+}
+
+impl<'a> Samples<'a> {
+    /// Run the TD-S preprocessing: aggregate the travel time functions for each window, optionally merge windows and customize the CCH for each sample.
+    pub fn customize(graph: &TDGraph, cch: &'a CCH, config: &SamplingConfig) -> Self {
+        assert!(!config.windows.is_empty());
+        let mut metrics: Vec<Vec<Weight>> = report_time_with_key("TD-S window aggregation", "aggregation_running_time_ms", || {
+            config
+                .windows
+                .iter()
+                .map(|window| {
+                    (0..graph.num_arcs() as EdgeId)
+                        .map(|edge_id| config.aggregation.aggregate(&graph.travel_time_function(edge_id), window.clone()))
+                        .collect()
+                })
+                .collect()
+        });
+
+        let mut windows: Vec<Vec<Range<Timestamp>>> = config.windows.iter().map(|window| vec![window.clone()]).collect();
+        if let Some(num_samples) = config.num_samples {
+            let groups = report_time_with_key("TD-S window merging", "merging_running_time_ms", || {
+                metric_merging::merge(&metrics.iter().map(|metric| &metric[..]).collect::<Vec<_>>(), num_samples)
+            });
+            windows = groups
+                .iter()
+                .map(|group| group.iter().map(|&idx| config.windows[idx].clone()).collect())
+                .collect();
+            metrics = groups
+                .iter()
+                .map(|group| {
+                    (0..graph.num_arcs())
+                        .map(|edge_idx| group.iter().map(|&idx| metrics[idx][edge_idx]).min().unwrap())
+                        .collect()
+                })
+                .collect();
+        }
+        report!("num_samples", metrics.len());
+
+        // customize the CCH for four samples in one pass
+        let customized = customize_batched(cch, &metrics.iter().map(|metric| &metric[..]).collect::<Vec<_>>());
+
+        Samples { windows, customized }
+    }
+
+    /// The time windows each sample was built from.
+    pub fn windows(&self) -> &[Vec<Range<Timestamp>>] {
+        &self.windows
+    }
+
+    pub fn customized(&self) -> &[CustomizedBasic<'a, CCH>] {
+        &self.customized
+    }
+}
+
+impl Deconstruct for Samples<'_> {
+    fn save_each(&self, store: &dyn Fn(&str, &dyn Save) -> std::io::Result<()>) -> std::io::Result<()> {
+        let mut first_window = vec![0u32];
+        for windows in &self.windows {
+            first_window.push(first_window.last().unwrap() + windows.len() as u32);
+        }
+        store("first_window", &first_window)?;
+        store("window_start", &self.windows.iter().flatten().map(|window| window.start).collect::<Vec<_>>())?;
+        store("window_end", &self.windows.iter().flatten().map(|window| window.end).collect::<Vec<_>>())?;
+        for (idx, customized) in self.customized.iter().enumerate() {
+            store(&format!("sample{}", idx), &Sub(customized))?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> ReconstructPrepared<Samples<'a>> for &'a CCH {
+    fn reconstruct_with(self, loader: Loader) -> std::io::Result<Samples<'a>> {
+        let first_window: Vec<u32> = loader.load("first_window")?;
+        let window_start: Vec<Timestamp> = loader.load("window_start")?;
+        let window_end: Vec<Timestamp> = loader.load("window_end")?;
+        if window_start.len() != window_end.len() || first_window.last().copied() != Some(window_start.len() as u32) {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "inconsistent TD-S window data"));
+        }
+
+        let windows = first_window
+            .array_windows::<2>()
+            .map(|&[first, end]| (first as usize..end as usize).map(|idx| window_start[idx]..window_end[idx]).collect())
+            .collect();
+        let customized = (0..first_window.len() - 1)
+            .map(|idx| loader.reconstruct_prepared(format!("sample{}", idx), self))
+            .collect::<std::io::Result<_>>()?;
+
+        Ok(Samples { windows, customized })
+    }
+}
 
 /// Query server struct for TD-S.
 /// Implements the common query trait.
@@ -25,47 +234,41 @@ pub struct Server<'a> {
     graph: TDGraph,
     // The Dijkstra algo on the original graph
     dijkstra_data: DijkstraData<Weight>,
-    // A CCH Server for each time window
+    // The time windows of each sample
+    windows: Vec<Vec<Range<Timestamp>>>,
+    // A CCH Server for each sample
     samples: Vec<CCHServer<CustomizedBasic<'a, CCH>>>,
     // marking edges in the subgraph we perform dijkstra on
     active_edges: TimestampedVector<bool>,
 }
 
 impl<'a> Server<'a> {
+    /// Setup with the default windows, see `SamplingConfig::default`.
     pub fn new(graph: TDGraph, cch: &'a CCH) -> Server<'a> {
-        let hour = period() / 24; // here, we assume, that our travel time functions cover one day
-                                  // hardcoded four time windows
-        let samples = [
-            Range { start: 22, end: 5 },
-            Range { start: 7, end: 10 },
-            Range { start: 11, end: 15 },
-            Range { start: 16, end: 19 },
-        ]
-        .iter()
-        .map(|range| {
-            let range = Range {
-                start: range.start * hour,
-                end: range.end * hour,
-            };
-            WrappingRange::new(range)
-        })
-        .map(|range| {
-            // average travel time for each window
-            (0..graph.num_arcs() as EdgeId)
-                .map(|edge_id| graph.travel_time_function(edge_id).average(range.clone()))
-                .collect::<Vec<Weight>>()
-        })
-        .map(|metric| CCHServer::new(customize(cch, &FirstOutGraph::new(graph.first_out(), graph.head(), metric)))) // customize CCH for each window
-        .collect();
+        Self::with_config(graph, cch, &SamplingConfig::default())
+    }
 
+    pub fn with_config(graph: TDGraph, cch: &'a CCH, config: &SamplingConfig) -> Server<'a> {
+        let samples = Samples::customize(&graph, cch, config);
+        Self::with_samples(graph, samples)
+    }
+
+    /// Setup with already customized (for example reconstructed) samples.
+    pub fn with_samples(graph: TDGraph, samples: Samples<'a>) -> Server<'a> {
         Server {
             active_edges: TimestampedVector::new(graph.num_arcs()),
             dijkstra_data: DijkstraData::new(graph.num_nodes()),
-            samples,
+            windows: samples.windows,
+            samples: samples.customized.into_iter().map(CCHServer::new).collect(),
             graph,
         }
     }
 
+    /// The time windows each sample was built from.
+    pub fn windows(&self) -> &[Vec<Range<Timestamp>>] {
+        &self.windows
+    }
+
     fn distance(&mut self, from: NodeId, to: NodeId, departure: Timestamp) -> Option<Weight> {
         self.active_edges.reset();
 
@@ -129,3 +332,57 @@ impl<'a> TDQueryServer<Timestamp, Weight> for Server<'a> {
         QueryResult::new(self.distance(query.from, query.to, query.departure), PathServerWrapper(self, query))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aggregations() {
+        run_test_with_periodicity(100, || {
+            // 10 until 40, then rising to 30 at 60 and falling back to 10 at 80
+            let departure_time = [0, 40, 60, 80, 100];
+            let travel_time = [10, 10, 30, 10, 10];
+            let plf = PiecewiseLinearFunction::new(&departure_time, &travel_time);
+
+            assert_eq!(Aggregation::Average.aggregate(&plf, 40..80), 20);
+            assert_eq!(Aggregation::Average.aggregate(&plf, 0..0), 14);
+            assert_eq!(Aggregation::Min.aggregate(&plf, 50..70), 20);
+            assert_eq!(Aggregation::Min.aggregate(&plf, Range { start: 90, end: 20 }), 10);
+            assert_eq!(Aggregation::Instant.aggregate(&plf, 50..70), 20);
+            assert_eq!(Aggregation::Instant.aggregate(&plf, Range { start: 90, end: 50 }), 10);
+            // evaluated at 40, 48, 56, 64 and 72
+            let median = Aggregation::Percentile { percentile: 50, num_points: 5 };
+            assert_eq!(median.aggregate(&plf, 40..80), 18);
+            let max = Aggregation::Percentile {
+                percentile: 100,
+                num_points: 5,
+            };
+            assert_eq!(max.aggregate(&plf, 40..80), 26);
+        });
+    }
+
+    #[test]
+    fn uniform_windows() {
+        run_test_with_periodicity(100, || {
+            assert_eq!(
+                SamplingConfig::uniform(4, Aggregation::Min).unwrap().windows,
+                vec![0..25, 25..50, 50..75, Range { start: 75, end: 0 }]
+            );
+            assert_eq!(SamplingConfig::uniform(1, Aggregation::Min).unwrap().windows, vec![Range { start: 0, end: 0 }]);
+        });
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        run_test_with_periodicity(100, || {
+            assert!(SamplingConfig::uniform(0, Aggregation::Min).is_err());
+            assert!(SamplingConfig::new(vec![0..100], Aggregation::Min, None).is_err());
+            assert!(SamplingConfig::new(vec![0..50], Aggregation::Min, Some(0)).is_err());
+            let percentile = |percentile, num_points| SamplingConfig::uniform(4, Aggregation::Percentile { percentile, num_points });
+            assert!(percentile(101, 5).is_err());
+            assert!(percentile(50, 0).is_err());
+            assert!(percentile(100, 1).is_ok());
+        });
+    }
+}
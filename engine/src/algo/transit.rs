@@ -0,0 +1,451 @@
+//! Door-to-door routing with public transit: walk, ride (possibly with transfers) and walk again.
+//!
+//! Earliest arrival queries run the connection scan algorithm (CSA) on a `Timetable`.
+//! Access walks from the source to nearby stops and egress walks from nearby stops to the target
+//! are computed with Dijkstra on a walking metric of the road graph, bounded by a maximum walking time.
+//! Walking directly to the target is always considered as an alternative.
+//! For park and ride, the access leg can use a different metric, for example car travel times.
+
+use super::*;
+use crate::{
+    algo::dijkstra::{generic_dijkstra::*, *},
+    datastr::{
+        graph::time_dependent::Timestamp,
+        timetable::{Connection, StopId, Timetable, TripId},
+    },
+    report::*,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Leg {
+    Walk {
+        path: Vec<NodeId>,
+        departure: Timestamp,
+        arrival: Timestamp,
+    },
+    Drive {
+        path: Vec<NodeId>,
+        departure: Timestamp,
+        arrival: Timestamp,
+    },
+    Ride {
+        trip: TripId,
+        from_stop: StopId,
+        to_stop: StopId,
+        departure: Timestamp,
+        arrival: Timestamp,
+    },
+    /// Walking between nearby stops.
+    Transfer {
+        from_stop: StopId,
+        to_stop: StopId,
+        departure: Timestamp,
+        arrival: Timestamp,
+    },
+}
+
+// How a stop was reached best.
+#[derive(Debug, Clone, Copy)]
+enum StopLabel {
+    Unreached,
+    Access,
+    Ride { board: usize, alight: usize },
+    Transfer { from: StopId },
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Target {
+    Direct,
+    ViaStop(StopId),
+}
+
+pub struct Server {
+    timetable: Timetable,
+    walking: OwnedGraph,
+    reversed_walking: OwnedGraph,
+    max_walking_time: Weight,
+
+    forward_data: DijkstraData<Weight>,
+    backward_data: DijkstraData<Weight>,
+    access: Vec<Weight>,
+    egress: Vec<Weight>,
+    stop_arrival: Vec<Timestamp>,
+    stop_label: Vec<StopLabel>,
+    trip_boarded: Vec<Option<usize>>,
+
+    // state of the last query for journey reconstruction
+    query: Option<TDQuery<Timestamp>>,
+    drive_access: bool,
+    target: Option<Target>,
+}
+
+impl Server {
+    /// `walking` is the road graph with walking times, the node ids of the timetable stops refer to it.
+    pub fn new(timetable: Timetable, walking: OwnedGraph, max_walking_time: Weight) -> Self {
+        let n = walking.num_nodes();
+        let num_stops = timetable.num_stops();
+        Self {
+            reversed_walking: OwnedGraph::reversed(&walking),
+            forward_data: DijkstraData::new(n),
+            backward_data: DijkstraData::new(n),
+            access: vec![INFINITY; num_stops],
+            egress: vec![INFINITY; num_stops],
+            stop_arrival: vec![INFINITY; num_stops],
+            stop_label: vec![StopLabel::Unreached; num_stops],
+            trip_boarded: vec![None; timetable.num_trips()],
+            timetable,
+            walking,
+            max_walking_time,
+            query: None,
+            drive_access: false,
+            target: None,
+        }
+    }
+
+    pub fn timetable(&self) -> &Timetable {
+        &self.timetable
+    }
+
+    // Bounded Dijkstra, returns the distance to `target` if it is within `max_time` and records the distances to all stops within `max_time`.
+    fn road_times(
+        graph: &OwnedGraph,
+        data: &mut DijkstraData<Weight>,
+        timetable: &Timetable,
+        stop_times: &mut [Weight],
+        from: NodeId,
+        target: NodeId,
+        max_time: Weight,
+    ) -> Option<Weight> {
+        let mut ops = DefaultOps::default();
+        let mut run = DijkstraRun::query(graph, data, &mut ops, DijkstraInit::from(from));
+        while let Some(node) = run.next() {
+            if *run.tentative_distance(node) > max_time {
+                break;
+            }
+        }
+        // all nodes with a tentative distance within the bound are settled
+        let within_bound = |node: NodeId| Some(data.distances[node as usize]).filter(|&dist| dist <= max_time);
+        for (stop, time) in stop_times.iter_mut().enumerate() {
+            *time = within_bound(timetable.stop_node(stop as StopId)).unwrap_or(INFINITY);
+        }
+        within_bound(target)
+    }
+
+    fn run(&mut self, query: TDQuery<Timestamp>, drive: Option<(&OwnedGraph, Weight)>) -> Option<Weight> {
+        self.query = Some(query);
+        self.drive_access = drive.is_some();
+
+        Self::road_times(
+            &self.reversed_walking,
+            &mut self.backward_data,
+            &self.timetable,
+            &mut self.egress,
+            query.to,
+            query.from,
+            self.max_walking_time,
+        );
+        let (access_graph, max_access_time) = drive.unwrap_or((&self.walking, self.max_walking_time));
+        let direct = Self::road_times(
+            access_graph,
+            &mut self.forward_data,
+            &self.timetable,
+            &mut self.access,
+            query.from,
+            query.to,
+            max_access_time,
+        );
+
+        // with park and ride the car has to be parked at a stop, so driving directly is no option
+        let mut best_arrival = direct.filter(|_| drive.is_none()).map(|walk| query.departure + walk).unwrap_or(INFINITY);
+        let mut target = if best_arrival < INFINITY { Some(Target::Direct) } else { None };
+
+        for stop in 0..self.timetable.num_stops() {
+            self.stop_label[stop] = StopLabel::Unreached;
+            self.stop_arrival[stop] = INFINITY;
+            if self.access[stop] < INFINITY {
+                self.stop_arrival[stop] = query.departure + self.access[stop];
+                self.stop_label[stop] = StopLabel::Access;
+                if self.egress[stop] < INFINITY && self.stop_arrival[stop] + self.egress[stop] < best_arrival {
+                    best_arrival = self.stop_arrival[stop] + self.egress[stop];
+                    target = Some(Target::ViaStop(stop as StopId));
+                }
+            }
+        }
+        for boarded in &mut self.trip_boarded {
+            *boarded = None;
+        }
+
+        let mut num_scanned_connections: usize = 0;
+        let connections = self.timetable.connections();
+        for (
+            idx,
+            &Connection {
+                dep_stop,
+                arr_stop,
+                departure,
+                arrival,
+                trip,
+            },
+        ) in connections
+            .iter()
+            .enumerate()
+            .skip(self.timetable.first_connection_departing_at_or_after(query.departure))
+        {
+            // arrival is never before departure, so no later connection can improve the result
+            if departure >= best_arrival {
+                break;
+            }
+            num_scanned_connections += 1;
+
+            if self.trip_boarded[trip as usize].is_none() && self.stop_arrival[dep_stop as usize] <= departure {
+                self.trip_boarded[trip as usize] = Some(idx);
+            }
+            let board = match self.trip_boarded[trip as usize] {
+                Some(board) => board,
+                None => continue,
+            };
+            if arrival >= self.stop_arrival[arr_stop as usize] {
+                continue;
+            }
+
+            self.stop_arrival[arr_stop as usize] = arrival;
+            self.stop_label[arr_stop as usize] = StopLabel::Ride { board, alight: idx };
+            if self.egress[arr_stop as usize] < INFINITY && arrival + self.egress[arr_stop as usize] < best_arrival {
+                best_arrival = arrival + self.egress[arr_stop as usize];
+                target = Some(Target::ViaStop(arr_stop));
+            }
+
+            for Link { node: other, weight } in LinkIterable::<Link>::link_iter(self.timetable.footpaths(), arr_stop) {
+                let other_arrival = arrival + weight;
+                if other_arrival < self.stop_arrival[other as usize] {
+                    self.stop_arrival[other as usize] = other_arrival;
+                    self.stop_label[other as usize] = StopLabel::Transfer { from: arr_stop };
+                    if self.egress[other as usize] < INFINITY && other_arrival + self.egress[other as usize] < best_arrival {
+                        best_arrival = other_arrival + self.egress[other as usize];
+                        target = Some(Target::ViaStop(other));
+                    }
+                }
+            }
+        }
+        report!("num_scanned_connections", num_scanned_connections);
+
+        self.target = target;
+        target.map(|_| best_arrival - query.departure)
+    }
+
+    /// Door-to-door query with the access leg by car.
+    /// The car is parked at a stop which can be reached within `max_driving_time`, the egress leg is walked.
+    /// `car` has to use the same node ids as the walking graph.
+    pub fn park_and_ride(&mut self, query: TDQuery<Timestamp>, car: &OwnedGraph, max_driving_time: Weight) -> QueryResult<PathServerWrapper<'_>, Weight> {
+        assert_eq!(car.num_nodes(), self.walking.num_nodes());
+        QueryResult::new(self.run(query, Some((car, max_driving_time))), PathServerWrapper(self))
+    }
+
+    /// Legs of the journey found by the last query.
+    pub fn journey(&self) -> Option<Vec<Leg>> {
+        let query = self.query?;
+        let target = self.target?;
+        let road_leg = |path, departure, arrival, drive| {
+            if drive {
+                Leg::Drive { path, departure, arrival }
+            } else {
+                Leg::Walk { path, departure, arrival }
+            }
+        };
+
+        let mut stop = match target {
+            Target::Direct => {
+                let arrival = query.departure + self.forward_data.distances[query.to as usize];
+                return Some(vec![road_leg(
+                    self.forward_data.node_path(query.from, query.to),
+                    query.departure,
+                    arrival,
+                    false,
+                )]);
+            }
+            Target::ViaStop(stop) => stop,
+        };
+
+        let mut legs = Vec::new();
+        let node = self.timetable.stop_node(stop);
+        let mut egress_path = self.backward_data.node_path(query.to, node);
+        egress_path.reverse();
+        let departure = self.stop_arrival[stop as usize];
+        // no walking when the target is the stop itself
+        if egress_path.len() > 1 {
+            legs.push(road_leg(egress_path, departure, departure + self.egress[stop as usize], false));
+        }
+
+        // every step strictly decreases the arrival time, so this terminates
+        loop {
+            match self.stop_label[stop as usize] {
+                StopLabel::Access => {
+                    let path = self.forward_data.node_path(query.from, self.timetable.stop_node(stop));
+                    if path.len() > 1 {
+                        legs.push(road_leg(path, query.departure, self.stop_arrival[stop as usize], self.drive_access));
+                    }
+                    break;
+                }
+                StopLabel::Ride { board, alight } => {
+                    let connections = self.timetable.connections();
+                    legs.push(Leg::Ride {
+                        trip: connections[board].trip,
+                        from_stop: connections[board].dep_stop,
+                        to_stop: stop,
+                        departure: connections[board].departure,
+                        arrival: connections[alight].arrival,
+                    });
+                    stop = connections[board].dep_stop;
+                }
+                StopLabel::Transfer { from } => {
+                    legs.push(Leg::Transfer {
+                        from_stop: from,
+                        to_stop: stop,
+                        departure: self.stop_arrival[from as usize],
+                        arrival: self.stop_arrival[stop as usize],
+                    });
+                    stop = from;
+                }
+                StopLabel::Unreached => unreachable!("journey through unreached stop"),
+            }
+        }
+
+        legs.reverse();
+        Some(legs)
+    }
+}
+
+pub struct PathServerWrapper<'s>(&'s Server);
+
+impl PathServer for PathServerWrapper<'_> {
+    type NodeInfo = Leg;
+    type EdgeInfo = Leg;
+
+    /// The legs of the journey.
+    fn reconstruct_node_path(&mut self) -> Vec<Self::NodeInfo> {
+        self.0.journey().unwrap()
+    }
+    /// The legs of the journey.
+    fn reconstruct_edge_path(&mut self) -> Vec<Self::EdgeInfo> {
+        self.0.journey().unwrap()
+    }
+}
+
+impl TDQueryServer<Timestamp, Weight> for Server {
+    type P<'s> = PathServerWrapper<'s>;
+
+    /// Door-to-door travel time with walking and transit.
+    fn td_query(&mut self, query: TDQuery<Timestamp>) -> QueryResult<Self::P<'_>, Weight> {
+        QueryResult::new(self.run(query, None), PathServerWrapper(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastr::timetable::Connection;
+
+    const MIN: Weight = 60_000;
+
+    // path 0 - 1 - ... - 6 in both directions, one minute walking per arc
+    fn walking() -> OwnedGraph {
+        let mut lists = vec![Vec::new(); 7];
+        for node in 0..6 {
+            lists[node].push(Link {
+                node: node as NodeId + 1,
+                weight: MIN,
+            });
+            lists[node + 1].push(Link {
+                node: node as NodeId,
+                weight: MIN,
+            });
+        }
+        OwnedGraph::from_adjancecy_lists(lists)
+    }
+
+    fn server() -> Server {
+        // stops at node 1, 5 and 6 - a trip from 1 to 5 and a second one from 6 to 5 which is useless
+        let connections = vec![
+            Connection {
+                dep_stop: 0,
+                arr_stop: 1,
+                departure: 10 * MIN,
+                arrival: 12 * MIN,
+                trip: 0,
+            },
+            Connection {
+                dep_stop: 2,
+                arr_stop: 1,
+                departure: 20 * MIN,
+                arrival: 21 * MIN,
+                trip: 1,
+            },
+        ];
+        let mut timetable = Timetable::new(vec![1, 5, 6], connections);
+        timetable.compute_footpaths(&walking(), 2 * MIN);
+        Server::new(timetable, walking(), 10 * MIN)
+    }
+
+    #[test]
+    fn walk_ride_walk() {
+        let mut server = server();
+        let mut result = server.td_query(TDQuery {
+            from: 0,
+            to: 6,
+            departure: 8 * MIN,
+        });
+        assert_eq!(result.distance(), Some(5 * MIN));
+        assert_eq!(
+            result.node_path().unwrap(),
+            vec![
+                Leg::Walk {
+                    path: vec![0, 1],
+                    departure: 8 * MIN,
+                    arrival: 9 * MIN
+                },
+                Leg::Ride {
+                    trip: 0,
+                    from_stop: 0,
+                    to_stop: 1,
+                    departure: 10 * MIN,
+                    arrival: 12 * MIN
+                },
+                Leg::Walk {
+                    path: vec![5, 6],
+                    departure: 12 * MIN,
+                    arrival: 13 * MIN
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn missed_connection_means_walking() {
+        let mut server = server();
+        let mut result = server.td_query(TDQuery {
+            from: 0,
+            to: 6,
+            departure: 9 * MIN + 1,
+        });
+        assert_eq!(result.distance(), Some(6 * MIN));
+        assert!(matches!(&result.node_path().unwrap()[..], [Leg::Walk { .. }]));
+    }
+
+    #[test]
+    fn park_and_ride() {
+        let mut server = server();
+        // driving is twice as fast, but the car has to be parked at a stop
+        let car = OwnedGraph::new(walking().first_out().to_vec(), walking().head().to_vec(), vec![MIN / 2; 12]);
+        let mut result = server.park_and_ride(TDQuery { from: 0, to: 5, departure: 0 }, &car, 10 * MIN);
+        // driving to the stop at the target beats waiting for the train
+        assert_eq!(result.distance(), Some(2 * MIN + MIN / 2));
+        assert_eq!(
+            result.node_path().unwrap(),
+            vec![Leg::Drive {
+                path: vec![0, 1, 2, 3, 4, 5],
+                departure: 0,
+                arrival: 2 * MIN + MIN / 2
+            }]
+        );
+    }
+}
@@ -0,0 +1,612 @@
+//! Vehicle routing with capacities and time windows.
+//!
+//! Travel times between all depots and jobs are computed up front with bucket many-to-many queries on a perfectly customized CCH (`BCCHNearestNeighbor`).
+//! The closest jobs of each job serve as candidates for insertions and local search moves.
+//! Tours are built by cheapest insertion and improved by local search with 2-opt, relocate and exchange moves.
+//! Several runs with different insertion orders are executed in parallel and the best solution is returned.
+//! The objective is to serve as many jobs as possible and then to minimize the total travel time.
+
+use super::*;
+use crate::algo::customizable_contraction_hierarchy::query::nearest_neighbor::BCCHNearestNeighbor;
+use rand::{prelude::*, rngs::StdRng};
+use rayon::prelude::*;
+
+/// Travel times between all pairs of a set of locations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DistanceTable {
+    num_locations: usize,
+    distances: Vec<Weight>,
+}
+
+impl DistanceTable {
+    /// Bucket many-to-many queries between all `locations`.
+    pub fn new(nn: &mut BCCHNearestNeighbor, locations: &[NodeId]) -> Self {
+        Self::between(nn, locations, locations)
+    }
+
+    /// Bucket many-to-many queries from `from_nodes[i]` to `to_nodes[j]` for all pairs of location indices.
+    /// Locations may be left at another node than they are entered at, for example positions on edges.
+    pub fn between(nn: &mut BCCHNearestNeighbor, from_nodes: &[NodeId], to_nodes: &[NodeId]) -> Self {
+        assert_eq!(from_nodes.len(), to_nodes.len());
+        // bucket selection does not support duplicate targets
+        let dedup = |nodes: &[NodeId]| {
+            let mut nodes = nodes.to_vec();
+            nodes.sort_unstable();
+            nodes.dedup();
+            nodes
+        };
+        let (sources, targets) = (dedup(from_nodes), dedup(to_nodes));
+        let idx = |nodes: &[NodeId], node: NodeId| nodes.binary_search(&node).unwrap();
+
+        let mut node_distances = vec![INFINITY; sources.len() * targets.len()];
+        let mut selected = nn.select_targets(&targets);
+        for (source_idx, &source) in sources.iter().enumerate() {
+            for (distance, target) in selected.query(source, targets.len()) {
+                node_distances[source_idx * targets.len() + idx(&targets, target)] = distance;
+            }
+        }
+
+        let distances = from_nodes
+            .iter()
+            .flat_map(|&from| {
+                let row = idx(&sources, from) * targets.len();
+                to_nodes.iter().map(|&to| node_distances[row + idx(&targets, to)]).collect::<Vec<_>>()
+            })
+            .collect();
+        Self::from_matrix(from_nodes.len(), distances)
+    }
+
+    /// `distances` row by row.
+    pub fn from_matrix(num_locations: usize, distances: Vec<Weight>) -> Self {
+        assert_eq!(distances.len(), num_locations * num_locations);
+        Self { num_locations, distances }
+    }
+
+    pub fn num_locations(&self) -> usize {
+        self.num_locations
+    }
+
+    pub fn get(&self, from: usize, to: usize) -> Weight {
+        self.distances[from * self.num_locations + to]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeWindow {
+    pub earliest: Weight,
+    pub latest: Weight,
+}
+
+impl TimeWindow {
+    pub const ALWAYS: TimeWindow = TimeWindow { earliest: 0, latest: INFINITY };
+}
+
+/// Vehicles leave their depot and return to it within its opening hours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Depot {
+    pub node: NodeId,
+    pub opening: TimeWindow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vehicle {
+    pub depot: usize,
+    pub capacity: u32,
+}
+
+/// Service has to start within the time window, vehicles arriving early wait.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Job {
+    pub node: NodeId,
+    pub demand: u32,
+    pub service_time: Weight,
+    pub time_window: TimeWindow,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub depots: Vec<Depot>,
+    pub vehicles: Vec<Vehicle>,
+    pub jobs: Vec<Job>,
+}
+
+impl Problem {
+    /// Depot nodes followed by job nodes, the locations of the `DistanceTable` for this problem.
+    pub fn locations(&self) -> Vec<NodeId> {
+        self.depots.iter().map(|depot| depot.node).chain(self.jobs.iter().map(|job| job.node)).collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stop {
+    pub job: usize,
+    pub arrival: Weight,
+    pub service_start: Weight,
+    pub departure: Weight,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tour {
+    pub vehicle: usize,
+    /// Vehicles leave as soon as the depot opens.
+    pub departure: Weight,
+    pub stops: Vec<Stop>,
+    pub return_time: Weight,
+    pub travel_time: Weight,
+    pub load: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Solution {
+    /// Only vehicles with at least one job.
+    pub tours: Vec<Tour>,
+    pub unassigned: Vec<usize>,
+    pub travel_time: Weight,
+}
+
+// Tours of all vehicles during construction and local search.
+#[derive(Clone)]
+struct State {
+    routes: Vec<Vec<usize>>,
+    costs: Vec<Weight>,
+    route_of: Vec<Option<usize>>,
+}
+
+impl State {
+    fn set_route(&mut self, vehicle: usize, jobs: Vec<usize>, cost: Weight) {
+        for &job in &self.routes[vehicle] {
+            self.route_of[job] = None;
+        }
+        for &job in &jobs {
+            self.route_of[job] = Some(vehicle);
+        }
+        self.routes[vehicle] = jobs;
+        self.costs[vehicle] = cost;
+    }
+
+    fn position(&self, job: usize) -> Option<(usize, usize)> {
+        let vehicle = self.route_of[job]?;
+        Some((vehicle, self.routes[vehicle].iter().position(|&other| other == job).unwrap()))
+    }
+}
+
+pub struct Solver<'a> {
+    problem: &'a Problem,
+    table: &'a DistanceTable,
+    // closest jobs of each job
+    neighbors: Vec<Vec<usize>>,
+}
+
+impl<'a> Solver<'a> {
+    /// `table` has to contain the travel times between `problem.locations()`.
+    /// Moves only consider the `num_neighbors` closest jobs of each job.
+    pub fn new(problem: &'a Problem, table: &'a DistanceTable, num_neighbors: usize) -> Self {
+        assert_eq!(table.num_locations(), problem.depots.len() + problem.jobs.len());
+        assert!(problem.vehicles.iter().all(|vehicle| vehicle.depot < problem.depots.len()));
+        let num_depots = problem.depots.len();
+        let neighbors = (0..problem.jobs.len())
+            .map(|job| {
+                let mut others: Vec<usize> = (0..problem.jobs.len()).filter(|&other| other != job).collect();
+                others.sort_by_key(|&other| std::cmp::min(table.get(num_depots + job, num_depots + other), table.get(num_depots + other, num_depots + job)));
+                others.truncate(num_neighbors);
+                others
+            })
+            .collect();
+        Self { problem, table, neighbors }
+    }
+
+    /// Best solution of `num_runs` parallel runs, the first one inserts jobs by the end of their time windows, the others in random order.
+    pub fn solve(&self, num_runs: usize) -> Solution {
+        let (_, state, unassigned) = (0..std::cmp::max(num_runs, 1))
+            .into_par_iter()
+            .map(|run| {
+                let (state, unassigned) = self.run(run as u64);
+                ((unassigned.len(), state.costs.iter().sum::<Weight>(), run), state, unassigned)
+            })
+            .min_by_key(|(key, _, _)| *key)
+            .unwrap();
+
+        let tours: Vec<Tour> = state
+            .routes
+            .iter()
+            .enumerate()
+            .filter(|(_, jobs)| !jobs.is_empty())
+            .map(|(vehicle, jobs)| {
+                let mut stops = Vec::with_capacity(jobs.len());
+                let (travel_time, return_time) = self.schedule(vehicle, jobs, |stop| stops.push(stop)).unwrap();
+                Tour {
+                    vehicle,
+                    departure: self.problem.depots[self.problem.vehicles[vehicle].depot].opening.earliest,
+                    stops,
+                    return_time,
+                    travel_time,
+                    load: jobs.iter().map(|&job| self.problem.jobs[job].demand).sum(),
+                }
+            })
+            .collect();
+        Solution {
+            travel_time: tours.iter().map(|tour| tour.travel_time).sum(),
+            tours,
+            unassigned,
+        }
+    }
+
+    fn run(&self, seed: u64) -> (State, Vec<usize>) {
+        let mut order: Vec<usize> = (0..self.problem.jobs.len()).collect();
+        if seed == 0 {
+            order.sort_by_key(|&job| {
+                let time_window = self.problem.jobs[job].time_window;
+                (time_window.latest, time_window.earliest)
+            });
+        } else {
+            order.shuffle(&mut StdRng::seed_from_u64(seed));
+        }
+
+        let num_vehicles = self.problem.vehicles.len();
+        let mut state = State {
+            routes: vec![Vec::new(); num_vehicles],
+            costs: vec![0; num_vehicles],
+            route_of: vec![None; self.problem.jobs.len()],
+        };
+        let mut unassigned: Vec<usize> = order.into_iter().filter(|&job| !self.insert(&mut state, job)).collect();
+
+        loop {
+            if self.two_opt(&mut state) || self.relocate(&mut state) || self.exchange(&mut state) {
+                continue;
+            }
+            // local search may have made room for jobs which did not fit before
+            let num_unassigned = unassigned.len();
+            unassigned.retain(|&job| !self.insert(&mut state, job));
+            if unassigned.len() == num_unassigned {
+                break;
+            }
+        }
+
+        unassigned.sort_unstable();
+        (state, unassigned)
+    }
+
+    // Simulate a tour, returns travel time and return time if it is feasible.
+    fn schedule(&self, vehicle: usize, jobs: &[usize], mut stop_cb: impl FnMut(Stop)) -> Option<(Weight, Weight)> {
+        let vehicle = &self.problem.vehicles[vehicle];
+        let depot = &self.problem.depots[vehicle.depot];
+        if jobs.is_empty() {
+            return Some((0, depot.opening.earliest));
+        }
+        if jobs.iter().map(|&job| self.problem.jobs[job].demand).sum::<u32>() > vehicle.capacity {
+            return None;
+        }
+
+        let num_depots = self.problem.depots.len();
+        let mut time = depot.opening.earliest;
+        let mut travel_time = 0;
+        let mut location = vehicle.depot;
+        for &job_idx in jobs {
+            let job = &self.problem.jobs[job_idx];
+            let travel = self.table.get(location, num_depots + job_idx);
+            if travel >= INFINITY {
+                return None;
+            }
+            let arrival = time + travel;
+            if arrival > job.time_window.latest {
+                return None;
+            }
+            let service_start = std::cmp::max(arrival, job.time_window.earliest);
+            time = service_start + job.service_time;
+            travel_time += travel;
+            location = num_depots + job_idx;
+            stop_cb(Stop {
+                job: job_idx,
+                arrival,
+                service_start,
+                departure: time,
+            });
+        }
+
+        let travel = self.table.get(location, vehicle.depot);
+        if travel >= INFINITY || time + travel > depot.opening.latest {
+            return None;
+        }
+        Some((travel_time + travel, time + travel))
+    }
+
+    fn cost(&self, vehicle: usize, jobs: &[usize]) -> Option<Weight> {
+        self.schedule(vehicle, jobs, |_| ()).map(|(travel_time, _)| travel_time)
+    }
+
+    // Cheapest insertion into the tours containing close jobs or into an unused vehicle,
+    // into any tour only if that fails.
+    fn insert(&self, state: &mut State, job: usize) -> bool {
+        let mut candidates: Vec<usize> = self.neighbors[job].iter().filter_map(|&other| state.route_of[other]).collect();
+        let mut unused_depots = Vec::new();
+        for (vehicle, jobs) in state.routes.iter().enumerate() {
+            let depot = self.problem.vehicles[vehicle].depot;
+            if jobs.is_empty() && !unused_depots.contains(&(depot, self.problem.vehicles[vehicle].capacity)) {
+                unused_depots.push((depot, self.problem.vehicles[vehicle].capacity));
+                candidates.push(vehicle);
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let best = self
+            .best_insertion(state, job, candidates.iter().copied())
+            .or_else(|| self.best_insertion(state, job, 0..state.routes.len()));
+        if let Some((_, vehicle, position, cost)) = best {
+            let mut jobs = state.routes[vehicle].clone();
+            jobs.insert(position, job);
+            state.set_route(vehicle, jobs, cost);
+            true
+        } else {
+            false
+        }
+    }
+
+    // (added travel time, vehicle, position, new cost)
+    fn best_insertion(&self, state: &State, job: usize, vehicles: impl Iterator<Item = usize>) -> Option<(Weight, usize, usize, Weight)> {
+        let mut best: Option<(Weight, usize, usize, Weight)> = None;
+        for vehicle in vehicles {
+            let mut jobs = state.routes[vehicle].clone();
+            for position in 0..=jobs.len() {
+                jobs.insert(position, job);
+                if let Some(cost) = self.cost(vehicle, &jobs) {
+                    let delta = cost - state.costs[vehicle];
+                    if best.map(|(best_delta, ..)| delta < best_delta).unwrap_or(true) {
+                        best = Some((delta, vehicle, position, cost));
+                    }
+                }
+                jobs.remove(position);
+            }
+        }
+        best
+    }
+
+    // Reverse a part of a tour.
+    fn two_opt(&self, state: &mut State) -> bool {
+        for vehicle in 0..state.routes.len() {
+            let len = state.routes[vehicle].len();
+            for start in 0..len {
+                for end in start + 1..len {
+                    let mut jobs = state.routes[vehicle].clone();
+                    jobs[start..=end].reverse();
+                    if let Some(cost) = self.cost(vehicle, &jobs) {
+                        if cost < state.costs[vehicle] {
+                            state.set_route(vehicle, jobs, cost);
+                            return true;
+                        }
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    // Move a job next to one of its neighbors.
+    fn relocate(&self, state: &mut State) -> bool {
+        for job in 0..self.problem.jobs.len() {
+            let (from_vehicle, from_position) = match state.position(job) {
+                Some(position) => position,
+                None => continue,
+            };
+            for &neighbor in &self.neighbors[job] {
+                let (to_vehicle, neighbor_position) = match state.position(neighbor) {
+                    Some(position) => position,
+                    None => continue,
+                };
+                for target in [neighbor_position, neighbor_position + 1] {
+                    if from_vehicle == to_vehicle {
+                        let mut jobs = state.routes[from_vehicle].clone();
+                        jobs.remove(from_position);
+                        jobs.insert(if target > from_position { target - 1 } else { target }, job);
+                        if let Some(cost) = self.cost(from_vehicle, &jobs) {
+                            if cost < state.costs[from_vehicle] {
+                                state.set_route(from_vehicle, jobs, cost);
+                                return true;
+                            }
+                        }
+                    } else {
+                        let mut from_jobs = state.routes[from_vehicle].clone();
+                        from_jobs.remove(from_position);
+                        let mut to_jobs = state.routes[to_vehicle].clone();
+                        to_jobs.insert(target, job);
+                        if let (Some(from_cost), Some(to_cost)) = (self.cost(from_vehicle, &from_jobs), self.cost(to_vehicle, &to_jobs)) {
+                            if from_cost + to_cost < state.costs[from_vehicle] + state.costs[to_vehicle] {
+                                state.set_route(from_vehicle, from_jobs, from_cost);
+                                state.set_route(to_vehicle, to_jobs, to_cost);
+                                return true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    // Swap a job with one of its neighbors.
+    fn exchange(&self, state: &mut State) -> bool {
+        for job in 0..self.problem.jobs.len() {
+            let (vehicle, position) = match state.position(job) {
+                Some(position) => position,
+                None => continue,
+            };
+            for &neighbor in &self.neighbors[job] {
+                let (other_vehicle, other_position) = match state.position(neighbor) {
+                    Some(position) => position,
+                    None => continue,
+                };
+                if vehicle == other_vehicle {
+                    let mut jobs = state.routes[vehicle].clone();
+                    jobs.swap(position, other_position);
+                    if let Some(cost) = self.cost(vehicle, &jobs) {
+                        if cost < state.costs[vehicle] {
+                            state.set_route(vehicle, jobs, cost);
+                            return true;
+                        }
+                    }
+                } else {
+                    let mut jobs = state.routes[vehicle].clone();
+                    jobs[position] = neighbor;
+                    let mut other_jobs = state.routes[other_vehicle].clone();
+                    other_jobs[other_position] = job;
+                    if let (Some(cost), Some(other_cost)) = (self.cost(vehicle, &jobs), self.cost(other_vehicle, &other_jobs)) {
+                        if cost + other_cost < state.costs[vehicle] + state.costs[other_vehicle] {
+                            state.set_route(vehicle, jobs, cost);
+                            state.set_route(other_vehicle, other_jobs, other_cost);
+                            return true;
+                        }
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        algo::{
+            customizable_contraction_hierarchy::*,
+            dijkstra::{query::dijkstra::Server as DijkServer, *},
+        },
+        datastr::node_order::NodeOrder,
+    };
+
+    // jobs at integer points of a line with the depot at 0
+    fn line_table(positions: &[i32]) -> DistanceTable {
+        let n = positions.len();
+        DistanceTable::from_matrix(n, (0..n * n).map(|idx| (positions[idx / n] - positions[idx % n]).unsigned_abs() * 10).collect())
+    }
+
+    fn job(node: NodeId, time_window: TimeWindow) -> Job {
+        Job {
+            node,
+            demand: 1,
+            service_time: 5,
+            time_window,
+        }
+    }
+
+    fn check_feasible(problem: &Problem, solution: &Solution) {
+        let mut served = solution.unassigned.clone();
+        for tour in &solution.tours {
+            let vehicle = problem.vehicles[tour.vehicle];
+            assert!(tour.load <= vehicle.capacity);
+            assert!(tour.return_time <= problem.depots[vehicle.depot].opening.latest);
+            for stop in &tour.stops {
+                let time_window = problem.jobs[stop.job].time_window;
+                assert!(time_window.earliest <= stop.service_start && stop.service_start <= time_window.latest);
+                served.push(stop.job);
+            }
+        }
+        served.sort_unstable();
+        assert_eq!(served, (0..problem.jobs.len()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn distance_table_matches_dijkstra() {
+        // 3x3 grid with two way streets and one one-way street
+        let mut adjacency = vec![Vec::new(); 9];
+        for node in 0..9u32 {
+            if node % 3 < 2 {
+                adjacency[node as usize].push(Link {
+                    node: node + 1,
+                    weight: node + 1,
+                });
+                adjacency[node as usize + 1].push(Link { node, weight: node + 1 });
+            }
+            if node < 6 {
+                adjacency[node as usize].push(Link {
+                    node: node + 3,
+                    weight: 2 * node + 1,
+                });
+                if node != 1 {
+                    adjacency[node as usize + 3].push(Link { node, weight: 2 * node + 1 });
+                }
+            }
+        }
+        let graph = OwnedGraph::from_adjancecy_lists(adjacency);
+        let cch = CCH::fix_order_and_build(&graph, NodeOrder::from_node_order(vec![4, 0, 2, 6, 8, 1, 7, 3, 5]));
+        let customized = customize_perfect(customize(&cch, &graph));
+        let mut nn = BCCHNearestNeighbor::new(&customized);
+
+        let locations = [0, 8, 4, 0, 7];
+        let table = DistanceTable::new(&mut nn, &locations);
+        let mut dijkstra = DijkServer::<_, DefaultOps>::new(graph);
+        for (from_idx, &from) in locations.iter().enumerate() {
+            for (to_idx, &to) in locations.iter().enumerate() {
+                assert_eq!(Some(table.get(from_idx, to_idx)), dijkstra.query(Query { from, to }).distance());
+            }
+        }
+
+        let (from_nodes, to_nodes) = ([0, 8, 4, 1], [3, 3, 5, 4]);
+        let table = DistanceTable::between(&mut nn, &from_nodes, &to_nodes);
+        for (from_idx, &from) in from_nodes.iter().enumerate() {
+            for (to_idx, &to) in to_nodes.iter().enumerate() {
+                assert_eq!(Some(table.get(from_idx, to_idx)), dijkstra.query(Query { from, to }).distance());
+            }
+        }
+    }
+
+    #[test]
+    fn optimal_single_tour() {
+        let positions = [0, 3, -2, 7, 1, -4];
+        let problem = Problem {
+            depots: vec![Depot {
+                node: 0,
+                opening: TimeWindow::ALWAYS,
+            }],
+            vehicles: vec![Vehicle { depot: 0, capacity: 10 }],
+            // visit 7 before 3 and -4 last
+            jobs: vec![
+                job(1, TimeWindow { earliest: 150, latest: 300 }),
+                job(2, TimeWindow::ALWAYS),
+                job(3, TimeWindow { earliest: 0, latest: 100 }),
+                job(4, TimeWindow::ALWAYS),
+                job(5, TimeWindow { earliest: 300, latest: 500 }),
+            ],
+        };
+        let table = line_table(&positions);
+        let solution = Solver::new(&problem, &table, 4).solve(4);
+        check_feasible(&problem, &solution);
+        assert!(solution.unassigned.is_empty());
+        // 0 -> 7 -> -4 -> 0 is a lower bound and can be achieved
+        assert_eq!(solution.travel_time, 220);
+        let order: Vec<usize> = solution.tours[0].stops.iter().map(|stop| stop.job).collect();
+        let position = |job: usize| order.iter().position(|&other| other == job).unwrap();
+        assert!(position(2) < position(0));
+        assert_eq!(position(4), 4);
+        // waiting for the time window to open
+        let stop = solution.tours[0].stops[position(0)];
+        assert_eq!(stop.service_start, 150);
+        assert_eq!(stop.departure, 155);
+    }
+
+    #[test]
+    fn capacities_and_infeasible_jobs() {
+        let positions = [0, -3, -2, 2, 3, 1];
+        let problem = Problem {
+            depots: vec![Depot {
+                node: 0,
+                opening: TimeWindow { earliest: 0, latest: 1000 },
+            }],
+            vehicles: vec![Vehicle { depot: 0, capacity: 2 }, Vehicle { depot: 0, capacity: 2 }],
+            jobs: vec![
+                job(1, TimeWindow::ALWAYS),
+                job(2, TimeWindow::ALWAYS),
+                job(3, TimeWindow::ALWAYS),
+                job(4, TimeWindow::ALWAYS),
+                // too far to make it in time
+                job(5, TimeWindow { earliest: 0, latest: 5 }),
+            ],
+        };
+        let table = line_table(&positions);
+        let solution = Solver::new(&problem, &table, 2).solve(2);
+        check_feasible(&problem, &solution);
+        assert_eq!(solution.unassigned, vec![4]);
+        assert_eq!(solution.tours.len(), 2);
+        assert_eq!(solution.travel_time, 120);
+    }
+}
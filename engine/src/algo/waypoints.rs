@@ -0,0 +1,413 @@
+//! Routes through several waypoints, optionally visiting the intermediate ones in the best order.
+//!
+//! Legs between consecutive waypoints are queried with any `Router`, each leg departing when the previous one arrived,
+//! so time-dependent routers see the actual time of day at every waypoint.
+//! Waypoints are either stops with a dwell time, which split the route into legs, or pass-through points, which only shape the route.
+//!
+//! To find a good order of the intermediate waypoints, travel times between all waypoints are collected in a `DistanceTable`
+//! with `Router::distance_table`, which uses bucket many-to-many queries for a `BucketTableRouter`.
+//! The order is then optimized with fixed first and last waypoint, exactly by dynamic programming for few waypoints
+//! and otherwise with cheapest insertion followed by 2-opt and or-opt moves.
+//! Time-dependent travel times are only considered at the departure time while ordering.
+
+use super::{router::*, vrp::DistanceTable, *};
+use crate::datastr::graph::time_dependent::Timestamp;
+
+/// Up to this many intermediate waypoints, the order is optimized exactly.
+pub const MAX_EXACT_INTERMEDIATE: usize = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Visit {
+    /// The route only has to pass the waypoint.
+    PassThrough,
+    /// A stop where the vehicle waits for `dwell` before continuing.
+    Stop { dwell: Weight },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Waypoint {
+    pub node: NodeId,
+    pub visit: Visit,
+}
+
+impl Waypoint {
+    pub fn stop(node: NodeId, dwell: Weight) -> Self {
+        Self {
+            node,
+            visit: Visit::Stop { dwell },
+        }
+    }
+
+    pub fn pass_through(node: NodeId) -> Self {
+        Self {
+            node,
+            visit: Visit::PassThrough,
+        }
+    }
+
+    fn dwell(&self) -> Weight {
+        match self.visit {
+            Visit::PassThrough => 0,
+            Visit::Stop { dwell } => dwell,
+        }
+    }
+}
+
+/// The part of a route between two consecutive stops, including all pass-through waypoints in between.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leg {
+    /// Index of the waypoint the leg starts at.
+    pub from: usize,
+    /// Index of the stop the leg ends at.
+    pub to: usize,
+    pub route: Route,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WaypointRoute {
+    /// Waypoint indices in the order they are visited.
+    pub order: Vec<usize>,
+    pub legs: Vec<Leg>,
+}
+
+impl WaypointRoute {
+    pub fn departure(&self) -> Timestamp {
+        self.legs[0].route.departure
+    }
+
+    /// Arrival at the last waypoint.
+    pub fn arrival(&self) -> Timestamp {
+        self.legs.last().unwrap().route.arrival()
+    }
+
+    /// Driving time without dwell times.
+    pub fn travel_time(&self) -> Weight {
+        self.legs.iter().map(|leg| leg.route.travel_time).sum()
+    }
+
+    pub fn dwell_time(&self) -> Weight {
+        self.arrival() - self.departure() - self.travel_time()
+    }
+
+    /// Node path of the complete route.
+    pub fn node_path(&self) -> Vec<NodeId> {
+        let mut path = vec![self.legs[0].route.node_path[0]];
+        for leg in &self.legs {
+            path.extend_from_slice(&leg.route.node_path[1..]);
+        }
+        path
+    }
+}
+
+/// Route through `waypoints` in the given `order` of their indices, leaving the first one at `departure`.
+/// The first and last waypoint are always treated as stops, their dwell times are not part of the route.
+/// `None` if some waypoint cannot be reached.
+pub fn route_waypoints<R: Router + ?Sized>(router: &mut R, waypoints: &[Waypoint], order: &[usize], departure: Timestamp) -> Option<WaypointRoute> {
+    assert!(order.len() >= 2);
+    let mut legs = Vec::new();
+    let mut leg_start = order[0];
+    let mut current: Option<Route> = None;
+    let mut t = departure;
+
+    for (pos, pair) in order.windows(2).enumerate() {
+        let (from, to) = (waypoints[pair[0]].node, waypoints[pair[1]].node);
+        let mut part = router.route(from, to, t)?;
+        t = part.arrival();
+        current = Some(match current.take() {
+            None => part,
+            Some(mut route) => {
+                route.travel_time += part.travel_time;
+                route.node_path.extend_from_slice(&part.node_path[1..]);
+                route.statistics.append(&mut part.statistics);
+                route
+            }
+        });
+
+        let is_last = pos + 2 == order.len();
+        if is_last || waypoints[pair[1]].visit != Visit::PassThrough {
+            legs.push(Leg {
+                from: leg_start,
+                to: pair[1],
+                route: current.take().unwrap(),
+            });
+            leg_start = pair[1];
+            if !is_last {
+                t += waypoints[pair[1]].dwell();
+            }
+        }
+    }
+
+    Some(WaypointRoute { order: order.to_vec(), legs })
+}
+
+/// Route through all `waypoints`, visiting the intermediate ones in the order with the lowest travel time.
+/// Travel times for ordering are queried with `router` at `departure`.
+pub fn route_optimized<R: Router + ?Sized>(router: &mut R, waypoints: &[Waypoint], departure: Timestamp) -> Option<WaypointRoute> {
+    let nodes: Vec<NodeId> = waypoints.iter().map(|waypoint| waypoint.node).collect();
+    let order = optimize_order(&router.distance_table(&nodes, &nodes, departure));
+    route_waypoints(router, waypoints, &order, departure)
+}
+
+/// Order of all locations of `table` with the lowest total travel time, starting at the first and ending at the last location.
+pub fn optimize_order(table: &DistanceTable) -> Vec<usize> {
+    let n = table.num_locations();
+    assert!(n >= 2);
+    if n - 2 <= MAX_EXACT_INTERMEDIATE {
+        exact_order(table)
+    } else {
+        let mut order = cheapest_insertion(table);
+        while two_opt(table, &mut order) || or_opt(table, &mut order) {}
+        order
+    }
+}
+
+/// Total travel time when visiting the locations in `order`.
+pub fn order_cost(table: &DistanceTable, order: &[usize]) -> u64 {
+    order.windows(2).map(|pair| u64::from(table.get(pair[0], pair[1]))).sum()
+}
+
+// Held-Karp over subsets of the intermediate locations.
+fn exact_order(table: &DistanceTable) -> Vec<usize> {
+    let n = table.num_locations();
+    let last = n - 1;
+    let k = n - 2;
+    if k == 0 {
+        return vec![0, last];
+    }
+    // cost[set][i]: shortest path from the start through all intermediate locations in `set`, ending at intermediate location `i`
+    let mut cost = vec![u64::MAX; (1 << k) * k];
+    let mut pred = vec![usize::MAX; (1 << k) * k];
+    for i in 0..k {
+        cost[(1 << i) * k + i] = u64::from(table.get(0, i + 1));
+    }
+    for set in 1usize..(1 << k) {
+        for i in (0..k).filter(|&i| set & (1 << i) != 0) {
+            let current = cost[set * k + i];
+            if current == u64::MAX {
+                continue;
+            }
+            for j in (0..k).filter(|&j| set & (1 << j) == 0) {
+                let next = (set | (1 << j)) * k + j;
+                let candidate = current + u64::from(table.get(i + 1, j + 1));
+                if candidate < cost[next] {
+                    cost[next] = candidate;
+                    pred[next] = i;
+                }
+            }
+        }
+    }
+
+    let all = (1 << k) - 1;
+    let mut i = (0..k).min_by_key(|&i| cost[all * k + i] + u64::from(table.get(i + 1, last))).unwrap();
+    let mut set = all;
+    let mut order = vec![last];
+    loop {
+        order.push(i + 1);
+        let prev = pred[set * k + i];
+        if prev == usize::MAX {
+            break;
+        }
+        set &= !(1 << i);
+        i = prev;
+    }
+    order.push(0);
+    order.reverse();
+    order
+}
+
+fn cheapest_insertion(table: &DistanceTable) -> Vec<usize> {
+    let n = table.num_locations();
+    let mut order = vec![0, n - 1];
+    // may be negative, the triangle inequality does not hold for example for time-dependent tables
+    let detour = |order: &[usize], pos: usize, location: usize| {
+        i64::from(table.get(order[pos - 1], location)) + i64::from(table.get(location, order[pos])) - i64::from(table.get(order[pos - 1], order[pos]))
+    };
+    let mut remaining: Vec<usize> = (1..n - 1).collect();
+    while !remaining.is_empty() {
+        let (_, idx, pos) = remaining
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, &location)| (1..order.len()).map(move |pos| (idx, location, pos)))
+            .map(|(idx, location, pos)| (detour(&order, pos, location), idx, pos))
+            .min()
+            .unwrap();
+        order.insert(pos, remaining.swap_remove(idx));
+    }
+    order
+}
+
+// Reverse a segment of intermediate locations, the first improving move is applied.
+fn two_opt(table: &DistanceTable, order: &mut [usize]) -> bool {
+    let current = order_cost(table, order);
+    for i in 1..order.len() - 1 {
+        for j in i + 1..order.len() - 1 {
+            order[i..=j].reverse();
+            if order_cost(table, order) < current {
+                return true;
+            }
+            order[i..=j].reverse();
+        }
+    }
+    false
+}
+
+// Move a segment of up to three intermediate locations to another position, the first improving move is applied.
+fn or_opt(table: &DistanceTable, order: &mut Vec<usize>) -> bool {
+    let current = order_cost(table, order);
+    for len in 1..=3 {
+        for start in 1..order.len() - len {
+            let mut rest = order.clone();
+            let segment: Vec<usize> = rest.drain(start..start + len).collect();
+            for pos in (1..rest.len()).filter(|&pos| pos != start) {
+                let candidate: Vec<usize> = rest[..pos].iter().chain(&segment).chain(&rest[pos..]).copied().collect();
+                if order_cost(table, &candidate) < current {
+                    *order = candidate;
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        algo::{
+            dijkstra::{
+                query::{dijkstra::Server as DijkServer, td_dijkstra::TDDijkstraOps},
+                *,
+            },
+            path_statistics::PathStatistics,
+        },
+        datastr::graph::time_dependent::*,
+    };
+    use rand::{prelude::*, rngs::StdRng};
+
+    fn brute_force(table: &DistanceTable) -> u64 {
+        fn permute(table: &DistanceTable, order: &mut Vec<usize>, remaining: &mut Vec<usize>, best: &mut u64) {
+            if remaining.is_empty() {
+                order.push(table.num_locations() - 1);
+                *best = std::cmp::min(*best, order_cost(table, order));
+                order.pop();
+                return;
+            }
+            for idx in 0..remaining.len() {
+                let location = remaining.remove(idx);
+                order.push(location);
+                permute(table, order, remaining, best);
+                order.pop();
+                remaining.insert(idx, location);
+            }
+        }
+        let mut best = u64::MAX;
+        permute(table, &mut vec![0], &mut (1..table.num_locations() - 1).collect(), &mut best);
+        best
+    }
+
+    fn random_table(rng: &mut StdRng, n: usize) -> DistanceTable {
+        let points: Vec<(i32, i32)> = (0..n).map(|_| (rng.gen_range(0..100), rng.gen_range(0..100))).collect();
+        // asymmetric, like one-way streets
+        DistanceTable::from_matrix(
+            n,
+            (0..n * n)
+                .map(|idx| {
+                    let (from, to) = (points[idx / n], points[idx % n]);
+                    (from.0 - to.0).unsigned_abs() + (from.1 - to.1).unsigned_abs() + if from.0 < to.0 { 5 } else { 0 }
+                })
+                .collect(),
+        )
+    }
+
+    fn is_permutation_with_fixed_ends(order: &[usize], n: usize) -> bool {
+        let mut sorted = order.to_vec();
+        sorted.sort_unstable();
+        order[0] == 0 && order[n - 1] == n - 1 && sorted == (0..n).collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn exact_and_heuristic_order() {
+        let mut rng = StdRng::seed_from_u64(42);
+        for n in 2..9 {
+            let table = random_table(&mut rng, n);
+            let order = exact_order(&table);
+            assert!(is_permutation_with_fixed_ends(&order, n));
+            assert_eq!(order_cost(&table, &order), brute_force(&table));
+        }
+
+        // points on a line are solved optimally by the local search
+        let positions: [i32; 22] = [0, 17, 3, 25, 11, 8, 30, 1, 19, 22, 5, 14, 27, 9, 16, 2, 12, 29, 6, 21, 13, 31];
+        let n = positions.len();
+        let table = DistanceTable::from_matrix(n, (0..n * n).map(|idx| (positions[idx / n] - positions[idx % n]).unsigned_abs()).collect());
+        let order = optimize_order(&table);
+        assert!(is_permutation_with_fixed_ends(&order, n));
+        assert_eq!(order_cost(&table, &order), 31);
+
+        // at least as good as just inserting
+        for _ in 0..5 {
+            let table = random_table(&mut rng, 20);
+            let order = optimize_order(&table);
+            assert!(is_permutation_with_fixed_ends(&order, 20));
+            assert!(order_cost(&table, &order) <= order_cost(&table, &cheapest_insertion(&table)));
+        }
+
+        // going directly from 0 to 2 takes longer than the detour over 1
+        let table = DistanceTable::from_matrix(3, vec![0, 1, 10, 1, 0, 1, 10, 1, 0]);
+        assert_eq!(cheapest_insertion(&table), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn stops_and_pass_through_legs() {
+        // a line 0 - 1 - 2 - 3 in both directions
+        let graph = OwnedGraph::new(vec![0, 1, 3, 5, 6], vec![1, 0, 2, 1, 3, 2], vec![1, 1, 2, 2, 3, 3]);
+        let mut router = StaticRouter::new(DijkServer::<_, DefaultOps>::new(graph.clone()), &graph);
+        let waypoints = [Waypoint::stop(0, 0), Waypoint::stop(3, 10), Waypoint::pass_through(2), Waypoint::stop(1, 100)];
+
+        let route = route_waypoints(&mut router, &waypoints, &[0, 1, 2, 3], 5).unwrap();
+        assert_eq!(route.legs.len(), 2);
+        assert_eq!((route.legs[0].from, route.legs[0].to), (0, 1));
+        assert_eq!((route.legs[1].from, route.legs[1].to), (1, 3));
+        assert_eq!(route.legs[0].route.arrival(), 11);
+        assert_eq!(route.legs[1].route.departure, 21);
+        assert_eq!(route.node_path(), vec![0, 1, 2, 3, 2, 1]);
+        assert_eq!(route.travel_time(), 11);
+        assert_eq!(route.dwell_time(), 10);
+        assert_eq!(route.arrival(), 5 + 11 + 10);
+
+        // 3 and 2 are visited on the way to 1 when going there last
+        let route = route_optimized(
+            &mut router,
+            &[Waypoint::stop(0, 0), Waypoint::stop(1, 1), Waypoint::stop(2, 1), Waypoint::stop(3, 0)],
+            0,
+        )
+        .unwrap();
+        assert_eq!(route.order, vec![0, 1, 2, 3]);
+        assert_eq!(route.travel_time(), 6);
+        assert_eq!(route.arrival(), 8);
+    }
+
+    #[test]
+    fn td_legs_depart_after_dwell() {
+        run_test_with_periodicity(1000, || {
+            // 0 -> 1 -> 2, the second edge gets slow at 100
+            let graph = TDGraph::new(vec![0, 1, 2, 2], vec![1, 2], vec![0, 1, 5], vec![0, 0, 50, 100, 200], vec![10, 5, 5, 50, 5]);
+            let mut router = TDRouter::new(DijkServer::<_, TDDijkstraOps>::new(graph.clone()), &graph);
+
+            let route = route_waypoints(
+                &mut router,
+                &[Waypoint::stop(0, 0), Waypoint::pass_through(1), Waypoint::stop(2, 0)],
+                &[0, 1, 2],
+                0,
+            )
+            .unwrap();
+            assert_eq!(route.legs.len(), 1);
+            assert_eq!(route.legs[0].route.statistics.weights, vec![10, 5]);
+
+            let route = route_waypoints(&mut router, &[Waypoint::stop(0, 0), Waypoint::stop(1, 90), Waypoint::stop(2, 0)], &[0, 1, 2], 0).unwrap();
+            assert_eq!(route.legs[1].route.departure, 100);
+            assert_eq!(route.legs[1].route.statistics, PathStatistics::evaluate_td(&graph, 100, &[1, 2], &[]));
+            assert_eq!(route.arrival(), 150);
+        });
+    }
+}
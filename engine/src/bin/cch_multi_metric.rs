@@ -0,0 +1,73 @@
+// Benchmark of batched multi metric customization against customizing each metric separately.
+// Takes a directory as argument, which has to contain the graph (in RoutingKit format) and a nested disection order.
+// Four metrics are derived from the travel time by scaling, each run customizes them separately and then in one batch.
+
+use std::{env, error::Error, path::Path};
+
+#[macro_use]
+extern crate rust_road_router;
+use rust_road_router::{
+    algo::customizable_contraction_hierarchy::*,
+    cli::CliErr,
+    datastr::{graph::*, node_order::NodeOrder},
+    io::*,
+    report::{benchmark::measure, *},
+};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let _reporter = enable_reporting("cch_multi_metric");
+    report!("num_threads", rayon::current_num_threads());
+    report!("simd", cfg!(target_arch = "x86_64"));
+
+    let mut args = env::args().skip(1);
+    let arg = &args.next().ok_or(CliErr("No directory arg given"))?;
+    let path = Path::new(arg);
+
+    let graph = WeightedGraphReconstructor("travel_time").reconstruct_from(&path)?;
+    let order = NodeOrder::from_node_order(Vec::load_from(path.join("cch_perm"))?);
+    let cch = without_reporting(|| CCH::fix_order_and_build(&graph, order));
+
+    let metrics: Vec<Vec<Weight>> = [10, 11, 13, 16]
+        .iter()
+        .map(|&factor| {
+            graph
+                .weight()
+                .iter()
+                .map(|&weight| std::cmp::min(weight as u64 * factor / 10, INFINITY as u64) as Weight)
+                .collect()
+        })
+        .collect();
+    let metric_graphs: Vec<_> = metrics
+        .iter()
+        .map(|metric| BorrowedGraph::new(graph.first_out(), graph.head(), &metric[..]))
+        .collect();
+
+    let mut runs_ctxt = push_collection_context("runs");
+    for _ in 0..10 {
+        let _run = runs_ctxt.push_collection_item();
+
+        let (separate, separate_time) = measure(|| without_reporting(|| metric_graphs.iter().map(|metric| customize(&cch, metric)).collect::<Vec<_>>()));
+        let (batched, batched_time) =
+            measure(|| without_reporting(|| customize_multi_metric(&cch, [&metrics[0][..], &metrics[1][..], &metrics[2][..], &metrics[3][..]])));
+        report!("separate_running_time_ms", separate_time.as_secs_f64() * 1000.0);
+        report!("batched_running_time_ms", batched_time.as_secs_f64() * 1000.0);
+        report!("speedup", separate_time.as_secs_f64() / batched_time.as_secs_f64());
+
+        for (lane, customized) in separate.iter().enumerate() {
+            assert!(customized
+                .forward_graph()
+                .weight()
+                .iter()
+                .zip(batched.upward())
+                .all(|(&weight, lanes)| weight == lanes[lane]));
+            assert!(customized
+                .backward_graph()
+                .weight()
+                .iter()
+                .zip(batched.downward())
+                .all(|(&weight, lanes)| weight == lanes[lane]));
+        }
+    }
+
+    Ok(())
+}
@@ -1,14 +1,24 @@
 // Example of complete Time-Dependent Sampling toolchain.
 // Takes a directory as argument, which has to contain the graph (in RoutingKit format) and a nested disection order.
+// The customized samples are stored in `tds_samples` and reused on subsequent runs,
+// unless the graph, the order or the sampling config changed since.
 
-use std::{env, error::Error, path::Path};
+use std::{env, error::Error, path::Path, time::UNIX_EPOCH};
 
 use rust_road_router::{
-    algo::{customizable_contraction_hierarchy, time_dependent_sampling::Server, *},
+    algo::{
+        customizable_contraction_hierarchy,
+        time_dependent_sampling::{Samples, SamplingConfig, Server},
+        *,
+    },
     cli::CliErr,
-    datastr::{graph::time_dependent::*, node_order::NodeOrder},
+    datastr::{
+        graph::{time_dependent::*, Graph},
+        node_order::NodeOrder,
+    },
     io::*,
 };
+use serde_json::{json, Value};
 
 fn main() -> Result<(), Box<dyn Error>> {
     let arg = &env::args().skip(1).next().ok_or(CliErr("No directory arg given"))?;
@@ -18,8 +28,46 @@ fn main() -> Result<(), Box<dyn Error>> {
     let cch_order = Vec::load_from(path.join("cch_perm"))?;
 
     let cch = customizable_contraction_hierarchy::contract(&graph, NodeOrder::from_node_order(cch_order));
-    let mut server = Server::new(graph, &cch);
+    let config = SamplingConfig::default();
+    let samples_dir = path.join("tds_samples");
+    let key = cache_key(path, &graph, &config)?;
+    let samples: Samples = if is_cached(&samples_dir, &key)? {
+        (&cch).reconstruct_from(&samples_dir)?
+    } else {
+        let samples = Samples::customize(&graph, &cch, &config);
+        samples.deconstruct_to(&samples_dir)?;
+        std::fs::write(samples_dir.join("cache.json"), key.to_string())?;
+        samples
+    };
+    let mut server = Server::with_samples(graph, samples);
     println!("{:?}", server.td_query(TDQuery { from: 0, to: 1, departure: 42 }).distance());
 
     Ok(())
 }
+
+// Identifies the inputs of the samples, like the preprocessing caches of the `rust_road_router` tool.
+fn cache_key(path: &Path, graph: &TDGraph, config: &SamplingConfig) -> Result<Value, Box<dyn Error>> {
+    let modified = |file: &str| -> Result<u64, Box<dyn Error>> {
+        let modified = std::fs::metadata(path.join(file))?.modified()?;
+        Ok(modified.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()))
+    };
+    let mut key = json!({
+        "num_nodes": graph.num_nodes(),
+        "num_arcs": graph.num_arcs(),
+        "config": format!("{:?}", config),
+    });
+    for file in ["first_out", "head", "first_ipp_of_arc", "ipp_departure_time", "ipp_travel_time", "cch_perm"] {
+        key[format!("{}_modified", file)] = json!(modified(file)?);
+    }
+    Ok(key)
+}
+
+// Samples without a stored key, for example from earlier runs of this binary, never match.
+fn is_cached(dir: &Path, key: &Value) -> Result<bool, Box<dyn Error>> {
+    let path = dir.join("cache.json");
+    if !path.exists() {
+        return Ok(false);
+    }
+    let stored: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    Ok(&stored == key)
+}
@@ -13,3 +13,5 @@ impl Display for CliErr {
 }
 
 impl Error for CliErr {}
+
+pub mod config;
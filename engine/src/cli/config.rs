@@ -0,0 +1,530 @@
+//! Configuration files for the `rust_road_router` command line tool.
+//!
+//! Configs are JSON documents.
+//! All keys except `graph_dir` are optional.
+//! Relative paths in the config are resolved against the directory of the config file, except for files inside the graph directory, which are given relative to it.
+//!
+//! ```json
+//! {
+//!   "graph_dir": "karlsruhe",
+//!   "metrics": ["travel_time", "geo_distance"],
+//!   "algorithm": "cch",
+//!   "order": "cch_perm",
+//!   "threads": 8,
+//!   "flow_cutter": "../lib/InertialFlowCutter/build/console",
+//!   "queries": {
+//!     "source": "test/source",
+//!     "target": "test/target",
+//!     "ground_truth": { "travel_time": "test/travel_time_length" },
+//!     "count": 1000
+//!   },
+//!   "benchmark": {
+//!     "num_queries": 1000,
+//!     "seed": 42,
+//!     "warmup": 100,
+//!     "repetitions": 3,
+//!     "query_sets": [
+//!       { "kind": "uniform" },
+//!       { "name": "rank", "kind": "rank", "num_queries": 100 },
+//!       { "name": "10-50km", "kind": "distance_band", "min_m": 10000, "max_m": 50000 },
+//!       { "kind": "rush_hour", "windows": [[25200000, 32400000], [57600000, 68400000]] }
+//!     ]
+//!   },
+//!   "import": { "dimacs": "karlsruhe.gr", "coordinates": "karlsruhe.co" },
+//!   "export": { "dimacs": "karlsruhe_out.gr" }
+//! }
+//! ```
+
+use crate::experiments::benchmark::{QuerySetKind, QuerySetSpec};
+use serde_json::{Map, Value};
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+/// Error for malformed or incomplete config files.
+#[derive(Debug)]
+pub struct ConfigErr(pub String);
+
+impl Display for ConfigErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid config: {}", self.0)
+    }
+}
+
+impl Error for ConfigErr {}
+
+/// Speed-up technique used to answer queries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Dijkstra,
+    BidirDijkstra,
+    CH,
+    CCH,
+}
+
+impl Algorithm {
+    fn from_name(name: &str) -> Result<Self, ConfigErr> {
+        match name {
+            "dijkstra" => Ok(Algorithm::Dijkstra),
+            "bidir_dijkstra" => Ok(Algorithm::BidirDijkstra),
+            "ch" => Ok(Algorithm::CH),
+            "cch" => Ok(Algorithm::CCH),
+            _ => Err(ConfigErr(format!(
+                "unknown algorithm '{name}', expected one of 'dijkstra', 'bidir_dijkstra', 'ch' or 'cch'"
+            ))),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Algorithm::Dijkstra => "dijkstra",
+            Algorithm::BidirDijkstra => "bidir_dijkstra",
+            Algorithm::CH => "ch",
+            Algorithm::CCH => "cch",
+        }
+    }
+}
+
+/// Query files in the graph directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryConfig {
+    pub source: String,
+    pub target: String,
+    /// Expected distances for each metric, queries of metrics without an entry are not checked.
+    pub ground_truth: BTreeMap<String, String>,
+    /// Only run the first `count` queries.
+    pub count: Option<usize>,
+}
+
+impl Default for QueryConfig {
+    fn default() -> Self {
+        QueryConfig {
+            source: "test/source".to_string(),
+            target: "test/target".to_string(),
+            ground_truth: BTreeMap::new(),
+            count: None,
+        }
+    }
+}
+
+/// Parameters for query benchmarks.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkConfig {
+    /// Default number of queries of each query set.
+    pub num_queries: usize,
+    /// Default seed of each query set.
+    pub seed: u64,
+    /// Number of queries run before measuring.
+    pub warmup: usize,
+    /// How often each query is measured.
+    pub repetitions: usize,
+    /// Query sets to benchmark, a single uniform set if none are configured.
+    /// `rush_hour` sets can be generated, but not benchmarked with the static algorithms of the CLI.
+    pub query_sets: Vec<QuerySetSpec>,
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> Self {
+        BenchmarkConfig {
+            num_queries: 1000,
+            seed: 0,
+            warmup: 100,
+            repetitions: 1,
+            query_sets: Vec::new(),
+        }
+    }
+}
+
+impl BenchmarkConfig {
+    pub fn query_sets(&self) -> Vec<QuerySetSpec> {
+        if self.query_sets.is_empty() {
+            vec![QuerySetSpec {
+                name: "uniform".to_string(),
+                kind: QuerySetKind::Uniform,
+                num_queries: self.num_queries,
+                seed: self.seed,
+            }]
+        } else {
+            self.query_sets.clone()
+        }
+    }
+}
+
+/// External files in DIMACS format, used both for import and export.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DimacsFiles {
+    /// `.gr` file with the graph and the weights of the first metric.
+    pub dimacs: PathBuf,
+    /// Optional `.co` file with node coordinates.
+    pub coordinates: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Directory with the graph in RoutingKit format.
+    /// Preprocessing results are stored here as well.
+    pub graph_dir: PathBuf,
+    /// Names of the weight files in the graph directory.
+    pub metrics: Vec<String>,
+    pub algorithm: Algorithm,
+    /// Name of the nested dissection order file in the graph directory.
+    pub order: String,
+    /// Number of threads for parallel preprocessing, defaults to the number of cores.
+    pub threads: Option<usize>,
+    /// InertialFlowCutter console binary used to calculate orders.
+    pub flow_cutter: Option<PathBuf>,
+    pub queries: QueryConfig,
+    pub benchmark: BenchmarkConfig,
+    pub import: Option<DimacsFiles>,
+    pub export: Option<DimacsFiles>,
+}
+
+impl Config {
+    /// Load and validate a config file.
+    pub fn load_from<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|e| ConfigErr(format!("could not read {}: {e}", path.display())))?;
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        Ok(Self::parse(&content, base_dir)?)
+    }
+
+    /// Parse a config from a JSON string, resolving relative paths against `base_dir`.
+    pub fn parse(content: &str, base_dir: &Path) -> Result<Self, ConfigErr> {
+        let value: Value = serde_json::from_str(content).map_err(|e| ConfigErr(e.to_string()))?;
+        let root = as_object(&value, "config")?;
+        check_keys(
+            root,
+            "config",
+            &[
+                "graph_dir",
+                "metrics",
+                "algorithm",
+                "order",
+                "threads",
+                "flow_cutter",
+                "queries",
+                "benchmark",
+                "import",
+                "export",
+            ],
+        )?;
+
+        let graph_dir = base_dir.join(get_str(root, "graph_dir")?.ok_or_else(|| ConfigErr("missing 'graph_dir'".to_string()))?);
+
+        let metrics = match root.get("metrics") {
+            Some(metrics) => {
+                let metrics = metrics
+                    .as_array()
+                    .ok_or_else(|| ConfigErr("'metrics' must be a list of strings".to_string()))?
+                    .iter()
+                    .map(|metric| metric.as_str().map(str::to_string))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| ConfigErr("'metrics' must be a list of strings".to_string()))?;
+                if metrics.is_empty() {
+                    return Err(ConfigErr("'metrics' must not be empty".to_string()));
+                }
+                metrics
+            }
+            None => vec!["travel_time".to_string()],
+        };
+
+        let algorithm = get_str(root, "algorithm")?.map_or(Ok(Algorithm::CCH), Algorithm::from_name)?;
+        let order = get_str(root, "order")?.unwrap_or("cch_perm").to_string();
+        let threads = get_usize(root, "threads")?;
+        if threads == Some(0) {
+            return Err(ConfigErr("'threads' must be positive".to_string()));
+        }
+        let flow_cutter = get_str(root, "flow_cutter")?.map(|bin| base_dir.join(bin));
+
+        let mut queries = QueryConfig::default();
+        if let Some(obj) = root.get("queries") {
+            let obj = as_object(obj, "queries")?;
+            check_keys(obj, "queries", &["source", "target", "ground_truth", "count"])?;
+            if let Some(source) = get_str(obj, "source")? {
+                queries.source = source.to_string();
+            }
+            if let Some(target) = get_str(obj, "target")? {
+                queries.target = target.to_string();
+            }
+            if let Some(ground_truth) = obj.get("ground_truth") {
+                for (metric, file) in as_object(ground_truth, "ground_truth")? {
+                    if !metrics.contains(metric) {
+                        return Err(ConfigErr(format!("ground truth for unknown metric '{metric}'")));
+                    }
+                    let file = file
+                        .as_str()
+                        .ok_or_else(|| ConfigErr(format!("ground truth file for '{metric}' must be a string")))?;
+                    queries.ground_truth.insert(metric.clone(), file.to_string());
+                }
+            }
+            queries.count = get_usize(obj, "count")?;
+        }
+
+        let mut benchmark = BenchmarkConfig::default();
+        if let Some(obj) = root.get("benchmark") {
+            let obj = as_object(obj, "benchmark")?;
+            check_keys(obj, "benchmark", &["num_queries", "seed", "warmup", "repetitions", "query_sets"])?;
+            if let Some(num_queries) = get_usize(obj, "num_queries")? {
+                benchmark.num_queries = num_queries;
+            }
+            if let Some(seed) = get_u64(obj, "seed")? {
+                benchmark.seed = seed;
+            }
+            if let Some(warmup) = get_usize(obj, "warmup")? {
+                benchmark.warmup = warmup;
+            }
+            if let Some(repetitions) = get_usize(obj, "repetitions")? {
+                if repetitions == 0 {
+                    return Err(ConfigErr("'repetitions' must be positive".to_string()));
+                }
+                benchmark.repetitions = repetitions;
+            }
+            if let Some(sets) = obj.get("query_sets") {
+                let sets = sets.as_array().ok_or_else(|| ConfigErr("'query_sets' must be a list".to_string()))?;
+                for set in sets {
+                    let spec = query_set_spec(set, &benchmark)?;
+                    if benchmark.query_sets.iter().any(|other| other.name == spec.name) {
+                        return Err(ConfigErr(format!("duplicate query set '{}'", spec.name)));
+                    }
+                    benchmark.query_sets.push(spec);
+                }
+            }
+        }
+
+        let import = root.get("import").map(|obj| dimacs_files(obj, "import", base_dir)).transpose()?;
+        let export = root.get("export").map(|obj| dimacs_files(obj, "export", base_dir)).transpose()?;
+
+        Ok(Config {
+            graph_dir,
+            metrics,
+            algorithm,
+            order,
+            threads,
+            flow_cutter,
+            queries,
+            benchmark,
+            import,
+            export,
+        })
+    }
+
+    /// Directory where the CH for the given metric is stored.
+    /// Kept apart from the `<metric>_ch` directories, which other tools fill with RoutingKit CHs.
+    pub fn ch_dir(&self, metric: &str) -> PathBuf {
+        self.graph_dir.join("ch").join(metric)
+    }
+
+    /// Directory where the customized CCH for the given metric is stored.
+    pub fn customized_cch_dir(&self, metric: &str) -> PathBuf {
+        self.graph_dir.join(format!("{metric}_cch"))
+    }
+}
+
+fn as_object<'a>(value: &'a Value, name: &str) -> Result<&'a Map<String, Value>, ConfigErr> {
+    value.as_object().ok_or_else(|| ConfigErr(format!("'{name}' must be an object")))
+}
+
+// Typos in optional keys would otherwise silently fall back to the defaults
+fn check_keys(obj: &Map<String, Value>, name: &str, known: &[&str]) -> Result<(), ConfigErr> {
+    match obj.keys().find(|key| !known.contains(&key.as_str())) {
+        Some(key) => Err(ConfigErr(format!("unknown key '{key}' in '{name}'"))),
+        None => Ok(()),
+    }
+}
+
+fn get_str<'a>(obj: &'a Map<String, Value>, key: &str) -> Result<Option<&'a str>, ConfigErr> {
+    obj.get(key)
+        .map(|value| value.as_str().ok_or_else(|| ConfigErr(format!("'{key}' must be a string"))))
+        .transpose()
+}
+
+fn get_u64(obj: &Map<String, Value>, key: &str) -> Result<Option<u64>, ConfigErr> {
+    obj.get(key)
+        .map(|value| value.as_u64().ok_or_else(|| ConfigErr(format!("'{key}' must be a non negative integer"))))
+        .transpose()
+}
+
+fn get_usize(obj: &Map<String, Value>, key: &str) -> Result<Option<usize>, ConfigErr> {
+    get_u64(obj, key).map(|value| value.map(|value| value as usize))
+}
+
+fn dimacs_files(value: &Value, name: &str, base_dir: &Path) -> Result<DimacsFiles, ConfigErr> {
+    let obj = as_object(value, name)?;
+    check_keys(obj, name, &["dimacs", "coordinates"])?;
+    Ok(DimacsFiles {
+        dimacs: base_dir.join(get_str(obj, "dimacs")?.ok_or_else(|| ConfigErr(format!("missing 'dimacs' in '{name}'")))?),
+        coordinates: get_str(obj, "coordinates")?.map(|file| base_dir.join(file)),
+    })
+}
+
+fn query_set_spec(value: &Value, benchmark: &BenchmarkConfig) -> Result<QuerySetSpec, ConfigErr> {
+    let obj = as_object(value, "query_sets")?;
+    let kind_name = get_str(obj, "kind")?.ok_or_else(|| ConfigErr("missing 'kind' in query set".to_string()))?;
+    let kind = match kind_name {
+        "uniform" => QuerySetKind::Uniform,
+        "rank" => QuerySetKind::DijkstraRank,
+        "distance_band" => {
+            let bound = |key: &str| {
+                obj.get(key)
+                    .and_then(Value::as_f64)
+                    .ok_or_else(|| ConfigErr(format!("distance band query sets need a numeric '{key}'")))
+            };
+            let (min_m, max_m) = (bound("min_m")?, bound("max_m")?);
+            if min_m > max_m {
+                return Err(ConfigErr("'min_m' must not be larger than 'max_m'".to_string()));
+            }
+            QuerySetKind::DistanceBand { min_m, max_m }
+        }
+        "rush_hour" => {
+            let err = || ConfigErr("'windows' must be a non empty list of [start, end] pairs with start < end".to_string());
+            let windows = obj
+                .get("windows")
+                .and_then(Value::as_array)
+                .ok_or_else(err)?
+                .iter()
+                .map(|window| match window.as_array().map(Vec::as_slice) {
+                    Some([start, end]) => match (start.as_u64(), end.as_u64()) {
+                        (Some(start), Some(end)) if start < end && end <= u32::MAX as u64 => Ok((start as u32, end as u32)),
+                        _ => Err(err()),
+                    },
+                    _ => Err(err()),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if windows.is_empty() {
+                return Err(err());
+            }
+            QuerySetKind::RushHour { windows }
+        }
+        _ => {
+            return Err(ConfigErr(format!(
+                "unknown query set kind '{kind_name}', expected one of 'uniform', 'rank', 'distance_band' or 'rush_hour'"
+            )))
+        }
+    };
+
+    let mut known = vec!["name", "kind", "num_queries", "seed"];
+    match kind {
+        QuerySetKind::DistanceBand { .. } => known.extend(["min_m", "max_m"]),
+        QuerySetKind::RushHour { .. } => known.push("windows"),
+        _ => (),
+    }
+    check_keys(obj, "query_sets", &known)?;
+
+    Ok(QuerySetSpec {
+        name: get_str(obj, "name")?.unwrap_or(kind.name()).to_string(),
+        num_queries: get_usize(obj, "num_queries")?.unwrap_or(benchmark.num_queries),
+        seed: get_u64(obj, "seed")?.unwrap_or(benchmark.seed),
+        kind,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults() {
+        let config = Config::parse(r#"{ "graph_dir": "graph" }"#, Path::new("/configs")).unwrap();
+        assert_eq!(config.graph_dir, Path::new("/configs/graph"));
+        assert_eq!(config.metrics, vec!["travel_time".to_string()]);
+        assert_eq!(config.algorithm, Algorithm::CCH);
+        assert_eq!(config.order, "cch_perm");
+        assert_eq!(config.queries, QueryConfig::default());
+        assert_eq!(config.benchmark, BenchmarkConfig::default());
+        assert_eq!(config.import, None);
+    }
+
+    #[test]
+    fn full_config() {
+        let config = Config::parse(
+            r#"{
+                "graph_dir": "/data/graph",
+                "metrics": ["travel_time", "geo_distance"],
+                "algorithm": "bidir_dijkstra",
+                "order": "order",
+                "threads": 4,
+                "flow_cutter": "console",
+                "queries": { "ground_truth": { "geo_distance": "test/geo_distance_length" }, "count": 10 },
+                "benchmark": {
+                    "num_queries": 5,
+                    "seed": 42,
+                    "repetitions": 3,
+                    "query_sets": [{ "kind": "rank", "seed": 1 }, { "name": "long", "kind": "distance_band", "min_m": 1e5, "max_m": 2e5 }]
+                },
+                "export": { "dimacs": "out.gr", "coordinates": "out.co" }
+            }"#,
+            Path::new("configs"),
+        )
+        .unwrap();
+        assert_eq!(config.graph_dir, Path::new("/data/graph"));
+        assert_eq!(config.metrics, vec!["travel_time".to_string(), "geo_distance".to_string()]);
+        assert_eq!(config.algorithm, Algorithm::BidirDijkstra);
+        assert_eq!(config.threads, Some(4));
+        assert_eq!(config.flow_cutter, Some(PathBuf::from("configs/console")));
+        assert_eq!(config.queries.source, "test/source");
+        assert_eq!(
+            config.queries.ground_truth.get("geo_distance").map(String::as_str),
+            Some("test/geo_distance_length")
+        );
+        assert_eq!(config.queries.count, Some(10));
+        assert_eq!((config.benchmark.warmup, config.benchmark.repetitions), (100, 3));
+        assert_eq!(
+            config.benchmark.query_sets(),
+            vec![
+                QuerySetSpec {
+                    name: "rank".to_string(),
+                    kind: QuerySetKind::DijkstraRank,
+                    num_queries: 5,
+                    seed: 1
+                },
+                QuerySetSpec {
+                    name: "long".to_string(),
+                    kind: QuerySetKind::DistanceBand { min_m: 1e5, max_m: 2e5 },
+                    num_queries: 5,
+                    seed: 42
+                }
+            ]
+        );
+        assert_eq!(
+            config.export,
+            Some(DimacsFiles {
+                dimacs: PathBuf::from("configs/out.gr"),
+                coordinates: Some(PathBuf::from("configs/out.co"))
+            })
+        );
+        assert_eq!(config.ch_dir("travel_time"), Path::new("/data/graph/ch/travel_time"));
+    }
+
+    #[test]
+    fn rejects_invalid_configs() {
+        let base_dir = Path::new("");
+        assert!(Config::parse(r#"{}"#, base_dir).is_err());
+        assert!(Config::parse(r#"{ "graph_dir": 5 }"#, base_dir).is_err());
+        assert!(Config::parse(r#"{ "graph_dir": "g", "algorithm": "astar" }"#, base_dir).is_err());
+        assert!(Config::parse(r#"{ "graph_dir": "g", "metrics": [] }"#, base_dir).is_err());
+        assert!(Config::parse(r#"{ "graph_dir": "g", "metric": "travel_time" }"#, base_dir).is_err());
+        assert!(Config::parse(r#"{ "graph_dir": "g", "threads": 0 }"#, base_dir).is_err());
+        assert!(Config::parse(r#"{ "graph_dir": "g", "queries": { "ground_truth": { "geo_distance": "gt" } } }"#, base_dir).is_err());
+        assert!(Config::parse(r#"{ "graph_dir": "g", "import": {} }"#, base_dir).is_err());
+        assert!(Config::parse(r#"{ "graph_dir": "g", "benchmark": { "query_sets": [{ "kind": "random" }] } }"#, base_dir).is_err());
+        assert!(Config::parse(
+            r#"{ "graph_dir": "g", "benchmark": { "query_sets": [{ "kind": "uniform", "windows": [] }] } }"#,
+            base_dir
+        )
+        .is_err());
+        assert!(Config::parse(
+            r#"{ "graph_dir": "g", "benchmark": { "query_sets": [{ "kind": "rush_hour", "windows": [[5, 1]] }] } }"#,
+            base_dir
+        )
+        .is_err());
+        assert!(Config::parse(
+            r#"{ "graph_dir": "g", "benchmark": { "query_sets": [{ "kind": "uniform" }, { "kind": "uniform" }] } }"#,
+            base_dir
+        )
+        .is_err());
+    }
+}
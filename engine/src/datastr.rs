@@ -1,9 +1,15 @@
 //! Data structures used by algorithms.
 
 pub mod clearlist_vector;
+pub mod elevation;
 pub mod graph;
 pub mod heap;
 pub mod index_heap;
+pub mod link_geometry;
+pub mod live_traffic;
 pub mod node_order;
 pub mod rank_select_map;
+pub mod road_attributes;
+pub mod road_closures;
 pub mod timestamped_vector;
+pub mod timetable;
@@ -0,0 +1,121 @@
+//! Per edge elevation attributes and weights derived from them.
+//!
+//! Node heights are stored as `elevation` (m, `f32`) next to the graph, the total ascent and descent along each edge
+//! as `ascent` and `descent` (m, `f32`).
+//! Nodes without elevation data have `NaN` heights, edges between them neither ascent nor descent.
+//! Ascent and descent follow the road between the nodes, so hilly edges may have both.
+
+use crate::{datastr::graph::*, io::*};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EdgeElevation {
+    ascent: Vec<f32>,
+    descent: Vec<f32>,
+}
+
+impl EdgeElevation {
+    pub fn new(ascent: Vec<f32>, descent: Vec<f32>) -> Self {
+        assert_eq!(ascent.len(), descent.len());
+        assert!(ascent.iter().chain(&descent).all(|&meters| meters >= 0.0));
+        Self { ascent, descent }
+    }
+
+    /// Straight lines between the nodes, for graphs without better data.
+    pub fn from_node_elevation<G: LinkIterable<(NodeIdT, EdgeIdT)>>(graph: &G, elevation: &[f32]) -> Self {
+        let mut ascent = vec![0.0; graph.num_arcs()];
+        let mut descent = vec![0.0; graph.num_arcs()];
+        for tail in 0..graph.num_nodes() {
+            for (NodeIdT(head), EdgeIdT(edge)) in graph.link_iter(tail as NodeId) {
+                let delta = elevation[head as usize] - elevation[tail];
+                ascent[edge as usize] = delta.max(0.0);
+                descent[edge as usize] = (-delta).max(0.0);
+            }
+        }
+        Self { ascent, descent }
+    }
+
+    pub fn num_arcs(&self) -> usize {
+        self.ascent.len()
+    }
+
+    pub fn ascent(&self) -> &[f32] {
+        &self.ascent
+    }
+
+    pub fn descent(&self) -> &[f32] {
+        &self.descent
+    }
+
+    /// Steepest average gradient of an edge, uphill or downhill, as a fraction.
+    /// Edges of length zero have gradient zero.
+    pub fn max_gradient(&self, edge: EdgeId, length: Weight) -> f32 {
+        if length == 0 {
+            return 0.0;
+        }
+        self.ascent[edge as usize].max(self.descent[edge as usize]) / length as f32
+    }
+
+    /// Effort for cycling or walking: each meter of ascent counts like `ascent_factor` meters on flat ground.
+    /// `geo_distance` in m.
+    pub fn effort_weights(&self, geo_distance: &[Weight], ascent_factor: f32) -> Vec<Weight> {
+        assert_eq!(geo_distance.len(), self.num_arcs());
+        geo_distance
+            .iter()
+            .zip(&self.ascent)
+            .map(|(&distance, &ascent)| std::cmp::min(distance as u64 + (ascent * ascent_factor).round() as u64, INFINITY as u64) as Weight)
+            .collect()
+    }
+
+    /// `weights` with `INFINITY` for edges steeper than `max_gradient`, for example for trucks.
+    pub fn restrict_gradient(&self, weights: &[Weight], geo_distance: &[Weight], max_gradient: f32) -> Vec<Weight> {
+        assert_eq!(weights.len(), self.num_arcs());
+        assert_eq!(geo_distance.len(), self.num_arcs());
+        weights
+            .iter()
+            .zip(geo_distance)
+            .enumerate()
+            .map(|(edge, (&weight, &distance))| {
+                if self.max_gradient(edge as EdgeId, distance) > max_gradient {
+                    INFINITY
+                } else {
+                    weight
+                }
+            })
+            .collect()
+    }
+}
+
+impl Deconstruct for EdgeElevation {
+    fn save_each(&self, store: &dyn Fn(&str, &dyn Save) -> std::io::Result<()>) -> std::io::Result<()> {
+        store("ascent", &self.ascent)?;
+        store("descent", &self.descent)?;
+        Ok(())
+    }
+}
+
+impl Reconstruct for EdgeElevation {
+    fn reconstruct_with(loader: Loader) -> std::io::Result<Self> {
+        Ok(Self::new(loader.load("ascent")?, loader.load("descent")?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn weights_from_elevation() {
+        // 0 -> 1 uphill, 1 -> 0 downhill, 1 -> 2 flat
+        let graph = UnweightedOwnedGraph::new(vec![0, 1, 3, 3], vec![1, 0, 2]);
+        let elevation = EdgeElevation::from_node_elevation(&graph, &[100.0, 110.0, 110.0]);
+        assert_eq!(elevation.ascent(), &[10.0, 0.0, 0.0]);
+        assert_eq!(elevation.descent(), &[0.0, 10.0, 0.0]);
+
+        let geo_distance = [100, 100, 0];
+        assert_eq!(elevation.max_gradient(0, 100), 0.1);
+        assert_eq!(elevation.max_gradient(2, 0), 0.0);
+        assert_eq!(elevation.effort_weights(&geo_distance, 8.0), vec![180, 100, 0]);
+        assert_eq!(elevation.restrict_gradient(&[5, 5, 5], &geo_distance, 0.08), vec![INFINITY, INFINITY, 5]);
+        assert_eq!(elevation.restrict_gradient(&[5, 5, 5], &geo_distance, 0.1), vec![5, 5, 5]);
+    }
+}
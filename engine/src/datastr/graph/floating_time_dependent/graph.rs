@@ -270,7 +270,8 @@ impl LiveGraph {
                 live_ipps.push(TTFPoint { at: t_live, val: live });
                 let pred_plf = graph.travel_time_function(edge_id as EdgeId);
                 let switchpoint = Self::switchpoint(pred_plf, live, t_soon);
-                if t_soon.fuzzy_lt(switchpoint.at) {
+                // `t_soon == t_live` is allowed, then the live travel time starts to approach the prediction immediately
+                if t_live.fuzzy_lt(t_soon) && t_soon.fuzzy_lt(switchpoint.at) {
                     live_ipps.push(TTFPoint { at: t_soon, val: live });
                 }
                 live_ipps.push(switchpoint);
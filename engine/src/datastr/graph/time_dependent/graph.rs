@@ -299,6 +299,18 @@ impl LiveTDGraph {
         }
     }
 
+    /// Lower bound of the travel time of an edge for all points in time, taking the live value into account.
+    pub fn global_lower_bound(&self, edge_id: EdgeId) -> Weight {
+        let lower = self.graph.travel_time_function(edge_id).lower_bound();
+        self.live[edge_id as usize].value().map(|live| std::cmp::min(live, lower)).unwrap_or(lower)
+    }
+
+    /// Upper bound of the travel time of an edge for all points in time, taking the live value into account.
+    pub fn global_upper_bound(&self, edge_id: EdgeId) -> Weight {
+        let upper = self.graph.travel_time_function(edge_id).upper_bound();
+        self.live[edge_id as usize].value().map(|live| std::cmp::max(live, upper)).unwrap_or(upper)
+    }
+
     pub fn line_graph(&self, mut turn_costs: impl FnMut(EdgeId, EdgeId) -> Option<Weight>) -> Self {
         let mut live = Vec::new();
         let graph = self.graph.line_graph(|from_edge, to_edge| {
@@ -437,6 +449,12 @@ impl PessimisticLiveTDGraph {
         }
     }
 
+    /// Upper bound of the travel time of an edge for all points in time, taking the live value into account.
+    pub fn global_upper_bound(&self, edge_id: EdgeId) -> Weight {
+        let upper = self.predicted_upper_bound(edge_id);
+        self.live[edge_id as usize].value().map(|(live, _)| std::cmp::max(live, upper)).unwrap_or(upper)
+    }
+
     pub fn predicted_upper_bound(&self, edge_id: EdgeId) -> Weight {
         self.graph.travel_time_function(edge_id).upper_bound()
     }
@@ -1,8 +1,8 @@
 //! Data structures for time-dependent routing with integer weights.
 //!
-//! The stuff in this module is actually not that interesting since it's just the
-//! leftovers from early attempts on time-dependend CCHs.
-//! What remains are the few parts that TD-S and TD-Dijkstra need.
+//! The stuff in this module started out as the leftovers from early attempts on time-dependend CCHs.
+//! It contains the parts that TD-S, TD-Dijkstra and the integer TCH (`algo::time_dependent_contraction_hierarchy`) need,
+//! including linking and merging of integer PLFs.
 
 use super::*;
 
@@ -182,6 +182,194 @@ impl<'a> PiecewiseLinearFunction<'a> {
             .max_by(|x, y| x.partial_cmp(y).unwrap())
             .unwrap_or(0.0)
     }
+
+    /// Link this function with another one, that is calculate the travel time function of traversing first this and then the other edge.
+    /// The result is exact at all breakpoints of this function and (rounded) at all departures which arrive at breakpoints of the other function.
+    /// In between, it is only an approximation of what integer evaluation of the two functions would yield.
+    pub fn link(&self, other: &PiecewiseLinearFunction) -> OwnedPLF {
+        if let (&[first_tt], &[second_tt]) = (self.travel_time, other.travel_time) {
+            return OwnedPLF::constant(first_tt + second_tt);
+        }
+
+        let first = self.full_period_ipps();
+        let mut departures: Vec<Timestamp> = first.iter().map(|ipp| ipp.at).collect();
+
+        for seg in first.windows(2) {
+            let from_arrival = seg[0].at + seg[0].val;
+            let to_arrival = seg[1].at + seg[1].val;
+            if from_arrival == to_arrival {
+                continue;
+            }
+            // find departures for which we arrive exactly at a breakpoint of the other function
+            let mut shift = from_arrival / period() * period();
+            while shift < to_arrival {
+                let relevant =
+                    other.departure_time.partition_point(|&dt| dt + shift <= from_arrival)..other.departure_time.partition_point(|&dt| dt + shift < to_arrival);
+                for &dt in &other.departure_time[relevant] {
+                    let delta = u64::from(dt + shift - from_arrival) * u64::from(seg[1].at - seg[0].at) / u64::from(to_arrival - from_arrival);
+                    let departure = seg[0].at + delta as Timestamp;
+                    departures.push(departure);
+                    if departure + 1 < seg[1].at {
+                        departures.push(departure + 1);
+                    }
+                }
+                shift += period();
+            }
+        }
+
+        departures.sort_unstable();
+        departures.dedup();
+
+        let travel_time = departures
+            .iter()
+            .map(|&departure| {
+                let first_tt = self.eval(departure);
+                first_tt + other.eval(departure + first_tt)
+            })
+            .collect();
+
+        OwnedPLF::new(departures, travel_time)
+    }
+
+    /// Merge this function with another one, that is calculate the pointwise minimum.
+    /// Additionally returns at which times which function is better, `true` meaning `self`.
+    /// Intersections are rounded to integer timestamps, so between breakpoints the result may slightly deviate from the exact minimum.
+    pub fn merge(&self, other: &PiecewiseLinearFunction) -> (OwnedPLF, Vec<(Timestamp, bool)>) {
+        if let (&[first_tt], &[second_tt]) = (self.travel_time, other.travel_time) {
+            return (OwnedPLF::constant(std::cmp::min(first_tt, second_tt)), vec![(0, first_tt <= second_tt)]);
+        }
+
+        let mut breakpoints: Vec<Timestamp> = self.full_period_ipps().into_iter().chain(other.full_period_ipps()).map(|ipp| ipp.at).collect();
+        breakpoints.sort_unstable();
+        breakpoints.dedup();
+
+        let mut departures = Vec::with_capacity(breakpoints.len());
+        for window in breakpoints.windows(2) {
+            departures.push(window[0]);
+            let delta_start = i64::from(self.eval(window[0])) - i64::from(other.eval(window[0]));
+            let delta_end = i64::from(self.eval(window[1])) - i64::from(other.eval(window[1]));
+            // the functions intersect somewhere in between
+            if (delta_start < 0 && delta_end > 0) || (delta_start > 0 && delta_end < 0) {
+                let intersection = window[0] + (delta_start.abs() * i64::from(window[1] - window[0]) / (delta_start - delta_end).abs()) as Timestamp;
+                if intersection > window[0] {
+                    departures.push(intersection);
+                }
+                if intersection + 1 < window[1] {
+                    departures.push(intersection + 1);
+                }
+            }
+        }
+        departures.push(period());
+
+        let mut travel_time = Vec::with_capacity(departures.len());
+        let mut better: Vec<(Timestamp, bool)> = Vec::new();
+        for &departure in &departures {
+            let (first_tt, second_tt) = (self.eval(departure), other.eval(departure));
+            travel_time.push(std::cmp::min(first_tt, second_tt));
+            let self_better = first_tt <= second_tt;
+            if departure < period() && better.last().map(|&(_, prev)| prev != self_better).unwrap_or(true) {
+                better.push((departure, self_better));
+            }
+        }
+
+        (OwnedPLF::new(departures, travel_time), better)
+    }
+
+    /// Approximate this function with the Douglas-Peucker algorithm.
+    /// The result will differ at most by `epsilon` from the original function, but is not necessarily a lower or upper bound.
+    pub fn approximate(&self, epsilon: Weight) -> OwnedPLF {
+        let ipps = self.full_period_ipps();
+        let mut result = Vec::with_capacity(ipps.len());
+        Self::douglas_peucker(&ipps, epsilon, &mut result);
+        let (departure_time, travel_time) = result.into_iter().map(|ipp| (ipp.at, ipp.val)).unzip();
+        OwnedPLF::new(departure_time, travel_time)
+    }
+
+    fn douglas_peucker(ipps: &[TTIpp], epsilon: Weight, result: &mut Vec<TTIpp>) {
+        if ipps.len() <= 2 {
+            result.extend_from_slice(ipps);
+            return;
+        }
+
+        let first = ipps.first().unwrap();
+        let last = ipps.last().unwrap();
+        let interpolate =
+            |at: Timestamp| i64::from(first.val) + (i64::from(last.val) - i64::from(first.val)) * i64::from(at - first.at) / i64::from(last.at - first.at);
+
+        let (i, delta) = ipps[1..ipps.len() - 1]
+            .iter()
+            .enumerate()
+            .map(|(i, ipp)| (i + 1, (i64::from(ipp.val) - interpolate(ipp.at)).abs()))
+            .max_by_key(|&(_, delta)| delta)
+            .unwrap();
+
+        if delta > i64::from(epsilon) {
+            Self::douglas_peucker(&ipps[0..=i], epsilon, result);
+            result.pop();
+            Self::douglas_peucker(&ipps[i..], epsilon, result);
+        } else {
+            result.push(*first);
+            result.push(*last);
+        }
+    }
+
+    // all interpolation points including one at the end of the period, also for constant functions
+    fn full_period_ipps(&self) -> Vec<TTIpp> {
+        if let &[const_tt] = self.travel_time {
+            return vec![TTIpp::new(0, const_tt), TTIpp::new(period(), const_tt)];
+        }
+        self.departure_time
+            .iter()
+            .zip(self.travel_time.iter())
+            .map(|(&at, &val)| TTIpp::new(at, val))
+            .collect()
+    }
+}
+
+/// Owned counterpart to `PiecewiseLinearFunction`, for example for the travel time profiles of shortcuts.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OwnedPLF {
+    departure_time: Vec<Timestamp>,
+    travel_time: Vec<Weight>,
+}
+
+impl OwnedPLF {
+    /// Create from two vecs of interpolation points covering the entire period.
+    /// Functions where all points have the same value will be reduced to a single point.
+    pub fn new(departure_time: Vec<Timestamp>, travel_time: Vec<Weight>) -> Self {
+        if travel_time.iter().all(|&tt| tt == travel_time[0]) {
+            return Self::constant(travel_time[0]);
+        }
+        let plf = Self { departure_time, travel_time };
+        // check invariants
+        plf.as_plf();
+        plf
+    }
+
+    /// A function with the same value for all points in time.
+    pub fn constant(travel_time: Weight) -> Self {
+        Self {
+            departure_time: vec![0],
+            travel_time: vec![travel_time],
+        }
+    }
+
+    pub fn as_plf(&self) -> PiecewiseLinearFunction<'_> {
+        PiecewiseLinearFunction::new(&self.departure_time, &self.travel_time)
+    }
+
+    pub fn num_ipps(&self) -> usize {
+        self.departure_time.len()
+    }
+}
+
+impl<'a> From<PiecewiseLinearFunction<'a>> for OwnedPLF {
+    fn from(plf: PiecewiseLinearFunction<'a>) -> Self {
+        Self {
+            departure_time: plf.departure_time.to_vec(),
+            travel_time: plf.travel_time.to_vec(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -275,4 +463,58 @@ mod tests {
             assert_eq!(all_ipps, vec![seg]);
         });
     }
+
+    #[test]
+    fn test_link_with_constant() {
+        run_test_with_periodicity(24, || {
+            let departure_time = vec![0, 12, 24];
+            let travel_time = vec![2, 4, 2];
+            let ttf = PiecewiseLinearFunction::new(&departure_time, &travel_time);
+            let constant = OwnedPLF::constant(1);
+            let linked = ttf.link(&constant.as_plf());
+            for &t in &departure_time {
+                assert_eq!(linked.as_plf().evaluate(t), ttf.evaluate(t) + 1);
+            }
+            // in between, interpolation of the sampled result may round differently
+            for t in 0..24 {
+                let expected = ttf.evaluate(t) + 1;
+                let actual = linked.as_plf().evaluate(t);
+                assert!(actual + 1 >= expected && actual <= expected + 1, "{} {} {}", t, actual, expected);
+            }
+            assert_eq!(constant.as_plf().link(&OwnedPLF::constant(2).as_plf()), OwnedPLF::constant(3));
+        });
+    }
+
+    #[test]
+    fn test_link_evaluates_at_arrival() {
+        run_test_with_periodicity(24, || {
+            let departure_time = vec![0, 12, 24];
+            let travel_time = vec![2, 4, 2];
+            let first = OwnedPLF::constant(6);
+            let second = PiecewiseLinearFunction::new(&departure_time, &travel_time);
+            let linked = first.as_plf().link(&second);
+            assert_eq!(linked.as_plf().evaluate(0), 6 + second.evaluate(6));
+            assert_eq!(linked.as_plf().evaluate(6), 6 + second.evaluate(12));
+            assert_eq!(linked.as_plf().evaluate(18), 6 + second.evaluate(0));
+        });
+    }
+
+    #[test]
+    fn test_merge() {
+        run_test_with_periodicity(24, || {
+            let departure_time = vec![0, 12, 24];
+            let travel_time = vec![2, 4, 2];
+            let ttf = PiecewiseLinearFunction::new(&departure_time, &travel_time);
+            let constant = OwnedPLF::constant(3);
+            let (merged, better) = ttf.merge(&constant.as_plf());
+            for t in 0..24 {
+                let expected = std::cmp::min(ttf.evaluate(t), 3);
+                let actual = merged.as_plf().evaluate(t);
+                assert!(actual <= expected && actual + 1 >= expected, "{} {} {}", t, actual, expected);
+            }
+            assert_eq!(better[0], (0, true));
+            let at_noon = better[better.partition_point(|&(t, _)| t <= 12) - 1];
+            assert!(!at_noon.1);
+        });
+    }
 }
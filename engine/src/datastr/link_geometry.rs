@@ -0,0 +1,180 @@
+//! Shape points of edges between their end nodes, for drawing routes along the actual roads.
+//!
+//! Stored in the same layout as the graph itself: `first_geometry_point_of_arc` holds `m + 1` offsets
+//! into `geometry_point_latitude` and `geometry_point_longitude` (degrees, `f32`).
+//! The points of each edge are in driving direction and exclude the tail and head node.
+
+use crate::{datastr::graph::*, io::*};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LinkGeometry {
+    first_point: Vec<u32>,
+    lat: Vec<f32>,
+    lng: Vec<f32>,
+}
+
+impl LinkGeometry {
+    pub fn new(first_point: Vec<u32>, lat: Vec<f32>, lng: Vec<f32>) -> Self {
+        assert!(!first_point.is_empty());
+        assert_eq!(first_point[0], 0);
+        assert_eq!(*first_point.last().unwrap() as usize, lat.len());
+        assert_eq!(lat.len(), lng.len());
+        assert!(first_point.windows(2).all(|range| range[0] <= range[1]));
+        Self { first_point, lat, lng }
+    }
+
+    /// Straight lines for all edges.
+    pub fn without_shapes(num_arcs: usize) -> Self {
+        Self::new(vec![0; num_arcs + 1], Vec::new(), Vec::new())
+    }
+
+    pub fn from_shapes(shapes: &[Vec<(f32, f32)>]) -> Self {
+        let mut first_point = Vec::with_capacity(shapes.len() + 1);
+        first_point.push(0);
+        let mut lat = Vec::new();
+        let mut lng = Vec::new();
+        for shape in shapes {
+            for &(point_lat, point_lng) in shape {
+                lat.push(point_lat);
+                lng.push(point_lng);
+            }
+            first_point.push(lat.len() as u32);
+        }
+        Self::new(first_point, lat, lng)
+    }
+
+    pub fn num_arcs(&self) -> usize {
+        self.first_point.len() - 1
+    }
+
+    pub fn shape(&self, edge: EdgeId) -> impl Iterator<Item = (f32, f32)> + '_ {
+        let range = self.first_point[edge as usize] as usize..self.first_point[edge as usize + 1] as usize;
+        self.lat[range.clone()].iter().copied().zip(self.lng[range].iter().copied())
+    }
+
+    /// Full resolution polyline of a path given as its edges and the coordinates of all nodes.
+    pub fn polyline<G: LinkIterable<(NodeIdT, EdgeIdT)>>(&self, graph: &G, from: NodeId, edge_path: &[EdgeId], lat: &[f32], lng: &[f32]) -> Vec<(f32, f32)> {
+        let mut polyline = vec![(lat[from as usize], lng[from as usize])];
+        let mut node = from;
+        for &edge in edge_path {
+            let head = graph
+                .link_iter(node)
+                .find(|&(_, EdgeIdT(other))| other == edge)
+                .map(|(NodeIdT(head), _)| head)
+                .expect("edge path not connected");
+            polyline.extend(self.shape(edge));
+            polyline.push((lat[head as usize], lng[head as usize]));
+            node = head;
+        }
+        polyline
+    }
+}
+
+impl Deconstruct for LinkGeometry {
+    fn save_each(&self, store: &dyn Fn(&str, &dyn Save) -> std::io::Result<()>) -> std::io::Result<()> {
+        store("first_geometry_point_of_arc", &self.first_point)?;
+        store("geometry_point_latitude", &self.lat)?;
+        store("geometry_point_longitude", &self.lng)?;
+        Ok(())
+    }
+}
+
+impl Reconstruct for LinkGeometry {
+    fn reconstruct_with(loader: Loader) -> std::io::Result<Self> {
+        Ok(Self::new(
+            loader.load("first_geometry_point_of_arc")?,
+            loader.load("geometry_point_latitude")?,
+            loader.load("geometry_point_longitude")?,
+        ))
+    }
+}
+
+/// Encode points in the Google polyline format with five decimal places.
+pub fn encode_polyline(points: &[(f32, f32)]) -> String {
+    let mut encoded = String::new();
+    let (mut prev_lat, mut prev_lng) = (0i64, 0i64);
+    for &(lat, lng) in points {
+        let (lat, lng) = ((f64::from(lat) * 1e5).round() as i64, (f64::from(lng) * 1e5).round() as i64);
+        encode_value(lat - prev_lat, &mut encoded);
+        encode_value(lng - prev_lng, &mut encoded);
+        prev_lat = lat;
+        prev_lng = lng;
+    }
+    encoded
+}
+
+fn encode_value(value: i64, encoded: &mut String) {
+    let mut value = if value < 0 { !(value << 1) } else { value << 1 } as u64;
+    while value >= 0x20 {
+        encoded.push(char::from(((0x20 | (value & 0x1f)) + 63) as u8));
+        value >>= 5;
+    }
+    encoded.push(char::from((value + 63) as u8));
+}
+
+/// Decode a Google polyline with five decimal places, `None` for malformed input.
+pub fn decode_polyline(encoded: &str) -> Option<Vec<(f32, f32)>> {
+    let mut bytes = encoded.bytes().peekable();
+    let mut points = Vec::new();
+    let (mut lat, mut lng) = (0i64, 0i64);
+    while bytes.peek().is_some() {
+        lat += decode_value(&mut bytes)?;
+        lng += decode_value(&mut bytes)?;
+        points.push(((lat as f64 / 1e5) as f32, (lng as f64 / 1e5) as f32));
+    }
+    Some(points)
+}
+
+fn decode_value(bytes: &mut impl Iterator<Item = u8>) -> Option<i64> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = bytes.next()?;
+        if !(63..127).contains(&byte) || shift > 60 {
+            return None;
+        }
+        let chunk = u64::from(byte - 63);
+        value |= (chunk & 0x1f) << shift;
+        shift += 5;
+        if chunk < 0x20 {
+            break;
+        }
+    }
+    let value = value as i64;
+    Some(if value & 1 == 1 { !(value >> 1) } else { value >> 1 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn google_polyline_example() {
+        let points = [(38.5, -120.2), (40.7, -120.95), (43.252, -126.453)];
+        let encoded = encode_polyline(&points);
+        assert_eq!(encoded, "_p~iF~ps|U_ulLnnqC_mqNvxq`@");
+        let decoded = decode_polyline(&encoded).unwrap();
+        for (&(lat, lng), &(decoded_lat, decoded_lng)) in points.iter().zip(&decoded) {
+            assert!((lat - decoded_lat).abs() < 1e-5 && (lng - decoded_lng).abs() < 1e-5);
+        }
+        assert_eq!(decode_polyline("_p~iF"), None);
+    }
+
+    #[test]
+    fn polyline_along_shapes() {
+        // 0 -> 1 -> 2, only the first edge is curved
+        let graph = UnweightedOwnedGraph::new(vec![0, 1, 2, 2], vec![1, 2]);
+        let geometry = LinkGeometry::from_shapes(&[vec![(0.5, 0.5), (0.5, 1.0)], vec![]]);
+        assert_eq!(geometry.shape(1).count(), 0);
+        let lat = [0.0, 1.0, 2.0];
+        let lng = [0.0, 1.0, 1.0];
+        assert_eq!(
+            geometry.polyline(&graph, 0, &[0, 1], &lat, &lng),
+            vec![(0.0, 0.0), (0.5, 0.5), (0.5, 1.0), (1.0, 1.0), (2.0, 1.0)]
+        );
+        assert_eq!(
+            LinkGeometry::without_shapes(2).polyline(&graph, 0, &[0, 1], &lat, &lng),
+            vec![(0.0, 0.0), (1.0, 1.0), (2.0, 1.0)]
+        );
+    }
+}
@@ -0,0 +1,182 @@
+//! Rolling state of live traffic reports.
+//!
+//! Reports arrive continuously from traffic feeds, each one is valid for a limited time.
+//! Newer reports for an edge replace older ones and expired reports are dropped.
+//! Timestamps here are wall clock milliseconds since the unix epoch, so the state survives midnight.
+//! For the different kinds of live graphs, the state is converted relative to the current time of day.
+
+use crate::{
+    datastr::graph::{time_dependent::period, time_dependent::Timestamp, *},
+    util::in_range_option::InRangeOption,
+};
+use std::collections::HashMap;
+
+/// Milliseconds since the unix epoch.
+pub type WallClock = u64;
+
+/// Time of day in the period of travel time functions for a wall clock time.
+pub fn time_of_day(now: WallClock) -> Timestamp {
+    (now % WallClock::from(period())) as Timestamp
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiveUpdate {
+    pub edge: EdgeId,
+    pub travel_time: Weight,
+    pub valid_until: WallClock,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LiveTraffic {
+    reports: HashMap<EdgeId, (Weight, WallClock)>,
+}
+
+impl LiveTraffic {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add reports, later reports for the same edge replace earlier ones.
+    /// Returns the number of edges where the live state changed.
+    pub fn ingest(&mut self, updates: impl IntoIterator<Item = LiveUpdate>) -> usize {
+        let mut changed = 0;
+        for LiveUpdate {
+            edge,
+            travel_time,
+            valid_until,
+        } in updates
+        {
+            if self.reports.insert(edge, (travel_time, valid_until)) != Some((travel_time, valid_until)) {
+                changed += 1;
+            }
+        }
+        changed
+    }
+
+    /// Drop all reports which are not valid anymore at `now`.
+    /// Returns the number of dropped reports.
+    pub fn expire(&mut self, now: WallClock) -> usize {
+        let before = self.reports.len();
+        self.reports.retain(|_, &mut (_, valid_until)| valid_until > now);
+        before - self.reports.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.reports.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.reports.is_empty()
+    }
+
+    /// Live travel time of `edge` if there is a report valid at `now`.
+    pub fn get(&self, edge: EdgeId, now: WallClock) -> Option<Weight> {
+        self.reports
+            .get(&edge)
+            .filter(|&&(_, valid_until)| valid_until > now)
+            .map(|&(travel_time, _)| travel_time)
+    }
+
+    fn valid_at(&self, now: WallClock) -> impl Iterator<Item = (EdgeId, Weight, Weight)> + '_ {
+        self.reports
+            .iter()
+            .filter(move |&(_, &(_, valid_until))| valid_until > now)
+            .map(move |(&edge, &(travel_time, valid_until))| (edge, travel_time, std::cmp::min(valid_until - now, WallClock::from(Weight::MAX)) as Weight))
+    }
+
+    /// Reports valid at `now` in the format of `live_data` files: edge id, travel time and remaining duration.
+    /// Sorted by edge id.
+    pub fn live_data(&self, now: WallClock) -> Vec<(EdgeId, Weight, Weight)> {
+        let mut live_data: Vec<_> = self.valid_at(now).collect();
+        live_data.sort_unstable();
+        live_data
+    }
+
+    /// Live data for `PessimisticLiveTDGraph` with `time_of_day(now)` as the live time.
+    /// Reports valid for a full period or longer are not really live and skipped, just like when loading `live_data` files.
+    pub fn pessimistic_live(&self, num_arcs: usize, now: WallClock) -> Vec<InRangeOption<(Weight, Timestamp)>> {
+        let t_live = time_of_day(now);
+        let mut live = vec![InRangeOption::NONE; num_arcs];
+        for (edge, travel_time, duration) in self.valid_at(now) {
+            if duration < period() {
+                live[edge as usize] = InRangeOption::some((travel_time, t_live + duration));
+            }
+        }
+        live
+    }
+
+    /// Live data for the floating point `LiveGraph` used by CATCHUp with `time_of_day(now)` as `t_live`.
+    pub fn catchup_live(&self, num_arcs: usize, now: WallClock) -> Vec<Option<(u32, u32)>> {
+        let t_live = time_of_day(now);
+        let mut live = vec![None; num_arcs];
+        for (edge, travel_time, duration) in self.valid_at(now) {
+            if duration < period() {
+                live[edge as usize] = Some((travel_time, t_live + duration));
+            }
+        }
+        live
+    }
+
+    /// Replace the weights of all edges with a report valid at `now`, for static metrics.
+    pub fn apply_to_weights(&self, weights: &mut [Weight], now: WallClock) {
+        for (edge, travel_time, _) in self.valid_at(now) {
+            weights[edge as usize] = travel_time;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastr::graph::time_dependent::run_test_with_periodicity;
+
+    fn update(edge: EdgeId, travel_time: Weight, valid_until: WallClock) -> LiveUpdate {
+        LiveUpdate {
+            edge,
+            travel_time,
+            valid_until,
+        }
+    }
+
+    #[test]
+    fn newer_reports_replace_older_ones_and_expire() {
+        let mut live = LiveTraffic::new();
+        assert_eq!(live.ingest(vec![update(1, 10, 100), update(2, 20, 200)]), 2);
+        assert_eq!(live.ingest(vec![update(1, 10, 100)]), 0);
+        assert_eq!(live.ingest(vec![update(1, 15, 300)]), 1);
+
+        assert_eq!(live.get(1, 150), Some(15));
+        assert_eq!(live.get(2, 200), None);
+        assert_eq!(live.live_data(150), vec![(1, 15, 150), (2, 20, 50)]);
+
+        assert_eq!(live.expire(200), 1);
+        assert_eq!(live.len(), 1);
+        assert_eq!(live.expire(300), 1);
+        assert!(live.is_empty());
+    }
+
+    #[test]
+    fn conversion_relative_to_time_of_day() {
+        run_test_with_periodicity(1000, || {
+            let mut live = LiveTraffic::new();
+            live.ingest(vec![update(0, 10, 5150), update(2, 20, 9000), update(3, 30, 5010)]);
+
+            let now = 5100;
+            assert_eq!(time_of_day(now), 100);
+
+            let pessimistic = live.pessimistic_live(4, now);
+            assert_eq!(pessimistic[0].value(), Some((10, 150)));
+            assert_eq!(pessimistic[1].value(), None);
+            // valid for more than a period
+            assert_eq!(pessimistic[2].value(), None);
+            // expired
+            assert_eq!(pessimistic[3].value(), None);
+
+            assert_eq!(live.catchup_live(4, now), vec![Some((10, 150)), None, None, None]);
+
+            let mut weights = vec![1; 4];
+            live.apply_to_weights(&mut weights, now);
+            assert_eq!(weights, vec![10, 1, 20, 1]);
+        });
+    }
+}
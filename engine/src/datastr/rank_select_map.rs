@@ -97,6 +97,12 @@ impl BitVec {
     pub fn count_ones(&self) -> usize {
         self.data.iter().map(|v| v.count_ones() as usize).sum()
     }
+
+    /// The ints containing the actual bits, for word level operations like popcounts of intersections.
+    /// Bits past `len()` are zero unless `set_all` was used.
+    pub fn words(&self) -> &[u64] {
+        &self.data
+    }
 }
 
 impl DataBytes for BitVec {
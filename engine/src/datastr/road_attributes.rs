@@ -0,0 +1,407 @@
+//! Typed per edge road attributes, stored column by column next to the graph.
+//!
+//! Columns are `functional_road_classes` (`u8`), `link_flags` (`u8`, see `LinkFlags`), `geo_distance` (`u32`, m),
+//! `speed_limit` (`u8`, km/h, `0` if unknown) and `street_name` (`u32` index into the street name table, `u32::MAX` for unnamed roads).
+//! The street name table is stored as `street_name_first_byte` offsets into the UTF-8 bytes in `street_name_bytes`.
+//!
+//! Derived graphs keep their attributes through edge origins: for each edge of the derived graph the id of the edge it stems from.
+//! `select` then gives the attributes of the derived graph.
+
+use crate::{algo::route_preferences::LinkFlags, datastr::graph::*, io::*};
+use std::collections::HashMap;
+
+/// Strings addressed by dense ids, stored in one byte vector.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StringTable {
+    first_byte: Vec<u32>,
+    bytes: Vec<u8>,
+}
+
+impl Default for StringTable {
+    fn default() -> Self {
+        Self {
+            first_byte: vec![0],
+            bytes: Vec::new(),
+        }
+    }
+}
+
+impl StringTable {
+    pub fn new(first_byte: Vec<u32>, bytes: Vec<u8>) -> Self {
+        Self::check(&first_byte, &bytes).unwrap();
+        Self { first_byte, bytes }
+    }
+
+    fn check(first_byte: &[u32], bytes: &[u8]) -> Result<(), &'static str> {
+        if first_byte.first() != Some(&0) || first_byte.last().map(|&last| last as usize) != Some(bytes.len()) {
+            return Err("string table offsets do not cover the bytes");
+        }
+        if first_byte.windows(2).any(|range| range[0] > range[1]) {
+            return Err("string table offsets not sorted");
+        }
+        if first_byte
+            .windows(2)
+            .any(|range| std::str::from_utf8(&bytes[range[0] as usize..range[1] as usize]).is_err())
+        {
+            return Err("string table contains invalid UTF-8");
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.first_byte.len() - 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push(&mut self, string: &str) -> u32 {
+        self.bytes.extend_from_slice(string.as_bytes());
+        self.first_byte.push(self.bytes.len() as u32);
+        (self.len() - 1) as u32
+    }
+
+    pub fn get(&self, idx: u32) -> &str {
+        std::str::from_utf8(self.bytes(idx)).unwrap()
+    }
+
+    fn bytes(&self, idx: u32) -> &[u8] {
+        &self.bytes[self.first_byte[idx as usize] as usize..self.first_byte[idx as usize + 1] as usize]
+    }
+}
+
+/// Deduplicates strings while building a `StringTable`.
+#[derive(Debug, Default)]
+pub struct StringTableBuilder {
+    table: StringTable,
+    ids: HashMap<String, u32>,
+}
+
+impl StringTableBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, string: &str) -> u32 {
+        if let Some(&id) = self.ids.get(string) {
+            return id;
+        }
+        let id = self.table.push(string);
+        self.ids.insert(string.to_string(), id);
+        id
+    }
+
+    pub fn build(self) -> StringTable {
+        self.table
+    }
+}
+
+pub const NO_NAME: u32 = u32::MAX;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoadAttributes {
+    road_classes: Vec<u8>,
+    flags: Vec<u8>,
+    geo_distance: Vec<Weight>,
+    speed_limits: Vec<u8>,
+    street_names: Vec<u32>,
+    names: StringTable,
+}
+
+impl RoadAttributes {
+    pub fn new(road_classes: Vec<u8>, flags: Vec<u8>, geo_distance: Vec<Weight>, speed_limits: Vec<u8>, street_names: Vec<u32>, names: StringTable) -> Self {
+        let attributes = Self {
+            road_classes,
+            flags,
+            geo_distance,
+            speed_limits,
+            street_names,
+            names,
+        };
+        attributes.check().unwrap();
+        attributes
+    }
+
+    fn check(&self) -> Result<(), &'static str> {
+        let m = self.road_classes.len();
+        if [self.flags.len(), self.geo_distance.len(), self.speed_limits.len(), self.street_names.len()]
+            .iter()
+            .any(|&len| len != m)
+        {
+            return Err("road attribute columns differ in length");
+        }
+        if self.street_names.iter().any(|&name| name != NO_NAME && name as usize >= self.names.len()) {
+            return Err("street name out of range of the street name table");
+        }
+        Ok(())
+    }
+
+    pub fn num_arcs(&self) -> usize {
+        self.road_classes.len()
+    }
+
+    pub fn road_class(&self, edge: EdgeId) -> u8 {
+        self.road_classes[edge as usize]
+    }
+
+    pub fn flags(&self, edge: EdgeId) -> LinkFlags {
+        LinkFlags(self.flags[edge as usize])
+    }
+
+    /// Length in m.
+    pub fn geo_distance(&self, edge: EdgeId) -> Weight {
+        self.geo_distance[edge as usize]
+    }
+
+    /// In km/h.
+    pub fn speed_limit(&self, edge: EdgeId) -> Option<u8> {
+        match self.speed_limits[edge as usize] {
+            0 => None,
+            limit => Some(limit),
+        }
+    }
+
+    pub fn street_name(&self, edge: EdgeId) -> Option<&str> {
+        match self.street_names[edge as usize] {
+            NO_NAME => None,
+            name => Some(self.names.get(name)),
+        }
+    }
+
+    /// Attributes of a derived graph, `edge_origins` contains the original edge of each derived edge.
+    /// The string table is shared, so names stay valid.
+    pub fn select(&self, edge_origins: &[EdgeId]) -> Self {
+        let column = |values: &[u8]| edge_origins.iter().map(|&edge| values[edge as usize]).collect();
+        Self {
+            road_classes: column(&self.road_classes),
+            flags: column(&self.flags),
+            geo_distance: edge_origins.iter().map(|&edge| self.geo_distance[edge as usize]).collect(),
+            speed_limits: column(&self.speed_limits),
+            street_names: edge_origins.iter().map(|&edge| self.street_names[edge as usize]).collect(),
+            names: self.names.clone(),
+        }
+    }
+
+    /// Totals along a path given by its edges.
+    pub fn summarize(&self, edge_path: &[EdgeId]) -> PathAttributes {
+        let mut summary = PathAttributes::default();
+        for &edge in edge_path {
+            let length = self.geo_distance(edge);
+            summary.length += length;
+            if self.flags(edge).contains(LinkFlags::TOLL) {
+                summary.toll_length += length;
+            }
+            if self.flags(edge).contains(LinkFlags::HIGHWAY) {
+                summary.motorway_length += length;
+            }
+            if let Some(name) = self.street_name(edge) {
+                if summary.street_names.last().map(|last| last != name).unwrap_or(true) {
+                    summary.street_names.push(name.to_string());
+                }
+            }
+        }
+        summary
+    }
+}
+
+/// Attribute totals of a route.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathAttributes {
+    /// In m.
+    pub length: Weight,
+    pub toll_length: Weight,
+    pub motorway_length: Weight,
+    /// Named streets in driving order, consecutive edges of the same street are merged.
+    pub street_names: Vec<String>,
+}
+
+impl PathAttributes {
+    /// Fraction of the length on controlled access roads.
+    pub fn motorway_share(&self) -> f64 {
+        if self.length == 0 {
+            0.0
+        } else {
+            f64::from(self.motorway_length) / f64::from(self.length)
+        }
+    }
+}
+
+/// One of possibly several names of a road, e.g. translations, alternative names or route numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NameCandidate<'a> {
+    pub name: &'a str,
+    pub language: &'a str,
+    /// The official street name, not an alternative name or route number.
+    pub official: bool,
+}
+
+/// The name to display for a road: official names before others, within those names in `preferred_language` first.
+/// Ties keep the first candidate, empty names are ignored.
+pub fn choose_street_name<'a>(candidates: impl IntoIterator<Item = NameCandidate<'a>>, preferred_language: Option<&str>) -> Option<&'a str> {
+    let mut best: Option<(NameCandidate, (bool, bool))> = None;
+    for candidate in candidates.into_iter().filter(|candidate| !candidate.name.is_empty()) {
+        let rank = (candidate.official, Some(candidate.language) == preferred_language);
+        if best.map(|(_, best_rank)| rank > best_rank).unwrap_or(true) {
+            best = Some((candidate, rank));
+        }
+    }
+    best.map(|(candidate, _)| candidate.name)
+}
+
+/// Edge origins of a line graph built by `line_graph`: each turn stems from the edge it starts on.
+pub fn line_graph_edge_origins(line_graph: &impl Graph) -> Vec<EdgeId> {
+    let mut origins = Vec::with_capacity(line_graph.num_arcs());
+    for edge in 0..line_graph.num_nodes() {
+        for _ in 0..line_graph.degree(edge as NodeId) {
+            origins.push(edge as EdgeId);
+        }
+    }
+    origins
+}
+
+impl Deconstruct for RoadAttributes {
+    fn save_each(&self, store: &dyn Fn(&str, &dyn Save) -> std::io::Result<()>) -> std::io::Result<()> {
+        store("functional_road_classes", &self.road_classes)?;
+        store("link_flags", &self.flags)?;
+        store("geo_distance", &self.geo_distance)?;
+        store("speed_limit", &self.speed_limits)?;
+        store("street_name", &self.street_names)?;
+        store("street_name_first_byte", &self.names.first_byte)?;
+        store("street_name_bytes", &self.names.bytes)?;
+        Ok(())
+    }
+}
+
+impl Reconstruct for RoadAttributes {
+    fn reconstruct_with(loader: Loader) -> std::io::Result<Self> {
+        let road_classes: Vec<u8> = loader.load("functional_road_classes")?;
+        let flags: Vec<u8> = loader.load("link_flags")?;
+        let geo_distance: Vec<Weight> = loader.load("geo_distance")?;
+        let speed_limits: Vec<u8> = loader.load("speed_limit")?;
+        let street_names: Vec<u32> = loader.load("street_name")?;
+        let first_byte: Vec<u32> = loader.load("street_name_first_byte")?;
+        let bytes: Vec<u8> = loader.load("street_name_bytes")?;
+
+        let invalid = |msg| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+        StringTable::check(&first_byte, &bytes).map_err(invalid)?;
+        let attributes = Self {
+            road_classes,
+            flags,
+            geo_distance,
+            speed_limits,
+            street_names,
+            names: StringTable { first_byte, bytes },
+        };
+        attributes.check().map_err(invalid)?;
+        Ok(attributes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastr::node_order::NodeOrder;
+
+    fn attributes() -> RoadAttributes {
+        let mut names = StringTableBuilder::new();
+        let main_street = names.intern("Hauptstraße");
+        let a5 = names.intern("A5");
+        assert_eq!(names.intern("Hauptstraße"), main_street);
+        RoadAttributes::new(
+            vec![5, 1, 1, 4],
+            vec![0, LinkFlags::HIGHWAY.0, (LinkFlags::HIGHWAY | LinkFlags::TOLL).0, 0],
+            vec![100, 1000, 500, 400],
+            vec![50, 0, 130, 30],
+            vec![main_street, a5, a5, NO_NAME],
+            names.build(),
+        )
+    }
+
+    #[test]
+    fn summarize_path() {
+        let attributes = attributes();
+        assert_eq!(attributes.speed_limit(1), None);
+        assert_eq!(attributes.speed_limit(2), Some(130));
+        let summary = attributes.summarize(&[0, 1, 2, 3]);
+        assert_eq!(summary.length, 2000);
+        assert_eq!(summary.toll_length, 500);
+        assert_eq!(summary.motorway_length, 1500);
+        assert_eq!(summary.motorway_share(), 0.75);
+        assert_eq!(summary.street_names, vec!["Hauptstraße".to_string(), "A5".to_string()]);
+    }
+
+    #[test]
+    fn select_through_line_graph() {
+        // 0 -> 1 -> 2 with edges 0 and 1 and a u-turn edge 2 from 1 back to 0
+        let graph = OwnedGraph::new(vec![0, 1, 3, 3], vec![1, 2, 0], vec![1, 1, 1]);
+        let line_graph = line_graph(&graph, |_, _| Some(0));
+        let origins = line_graph_edge_origins(&line_graph);
+        assert_eq!(origins, vec![0, 0, 2]);
+        let attributes = attributes().select(&[0, 1, 3]).select(&origins);
+        assert_eq!(attributes.num_arcs(), 3);
+        assert_eq!(attributes.street_name(1), Some("Hauptstraße"));
+        assert_eq!(attributes.street_name(2), None);
+        assert_eq!(attributes.geo_distance(2), 400);
+    }
+
+    #[test]
+    fn select_through_ch_split() {
+        // the parallel edges 1 -> 2 keep their own attributes
+        let graph = OwnedGraph::new(vec![0, 1, 4, 5], vec![1, 2, 2, 0, 1], vec![1, 1, 1, 1, 1]);
+        let mut names = StringTableBuilder::new();
+        let street_names = ["a", "b", "c", "d", "e"].iter().map(|name| names.intern(name)).collect();
+        let attributes = RoadAttributes::new(vec![0; 5], vec![0; 5], vec![10, 11, 12, 13, 14], vec![0; 5], street_names, names.build());
+
+        let order = NodeOrder::from_node_order(vec![2, 0, 1]);
+        let edge_ids = OwnedGraph::new(graph.first_out().to_vec(), graph.head().to_vec(), (0..graph.num_arcs() as EdgeId).collect());
+        let (forward, backward) = edge_ids.ch_split(&order);
+        let (forward, backward) = (OwnedGraph::permutated(&forward, &order), OwnedGraph::permutated(&backward, &order));
+        assert_eq!(forward.num_arcs() + backward.num_arcs(), 5);
+
+        for split in [&forward, &backward] {
+            let split_attributes = attributes.select(split.weight());
+            for (edge, &origin) in split.weight().iter().enumerate() {
+                assert_eq!(split_attributes.geo_distance(edge as EdgeId), 10 + origin);
+                assert_eq!(split_attributes.street_name(edge as EdgeId), attributes.street_name(origin));
+            }
+        }
+    }
+
+    #[test]
+    fn reconstruct_rejects_invalid_data() {
+        let dir = std::env::temp_dir().join(format!("rrr_road_attributes_{}", std::process::id()));
+        let attributes = attributes();
+        attributes.deconstruct_to(&dir).unwrap();
+        assert_eq!(RoadAttributes::reconstruct_from(&dir).unwrap(), attributes);
+
+        vec![0u32, 13, 12, 14].write_to(&dir.join("street_name_first_byte")).unwrap();
+        assert_eq!(RoadAttributes::reconstruct_from(&dir).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        attributes.deconstruct_to(&dir).unwrap();
+        vec![0u32, 7, 0, 0].write_to(&dir.join("street_name")).unwrap();
+        assert_eq!(RoadAttributes::reconstruct_from(&dir).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        attributes.deconstruct_to(&dir).unwrap();
+        // splits the ß
+        vec![0u32, 10, 14].write_to(&dir.join("street_name_first_byte")).unwrap();
+        assert_eq!(RoadAttributes::reconstruct_from(&dir).unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn choose_official_name_in_preferred_language() {
+        let candidate = |name, language, official| NameCandidate { name, language, official };
+        let names = [
+            candidate("A5", "GER", false),
+            candidate("Hauptstraße", "GER", true),
+            candidate("Main Street", "ENG", false),
+            candidate("Rue Principale", "FRE", true),
+        ];
+        assert_eq!(choose_street_name(names, Some("FRE")), Some("Rue Principale"));
+        assert_eq!(choose_street_name(names, Some("GER")), Some("Hauptstraße"));
+        // no official name in the preferred language, official names win
+        assert_eq!(choose_street_name(names, Some("ENG")), Some("Hauptstraße"));
+        assert_eq!(choose_street_name(names, None), Some("Hauptstraße"));
+        assert_eq!(choose_street_name(names[..1].iter().copied(), Some("ENG")), Some("A5"));
+        assert_eq!(choose_street_name([candidate("", "GER", true)], None), None);
+    }
+}
@@ -0,0 +1,268 @@
+//! Registry of temporary road closures, for example construction sites.
+//!
+//! A closure blocks a single edge during a time interval `start..end` of wall clock time,
+//! so closures can span midnight or several days.
+//! Closures feed into the different kinds of metrics:
+//! Static customizations use `INFINITY` for edges closed at the time of the customization.
+//! Since this is only correct until the next closure starts or ends, users should recustomize at `next_change`.
+//! In time-dependent evaluation, departing on a closed edge means waiting until the closure ends, which keeps travel time functions FIFO.
+//! Live updates for `PessimisticLiveTDGraph`, `live_data` files and the floating point `LiveGraph` for CATCHUp contain the edges which are closed right now.
+
+use crate::{
+    datastr::{
+        graph::{time_dependent::Timestamp, *},
+        live_traffic::{time_of_day, WallClock},
+    },
+    util::in_range_option::InRangeOption,
+};
+use std::{
+    cmp::min,
+    collections::{BTreeMap, HashMap},
+};
+
+pub type ClosureId = u32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Closure {
+    pub edge: EdgeId,
+    pub start: WallClock,
+    pub end: WallClock,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct RoadClosures {
+    closures: BTreeMap<ClosureId, Closure>,
+    by_edge: HashMap<EdgeId, Vec<ClosureId>>,
+    next_id: ClosureId,
+}
+
+impl RoadClosures {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a closure, returns an id to remove it later.
+    pub fn add(&mut self, closure: Closure) -> ClosureId {
+        assert!(closure.start < closure.end, "empty closure interval");
+        let id = self.next_id;
+        self.next_id += 1;
+        self.by_edge.entry(closure.edge).or_default().push(id);
+        self.closures.insert(id, closure);
+        id
+    }
+
+    pub fn remove(&mut self, id: ClosureId) -> Option<Closure> {
+        let closure = self.closures.remove(&id)?;
+        let ids = self.by_edge.get_mut(&closure.edge).unwrap();
+        ids.retain(|&other| other != id);
+        if ids.is_empty() {
+            self.by_edge.remove(&closure.edge);
+        }
+        Some(closure)
+    }
+
+    pub fn get(&self, id: ClosureId) -> Option<&Closure> {
+        self.closures.get(&id)
+    }
+
+    /// All closures ordered by id.
+    pub fn iter(&self) -> impl Iterator<Item = (ClosureId, &Closure)> {
+        self.closures.iter().map(|(&id, closure)| (id, closure))
+    }
+
+    pub fn len(&self) -> usize {
+        self.closures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.closures.is_empty()
+    }
+
+    pub fn is_closed(&self, edge: EdgeId, now: WallClock) -> bool {
+        self.closed_until(edge, now).is_some()
+    }
+
+    /// If `edge` is closed at `now`, the time when it opens again.
+    /// Overlapping and adjacent closures are treated as one.
+    pub fn closed_until(&self, edge: EdgeId, now: WallClock) -> Option<WallClock> {
+        let ids = self.by_edge.get(&edge)?;
+        let mut until = now;
+        // few closures per edge, so simply rescan until no closure extends the interval anymore
+        loop {
+            let extended = ids
+                .iter()
+                .map(|id| &self.closures[id])
+                .filter(|closure| closure.start <= until && until < closure.end)
+                .map(|closure| closure.end)
+                .max();
+            match extended {
+                Some(end) => until = end,
+                None => break,
+            }
+        }
+        if until > now {
+            Some(until)
+        } else {
+            None
+        }
+    }
+
+    /// The first time after `now` when a closure starts or ends, i.e. when metrics derived at `now` become outdated.
+    pub fn next_change(&self, now: WallClock) -> Option<WallClock> {
+        self.closures
+            .values()
+            .flat_map(|closure| [closure.start, closure.end])
+            .filter(|&t| t > now)
+            .min()
+    }
+
+    // Remaining time of a closure, capped so it can be used as a weight.
+    fn remaining(until: WallClock, now: WallClock) -> Weight {
+        min(until - now, WallClock::from(INFINITY)) as Weight
+    }
+
+    /// Set the weights of all edges closed at `now` to `INFINITY`.
+    pub fn apply_to_weights(&self, weights: &mut [Weight], now: WallClock) {
+        for &edge in self.by_edge.keys() {
+            if self.is_closed(edge, now) {
+                weights[edge as usize] = INFINITY;
+            }
+        }
+    }
+
+    /// Travel time on `edge` when departing at `t`, where `eval` yields the travel time without closures.
+    /// `origin` is the wall clock time of `t == 0`, usually the last midnight.
+    /// Departures during a closure have to wait until the edge opens again.
+    #[inline]
+    pub fn eval_td(&self, edge: EdgeId, origin: WallClock, t: Timestamp, eval: impl Fn(Timestamp) -> Weight) -> Weight {
+        let now = origin + WallClock::from(t);
+        match self.closed_until(edge, now) {
+            Some(end) => {
+                let wait = Self::remaining(end, now);
+                wait.saturating_add(eval(t.saturating_add(wait)))
+            }
+            None => eval(t),
+        }
+    }
+
+    /// Live data for `PessimisticLiveTDGraph` with `time_of_day(now)` as the live time:
+    /// edges closed now get an `INFINITY` live travel time until the closure ends.
+    /// Unlike traffic reports, closures longer than a period are kept, they are still closed right now.
+    /// Existing live data of these edges is replaced.
+    pub fn apply_to_pessimistic_live(&self, live: &mut [InRangeOption<(Weight, Timestamp)>], now: WallClock) {
+        let t_live = time_of_day(now);
+        for &edge in self.by_edge.keys() {
+            if let Some(end) = self.closed_until(edge, now) {
+                live[edge as usize] = InRangeOption::some((INFINITY, t_live + Self::remaining(end, now)));
+            }
+        }
+    }
+
+    /// Closures in the format of `live_data` files: edges closed now get an `INFINITY` travel time for the remaining duration.
+    /// Existing entries for these edges are replaced, the result is sorted by edge id.
+    pub fn apply_to_live_data(&self, live_data: &mut Vec<(EdgeId, Weight, Weight)>, now: WallClock) {
+        live_data.retain(|&(edge, _, _)| !self.is_closed(edge, now));
+        for &edge in self.by_edge.keys() {
+            if let Some(end) = self.closed_until(edge, now) {
+                live_data.push((edge, INFINITY, Self::remaining(end, now)));
+            }
+        }
+        live_data.sort_unstable();
+    }
+
+    /// Live data in milliseconds for the floating point `LiveGraph` used by CATCHUp, with `time_of_day(now)` as `t_live`.
+    /// Edges closed now get the travel time of waiting until the closure ends and `t_live` as `t_soon`,
+    /// so the live travel time decreases with slope -1 until it meets the prediction at the end of the closure.
+    /// `predicted` yields the predicted travel time of an edge for a departure time of day.
+    /// Existing live data of these edges is replaced.
+    pub fn apply_to_live(&self, live: &mut [Option<(u32, u32)>], now: WallClock, predicted: impl Fn(EdgeId, Timestamp) -> Weight) {
+        let t_live = time_of_day(now);
+        for &edge in self.by_edge.keys() {
+            if let Some(end) = self.closed_until(edge, now) {
+                let wait = Self::remaining(end, now);
+                live[edge as usize] = Some((wait.saturating_add(predicted(edge, t_live + wait)), t_live));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastr::graph::time_dependent::run_test_with_periodicity;
+
+    fn closure(edge: EdgeId, start: WallClock, end: WallClock) -> Closure {
+        Closure {
+            edge,
+            start,
+            end,
+            reason: String::from("construction site"),
+        }
+    }
+
+    #[test]
+    fn overlapping_closures_are_merged() {
+        let mut closures = RoadClosures::new();
+        let first = closures.add(closure(3, 10, 20));
+        closures.add(closure(3, 20, 30));
+        closures.add(closure(3, 25, 40));
+        closures.add(closure(3, 50, 60));
+        closures.add(closure(4, 0, 100));
+
+        assert_eq!(closures.closed_until(3, 9), None);
+        assert_eq!(closures.closed_until(3, 10), Some(40));
+        assert_eq!(closures.closed_until(3, 35), Some(40));
+        assert_eq!(closures.closed_until(3, 40), None);
+        assert_eq!(closures.closed_until(3, 55), Some(60));
+        assert_eq!(closures.closed_until(2, 55), None);
+
+        assert_eq!(closures.next_change(0), Some(10));
+        assert_eq!(closures.next_change(20), Some(25));
+        assert_eq!(closures.next_change(60), Some(100));
+        assert_eq!(closures.next_change(100), None);
+
+        assert_eq!(closures.remove(first), Some(closure(3, 10, 20)));
+        assert_eq!(closures.remove(first), None);
+        assert_eq!(closures.closed_until(3, 15), None);
+        assert_eq!(closures.len(), 4);
+    }
+
+    #[test]
+    fn closures_in_metrics() {
+        run_test_with_periodicity(1000, || {
+            let mut closures = RoadClosures::new();
+            closures.add(closure(1, 5010, 5020));
+            // spans midnight
+            closures.add(closure(2, 5990, 7040));
+
+            let mut weights = vec![5; 4];
+            closures.apply_to_weights(&mut weights, 5015);
+            assert_eq!(weights, vec![5, INFINITY, 5, 5]);
+
+            // waiting until the closure ends
+            assert_eq!(closures.eval_td(1, 5000, 12, |t| t), 8 + 20);
+            assert_eq!(closures.eval_td(1, 5000, 20, |t| t), 20);
+            assert_eq!(closures.eval_td(2, 6000, 500, |_| 3), 540 + 3);
+
+            let mut live = vec![InRangeOption::NONE; 4];
+            live[2] = InRangeOption::some((7, 50));
+            closures.apply_to_pessimistic_live(&mut live, 5015);
+            assert_eq!(live[1].value(), Some((INFINITY, 20)));
+            assert_eq!(live[2].value(), Some((7, 50)));
+
+            // closed for longer than a period
+            let mut live = vec![InRangeOption::NONE; 4];
+            closures.apply_to_pessimistic_live(&mut live, 6000);
+            assert_eq!(live[2].value(), Some((INFINITY, 1040)));
+
+            let mut live_data = vec![(0, 4, 10), (2, 7, 10)];
+            closures.apply_to_live_data(&mut live_data, 6000);
+            assert_eq!(live_data, vec![(0, 4, 10), (2, INFINITY, 1040)]);
+
+            let mut live = vec![None; 4];
+            closures.apply_to_live(&mut live, 7035, |_, _| 3);
+            assert_eq!(live, vec![None, None, Some((8, 35)), None]);
+        });
+    }
+}
@@ -0,0 +1,178 @@
+//! Public transit timetables as elementary connections for the connection scan algorithm.
+//!
+//! Each stop is linked to a node of the road graph.
+//! Transfers between nearby stops are modeled as footpaths, which are computed on a walking metric of the road graph.
+//! Times are in milliseconds after midnight of the service day and may exceed one day for trips running past midnight.
+
+use crate::{
+    algo::dijkstra::*,
+    datastr::graph::{time_dependent::Timestamp, *},
+    io::*,
+};
+
+pub type StopId = u32;
+pub type TripId = u32;
+
+/// A vehicle of `trip` going from `dep_stop` to the next stop `arr_stop` without intermediate stop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Connection {
+    pub dep_stop: StopId,
+    pub arr_stop: StopId,
+    pub departure: Timestamp,
+    pub arrival: Timestamp,
+    pub trip: TripId,
+}
+
+#[derive(Debug, Clone)]
+pub struct Timetable {
+    stop_node: Vec<NodeId>,
+    // sorted by departure
+    connections: Vec<Connection>,
+    num_trips: usize,
+    // stops as nodes, walking times as weights
+    footpaths: OwnedGraph,
+}
+
+impl Timetable {
+    /// `stop_node` contains the road node of each stop.
+    /// No footpaths, see `compute_footpaths`.
+    pub fn new(stop_node: Vec<NodeId>, mut connections: Vec<Connection>) -> Self {
+        for connection in &connections {
+            assert!((connection.dep_stop as usize) < stop_node.len() && (connection.arr_stop as usize) < stop_node.len());
+            assert!(
+                connection.departure <= connection.arrival,
+                "connection arrives before departure: {:?}",
+                connection
+            );
+        }
+        connections.sort_by_key(|connection| (connection.departure, connection.arrival));
+        let num_trips = connections.iter().map(|connection| connection.trip as usize + 1).max().unwrap_or(0);
+        let footpaths = OwnedGraph::from_adjancecy_lists(vec![Vec::new(); stop_node.len()]);
+        Self {
+            stop_node,
+            connections,
+            num_trips,
+            footpaths,
+        }
+    }
+
+    /// Footpaths between all pairs of stops with a walking time of at most `max_walking_time` on `walking_graph`.
+    pub fn compute_footpaths(&mut self, walking_graph: &OwnedGraph, max_walking_time: Weight) {
+        let mut stops_at_node = vec![Vec::new(); walking_graph.num_nodes()];
+        for (stop, &node) in self.stop_node.iter().enumerate() {
+            stops_at_node[node as usize].push(stop as StopId);
+        }
+
+        let mut data = DijkstraData::new(walking_graph.num_nodes());
+        let mut ops = DefaultOps::default();
+        let mut footpaths = Vec::with_capacity(self.num_stops());
+        for (stop, &node) in self.stop_node.iter().enumerate() {
+            let mut links = Vec::new();
+            let mut run = DijkstraRun::query(walking_graph, &mut data, &mut ops, DijkstraInit::from(node));
+            while let Some(node) = run.next() {
+                let walking_time = *run.tentative_distance(node);
+                if walking_time > max_walking_time {
+                    break;
+                }
+                links.extend(stops_at_node[node as usize].iter().filter(|&&other| other as usize != stop).map(|&other| Link {
+                    node: other,
+                    weight: walking_time,
+                }));
+            }
+            footpaths.push(links);
+        }
+        self.footpaths = OwnedGraph::from_adjancecy_lists(footpaths);
+    }
+
+    pub fn num_stops(&self) -> usize {
+        self.stop_node.len()
+    }
+
+    pub fn num_trips(&self) -> usize {
+        self.num_trips
+    }
+
+    pub fn stop_node(&self, stop: StopId) -> NodeId {
+        self.stop_node[stop as usize]
+    }
+
+    /// All connections ordered by departure.
+    pub fn connections(&self) -> &[Connection] {
+        &self.connections
+    }
+
+    /// Index of the first connection departing at or after `t`.
+    pub fn first_connection_departing_at_or_after(&self, t: Timestamp) -> usize {
+        self.connections.partition_point(|connection| connection.departure < t)
+    }
+
+    pub fn footpaths(&self) -> &OwnedGraph {
+        &self.footpaths
+    }
+}
+
+impl Deconstruct for Timetable {
+    fn save_each(&self, store: &dyn Fn(&str, &dyn Save) -> std::io::Result<()>) -> std::io::Result<()> {
+        store("stop_node", &self.stop_node)?;
+        store("connection_dep_stop", &self.connections.iter().map(|c| c.dep_stop).collect::<Vec<_>>())?;
+        store("connection_arr_stop", &self.connections.iter().map(|c| c.arr_stop).collect::<Vec<_>>())?;
+        store("connection_departure", &self.connections.iter().map(|c| c.departure).collect::<Vec<_>>())?;
+        store("connection_arrival", &self.connections.iter().map(|c| c.arrival).collect::<Vec<_>>())?;
+        store("connection_trip", &self.connections.iter().map(|c| c.trip).collect::<Vec<_>>())?;
+        store("footpath_first_out", &self.footpaths.first_out())?;
+        store("footpath_head", &self.footpaths.head())?;
+        store("footpath_walking_time", &self.footpaths.weight())?;
+        Ok(())
+    }
+}
+
+impl Reconstruct for Timetable {
+    fn reconstruct_with(loader: Loader) -> std::io::Result<Self> {
+        let stop_node: Vec<NodeId> = loader.load("stop_node")?;
+        let dep_stop: Vec<StopId> = loader.load("connection_dep_stop")?;
+        let arr_stop: Vec<StopId> = loader.load("connection_arr_stop")?;
+        let departure: Vec<Timestamp> = loader.load("connection_departure")?;
+        let arrival: Vec<Timestamp> = loader.load("connection_arrival")?;
+        let trip: Vec<TripId> = loader.load("connection_trip")?;
+        let connections = (0..dep_stop.len())
+            .map(|i| Connection {
+                dep_stop: dep_stop[i],
+                arr_stop: arr_stop[i],
+                departure: departure[i],
+                arrival: arrival[i],
+                trip: trip[i],
+            })
+            .collect();
+        let mut timetable = Timetable::new(stop_node, connections);
+        timetable.footpaths = OwnedGraph::new(
+            loader.load("footpath_first_out")?,
+            loader.load("footpath_head")?,
+            loader.load("footpath_walking_time")?,
+        );
+        Ok(timetable)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn footpaths_within_walking_distance() {
+        // path 0 - 1 - 2 - 3 in both directions, 60s per arc
+        let walking = OwnedGraph::new(vec![0, 1, 3, 5, 6], vec![1, 0, 2, 1, 3, 2], vec![60_000; 6]);
+        let mut timetable = Timetable::new(vec![0, 1, 3, 1], Vec::new());
+        timetable.compute_footpaths(&walking, 120_000);
+
+        let footpaths = |stop: StopId| {
+            let mut links: Vec<_> = LinkIterable::<Link>::link_iter(timetable.footpaths(), stop)
+                .map(|l| (l.node, l.weight))
+                .collect();
+            links.sort_unstable();
+            links
+        };
+        assert_eq!(footpaths(0), vec![(1, 60_000), (3, 60_000)]);
+        assert_eq!(footpaths(1), vec![(0, 60_000), (2, 120_000), (3, 0)]);
+        assert_eq!(footpaths(2), vec![(1, 120_000), (3, 120_000)]);
+    }
+}
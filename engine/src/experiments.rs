@@ -13,6 +13,7 @@ use crate::{
     report::*,
 };
 
+pub mod benchmark;
 pub mod catchup;
 pub mod chpot;
 
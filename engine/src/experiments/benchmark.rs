@@ -0,0 +1,667 @@
+//! Reproducible query benchmarks.
+//!
+//! A `QuerySet` is a named list of queries together with the seed it was generated from.
+//! Sets are stored in the graph directory in the same layout as the query files written by the old generator binaries,
+//! so benchmarks of different algorithms or releases can run the exact same queries.
+//! Any `QueryServer` or `TDQueryServer` can be benchmarked with a number of warmup queries and repeated measurements.
+//! Results are summarized in percentile tables and can be checked against stored ground truth distances.
+
+use super::*;
+use crate::{datastr::graph::time_dependent::Timestamp, io::*};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+/// Create a `StdRng` from a 64 bit seed, the way the CLI always did.
+pub fn seeded_rng(seed: u64) -> StdRng {
+    let mut full_seed = <StdRng as SeedableRng>::Seed::default();
+    full_seed[..8].copy_from_slice(&seed.to_le_bytes());
+    StdRng::from_seed(full_seed)
+}
+
+/// How the queries of a set are chosen.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuerySetKind {
+    /// Source and target drawn uniformly at random.
+    Uniform,
+    /// One query for each power of two Dijkstra rank from random sources, in random order.
+    /// Each source yields about log2(n) queries.
+    DijkstraRank,
+    /// Uniform sources with targets at a great circle distance between `min_m` and `max_m` meters.
+    DistanceBand { min_m: f64, max_m: f64 },
+    /// Uniform queries with departures drawn uniformly from the given time windows.
+    RushHour { windows: Vec<(Timestamp, Timestamp)> },
+}
+
+impl QuerySetKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            QuerySetKind::Uniform => "uniform",
+            QuerySetKind::DijkstraRank => "rank",
+            QuerySetKind::DistanceBand { .. } => "distance_band",
+            QuerySetKind::RushHour { .. } => "rush_hour",
+        }
+    }
+}
+
+/// Everything needed to (re)generate a query set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuerySetSpec {
+    pub name: String,
+    pub kind: QuerySetKind,
+    /// Number of queries, for `DijkstraRank` the number of sources.
+    pub num_queries: usize,
+    pub seed: u64,
+}
+
+impl QuerySetSpec {
+    /// Generate the queries. `coords` (latitude, longitude) are only required for distance bands.
+    pub fn generate(&self, graph: &impl LinkIterGraph, coords: Option<(&[f32], &[f32])>) -> QuerySet {
+        let mut rng = seeded_rng(self.seed);
+        let n = graph.num_nodes();
+        let mut set = match &self.kind {
+            QuerySetKind::Uniform => QuerySet::uniform(n, self.num_queries, &mut rng),
+            QuerySetKind::DijkstraRank => QuerySet::dijkstra_rank(graph, self.num_queries, &mut rng),
+            &QuerySetKind::DistanceBand { min_m, max_m } => {
+                let (lat, lng) = coords.expect("distance band query sets require coordinates");
+                QuerySet::distance_band(lat, lng, min_m, max_m, self.num_queries, &mut rng)
+            }
+            QuerySetKind::RushHour { windows } => {
+                let mut set = QuerySet::uniform(n, self.num_queries, &mut rng);
+                set.add_departures(windows, &mut rng);
+                set
+            }
+        };
+        set.name = self.name.clone();
+        set.seed = self.seed;
+        set
+    }
+
+    fn to_json(&self) -> Value {
+        let mut spec = json!({
+            "name": self.name,
+            "kind": self.kind.name(),
+            "num_queries": self.num_queries,
+            "seed": self.seed,
+        });
+        match &self.kind {
+            QuerySetKind::Uniform | QuerySetKind::DijkstraRank => (),
+            &QuerySetKind::DistanceBand { min_m, max_m } => {
+                spec["min_m"] = json!(min_m);
+                spec["max_m"] = json!(max_m);
+            }
+            QuerySetKind::RushHour { windows } => spec["windows"] = json!(windows),
+        }
+        spec
+    }
+
+    /// Store the spec next to the set generated from it in `dir`.
+    pub fn write_to_dir(&self, dir: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(dir.join("spec.json"), self.to_json().to_string())
+    }
+
+    /// Whether the set in `dir` was generated from exactly this spec.
+    /// Sets without a stored spec, for example from the old generator binaries, never match.
+    pub fn is_stored_in(&self, dir: &Path) -> std::io::Result<bool> {
+        let path = dir.join("spec.json");
+        if !path.exists() {
+            return Ok(false);
+        }
+        let stored: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        Ok(stored == self.to_json())
+    }
+}
+
+/// A named list of queries.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct QuerySet {
+    pub name: String,
+    pub seed: u64,
+    pub sources: Vec<NodeId>,
+    pub targets: Vec<NodeId>,
+    /// Departure times, only for time-dependent sets.
+    pub departures: Option<Vec<Timestamp>>,
+    /// The Dijkstra rank of each query as log2, only for rank sets.
+    pub ranks: Option<Vec<u32>>,
+}
+
+// Rejection sampling of distance band targets gives up after this many tries per query.
+const MAX_TARGET_ATTEMPTS: usize = 10_000;
+
+impl QuerySet {
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    pub fn uniform(num_nodes: usize, num_queries: usize, rng: &mut StdRng) -> Self {
+        let (sources, targets) = (0..num_queries)
+            .map(|_| (rng.gen_range(0..num_nodes as NodeId), rng.gen_range(0..num_nodes as NodeId)))
+            .unzip();
+        QuerySet {
+            sources,
+            targets,
+            ..Default::default()
+        }
+    }
+
+    pub fn dijkstra_rank(graph: &impl LinkIterGraph, num_sources: usize, rng: &mut StdRng) -> Self {
+        let n = graph.num_nodes();
+        let mut dijk_data = DijkstraData::new(n);
+        let mut ops = DefaultOps();
+        let mut queries = Vec::new();
+
+        for _ in 0..num_sources {
+            let from = rng.gen_range(0..n as NodeId);
+            let dijkstra = DijkstraRun::query(graph, &mut dijk_data, &mut ops, DijkstraInit::from(from));
+            for (i, node) in dijkstra.enumerate() {
+                let rank = i + 1;
+                if rank & (rank - 1) == 0 {
+                    queries.push((from, node, rank.trailing_zeros()));
+                }
+            }
+        }
+
+        queries.shuffle(rng);
+        QuerySet {
+            sources: queries.iter().map(|&(from, _, _)| from).collect(),
+            targets: queries.iter().map(|&(_, to, _)| to).collect(),
+            ranks: Some(queries.iter().map(|&(_, _, rank)| rank).collect()),
+            ..Default::default()
+        }
+    }
+
+    /// Queries for which no target in the band could be found are skipped, so the set may be smaller than requested.
+    pub fn distance_band(lat: &[f32], lng: &[f32], min_m: f64, max_m: f64, num_queries: usize, rng: &mut StdRng) -> Self {
+        let n = lat.len();
+        let mut set = QuerySet::default();
+
+        for _ in 0..num_queries {
+            let from = rng.gen_range(0..n as NodeId);
+            let target = (0..MAX_TARGET_ATTEMPTS).map(|_| rng.gen_range(0..n as NodeId)).find(|&to| {
+                let dist = great_circle_distance_m((lat[from as usize], lng[from as usize]), (lat[to as usize], lng[to as usize]));
+                dist >= min_m && dist <= max_m
+            });
+            if let Some(to) = target {
+                set.sources.push(from);
+                set.targets.push(to);
+            }
+        }
+
+        set
+    }
+
+    /// Draw a departure for each query, first a window weighted by its length, then a time within it.
+    pub fn add_departures(&mut self, windows: &[(Timestamp, Timestamp)], rng: &mut StdRng) {
+        assert!(windows.iter().all(|&(start, end)| start < end), "empty departure window");
+        let total: u64 = windows.iter().map(|&(start, end)| (end - start) as u64).sum();
+        assert!(total > 0, "no departure windows");
+
+        self.departures = Some(
+            (0..self.len())
+                .map(|_| {
+                    let mut offset = rng.gen_range(0..total);
+                    for &(start, end) in windows {
+                        let len = (end - start) as u64;
+                        if offset < len {
+                            return start + offset as Timestamp;
+                        }
+                        offset -= len;
+                    }
+                    unreachable!()
+                })
+                .collect(),
+        );
+    }
+
+    /// Store the set in `dir` with one file per attribute, the name is the name of the directory.
+    pub fn write_to_dir(&self, dir: &Path) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        self.sources.write_to(&dir.join("source"))?;
+        self.targets.write_to(&dir.join("target"))?;
+        vec![self.seed].write_to(&dir.join("seed"))?;
+        if let Some(departures) = &self.departures {
+            departures.write_to(&dir.join("departure"))?;
+        }
+        if let Some(ranks) = &self.ranks {
+            ranks.write_to(&dir.join("rank"))?;
+        }
+        Ok(())
+    }
+
+    /// Load a set stored with `write_to_dir`. Sets from the old generator binaries have no seed file, their seed is 0.
+    pub fn load_from_dir(dir: &Path) -> std::io::Result<Self> {
+        let optional = |name: &str| -> std::io::Result<Option<Vec<u32>>> {
+            let path = dir.join(name);
+            if path.exists() {
+                Ok(Some(Vec::load_from(path)?))
+            } else {
+                Ok(None)
+            }
+        };
+
+        let set = QuerySet {
+            name: dir.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+            seed: if dir.join("seed").exists() {
+                Vec::<u64>::load_from(dir.join("seed"))?.first().copied().unwrap_or(0)
+            } else {
+                0
+            },
+            sources: Vec::load_from(dir.join("source"))?,
+            targets: Vec::load_from(dir.join("target"))?,
+            departures: optional("departure")?,
+            ranks: optional("rank")?,
+        };
+
+        let len = set.len();
+        if set.targets.len() != len || set.departures.as_ref().is_some_and(|d| d.len() != len) || set.ranks.as_ref().is_some_and(|r| r.len() != len) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("inconsistent query set files in {}", dir.display()),
+            ));
+        }
+        Ok(set)
+    }
+}
+
+fn great_circle_distance_m((lat1, lng1): (f32, f32), (lat2, lng2): (f32, f32)) -> f64 {
+    const EARTH_RADIUS_M: f64 = 6_371_000.0;
+    let (lat1, lat2) = ((lat1 as f64).to_radians(), (lat2 as f64).to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lng = (lng2 as f64 - lng1 as f64).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lng / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
+/// Store distances in the format of ground truth files, unreachable targets as `INFINITY`.
+pub fn write_distances(distances: &[Option<Weight>], path: &Path) -> std::io::Result<()> {
+    distances.iter().map(|dist| dist.unwrap_or(INFINITY)).collect::<Vec<Weight>>().write_to(&path)
+}
+
+pub fn load_distances(path: &Path) -> std::io::Result<Vec<Option<Weight>>> {
+    Ok(Vec::<Weight>::load_from(path)?
+        .into_iter()
+        .map(|dist| Some(dist).filter(|&dist| dist < INFINITY))
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchmarkParams {
+    /// Number of queries run before measuring, cycling through the set.
+    pub warmup: usize,
+    /// How often each query is measured, the median time counts.
+    pub repetitions: usize,
+}
+
+impl Default for BenchmarkParams {
+    fn default() -> Self {
+        BenchmarkParams { warmup: 100, repetitions: 1 }
+    }
+}
+
+/// Running time percentiles of a number of queries.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub count: usize,
+    pub mean_ms: f64,
+    pub min_ms: f64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl Summary {
+    pub fn from_times(times: &[Duration]) -> Self {
+        let mut ms: Vec<f64> = times.iter().map(|time| time.as_secs_f64() * 1000.0).collect();
+        ms.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        // nearest rank percentile
+        let percentile = |p: f64| {
+            if ms.is_empty() {
+                return 0.0;
+            }
+            let rank = (p / 100.0 * ms.len() as f64).ceil() as usize;
+            ms[rank.clamp(1, ms.len()) - 1]
+        };
+        Summary {
+            count: ms.len(),
+            mean_ms: if ms.is_empty() { 0.0 } else { ms.iter().sum::<f64>() / ms.len() as f64 },
+            min_ms: percentile(0.0),
+            p50_ms: percentile(50.0),
+            p90_ms: percentile(90.0),
+            p99_ms: percentile(99.0),
+            max_ms: percentile(100.0),
+        }
+    }
+
+    fn to_json(self) -> Value {
+        json!({
+            "count": self.count,
+            "mean_ms": self.mean_ms,
+            "min_ms": self.min_ms,
+            "p50_ms": self.p50_ms,
+            "p90_ms": self.p90_ms,
+            "p99_ms": self.p99_ms,
+            "max_ms": self.max_ms,
+        })
+    }
+}
+
+/// A query whose result differs from the ground truth.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mismatch {
+    pub index: usize,
+    pub from: NodeId,
+    pub to: NodeId,
+    pub departure: Option<Timestamp>,
+    pub expected: Option<Weight>,
+    pub actual: Option<Weight>,
+}
+
+#[derive(Debug, Clone)]
+pub struct BenchmarkResult {
+    /// Usually the algorithm and the query set.
+    pub name: String,
+    /// Median time of each query over all repetitions.
+    pub times: Vec<Duration>,
+    pub distances: Vec<Option<Weight>>,
+    ranks: Option<Vec<u32>>,
+}
+
+impl BenchmarkResult {
+    pub fn summary(&self) -> Summary {
+        Summary::from_times(&self.times)
+    }
+
+    /// Summaries for each Dijkstra rank, empty if the set has no ranks.
+    pub fn summary_by_rank(&self) -> Vec<(u32, Summary)> {
+        let ranks = match &self.ranks {
+            Some(ranks) => ranks,
+            None => return Vec::new(),
+        };
+        let mut by_rank = BTreeMap::<u32, Vec<Duration>>::new();
+        for (&rank, &time) in ranks.iter().zip(&self.times) {
+            by_rank.entry(rank).or_default().push(time);
+        }
+        by_rank.into_iter().map(|(rank, times)| (rank, Summary::from_times(&times))).collect()
+    }
+
+    /// Compare distances against the ground truth, which has to contain a distance for each query of `set`.
+    pub fn diff(&self, set: &QuerySet, ground_truth: &[Option<Weight>]) -> Vec<Mismatch> {
+        assert!(ground_truth.len() >= self.distances.len(), "less ground truth values than queries");
+        self.distances
+            .iter()
+            .zip(ground_truth)
+            .enumerate()
+            .filter(|(_, (actual, expected))| actual != expected)
+            .map(|(index, (&actual, &expected))| Mismatch {
+                index,
+                from: set.sources[index],
+                to: set.targets[index],
+                departure: set.departures.as_ref().map(|departures| departures[index]),
+                expected,
+                actual,
+            })
+            .collect()
+    }
+
+    /// Report the summaries into the current context, and each query into a `queries` collection.
+    pub fn report(&self, set: &QuerySet) {
+        report!("name", self.name);
+        report!("query_set", set.name);
+        report!("seed", set.seed);
+        report!("summary", self.summary().to_json());
+        if self.ranks.is_some() {
+            report!(
+                "summary_by_rank",
+                self.summary_by_rank()
+                    .into_iter()
+                    .map(|(rank, summary)| json!({ "rank": rank, "summary": summary.to_json() }))
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        let mut queries_ctxt = push_collection_context("queries");
+        for (index, (time, dist)) in self.times.iter().zip(&self.distances).enumerate() {
+            let _query_ctxt = queries_ctxt.push_collection_item();
+            report_silent!("from", set.sources[index]);
+            report_silent!("to", set.targets[index]);
+            if let Some(departures) = &set.departures {
+                report_silent!("at", departures[index]);
+            }
+            report_silent!("running_time_ms", time.as_secs_f64() * 1000.0);
+            report_silent!("result", dist);
+        }
+    }
+}
+
+fn benchmark(name: &str, set: &QuerySet, params: &BenchmarkParams, mut query: impl FnMut(usize) -> Option<Weight>) -> BenchmarkResult {
+    assert!(params.repetitions > 0, "at least one repetition required");
+    // what servers report would collide between repetitions and distort the measurements
+    let _blocked = block_reporting();
+    if !set.is_empty() {
+        for index in (0..set.len()).cycle().take(params.warmup) {
+            query(index);
+        }
+    }
+
+    let mut times = Vec::with_capacity(set.len());
+    let mut distances = Vec::with_capacity(set.len());
+    let mut repetitions = Vec::with_capacity(params.repetitions);
+    for index in 0..set.len() {
+        repetitions.clear();
+        let mut dist = None;
+        for _ in 0..params.repetitions {
+            let start = Instant::now();
+            dist = query(index);
+            repetitions.push(start.elapsed());
+        }
+        repetitions.sort();
+        times.push(repetitions[repetitions.len() / 2]);
+        distances.push(dist);
+    }
+
+    BenchmarkResult {
+        name: name.to_string(),
+        times,
+        distances,
+        ranks: set.ranks.clone(),
+    }
+}
+
+/// Benchmark a static query server, departures of the set are ignored.
+pub fn run_benchmark<S: QueryServer>(name: &str, set: &QuerySet, server: &mut S, params: &BenchmarkParams) -> BenchmarkResult {
+    benchmark(name, set, params, |index| {
+        server
+            .query_no_inline(Query {
+                from: set.sources[index],
+                to: set.targets[index],
+            })
+            .distance()
+    })
+}
+
+/// Benchmark a time-dependent query server, the set must have departures.
+pub fn run_td_benchmark<S: TDQueryServer<Timestamp, Weight>>(name: &str, set: &QuerySet, server: &mut S, params: &BenchmarkParams) -> BenchmarkResult {
+    let departures = set.departures.as_ref().expect("time-dependent benchmarks require departures");
+    benchmark(name, set, params, |index| {
+        server
+            .td_query(TDQuery {
+                from: set.sources[index],
+                to: set.targets[index],
+                departure: departures[index],
+            })
+            .distance()
+    })
+}
+
+/// A table with one row of running time percentiles per result, for comparing algorithms.
+pub fn percentile_table(results: &[BenchmarkResult]) -> String {
+    let name_width = results.iter().map(|result| result.name.len()).chain(std::iter::once(4)).max().unwrap();
+    let mut table = format!(
+        "{:<name_width$} {:>8} {:>10} {:>10} {:>10} {:>10} {:>10} {:>10}\n",
+        "name", "queries", "mean [ms]", "min", "p50", "p90", "p99", "max"
+    );
+    for result in results {
+        let s = result.summary();
+        writeln!(
+            table,
+            "{:<name_width$} {:>8} {:>10.3} {:>10.3} {:>10.3} {:>10.3} {:>10.3} {:>10.3}",
+            result.name, s.count, s.mean_ms, s.min_ms, s.p50_ms, s.p90_ms, s.p99_ms, s.max_ms
+        )
+        .unwrap();
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::algo::dijkstra::query::dijkstra::Server as DijkServer;
+
+    fn grid() -> OwnedGraph {
+        // 3x3 bidirectional grid with unit weights
+        let mut edges = Vec::new();
+        for node in 0..9u32 {
+            let (x, y) = (node % 3, node / 3);
+            if x > 0 {
+                edges.push((node, node - 1));
+            }
+            if x < 2 {
+                edges.push((node, node + 1));
+            }
+            if y > 0 {
+                edges.push((node, node - 3));
+            }
+            if y < 2 {
+                edges.push((node, node + 3));
+            }
+        }
+        let mut first_out = vec![0];
+        for node in 0..9 {
+            first_out.push(first_out.last().unwrap() + edges.iter().filter(|&&(tail, _)| tail == node).count() as EdgeId);
+        }
+        OwnedGraph::new(first_out, edges.iter().map(|&(_, head)| head).collect(), vec![1; edges.len()])
+    }
+
+    #[test]
+    fn generation_is_reproducible() {
+        let graph = grid();
+        let spec = QuerySetSpec {
+            name: "rank".to_string(),
+            kind: QuerySetKind::DijkstraRank,
+            num_queries: 5,
+            seed: 7,
+        };
+        let set = spec.generate(&graph, None);
+        assert_eq!(set, spec.generate(&graph, None));
+        // ranks 1, 2, 4 and 8 for each source
+        assert_eq!(set.len(), 20);
+        assert_eq!(set.ranks.as_ref().unwrap().iter().filter(|&&rank| rank == 0).count(), 5);
+
+        let rush_hour = QuerySetSpec {
+            name: "rush_hour".to_string(),
+            kind: QuerySetKind::RushHour {
+                windows: vec![(10, 20), (100, 110)],
+            },
+            num_queries: 50,
+            seed: 7,
+        }
+        .generate(&graph, None);
+        assert!(rush_hour
+            .departures
+            .unwrap()
+            .iter()
+            .all(|&at| (10..20).contains(&at) || (100..110).contains(&at)));
+
+        let lat = [49.0, 49.0, 49.1];
+        let lng = [8.4, 8.41, 8.4];
+        let band = QuerySet::distance_band(&lat, &lng, 5_000.0, 20_000.0, 10, &mut seeded_rng(1));
+        assert_eq!(band.len(), 10);
+        assert!(band.sources.iter().zip(&band.targets).all(|(&from, &to)| (from == 2) != (to == 2)));
+    }
+
+    #[test]
+    fn store_and_load() {
+        let dir = std::env::temp_dir().join(format!("rrr_query_set_{}", std::process::id()));
+        let mut set = QuerySet::uniform(100, 10, &mut seeded_rng(3));
+        set.name = "uniform".to_string();
+        set.seed = 3;
+        set.add_departures(&[(0, 1000)], &mut seeded_rng(3));
+        set.write_to_dir(&dir.join("uniform")).unwrap();
+        assert_eq!(QuerySet::load_from_dir(&dir.join("uniform")).unwrap(), set);
+
+        let spec = QuerySetSpec {
+            name: "10-50km".to_string(),
+            kind: QuerySetKind::DistanceBand {
+                min_m: 10_000.0,
+                max_m: 50_000.0,
+            },
+            num_queries: 10,
+            seed: 3,
+        };
+        assert!(!spec.is_stored_in(&dir.join("10-50km")).unwrap());
+        spec.write_to_dir(&dir.join("10-50km")).unwrap();
+        assert!(spec.is_stored_in(&dir.join("10-50km")).unwrap());
+        let other_band = QuerySetSpec {
+            kind: QuerySetKind::DistanceBand {
+                min_m: 10_000.0,
+                max_m: 20_000.0,
+            },
+            ..spec.clone()
+        };
+        assert!(!other_band.is_stored_in(&dir.join("10-50km")).unwrap());
+        assert!(!QuerySetSpec { num_queries: 20, ..spec }.is_stored_in(&dir.join("10-50km")).unwrap());
+
+        let distances = vec![Some(3), None, Some(0)];
+        write_distances(&distances, &dir.join("dist")).unwrap();
+        assert_eq!(load_distances(&dir.join("dist")).unwrap(), distances);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn benchmark_and_diff() {
+        let graph = grid();
+        let set = QuerySet {
+            sources: vec![0, 0, 4],
+            targets: vec![8, 0, 5],
+            ranks: Some(vec![3, 0, 0]),
+            ..Default::default()
+        };
+        let mut server = DijkServer::<_, DefaultOps>::new(graph.borrowed());
+        let result = run_benchmark("dijkstra", &set, &mut server, &BenchmarkParams { warmup: 4, repetitions: 3 });
+        assert_eq!(result.distances, vec![Some(4), Some(0), Some(1)]);
+        assert_eq!(result.summary().count, 3);
+        assert_eq!(
+            result.summary_by_rank().iter().map(|&(rank, s)| (rank, s.count)).collect::<Vec<_>>(),
+            vec![(0, 2), (3, 1)]
+        );
+
+        let mismatches = result.diff(&set, &[Some(4), Some(0), Some(2)]);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!((mismatches[0].index, mismatches[0].from, mismatches[0].to), (2, 4, 5));
+        assert_eq!((mismatches[0].expected, mismatches[0].actual), (Some(2), Some(1)));
+
+        let table = percentile_table(&[result]);
+        assert!(table.lines().nth(1).unwrap().starts_with("dijkstra        3"));
+    }
+
+    #[test]
+    fn percentiles() {
+        let times: Vec<_> = (1..=100).rev().map(Duration::from_millis).collect();
+        let summary = Summary::from_times(&times);
+        assert_eq!(
+            (summary.min_ms, summary.p50_ms, summary.p90_ms, summary.p99_ms, summary.max_ms),
+            (1.0, 50.0, 90.0, 99.0, 100.0)
+        );
+        assert_eq!(summary.mean_ms, 50.5);
+    }
+}
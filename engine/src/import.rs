@@ -0,0 +1,147 @@
+//! Functions to import routing data from different formats.
+//! Counterpart to the `export` module.
+
+use crate::datastr::graph::*;
+use std::io::{BufRead, Error, ErrorKind, Result};
+
+fn invalid_data(line_idx: usize, msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("line {}: {}", line_idx + 1, msg))
+}
+
+/// Read a graph in DIMACS .gr format.
+/// Returns `first_out`, `head` and `weight` arrays with arcs in the order of their tails (stable for arcs with the same tail).
+pub fn read_graph_from_gr<R: BufRead>(reader: R) -> Result<(Vec<EdgeId>, Vec<NodeId>, Vec<Weight>)> {
+    let mut num_nodes = None;
+    let mut arcs = Vec::new();
+
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            None | Some("c") => (),
+            Some("p") => {
+                let n = match (tokens.next(), tokens.next(), tokens.next()) {
+                    (Some("sp"), Some(n), Some(m)) => {
+                        arcs.reserve(m.parse().map_err(|_| invalid_data(line_idx, "invalid arc count"))?);
+                        n.parse::<usize>().map_err(|_| invalid_data(line_idx, "invalid node count"))?
+                    }
+                    _ => return Err(invalid_data(line_idx, "expected 'p sp <num_nodes> <num_arcs>'")),
+                };
+                num_nodes = Some(n);
+            }
+            Some("a") => {
+                let n = num_nodes.ok_or_else(|| invalid_data(line_idx, "arc before problem line"))?;
+                let mut next_num = || tokens.next().and_then(|token| token.parse::<u64>().ok());
+                let (tail, head, weight) = match (next_num(), next_num(), next_num()) {
+                    (Some(tail), Some(head), Some(weight)) => (tail, head, weight),
+                    _ => return Err(invalid_data(line_idx, "expected 'a <tail> <head> <weight>'")),
+                };
+                // DIMACS node ids are one based
+                if tail == 0 || head == 0 || tail > n as u64 || head > n as u64 {
+                    return Err(invalid_data(line_idx, "node id out of range"));
+                }
+                if weight >= INFINITY as u64 {
+                    return Err(invalid_data(line_idx, "weight too large"));
+                }
+                arcs.push(((tail - 1) as NodeId, (head - 1) as NodeId, weight as Weight));
+            }
+            Some(_) => return Err(invalid_data(line_idx, "unknown line type")),
+        }
+    }
+
+    let num_nodes = num_nodes.ok_or_else(|| Error::new(ErrorKind::InvalidData, "missing problem line"))?;
+    arcs.sort_by_key(|&(tail, _, _)| tail);
+
+    let mut first_out = vec![0 as EdgeId; num_nodes + 1];
+    for &(tail, _, _) in &arcs {
+        first_out[tail as usize + 1] += 1;
+    }
+    for node in 0..num_nodes {
+        first_out[node + 1] += first_out[node];
+    }
+
+    Ok((
+        first_out,
+        arcs.iter().map(|&(_, head, _)| head).collect(),
+        arcs.iter().map(|&(_, _, weight)| weight).collect(),
+    ))
+}
+
+/// Read geocoordinates in DIMACs .co format as written by `export::write_coords_to_co`.
+/// Returns latitude and longitude.
+pub fn read_coords_from_co<R: BufRead>(reader: R) -> Result<(Vec<f32>, Vec<f32>)> {
+    let mut lat = Vec::new();
+    let mut lng = Vec::new();
+    let mut assigned = Vec::new();
+
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        match tokens.next() {
+            None | Some("c") => (),
+            Some("p") => {
+                let n = match (tokens.next(), tokens.next(), tokens.next(), tokens.next()) {
+                    (Some("aux"), Some("sp"), Some("co"), Some(n)) => n.parse::<usize>().map_err(|_| invalid_data(line_idx, "invalid node count"))?,
+                    _ => return Err(invalid_data(line_idx, "expected 'p aux sp co <num_nodes>'")),
+                };
+                lat = vec![0.0; n];
+                lng = vec![0.0; n];
+                assigned = vec![false; n];
+            }
+            Some("v") => {
+                let (id, node_lat, node_lng) = match (
+                    tokens.next().and_then(|t| t.parse::<usize>().ok()),
+                    tokens.next().and_then(|t| t.parse::<i32>().ok()),
+                    tokens.next().and_then(|t| t.parse::<i32>().ok()),
+                ) {
+                    (Some(id), Some(lat), Some(lng)) => (id, lat, lng),
+                    _ => return Err(invalid_data(line_idx, "expected 'v <node> <lat> <lng>'")),
+                };
+                if id == 0 || id > lat.len() {
+                    return Err(invalid_data(line_idx, "node id out of range"));
+                }
+                lat[id - 1] = node_lat as f32 / 1_000_000.0;
+                lng[id - 1] = node_lng as f32 / 1_000_000.0;
+                assigned[id - 1] = true;
+            }
+            Some(_) => return Err(invalid_data(line_idx, "unknown line type")),
+        }
+    }
+
+    if let Some(node) = assigned.iter().position(|&assigned| !assigned) {
+        return Err(Error::new(ErrorKind::InvalidData, format!("missing coordinates for node {}", node + 1)));
+    }
+
+    Ok((lat, lng))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_gr() {
+        let gr = "c example\np sp 3 4\na 2 1 5\na 1 2 3\na 1 3 1\n\na 3 1 7\n";
+        let (first_out, head, weight) = read_graph_from_gr(gr.as_bytes()).unwrap();
+        assert_eq!(first_out, vec![0, 2, 3, 4]);
+        assert_eq!(head, vec![1, 2, 0, 0]);
+        assert_eq!(weight, vec![3, 1, 5, 7]);
+    }
+
+    #[test]
+    fn reject_invalid_gr() {
+        assert!(read_graph_from_gr("a 1 2 3\n".as_bytes()).is_err());
+        assert!(read_graph_from_gr("p sp 2 1\na 1 3 3\n".as_bytes()).is_err());
+        assert!(read_graph_from_gr("p sp 2 1\na 1 2\n".as_bytes()).is_err());
+        assert!(read_graph_from_gr("c no problem line\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn read_co() {
+        let co = "p aux sp co 2\nv 2 49000000 8500000\nv 1 -1 2\n";
+        let (lat, lng) = read_coords_from_co(co.as_bytes()).unwrap();
+        assert_eq!(lat, vec![-0.000001, 49.0]);
+        assert_eq!(lng, vec![0.000002, 8.5]);
+        assert!(read_coords_from_co("p aux sp co 2\nv 1 1 1\n".as_bytes()).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 #![feature(array_windows)]
 #![feature(slice_group_by)]
 #![feature(type_alias_impl_trait)]
+#![feature(impl_trait_in_assoc_type)]
 #![feature(binary_heap_retain)]
 #![allow(clippy::collapsible_if)]
 #![allow(clippy::redundant_closure_call)]
@@ -25,9 +26,15 @@ pub mod cli;
 pub mod datastr;
 pub mod experiments;
 pub mod export;
+pub mod import;
 pub mod io;
 pub mod link_speed_estimates;
+pub mod live_feed;
+#[macro_use]
+pub mod logging;
+pub mod metrics;
 pub mod util;
+pub mod validate;
 
 /// Build time information for experiments.
 #[allow(dead_code)]
@@ -0,0 +1,524 @@
+//! Ingestion of live traffic feeds.
+//!
+//! Feeds are CSV like text files with one report per line, in one of four styles:
+//!
+//! - `mapbox`: `<osm_from_node>,<osm_to_node>,<speed in km/h>` without header.
+//!   Travel times are derived from the geo distance of the edge, reports are valid for a default duration.
+//! - `ptv`: `<from_node>;<to_node>;<speed in km/h>;<distance in m>;<duration in s>` with a header line and local node ids.
+//! - `here`: `<link_id>,<F|T>,<speed in km/h>,<jam factor>` with a header line.
+//!   Links are directed from (`F`) or towards (`T`) their reference node, a jam factor of 10 means the link is closed.
+//! - `tomtom`: `<osm_from_node>,<osm_to_node>,<travel time in s>,<road closure 0|1>` with a header line.
+//!
+//! A speed of 0 always means the edge is blocked.
+//! Feed files are picked up from a directory (`FeedDirectory`) or received through a unix socket (`FeedSocket`),
+//! mapped to local edge ids with a `FeedMapper` and then go into a `datastr::live_traffic::LiveTraffic` state.
+
+use crate::{
+    datastr::{graph::*, live_traffic::*, rank_select_map::*},
+    logging::Level,
+    util::in_range_option::InRangeOption,
+};
+use std::{
+    collections::{HashMap, HashSet},
+    io::{BufRead, Error, ErrorKind, Read, Result},
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
+
+fn invalid_data(line_idx: usize, msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("line {}: {}", line_idx + 1, msg))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedFormat {
+    Mapbox,
+    Ptv,
+    Here,
+    TomTom,
+}
+
+impl FromStr for FeedFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "mapbox" => Ok(FeedFormat::Mapbox),
+            "ptv" => Ok(FeedFormat::Ptv),
+            "here" => Ok(FeedFormat::Here),
+            "tomtom" => Ok(FeedFormat::TomTom),
+            _ => Err(Error::new(ErrorKind::InvalidInput, format!("unknown feed format '{}'", s))),
+        }
+    }
+}
+
+/// How a feed references an edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedEdge {
+    /// Tail and head, either local or OSM node ids.
+    Nodes { from: u64, to: u64 },
+    /// A HERE link in the direction from or towards its reference node.
+    HereLink { link_id: u64, from_ref: bool },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeedValue {
+    /// km/h
+    Speed(u32),
+    /// s
+    TravelTime(u32),
+    Blocked,
+}
+
+/// A single report as given in the feed, not yet mapped to the graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeedRecord {
+    pub edge: FeedEdge,
+    pub value: FeedValue,
+    /// m
+    pub distance: Option<u32>,
+    /// s
+    pub duration: Option<u32>,
+}
+
+impl FeedFormat {
+    fn delimiter(self) -> char {
+        match self {
+            FeedFormat::Ptv => ';',
+            _ => ',',
+        }
+    }
+
+    fn num_columns(self) -> usize {
+        match self {
+            FeedFormat::Mapbox => 3,
+            FeedFormat::Ptv => 5,
+            FeedFormat::Here | FeedFormat::TomTom => 4,
+        }
+    }
+
+    fn has_header(self) -> bool {
+        self != FeedFormat::Mapbox
+    }
+
+    fn parse_record(self, fields: &[&str]) -> std::result::Result<FeedRecord, &'static str> {
+        let int = |idx: usize| fields[idx].parse::<u64>().map_err(|_| "expected only numeric fields");
+        let small = |idx: usize| int(idx).and_then(|value| u32::try_from(value).map_err(|_| "value too large"));
+        let speed = |idx: usize| small(idx).map(|speed| if speed == 0 { FeedValue::Blocked } else { FeedValue::Speed(speed) });
+        let nodes = || -> std::result::Result<FeedEdge, &'static str> { Ok(FeedEdge::Nodes { from: int(0)?, to: int(1)? }) };
+
+        Ok(match self {
+            FeedFormat::Mapbox => FeedRecord {
+                edge: nodes()?,
+                value: speed(2)?,
+                distance: None,
+                duration: None,
+            },
+            FeedFormat::Ptv => FeedRecord {
+                edge: nodes()?,
+                value: speed(2)?,
+                distance: Some(small(3)?),
+                duration: Some(small(4)?),
+            },
+            FeedFormat::Here => {
+                let from_ref = match fields[1] {
+                    "F" => true,
+                    "T" => false,
+                    _ => return Err("expected F or T as direction"),
+                };
+                let jam_factor: f32 = fields[3].parse().map_err(|_| "expected a number as jam factor")?;
+                FeedRecord {
+                    edge: FeedEdge::HereLink { link_id: int(0)?, from_ref },
+                    value: if jam_factor >= 10.0 { FeedValue::Blocked } else { speed(2)? },
+                    distance: None,
+                    duration: None,
+                }
+            }
+            FeedFormat::TomTom => FeedRecord {
+                edge: nodes()?,
+                value: match fields[3] {
+                    "0" => FeedValue::TravelTime(small(2)?),
+                    "1" => FeedValue::Blocked,
+                    _ => return Err("expected 0 or 1 as road closure flag"),
+                },
+                distance: None,
+                duration: None,
+            },
+        })
+    }
+}
+
+/// Parse all records of a feed file.
+pub fn read_feed<R: BufRead>(reader: R, format: FeedFormat) -> Result<Vec<FeedRecord>> {
+    let mut records = Vec::new();
+
+    for (line_idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        if (format.has_header() && line_idx == 0) || line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(format.delimiter()).map(str::trim).collect();
+        if fields.len() != format.num_columns() {
+            return Err(invalid_data(line_idx, &format!("expected {} fields", format.num_columns())));
+        }
+        records.push(format.parse_record(&fields).map_err(|msg| invalid_data(line_idx, msg))?);
+    }
+
+    Ok(records)
+}
+
+/// Edges from and towards the reference node for each HERE link, indexed by the rank of the link id, see `with_here_link_ids`.
+pub type HereRankToLinkId = Vec<(InRangeOption<EdgeId>, InRangeOption<EdgeId>)>;
+
+/// Maps feed records to live updates for the edges of a graph.
+pub struct FeedMapper {
+    graph: UnweightedOwnedGraph,
+    node_ids: Option<RankSelectMap>,
+    here_links: Option<(RankSelectMap, HereRankToLinkId)>,
+    geo_distance: Option<Vec<Weight>>,
+    default_validity: WallClock,
+}
+
+impl FeedMapper {
+    /// Mapper for feeds with local node ids.
+    pub fn new(graph: UnweightedOwnedGraph) -> Self {
+        Self {
+            graph,
+            node_ids: None,
+            here_links: None,
+            geo_distance: None,
+            default_validity: 3600 * 1000,
+        }
+    }
+
+    /// Feeds reference nodes by OSM ids, `osm_node_ids` has to be sorted ascending.
+    pub fn with_osm_node_ids(mut self, osm_node_ids: &[u64]) -> Self {
+        let mut osm_ids_present = BitVec::new(osm_node_ids.last().map(|&id| id as usize + 1).unwrap_or(0));
+        for &osm_id in osm_node_ids {
+            osm_ids_present.set(osm_id as usize);
+        }
+        self.node_ids = Some(RankSelectMap::new(osm_ids_present));
+        self
+    }
+
+    /// Feeds reference HERE links, with the `link_id_mapping` and `here_rank_to_link_id` files of the HERE import.
+    pub fn with_here_link_ids(mut self, link_id_mapping: BitVec, here_rank_to_link_id: HereRankToLinkId) -> Self {
+        self.here_links = Some((RankSelectMap::new(link_id_mapping), here_rank_to_link_id));
+        self
+    }
+
+    /// Edge lengths in meters, used for records without a distance.
+    pub fn with_geo_distance(mut self, geo_distance: Vec<Weight>) -> Self {
+        assert_eq!(geo_distance.len(), self.graph.num_arcs());
+        self.geo_distance = Some(geo_distance);
+        self
+    }
+
+    /// Validity in ms for records without a duration.
+    pub fn with_default_validity(mut self, default_validity: WallClock) -> Self {
+        self.default_validity = default_validity;
+        self
+    }
+
+    fn node(&self, id: u64) -> Option<NodeId> {
+        let node = match &self.node_ids {
+            Some(map) => map.get(usize::try_from(id).ok()?)?,
+            None => usize::try_from(id).ok()?,
+        };
+        if node < self.graph.num_nodes() {
+            Some(node as NodeId)
+        } else {
+            None
+        }
+    }
+
+    fn edge(&self, edge: FeedEdge) -> Option<EdgeId> {
+        match edge {
+            FeedEdge::Nodes { from, to } => self.graph.edge_indices(self.node(from)?, self.node(to)?).next().map(|EdgeIdT(edge)| edge),
+            FeedEdge::HereLink { link_id, from_ref } => {
+                let (link_id_mapping, here_rank_to_link_id) = self.here_links.as_ref()?;
+                let (from_ref_edge, to_ref_edge) = here_rank_to_link_id[link_id_mapping.get(usize::try_from(link_id).ok()?)?];
+                if from_ref {
+                    from_ref_edge.value()
+                } else {
+                    to_ref_edge.value()
+                }
+            }
+        }
+    }
+
+    /// `None` if the record does not match an edge of the graph.
+    pub fn map(&self, record: &FeedRecord, now: WallClock) -> Option<LiveUpdate> {
+        let edge = self.edge(record.edge)?;
+        let travel_time = match record.value {
+            FeedValue::Blocked => INFINITY,
+            FeedValue::Speed(speed) => {
+                let distance = record
+                    .distance
+                    .or_else(|| self.geo_distance.as_ref().map(|geo_distance| geo_distance[edge as usize]))?;
+                // km/h to m/ms, use u64 to avoid overflows for long edges
+                std::cmp::min(100 * 36 * u64::from(distance) / u64::from(speed), u64::from(INFINITY)) as Weight
+            }
+            FeedValue::TravelTime(seconds) => std::cmp::min(u64::from(seconds) * 1000, u64::from(INFINITY)) as Weight,
+        };
+        let validity = record
+            .duration
+            .map(|duration| WallClock::from(duration) * 1000)
+            .unwrap_or(self.default_validity);
+        Some(LiveUpdate {
+            edge,
+            travel_time,
+            valid_until: now + validity,
+        })
+    }
+
+    /// Map all records, records without a matching edge are skipped.
+    pub fn map_all(&self, records: &[FeedRecord], now: WallClock) -> Vec<LiveUpdate> {
+        records.iter().filter_map(|record| self.map(record, now)).collect()
+    }
+}
+
+/// Watches a directory for new or modified feed files.
+/// Files starting with a `.` are ignored, so writers can create files under a hidden name and rename them once complete.
+pub struct FeedDirectory {
+    dir: PathBuf,
+    seen: HashMap<PathBuf, SystemTime>,
+}
+
+impl FeedDirectory {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            seen: HashMap::new(),
+        }
+    }
+
+    /// Files which are new or were modified since the last poll, oldest first.
+    /// Files which were removed are forgotten, so long running ingestion does not accumulate all file names ever seen.
+    /// On errors nothing is marked as seen, so the next poll reports the same files again.
+    pub fn poll(&mut self) -> Result<Vec<PathBuf>> {
+        let mut changed = Vec::new();
+        let mut present = HashSet::new();
+        for entry in std::fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.file_name().to_string_lossy().starts_with('.') || !entry.file_type()?.is_file() {
+                continue;
+            }
+            let modified = entry.metadata()?.modified()?;
+            let path = entry.path();
+            if self.seen.get(&path) != Some(&modified) {
+                changed.push((modified, path.clone()));
+            }
+            present.insert(path);
+        }
+        self.seen.retain(|path, _| present.contains(path));
+        self.seen.extend(changed.iter().map(|(modified, path)| (path.clone(), *modified)));
+        changed.sort();
+        Ok(changed.into_iter().map(|(_, path)| path).collect())
+    }
+}
+
+/// Receives feed files through a unix socket, one file per connection.
+pub struct FeedSocket {
+    listener: UnixListener,
+}
+
+impl FeedSocket {
+    /// How long a client may stall while sending, before its connection is dropped.
+    pub const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Bind to `path`, a stale socket file from an earlier run is removed.
+    /// Any other existing file at `path` is an error and left untouched.
+    pub fn bind(path: &Path) -> Result<Self> {
+        match std::fs::metadata(path) {
+            Ok(meta) if meta.file_type().is_socket() => std::fs::remove_file(path)?,
+            Ok(_) => return Err(Error::new(ErrorKind::AlreadyExists, format!("{} exists and is not a socket", path.display()))),
+            Err(e) if e.kind() == ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+        let listener = UnixListener::bind(path)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener })
+    }
+
+    /// Contents of all connections which are pending right now.
+    /// Does not wait for new connections, reading from each accepted one is bounded by `READ_TIMEOUT`.
+    /// Connections which fail or time out are logged and skipped.
+    pub fn poll(&self) -> Result<Vec<Vec<u8>>> {
+        let mut received = Vec::new();
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => match Self::receive(stream) {
+                    Ok(content) => received.push(content),
+                    Err(e) => crate::log_event!(Level::Warn, "skipping feed connection", { "error": e.to_string() }),
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(received),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn receive(mut stream: UnixStream) -> Result<Vec<u8>> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(Self::READ_TIMEOUT))?;
+        let mut content = Vec::new();
+        stream.read_to_end(&mut content)?;
+        Ok(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nodes(from: u64, to: u64, value: FeedValue) -> FeedRecord {
+        FeedRecord {
+            edge: FeedEdge::Nodes { from, to },
+            value,
+            distance: None,
+            duration: None,
+        }
+    }
+
+    #[test]
+    fn read_feeds() {
+        let mapbox = "1,2,50\n\n3, 1,0\n";
+        assert_eq!(
+            read_feed(mapbox.as_bytes(), FeedFormat::Mapbox).unwrap(),
+            vec![nodes(1, 2, FeedValue::Speed(50)), nodes(3, 1, FeedValue::Blocked)]
+        );
+        let ptv = "from;to;speed;distance;duration\n0;1;36;100;60\n";
+        assert_eq!(
+            read_feed(ptv.as_bytes(), FeedFormat::Ptv).unwrap(),
+            vec![FeedRecord {
+                distance: Some(100),
+                duration: Some(60),
+                ..nodes(0, 1, FeedValue::Speed(36))
+            }]
+        );
+        let here = "link_id,direction,speed,jam_factor\n42,F,30,2.5\n42,T,30,10.0\n";
+        assert_eq!(
+            read_feed(here.as_bytes(), FeedFormat::Here).unwrap(),
+            vec![
+                FeedRecord {
+                    edge: FeedEdge::HereLink { link_id: 42, from_ref: true },
+                    value: FeedValue::Speed(30),
+                    distance: None,
+                    duration: None
+                },
+                FeedRecord {
+                    edge: FeedEdge::HereLink { link_id: 42, from_ref: false },
+                    value: FeedValue::Blocked,
+                    distance: None,
+                    duration: None
+                },
+            ]
+        );
+        let tomtom = "from,to,travel_time,closed\n1,2,17,0\n2,1,17,1\n";
+        assert_eq!(
+            read_feed(tomtom.as_bytes(), FeedFormat::TomTom).unwrap(),
+            vec![nodes(1, 2, FeedValue::TravelTime(17)), nodes(2, 1, FeedValue::Blocked)]
+        );
+        assert!(read_feed("1,2\n".as_bytes(), FeedFormat::Mapbox).is_err());
+        assert!(read_feed("1,2,fast\n".as_bytes(), FeedFormat::Mapbox).is_err());
+        assert!(read_feed("header\n42,X,30,1.0\n".as_bytes(), FeedFormat::Here).is_err());
+    }
+
+    #[test]
+    fn map_records() {
+        // 0 -> 1, 0 -> 2, 1 -> 2
+        let graph = UnweightedOwnedGraph::new(vec![0, 2, 3, 3], vec![1, 2, 2]);
+        let mapper = FeedMapper::new(graph)
+            .with_osm_node_ids(&[10, 20, 30])
+            .with_geo_distance(vec![100, 200, 300])
+            .with_default_validity(1000);
+
+        let record = |from, to, speed| nodes(from, to, if speed == 0 { FeedValue::Blocked } else { FeedValue::Speed(speed) });
+        assert_eq!(
+            mapper.map(&record(20, 30, 36), 5000),
+            Some(LiveUpdate {
+                edge: 2,
+                travel_time: 30_000,
+                valid_until: 6000
+            })
+        );
+        assert_eq!(mapper.map(&record(10, 30, 0), 5000).map(|update| update.travel_time), Some(INFINITY));
+        assert_eq!(mapper.map(&record(30, 10, 36), 5000), None);
+        assert_eq!(mapper.map(&record(11, 20, 36), 5000), None);
+        assert_eq!(mapper.map_all(&[record(10, 20, 36), record(40, 10, 36)], 0).len(), 1);
+        assert_eq!(
+            mapper.map(&nodes(10, 20, FeedValue::TravelTime(12)), 0).map(|update| update.travel_time),
+            Some(12_000)
+        );
+    }
+
+    #[test]
+    fn map_here_links() {
+        let graph = UnweightedOwnedGraph::new(vec![0, 2, 3, 3], vec![1, 2, 2]);
+        // link 5 has edge 0 from its reference node and edge 2 towards it, link 7 only has edge 1 towards its reference node
+        let mut link_id_mapping = BitVec::new(8);
+        link_id_mapping.set(5);
+        link_id_mapping.set(7);
+        let mapper = FeedMapper::new(graph).with_geo_distance(vec![100, 200, 300]).with_here_link_ids(
+            link_id_mapping,
+            vec![(InRangeOption::some(0), InRangeOption::some(2)), (InRangeOption::NONE, InRangeOption::some(1))],
+        );
+
+        let link = |link_id, from_ref| FeedRecord {
+            edge: FeedEdge::HereLink { link_id, from_ref },
+            value: FeedValue::Speed(36),
+            distance: None,
+            duration: None,
+        };
+        assert_eq!(mapper.map(&link(5, true), 0).map(|update| update.edge), Some(0));
+        assert_eq!(
+            mapper.map(&link(5, false), 0).map(|update| (update.edge, update.travel_time)),
+            Some((2, 30_000))
+        );
+        assert_eq!(mapper.map(&link(7, true), 0), None);
+        assert_eq!(mapper.map(&link(7, false), 0).map(|update| update.edge), Some(1));
+        assert_eq!(mapper.map(&link(6, true), 0), None);
+        assert_eq!(mapper.map(&link(100, true), 0), None);
+    }
+
+    #[test]
+    fn removed_files_are_forgotten() {
+        let dir = std::env::temp_dir().join(format!("rrr_feed_directory_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut feeds = FeedDirectory::new(&dir);
+
+        std::fs::write(dir.join("a.csv"), "1,2,50\n").unwrap();
+        std::fs::write(dir.join(".b.csv.tmp"), "1,2,50\n").unwrap();
+        assert_eq!(feeds.poll().unwrap(), vec![dir.join("a.csv")]);
+        assert!(feeds.poll().unwrap().is_empty());
+
+        std::fs::remove_file(dir.join("a.csv")).unwrap();
+        assert!(feeds.poll().unwrap().is_empty());
+        assert!(feeds.seen.is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn bind_only_replaces_sockets() {
+        let dir = std::env::temp_dir().join(format!("rrr_feed_socket_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let file = dir.join("feeds");
+        std::fs::write(&file, "1,2,50\n").unwrap();
+        assert!(FeedSocket::bind(&file).is_err());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "1,2,50\n");
+
+        let socket = dir.join("feeds.sock");
+        drop(FeedSocket::bind(&socket).unwrap());
+        let feeds = FeedSocket::bind(&socket).unwrap();
+        std::io::Write::write_all(&mut UnixStream::connect(&socket).unwrap(), b"1,2,50\n").unwrap();
+        assert_eq!(feeds.poll().unwrap(), vec![b"1,2,50\n".to_vec()]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
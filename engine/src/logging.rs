@@ -0,0 +1,114 @@
+//! Structured logging with one JSON object per line on stderr.
+//!
+//! Each record has a timestamp, a level and a message and arbitrary additional fields,
+//! so logs can be ingested and filtered by log processors without parsing free text.
+//! Records are written with a single locked write, so lines of concurrent threads never interleave.
+
+use serde_json::{json, Map, Value};
+use std::{
+    io::Write,
+    sync::atomic::{AtomicU8, Ordering::Relaxed},
+    time::Instant,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+        }
+    }
+}
+
+impl std::str::FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "debug" => Ok(Level::Debug),
+            "info" => Ok(Level::Info),
+            "warn" => Ok(Level::Warn),
+            "error" => Ok(Level::Error),
+            _ => Err(format!("unknown log level {}", s)),
+        }
+    }
+}
+
+static MIN_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Records below `level` are dropped, the default is `Info`.
+pub fn set_level(level: Level) {
+    MIN_LEVEL.store(level as u8, Relaxed);
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 >= MIN_LEVEL.load(Relaxed)
+}
+
+/// The JSON object for a record, `fields` should be an object, other values are stored under `value`.
+pub fn record(level: Level, message: &str, fields: Value) -> Value {
+    let mut object = Map::new();
+    object.insert("timestamp".to_string(), Value::String(chrono::prelude::Utc::now().to_rfc3339()));
+    object.insert("level".to_string(), Value::String(level.as_str().to_string()));
+    object.insert("message".to_string(), Value::String(message.to_string()));
+    match fields {
+        Value::Object(fields) => object.extend(fields),
+        Value::Null => (),
+        value => {
+            object.insert("value".to_string(), value);
+        }
+    }
+    Value::Object(object)
+}
+
+pub fn log(level: Level, message: &str, fields: Value) {
+    if !enabled(level) {
+        return;
+    }
+    let mut line = serde_json::to_vec(&record(level, message, fields)).unwrap();
+    line.push(b'\n');
+    // nowhere to report a failing stderr
+    let _ = std::io::stderr().lock().write_all(&line);
+}
+
+/// Run `f` and log how long it took with `message` and the duration in ms.
+pub fn log_time<O>(level: Level, message: &str, f: impl FnOnce() -> O) -> O {
+    let start = Instant::now();
+    let res = f();
+    log(level, message, json!({ "duration_ms": start.elapsed().as_secs_f64() * 1000.0 }));
+    res
+}
+
+/// Log a record, optionally with fields given in `json!` syntax.
+#[macro_export]
+macro_rules! log_event {
+    ($level:expr, $message:expr) => { $crate::logging::log($level, $message, $crate::report::json!(null)) };
+    ($level:expr, $message:expr, $($json:tt)+) => { $crate::logging::log($level, $message, $crate::report::json!($($json)+)) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_contain_fields() {
+        let record = record(Level::Warn, "slow query", json!({ "from": 1, "to": 2, "ms": 12.5 }));
+        assert_eq!(record["level"], "warn");
+        assert_eq!(record["message"], "slow query");
+        assert_eq!(record["to"], 2);
+        assert_eq!(record["ms"], 12.5);
+        assert!(record["timestamp"].is_string());
+        assert!(Level::Debug < Level::Error);
+        assert_eq!("ERROR".parse(), Ok(Level::Error));
+    }
+}
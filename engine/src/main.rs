@@ -1,61 +1,584 @@
-use std::{env, error::Error, path::Path};
+// The `rust_road_router` command line tool.
+// Runs the complete toolchain from importing a graph to benchmarking queries, driven by a JSON config file.
+// See `cli::config` for the config format.
 
+use std::{
+    env,
+    error::Error,
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    process::Command,
+    time::UNIX_EPOCH,
+};
+
+use serde_json::{json, Value};
+
+#[macro_use]
+extern crate rust_road_router;
 use rust_road_router::{
     algo::{
         contraction_hierarchy::{self, query::Server as CHServer, ContractionHierarchy},
+        customizable_contraction_hierarchy::{query::Server as CCHServer, *},
         dijkstra::{
             query::{bidirectional_dijkstra::Server as BiDijkServer, dijkstra::Server as DijkServer},
             DefaultOps,
         },
         *,
     },
-    cli::CliErr,
+    cli::{
+        config::{Algorithm, Config},
+        CliErr,
+    },
     datastr::{graph::*, node_order::NodeOrder},
-    io::{Load, ReconstructPrepared},
-    report::benchmark::report_time,
+    experiments::{self, benchmark::*},
+    export, import,
+    io::*,
+    report::{benchmark::report_time_with_key, *},
+    validate,
 };
 
+const USAGE: &str = "Usage: rust_road_router <command> <config.json>
+
+Commands:
+  import       Import a DIMACS graph into the graph directory
+  order        Calculate a nested dissection order with InertialFlowCutter
+  contract     Build and store a CH for each metric
+  customize    Build the CCH and store a perfect customization for each metric
+  query        Run the queries from the graph directory with the configured algorithm
+  benchmark    Benchmark the configured algorithm on the configured query sets
+  export       Export the graph with the first metric in DIMACS format
+  validate     Check the graph directory for inconsistent data
+  repair       Write a copy of the graph directory without self-loops, parallel arc weights unified and FIFO travel time functions
+  largest_scc  Report the strongly connected components and write a copy of the graph directory restricted to the largest one";
+
 fn main() -> Result<(), Box<dyn Error>> {
-    let arg = &env::args().skip(1).next().ok_or(CliErr("No directory arg given"))?;
-    let path = Path::new(arg);
-
-    let from = Vec::load_from(path.join("test/source"))?;
-    let to = Vec::load_from(path.join("test/target"))?;
-    let ground_truth = Vec::load_from(path.join("test/travel_time_length"))?;
-
-    let graph = WeightedGraphReconstructor("travel_time").reconstruct_from(&path)?;
-    let mut simple_server = DijkServer::<_, DefaultOps>::new(graph.borrowed());
-    let mut bi_dir_server = BiDijkServer::<_, _, _>::new(graph.borrowed());
-
-    let ch_first_out = Vec::load_from(path.join("travel_time_ch/first_out"))?;
-    let ch_head = Vec::load_from(path.join("travel_time_ch/head"))?;
-    let ch_weight = Vec::load_from(path.join("travel_time_ch/weight"))?;
-    let ch_order = NodeOrder::from_node_order(Vec::load_from(path.join("travel_time_ch/order"))?);
-    let mut ch_server = CHServer::new(
-        ContractionHierarchy::from_contracted_graph(OwnedGraph::new(ch_first_out, ch_head, ch_weight), &ch_order),
-        NodeOrder::identity(graph.num_nodes()),
+    let mut args = env::args().skip(1);
+    let command = args.next().ok_or(CliErr("No command given, see --help for usage"))?;
+    if command == "help" || command == "--help" || command == "-h" {
+        println!("{USAGE}");
+        return Ok(());
+    }
+    let config_path = args.next().ok_or(CliErr("No config file given"))?;
+    if args.next().is_some() {
+        return Err(Box::new(CliErr("Too many arguments, see --help for usage")));
+    }
+
+    let run = match command.as_str() {
+        "import" => import,
+        "order" => order,
+        "contract" => contract,
+        "customize" => customize,
+        "query" => query,
+        "benchmark" => benchmark,
+        "export" => export,
+        "validate" => validate,
+        "repair" => repair,
+        "largest_scc" => largest_scc,
+        _ => return Err(Box::new(CliErr("Unknown command, see --help for usage"))),
+    };
+
+    let config = Config::load_from(config_path)?;
+    if let Some(threads) = config.threads {
+        rayon::ThreadPoolBuilder::new().num_threads(threads).build_global()?;
+    }
+
+    let _reporter = enable_reporting(&format!("rust_road_router {command}"));
+    report!("num_threads", rayon::current_num_threads());
+    report!("graph_dir", config.graph_dir.display().to_string());
+    report!("algorithm", config.algorithm.name());
+
+    run(&config)
+}
+
+fn load_graph(config: &Config, metric: &str) -> Result<OwnedGraph, Box<dyn Error>> {
+    let graph = OwnedGraph::new(
+        Vec::load_from(config.graph_dir.join("first_out"))?,
+        Vec::load_from(config.graph_dir.join("head"))?,
+        Vec::load_from(config.graph_dir.join(metric))?,
     );
-    let mut ch_server_with_own_ch = CHServer::new(contraction_hierarchy::contract(&graph, ch_order.clone()), ch_order);
+    report!("graph", { "num_nodes": graph.num_nodes(), "num_arcs": graph.num_arcs() });
+    Ok(graph)
+}
 
-    for ((&from, &to), &ground_truth) in from.iter().zip(to.iter()).zip(ground_truth.iter()).take(100) {
-        let ground_truth = match ground_truth {
-            INFINITY => None,
-            val => Some(val),
-        };
+fn load_order(config: &Config) -> Result<NodeOrder, Box<dyn Error>> {
+    Ok(NodeOrder::from_node_order(Vec::load_from(config.graph_dir.join(&config.order))?))
+}
 
-        report_time("simple dijkstra", || {
-            assert_eq!(simple_server.query(Query { from, to }).distance(), ground_truth);
-        });
-        report_time("bidir dijkstra", || {
-            assert_eq!(bi_dir_server.query(Query { from, to }).distance(), ground_truth);
+fn cch_dir(config: &Config) -> PathBuf {
+    config.graph_dir.join("cch")
+}
+
+// Stored preprocessing is only reused if it was built from the same graph, order and metric.
+// Rerunning `order` or replacing the graph files, for example with the output of `repair` or `largest_scc`, invalidates it.
+fn cache_key(config: &Config, graph: &OwnedGraph, metric: Option<&str>) -> Result<Value, Box<dyn Error>> {
+    let modified = |file: &str| -> Result<u64, Box<dyn Error>> {
+        let modified = std::fs::metadata(config.graph_dir.join(file))?.modified()?;
+        Ok(modified.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()))
+    };
+    let mut key = json!({
+        "order": config.order,
+        "order_modified": modified(&config.order)?,
+        "first_out_modified": modified("first_out")?,
+        "head_modified": modified("head")?,
+        "num_nodes": graph.num_nodes(),
+        "num_arcs": graph.num_arcs(),
+    });
+    if let Some(metric) = metric {
+        key["metric"] = json!(metric);
+        key["metric_modified"] = json!(modified(metric)?);
+    }
+    Ok(key)
+}
+
+fn write_cache_key(dir: &Path, key: &Value) -> std::io::Result<()> {
+    std::fs::write(dir.join("cache.json"), key.to_string())
+}
+
+// Caches without a stored key, for example from earlier releases, never match.
+fn is_cached(dir: &Path, key: &Value) -> Result<bool, Box<dyn Error>> {
+    let path = dir.join("cache.json");
+    if !path.exists() {
+        return Ok(false);
+    }
+    let stored: Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+    Ok(&stored == key)
+}
+
+fn import(config: &Config) -> Result<(), Box<dyn Error>> {
+    let files = config.import.as_ref().ok_or(CliErr("No 'import' section in config"))?;
+    let (first_out, head, weight) = report_time_with_key("DIMACS import", "import_running_time_ms", || {
+        import::read_graph_from_gr(BufReader::new(File::open(&files.dimacs)?))
+    })?;
+
+    std::fs::create_dir_all(&config.graph_dir)?;
+    first_out.write_to(&config.graph_dir.join("first_out"))?;
+    head.write_to(&config.graph_dir.join("head"))?;
+    // DIMACS graphs only have one weight per arc, so only the first metric can be imported
+    weight.write_to(&config.graph_dir.join(&config.metrics[0]))?;
+    report!("graph", { "num_nodes": first_out.len() - 1, "num_arcs": head.len() });
+
+    if let Some(coordinates) = &files.coordinates {
+        let (lat, lng) = import::read_coords_from_co(BufReader::new(File::open(coordinates)?))?;
+        if lat.len() != first_out.len() - 1 {
+            return Err(Box::new(CliErr("Number of coordinates does not match number of nodes")));
+        }
+        lat.write_to(&config.graph_dir.join("latitude"))?;
+        lng.write_to(&config.graph_dir.join("longitude"))?;
+    }
+
+    Ok(())
+}
+
+fn order(config: &Config) -> Result<(), Box<dyn Error>> {
+    let flow_cutter = config.flow_cutter.as_ref().ok_or(CliErr("No 'flow_cutter' binary in config"))?;
+    let dir = &config.graph_dir;
+    let threads = config.threads.map_or("-1".to_string(), |threads| threads.to_string());
+
+    // same parameters as in flow_cutter_cch_order.sh
+    let status = report_time_with_key("InertialFlowCutter", "order_running_time_ms", || {
+        Command::new(flow_cutter)
+            .arg("load_routingkit_unweighted_graph")
+            .args([dir.join("first_out"), dir.join("head")])
+            .arg("load_routingkit_longitude")
+            .arg(dir.join("longitude"))
+            .arg("load_routingkit_latitude")
+            .arg(dir.join("latitude"))
+            .args(["remove_multi_arcs", "remove_loops", "add_back_arcs", "sort_arcs"])
+            .args(["flow_cutter_set", "random_seed", "5489"])
+            .args(["reorder_nodes_at_random", "reorder_nodes_in_preorder"])
+            .args(["flow_cutter_set", "thread_count", &threads])
+            .args(["flow_cutter_set", "BulkDistance", "no"])
+            .args(["flow_cutter_set", "max_cut_size", "100000000"])
+            .args(["flow_cutter_set", "distance_ordering_cutter_count", "0"])
+            .args(["flow_cutter_set", "geo_pos_ordering_cutter_count", "8"])
+            .args(["flow_cutter_set", "bulk_assimilation_threshold", "0.4"])
+            .args(["flow_cutter_set", "bulk_assimilation_order_threshold", "0.25"])
+            .args(["flow_cutter_set", "bulk_step_fraction", "0.05"])
+            .args(["flow_cutter_set", "initial_assimilated_fraction", "0.05"])
+            .args([
+                "flow_cutter_config",
+                "report_time",
+                "reorder_nodes_in_accelerated_flow_cutter_cch_order",
+                "do_not_report_time",
+            ])
+            .args(["examine_chordal_supergraph", "save_routingkit_node_permutation_since_last_load"])
+            .arg(dir.join(&config.order))
+            .status()
+    })?;
+
+    if !status.success() {
+        return Err(Box::new(CliErr("InertialFlowCutter failed")));
+    }
+    Ok(())
+}
+
+fn contract(config: &Config) -> Result<(), Box<dyn Error>> {
+    let order = load_order(config)?;
+    let mut metrics_ctxt = push_collection_context("metrics");
+
+    for metric in &config.metrics {
+        let _metric_ctxt = metrics_ctxt.push_collection_item();
+        report!("metric", metric);
+
+        let graph = load_graph(config, metric)?;
+        let ch = report_time_with_key("CH contraction", "contraction_running_time_ms", || {
+            contraction_hierarchy::contract_parallel(&graph, order.clone())
         });
-        report_time("CH", || {
-            assert_eq!(ch_server.query(Query { from, to }).distance(), ground_truth);
+
+        let dir = config.ch_dir(metric);
+        std::fs::create_dir_all(&dir)?;
+        ch.deconstruct_to(&dir)?;
+        // RoutingKit stores the node order of a CH in `order`, so tools expecting RoutingKit CHs can use the directory as well
+        order.order().write_to(&dir.join("order"))?;
+        write_cache_key(&dir, &cache_key(config, &graph, Some(metric))?)?;
+    }
+
+    Ok(())
+}
+
+fn customize(config: &Config) -> Result<(), Box<dyn Error>> {
+    let order = load_order(config)?;
+    // all metrics share the same topology, so the CCH only has to be built once
+    let graph = load_graph(config, &config.metrics[0])?;
+    let cch = CCH::fix_order_and_build(&graph, order);
+
+    let dir = cch_dir(config);
+    std::fs::create_dir_all(&dir)?;
+    cch.deconstruct_to(&dir)?;
+    // the CCH reorders the nodes for customization, so the order to store is the one of the CCH
+    cch.node_order().deconstruct_to(&dir)?;
+    write_cache_key(&dir, &cache_key(config, &graph, None)?)?;
+
+    let mut metrics_ctxt = push_collection_context("metrics");
+    for metric in &config.metrics {
+        let _metric_ctxt = metrics_ctxt.push_collection_item();
+        report!("metric", metric);
+
+        let graph = load_graph(config, metric)?;
+        let customized = customize_perfect(customization::customize(&cch, &graph));
+
+        let dir = config.customized_cch_dir(metric);
+        std::fs::create_dir_all(&dir)?;
+        customized.deconstruct_to(&dir)?;
+        write_cache_key(&dir, &cache_key(config, &graph, Some(metric))?)?;
+    }
+
+    Ok(())
+}
+
+fn query(config: &Config) -> Result<(), Box<dyn Error>> {
+    let dir = &config.graph_dir;
+    let from: Vec<NodeId> = Vec::load_from(dir.join(&config.queries.source))?;
+    let to: Vec<NodeId> = Vec::load_from(dir.join(&config.queries.target))?;
+    if from.len() != to.len() {
+        return Err(Box::new(CliErr("Different number of query sources and targets")));
+    }
+    let count = config.queries.count.map_or(from.len(), |count| std::cmp::min(count, from.len()));
+
+    let mut metrics_ctxt = push_collection_context("metrics");
+    for metric in &config.metrics {
+        let _metric_ctxt = metrics_ctxt.push_collection_item();
+        report!("metric", metric);
+
+        let ground_truth: Option<Vec<Weight>> = config.queries.ground_truth.get(metric).map(|file| Vec::load_from(dir.join(file))).transpose()?;
+        if ground_truth.as_ref().is_some_and(|ground_truth| ground_truth.len() < count) {
+            return Err(Box::new(CliErr("Less ground truth values than queries")));
+        }
+
+        let graph = load_graph(config, metric)?;
+        with_server(
+            config,
+            metric,
+            &graph,
+            &mut GivenQueries {
+                from: &from[..count],
+                to: &to[..count],
+                ground_truth: ground_truth.as_deref(),
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+fn benchmark(config: &Config) -> Result<(), Box<dyn Error>> {
+    // all algorithms of the CLI are static and would silently ignore the departures
+    if config
+        .benchmark
+        .query_sets()
+        .iter()
+        .any(|spec| matches!(spec.kind, QuerySetKind::RushHour { .. }))
+    {
+        return Err(Box::new(CliErr("rush_hour query sets require a time-dependent algorithm")));
+    }
+    let sets = load_or_generate_query_sets(config)?;
+    let params = BenchmarkParams {
+        warmup: config.benchmark.warmup,
+        repetitions: config.benchmark.repetitions,
+    };
+    report!("warmup", params.warmup);
+    report!("repetitions", params.repetitions);
+
+    let mut results = Vec::new();
+    let mut num_mismatches = 0;
+    let mut metrics_ctxt = push_collection_context("metrics");
+    for metric in &config.metrics {
+        let _metric_ctxt = metrics_ctxt.push_collection_item();
+        report!("metric", metric);
+
+        let graph = load_graph(config, metric)?;
+        let mut run = BenchmarkRun {
+            name: format!("{}/{}", config.algorithm.name(), metric),
+            sets: &sets,
+            params,
+            results: Vec::new(),
+        };
+        with_server(config, metric, &graph, &mut run)?;
+
+        let mut sets_ctxt = push_collection_context("query_sets");
+        for (set, result) in sets.iter().zip(run.results) {
+            let _set_ctxt = sets_ctxt.push_collection_item();
+            result.report(set);
+
+            let ground_truth_file = query_set_dir(config, &set.name).join(format!("{metric}_length"));
+            if ground_truth_file.exists() {
+                let mismatches = result.diff(set, &load_distances(&ground_truth_file)?);
+                report!("num_mismatches", mismatches.len());
+                for mismatch in mismatches.iter().take(10) {
+                    eprintln!(
+                        "{}: query {} from {} to {} expected {:?} but got {:?}",
+                        result.name, mismatch.index, mismatch.from, mismatch.to, mismatch.expected, mismatch.actual
+                    );
+                }
+                num_mismatches += mismatches.len();
+            } else if config.algorithm == Algorithm::Dijkstra {
+                // plain Dijkstra is the reference for all other algorithms
+                write_distances(&result.distances, &ground_truth_file)?;
+            }
+            results.push(result);
+        }
+    }
+    drop(metrics_ctxt);
+
+    eprint!("{}", percentile_table(&results));
+    if num_mismatches > 0 {
+        return Err(Box::new(CliErr("Query results differ from ground truth")));
+    }
+    Ok(())
+}
+
+fn query_set_dir(config: &Config, name: &str) -> PathBuf {
+    config.graph_dir.join("benchmark_queries").join(name)
+}
+
+// Query sets are generated once and stored, so all algorithms and later releases run the same queries.
+// Stored sets are regenerated when anything in their configuration changes.
+fn load_or_generate_query_sets(config: &Config) -> Result<Vec<QuerySet>, Box<dyn Error>> {
+    let mut graph = None;
+    let mut sets = Vec::new();
+    let mut sets_ctxt = push_collection_context("query_sets");
+
+    for spec in config.benchmark.query_sets() {
+        let _set_ctxt = sets_ctxt.push_collection_item();
+        report!("name", spec.name);
+        report!("kind", spec.kind.name());
+        report!("seed", spec.seed);
+
+        let dir = query_set_dir(config, &spec.name);
+        if dir.join("source").exists() {
+            if spec.is_stored_in(&dir)? {
+                let set = QuerySet::load_from_dir(&dir)?;
+                report!("num_queries", set.len());
+                sets.push(set);
+                continue;
+            }
+            // the ground truth belongs to the old queries
+            std::fs::remove_dir_all(&dir)?;
+        }
+
+        if graph.is_none() {
+            graph = Some(without_reporting(|| load_graph(config, &config.metrics[0]))?);
+        }
+        let coords = match spec.kind {
+            QuerySetKind::DistanceBand { .. } => Some((
+                Vec::<f32>::load_from(config.graph_dir.join("latitude"))?,
+                Vec::<f32>::load_from(config.graph_dir.join("longitude"))?,
+            )),
+            _ => None,
+        };
+        let set = report_time_with_key("query set generation", "generation_running_time_ms", || {
+            spec.generate(graph.as_ref().unwrap(), coords.as_ref().map(|(lat, lng)| (&lat[..], &lng[..])))
         });
-        report_time("own CH", || {
-            assert_eq!(ch_server_with_own_ch.query(Query { from, to }).distance(), ground_truth);
+        report!("num_queries", set.len());
+        set.write_to_dir(&dir)?;
+        spec.write_to_dir(&dir)?;
+        sets.push(set);
+    }
+
+    Ok(sets)
+}
+
+fn export(config: &Config) -> Result<(), Box<dyn Error>> {
+    let files = config.export.as_ref().ok_or(CliErr("No 'export' section in config"))?;
+    let graph = load_graph(config, &config.metrics[0])?;
+    export::write_graph_to_gr(&graph, path_str(&files.dimacs)?)?;
+
+    if let Some(coordinates) = &files.coordinates {
+        let lat: Vec<f32> = Vec::load_from(config.graph_dir.join("latitude"))?;
+        let lng: Vec<f32> = Vec::load_from(config.graph_dir.join("longitude"))?;
+        export::write_coords_to_co(&lat, &lng, path_str(coordinates)?)?;
+    }
+
+    Ok(())
+}
+
+fn validate(config: &Config) -> Result<(), Box<dyn Error>> {
+    let validation = validate::validate_dir(&config.graph_dir, &config.metrics, Some(&config.order))?;
+    let diagnostics = validation.diagnostics();
+    for diagnostic in &diagnostics {
+        eprintln!("{diagnostic}");
+    }
+    report!("num_errors", validation.num_errors());
+    report!("num_warnings", validation.num_warnings());
+    report!("diagnostics", diagnostics.iter().map(ToString::to_string).collect::<Vec<_>>());
+
+    if !validation.is_valid() {
+        return Err(Box::new(CliErr("Graph contains errors, see diagnostics")));
+    }
+    Ok(())
+}
+
+fn repair(config: &Config) -> Result<(), Box<dyn Error>> {
+    let name = config.graph_dir.file_name().ok_or(CliErr("Graph directory has no name"))?;
+    let output = config.graph_dir.with_file_name(format!("{}_repaired", name.to_string_lossy()));
+    let summary = validate::repair_dir(&config.graph_dir, &output, &config.metrics, Some(&config.order))?;
+
+    report!("repaired_graph_dir", output.display().to_string());
+    report!("removed_self_loops", summary.removed_self_loops);
+    report!("unified_parallel_arc_weights", summary.unified_parallel_arc_weights);
+    report!("fifoized_arcs", summary.fifoized_arcs);
+    report!("raised_zero_travel_times", summary.raised_zero_travel_times);
+    report!("dropped_turn_restrictions", summary.dropped_turn_restrictions);
+    eprintln!("Repaired graph written to {}", output.display());
+    Ok(())
+}
+
+fn largest_scc(config: &Config) -> Result<(), Box<dyn Error>> {
+    let graph = UnweightedOwnedGraph::new(
+        Vec::load_from(config.graph_dir.join("first_out"))?,
+        Vec::load_from(config.graph_dir.join("head"))?,
+    );
+    scc::strongly_connected_components(&graph).report_connectivity();
+
+    let name = config.graph_dir.file_name().ok_or(CliErr("Graph directory has no name"))?;
+    let output = config.graph_dir.with_file_name(format!("{}_scc", name.to_string_lossy()));
+    let summary = validate::restrict_dir_to_largest_scc(&config.graph_dir, &output, &config.metrics, Some(&config.order))?;
+
+    report!("restricted_graph_dir", output.display().to_string());
+    report!("removed_nodes", summary.removed_nodes);
+    report!("removed_arcs", summary.removed_arcs);
+    report!("dropped_turn_restrictions", summary.dropped_turn_restrictions);
+    report!("skipped_files", summary.skipped_files);
+    for file in &summary.skipped_files {
+        eprintln!("Skipped {file}, it is not a known node or arc attribute");
+    }
+    eprintln!("Graph restricted to the largest strongly connected component written to {}", output.display());
+    Ok(())
+}
+
+fn path_str(path: &Path) -> Result<&str, CliErr> {
+    path.to_str().ok_or(CliErr("Export paths must be valid unicode"))
+}
+
+/// Something to do with the query server of the configured algorithm.
+trait ServerTask {
+    fn run<S: QueryServer>(&mut self, server: &mut S);
+}
+
+struct GivenQueries<'a> {
+    from: &'a [NodeId],
+    to: &'a [NodeId],
+    ground_truth: Option<&'a [Weight]>,
+}
+
+impl ServerTask for GivenQueries<'_> {
+    fn run<S: QueryServer>(&mut self, server: &mut S) {
+        let mut algo_runs_ctxt = push_collection_context("algo_runs");
+        let mut ground_truth = self.ground_truth.map(|ground_truth| {
+            ground_truth.iter().map(|&gt| match gt {
+                INFINITY => None,
+                val => Some(val),
+            })
         });
+        experiments::run_queries(
+            self.from.iter().copied().zip(self.to.iter().copied()),
+            server,
+            Some(&mut algo_runs_ctxt),
+            |_, _, _| (),
+            |_, _| ground_truth.as_mut().and_then(Iterator::next),
+        );
     }
+}
+
+struct BenchmarkRun<'a> {
+    name: String,
+    sets: &'a [QuerySet],
+    params: BenchmarkParams,
+    results: Vec<BenchmarkResult>,
+}
 
+impl ServerTask for BenchmarkRun<'_> {
+    fn run<S: QueryServer>(&mut self, server: &mut S) {
+        for set in self.sets {
+            let name = format!("{}/{}", self.name, set.name);
+            self.results.push(run_benchmark(&name, set, server, &self.params));
+        }
+    }
+}
+
+fn with_server(config: &Config, metric: &str, graph: &OwnedGraph, task: &mut impl ServerTask) -> Result<(), Box<dyn Error>> {
+    match config.algorithm {
+        Algorithm::Dijkstra => {
+            task.run(&mut DijkServer::<_, DefaultOps>::new(graph.borrowed()));
+        }
+        Algorithm::BidirDijkstra => {
+            task.run(&mut BiDijkServer::<_, _, _>::new(graph.borrowed()));
+        }
+        Algorithm::CH => {
+            let dir = config.ch_dir(metric);
+            let (ch, order) = if is_cached(&dir, &cache_key(config, graph, Some(metric))?)? {
+                (
+                    ContractionHierarchy::reconstruct_from(&dir)?,
+                    NodeOrder::from_node_order(Vec::load_from(dir.join("order"))?),
+                )
+            } else {
+                let order = load_order(config)?;
+                (
+                    report_time_with_key("CH contraction", "contraction_running_time_ms", || {
+                        contraction_hierarchy::contract_parallel(graph, order.clone())
+                    }),
+                    order,
+                )
+            };
+            task.run(&mut CHServer::new(ch, order));
+        }
+        Algorithm::CCH => {
+            let (cch_dir, customized_dir) = (cch_dir(config), config.customized_cch_dir(metric));
+            let cch_cached = is_cached(&cch_dir, &cache_key(config, graph, None)?)?;
+            let cch = if cch_cached {
+                CCHReconstrctor(graph).reconstruct_from(&cch_dir)?
+            } else {
+                CCH::fix_order_and_build(graph, load_order(config)?)
+            };
+            // a customization is only valid for the CCH it was computed on
+            let customized = if cch_cached && is_cached(&customized_dir, &cache_key(config, graph, Some(metric))?)? {
+                (&cch).reconstruct_from(&customized_dir)?
+            } else {
+                customize_perfect(customization::customize(&cch, graph))
+            };
+            task.run(&mut CCHServer::new(customized));
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,342 @@
+//! Thread-safe counters, gauges and histograms for monitoring long running services.
+//!
+//! In contrast to the `report` module, which collects results of single experiments, metrics are aggregated over the whole runtime
+//! and can be updated concurrently from any thread without locking.
+//! Metrics are created through a `Registry`, which renders all of them in the Prometheus text exposition format.
+//! Metrics with the same name but different labels form one family and share help text and type.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Upper bounds in seconds for query latencies, from 100µs to 10s.
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// `count` bucket bounds starting at `start`, each `factor` times the previous one.
+pub fn exponential_buckets(start: f64, factor: f64, count: usize) -> Vec<f64> {
+    assert!(start > 0.0 && factor > 1.0);
+    std::iter::successors(Some(start), |bound| Some(bound * factor)).take(count).collect()
+}
+
+/// Monotonically increasing count.
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.add(1)
+    }
+
+    pub fn add(&self, value: u64) {
+        self.0.fetch_add(value, Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Relaxed)
+    }
+}
+
+/// Value which can go up and down, for example the length of a queue.
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.add(1)
+    }
+
+    pub fn dec(&self) {
+        self.add(-1)
+    }
+
+    pub fn add(&self, value: i64) {
+        self.0.fetch_add(value, Relaxed);
+    }
+
+    pub fn set(&self, value: i64) {
+        self.0.store(value, Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Relaxed)
+    }
+}
+
+/// Distribution of observed values in buckets with fixed upper bounds.
+#[derive(Debug)]
+pub struct Histogram {
+    bounds: Vec<f64>,
+    // not cumulative, the last one counts values above all bounds
+    buckets: Vec<AtomicU64>,
+    // bits of an f64
+    sum: AtomicU64,
+}
+
+impl Histogram {
+    /// `bounds` have to be strictly increasing.
+    pub fn new(bounds: &[f64]) -> Self {
+        assert!(bounds.windows(2).all(|pair| pair[0] < pair[1]), "bucket bounds have to be increasing");
+        Self {
+            bounds: bounds.to_vec(),
+            buckets: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum: AtomicU64::new(0.0f64.to_bits()),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self.bounds.iter().position(|&bound| value <= bound).unwrap_or(self.bounds.len());
+        self.buckets[bucket].fetch_add(1, Relaxed);
+        // there is no atomic float addition, so we retry until no other thread interfered
+        let mut current = self.sum.load(Relaxed);
+        while let Err(actual) = self
+            .sum
+            .compare_exchange_weak(current, (f64::from_bits(current) + value).to_bits(), Relaxed, Relaxed)
+        {
+            current = actual;
+        }
+    }
+
+    /// Observe a duration in seconds.
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_secs_f64())
+    }
+
+    /// Run `f` and observe how long it took in seconds.
+    pub fn time<O>(&self, f: impl FnOnce() -> O) -> O {
+        let start = Instant::now();
+        let res = f();
+        self.observe_duration(start.elapsed());
+        res
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().map(|bucket| bucket.load(Relaxed)).sum()
+    }
+
+    pub fn sum(&self) -> f64 {
+        f64::from_bits(self.sum.load(Relaxed))
+    }
+
+    /// Upper bounds with the number of values less or equal to each of them.
+    pub fn cumulative_counts(&self) -> Vec<(f64, u64)> {
+        let mut total = 0;
+        self.bounds
+            .iter()
+            .chain(std::iter::once(&f64::INFINITY))
+            .zip(&self.buckets)
+            .map(|(&bound, bucket)| {
+                total += bucket.load(Relaxed);
+                (bound, total)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Metric {
+    Counter(Arc<Counter>),
+    Gauge(Arc<Gauge>),
+    Histogram(Arc<Histogram>),
+}
+
+impl Metric {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Metric::Counter(_) => "counter",
+            Metric::Gauge(_) => "gauge",
+            Metric::Histogram(_) => "histogram",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Family {
+    help: String,
+    members: Vec<(Vec<(String, String)>, Metric)>,
+}
+
+/// Collection of all metrics of a service.
+/// Creating metrics locks the registry, updating them does not.
+#[derive(Debug, Default)]
+pub struct Registry {
+    families: Mutex<BTreeMap<String, Family>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn counter(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Arc<Counter> {
+        match self.register(name, help, labels, || Metric::Counter(Default::default())) {
+            Metric::Counter(counter) => counter,
+            other => panic!("{} is already registered as {}", name, other.type_name()),
+        }
+    }
+
+    pub fn gauge(&self, name: &str, help: &str, labels: &[(&str, &str)]) -> Arc<Gauge> {
+        match self.register(name, help, labels, || Metric::Gauge(Default::default())) {
+            Metric::Gauge(gauge) => gauge,
+            other => panic!("{} is already registered as {}", name, other.type_name()),
+        }
+    }
+
+    /// Histograms with the same name should have the same `bounds`, the ones of the first registration are kept.
+    pub fn histogram(&self, name: &str, help: &str, labels: &[(&str, &str)], bounds: &[f64]) -> Arc<Histogram> {
+        match self.register(name, help, labels, || Metric::Histogram(Arc::new(Histogram::new(bounds)))) {
+            Metric::Histogram(histogram) => histogram,
+            other => panic!("{} is already registered as {}", name, other.type_name()),
+        }
+    }
+
+    // Registering the same name and labels again returns the existing metric.
+    fn register(&self, name: &str, help: &str, labels: &[(&str, &str)], create: impl FnOnce() -> Metric) -> Metric {
+        assert!(is_valid_name(name), "invalid metric name {}", name);
+        assert!(labels.iter().all(|(label, _)| is_valid_name(label)), "invalid label name in {:?}", labels);
+        let labels: Vec<(String, String)> = labels.iter().map(|&(label, value)| (label.to_string(), value.to_string())).collect();
+        let mut families = self.families.lock().unwrap();
+        let family = families.entry(name.to_string()).or_insert_with(|| Family {
+            help: help.to_string(),
+            members: Vec::new(),
+        });
+        if let Some((_, metric)) = family.members.iter().find(|(member_labels, _)| *member_labels == labels) {
+            return metric.clone();
+        }
+        let metric = create();
+        if let Some((_, existing)) = family.members.first() {
+            assert_eq!(
+                existing.type_name(),
+                metric.type_name(),
+                "{} is already registered as {}",
+                name,
+                existing.type_name()
+            );
+        }
+        family.members.push((labels, metric.clone()));
+        metric
+    }
+
+    /// All metrics in the Prometheus text format, version 0.0.4.
+    pub fn render(&self) -> String {
+        let families = self.families.lock().unwrap();
+        let mut out = String::new();
+        for (name, family) in families.iter() {
+            let type_name = family.members.first().map(|(_, metric)| metric.type_name()).unwrap_or("untyped");
+            writeln!(out, "# HELP {} {}", name, family.help.replace('\\', "\\\\").replace('\n', "\\n")).unwrap();
+            writeln!(out, "# TYPE {} {}", name, type_name).unwrap();
+            for (labels, metric) in &family.members {
+                match metric {
+                    Metric::Counter(counter) => writeln!(out, "{}{} {}", name, format_labels(labels, None), counter.get()).unwrap(),
+                    Metric::Gauge(gauge) => writeln!(out, "{}{} {}", name, format_labels(labels, None), gauge.get()).unwrap(),
+                    Metric::Histogram(histogram) => {
+                        for (bound, count) in histogram.cumulative_counts() {
+                            let le = if bound == f64::INFINITY { "+Inf".to_string() } else { bound.to_string() };
+                            writeln!(out, "{}_bucket{} {}", name, format_labels(labels, Some(&le)), count).unwrap();
+                        }
+                        writeln!(out, "{}_sum{} {}", name, format_labels(labels, None), histogram.sum()).unwrap();
+                        writeln!(out, "{}_count{} {}", name, format_labels(labels, None), histogram.count()).unwrap();
+                    }
+                }
+            }
+        }
+        out
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().map(|c| c.is_ascii_alphabetic() || c == '_').unwrap_or(false) && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn format_labels(labels: &[(String, String)], le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(label, value)| format!("{}=\"{}\"", label, value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets_and_rendering() {
+        let registry = Registry::new();
+        let latency = registry.histogram("query_seconds", "Query latency.", &[("algorithm", "cch")], &[0.1, 1.0]);
+        for value in [0.05, 0.1, 0.5, 3.0] {
+            latency.observe(value);
+        }
+        assert_eq!(latency.cumulative_counts(), vec![(0.1, 2), (1.0, 3), (f64::INFINITY, 4)]);
+        assert_eq!(latency.count(), 4);
+        assert!((latency.sum() - 3.65).abs() < 1e-9);
+
+        let queries = registry.counter("queries_total", "Number of queries.", &[("status", "ok")]);
+        queries.add(3);
+        registry.counter("queries_total", "Number of queries.", &[("status", "ok")]).inc();
+        registry.counter("queries_total", "Number of queries.", &[("status", "no \"route\"")]);
+        registry.gauge("queue_depth", "Pending requests.", &[]).set(-1);
+
+        assert_eq!(
+            registry.render(),
+            "# HELP queries_total Number of queries.
+# TYPE queries_total counter
+queries_total{status=\"ok\"} 4
+queries_total{status=\"no \\\"route\\\"\"} 0
+# HELP query_seconds Query latency.
+# TYPE query_seconds histogram
+query_seconds_bucket{algorithm=\"cch\",le=\"0.1\"} 2
+query_seconds_bucket{algorithm=\"cch\",le=\"1\"} 3
+query_seconds_bucket{algorithm=\"cch\",le=\"+Inf\"} 4
+query_seconds_sum{algorithm=\"cch\"} 3.65
+query_seconds_count{algorithm=\"cch\"} 4
+# HELP queue_depth Pending requests.
+# TYPE queue_depth gauge
+queue_depth -1
+"
+        );
+    }
+
+    #[test]
+    fn concurrent_updates() {
+        let registry = Registry::new();
+        let counter = registry.counter("events_total", "Events.", &[]);
+        let histogram = registry.histogram("sizes", "Sizes.", &[], &exponential_buckets(1.0, 2.0, 10));
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for i in 0..1000 {
+                        counter.inc();
+                        histogram.observe(f64::from(i % 4));
+                    }
+                });
+            }
+        });
+        assert_eq!(counter.get(), 4000);
+        assert_eq!(histogram.count(), 4000);
+        assert_eq!(histogram.sum(), 6000.0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn conflicting_types() {
+        let registry = Registry::new();
+        registry.counter("requests", "Requests.", &[("kind", "a")]);
+        registry.gauge("requests", "Requests.", &[("kind", "b")]);
+    }
+}
@@ -1,26 +1,33 @@
 //! Utilities for structured reporting of experimental results.
 //!
-//! Experimental take on an API using RAII to report experimental results within context
-//! somewhat isomorph to the callgraph and output everything as JSON.
+//! Results are collected in a `Report`, a tree of objects, collections and values which is output as JSON.
+//! The `recorder` module contains the thread-safe core with explicit `Recorder` handles.
 //!
-//! So far not really successful.
-//! While it worked quite well for the CATCHUp experiments, the API is not really robust.
-//! Keeping the ContextGuards around pollutes the algorithm code and is a bit error prone.
-//! When used in a multithreaded environment, weird stuff will happen.
-//! Not really ready for productive use.
-//! JSON output is nice though.
+//! On top of it, this module offers an implicit API with a thread-local stack of recorders,
+//! so algorithms can just `report!` values without passing handles around.
+//! Contexts are opened with RAII guards, which pop them again when dropped.
+//! Values are buffered in the innermost context and merged into the report when it is popped,
+//! so consistency checks like duplicate keys fail only then.
+//! The implicit API only reports on threads with a recorder, the main thread gets one with `enable_reporting`.
+//! To report from worker threads, hand them a handle obtained by `current_recorder` and `attach` it there.
+//! The report is printed to stdout when the `ReportingGuard` is dropped, as JSON by default.
+//! The `REPORT_FORMAT` environment variable selects another `ReportFormat`,
+//! e.g. `REPORT_FORMAT=csv:experiments` prints only the `experiments` collection as CSV.
 
 use crate::built_info;
 use serde::{Serialize, Serializer};
 use serde_json::Value;
+use std::cell::RefCell;
 use std::collections::BTreeMap;
-use std::{cell::RefCell, mem::swap};
 
 pub use serde_json::json;
 
+pub mod recorder;
+pub use recorder::*;
+
 pub enum ReportingValue {
     Collection(Vec<ReportingValue>),
-    Object(BTreeMap<&'static str, ReportingValue>),
+    Object(BTreeMap<String, ReportingValue>),
     Value(Value),
 }
 
@@ -37,237 +44,113 @@ impl Serialize for ReportingValue {
     }
 }
 
-enum ContextStackItem {
-    Key(&'static str),
-    Collection(Vec<ReportingValue>),
-    Object(BTreeMap<&'static str, ReportingValue>),
-    Capture(BTreeMap<&'static str, ReportingValue>),
-    Throwaway,
+impl From<ReportNode> for ReportingValue {
+    fn from(node: ReportNode) -> Self {
+        match node {
+            ReportNode::Object(object) => ReportingValue::Object(object.into_iter().map(|(key, node)| (key, node.into())).collect()),
+            ReportNode::Collection(items) => ReportingValue::Collection(items.into_values().map(Into::into).collect()),
+            ReportNode::Value(value) => ReportingValue::Value(value),
+        }
+    }
 }
 
-enum CurrentReportingContext {
-    Collection(Vec<ReportingValue>),
-    Object(BTreeMap<&'static str, ReportingValue>),
-    Capture(BTreeMap<&'static str, ReportingValue>),
+enum Frame {
+    Object(Recorder),
+    Collection(CollectionRecorder),
+    // records go into a separate report which can be retrieved by the guard
+    Capture(Report, Recorder),
     Throwaway,
 }
 
-pub struct Reporter {
-    current: CurrentReportingContext,
-    context_stack: Vec<ContextStackItem>,
-}
-
-impl Default for Reporter {
-    fn default() -> Self {
-        Reporter {
-            current: CurrentReportingContext::Object(BTreeMap::new()),
-            context_stack: Vec::new(),
+impl Frame {
+    fn child(&self, key: &'static str) -> Frame {
+        match self {
+            Frame::Object(recorder) | Frame::Capture(_, recorder) => Frame::Object(recorder.object(key)),
+            Frame::Collection(_) => panic!("Cannot create object at key in collection"),
+            Frame::Throwaway => Frame::Throwaway,
         }
     }
-}
 
-impl Reporter {
-    fn create_object_under_key(&mut self, key: &'static str) {
-        match &mut self.current {
-            CurrentReportingContext::Object(object) => {
-                let mut tmp = BTreeMap::new();
-                swap(&mut tmp, object);
-                self.context_stack.push(ContextStackItem::Object(tmp));
-                self.context_stack.push(ContextStackItem::Key(key));
-            }
-            CurrentReportingContext::Capture(object) => {
-                let mut tmp = BTreeMap::new();
-                swap(&mut tmp, object);
-                self.context_stack.push(ContextStackItem::Capture(tmp));
-                self.context_stack.push(ContextStackItem::Key(key));
-            }
-            CurrentReportingContext::Collection(_) => {
-                panic!("Cannot create object at key in collection");
-            }
-            CurrentReportingContext::Throwaway => (),
+    fn child_collection(&self, key: &'static str) -> Frame {
+        match self {
+            Frame::Object(recorder) | Frame::Capture(_, recorder) => Frame::Collection(recorder.collection(key)),
+            Frame::Collection(_) => panic!("Cannot create collection at key in collection"),
+            Frame::Throwaway => Frame::Throwaway,
         }
     }
 
-    fn create_collection_under_key(&mut self, key: &'static str) {
-        match &mut self.current {
-            CurrentReportingContext::Object(object) => {
-                let mut tmp = BTreeMap::new();
-                swap(&mut tmp, object);
-                self.context_stack.push(ContextStackItem::Object(tmp));
-                self.context_stack.push(ContextStackItem::Key(key));
-                self.current = CurrentReportingContext::Collection(Vec::new());
-            }
-            CurrentReportingContext::Capture(object) => {
-                let mut tmp = BTreeMap::new();
-                swap(&mut tmp, object);
-                self.context_stack.push(ContextStackItem::Capture(tmp));
-                self.context_stack.push(ContextStackItem::Key(key));
-                self.current = CurrentReportingContext::Collection(Vec::new());
-            }
-            CurrentReportingContext::Collection(_) => {
-                panic!("Cannot create collection at key in collection");
-            }
-            CurrentReportingContext::Throwaway => (),
+    fn item(&self) -> Frame {
+        match self {
+            Frame::Collection(collection) => Frame::Object(collection.push()),
+            Frame::Object(_) | Frame::Capture(..) => panic!("Cannot create collection item in object"),
+            Frame::Throwaway => Frame::Throwaway,
         }
     }
+}
 
-    fn create_collection_item(&mut self) {
-        match &mut self.current {
-            CurrentReportingContext::Object(_) | CurrentReportingContext::Capture(_) => {
-                panic!("Cannot create collection item in object");
-            }
-            CurrentReportingContext::Collection(collection) => {
-                let mut tmp = Vec::new();
-                swap(&mut tmp, collection);
-                self.context_stack.push(ContextStackItem::Collection(tmp));
-                self.current = CurrentReportingContext::Object(BTreeMap::new());
-            }
-            CurrentReportingContext::Throwaway => (),
-        }
-    }
+#[derive(Default)]
+struct Reporter {
+    // empty if reporting is not enabled on this thread
+    stack: Vec<Frame>,
+}
 
-    fn block_reporting(&mut self) {
-        match &mut self.current {
-            CurrentReportingContext::Object(object) => {
-                let mut tmp = BTreeMap::new();
-                swap(&mut tmp, object);
-                self.context_stack.push(ContextStackItem::Object(tmp));
-                self.current = CurrentReportingContext::Throwaway;
-            }
-            CurrentReportingContext::Capture(object) => {
-                let mut tmp = BTreeMap::new();
-                swap(&mut tmp, object);
-                self.context_stack.push(ContextStackItem::Capture(tmp));
-                self.current = CurrentReportingContext::Throwaway;
-            }
-            CurrentReportingContext::Collection(collection) => {
-                let mut tmp = Vec::new();
-                swap(&mut tmp, collection);
-                self.context_stack.push(ContextStackItem::Collection(tmp));
-                self.current = CurrentReportingContext::Throwaway;
-            }
-            CurrentReportingContext::Throwaway => {
-                self.context_stack.push(ContextStackItem::Throwaway);
-            }
+impl Reporter {
+    // Derive a new frame from the innermost one, only if reporting is enabled.
+    fn push_with(&mut self, f: impl FnOnce(&Frame) -> Frame) {
+        if let Some(top) = self.stack.last() {
+            let frame = f(top);
+            self.stack.push(frame);
         }
     }
 
-    fn capture_reporting(&mut self) {
-        match &mut self.current {
-            CurrentReportingContext::Object(object) => {
-                let mut tmp = BTreeMap::new();
-                swap(&mut tmp, object);
-                self.context_stack.push(ContextStackItem::Object(tmp));
-                self.current = CurrentReportingContext::Capture(BTreeMap::new());
-            }
-            CurrentReportingContext::Capture(object) => {
-                let mut tmp = BTreeMap::new();
-                swap(&mut tmp, object);
-                self.context_stack.push(ContextStackItem::Capture(tmp));
-                self.current = CurrentReportingContext::Capture(BTreeMap::new());
-            }
-            CurrentReportingContext::Collection(collection) => {
-                let mut tmp = Vec::new();
-                swap(&mut tmp, collection);
-                self.context_stack.push(ContextStackItem::Collection(tmp));
-                self.current = CurrentReportingContext::Capture(BTreeMap::new());
-            }
-            CurrentReportingContext::Throwaway => {
-                self.context_stack.push(ContextStackItem::Throwaway);
-                self.current = CurrentReportingContext::Capture(BTreeMap::new());
-            }
+    // Pop the innermost frame, flushing its records.
+    fn pop(&mut self) {
+        if let Some(Frame::Object(mut recorder)) | Some(Frame::Capture(_, mut recorder)) = self.stack.pop() {
+            recorder.flush();
         }
     }
 
     fn report(&mut self, key: &'static str, val: Value) {
-        match &mut self.current {
-            CurrentReportingContext::Object(object) | CurrentReportingContext::Capture(object) => {
-                let prev = object.insert(key, ReportingValue::Value(val));
-                if !cfg!(feature = "report-allow-override") {
-                    assert!(prev.is_none());
-                }
-            }
-            CurrentReportingContext::Collection(_) => {
-                panic!("Cannot report value on collection");
-            }
-            CurrentReportingContext::Throwaway => (),
+        match self.stack.last_mut() {
+            Some(Frame::Object(recorder)) | Some(Frame::Capture(_, recorder)) => recorder.record(key, val),
+            Some(Frame::Collection(_)) => panic!("Cannot report value on collection"),
+            Some(Frame::Throwaway) | None => (),
         }
     }
+}
 
-    fn pop_context(&mut self) {
-        if matches!(self.current, CurrentReportingContext::Throwaway) {
-            return;
-        }
-        let parent = self.context_stack.pop().expect("tried to pop from empty context");
-
-        match parent {
-            ContextStackItem::Key(key) => {
-                let parent = self.context_stack.pop().expect("tried to pop from empty context");
-
-                if let ContextStackItem::Object(mut object) = parent {
-                    let mut prev_current = CurrentReportingContext::Object(Default::default());
-                    swap(&mut self.current, &mut prev_current);
-
-                    let prev = match prev_current {
-                        CurrentReportingContext::Object(cur_object) => object.insert(key, ReportingValue::Object(cur_object)),
-                        CurrentReportingContext::Collection(collection) => object.insert(key, ReportingValue::Collection(collection)),
-                        CurrentReportingContext::Throwaway => None,
-                        CurrentReportingContext::Capture(_) => panic!("Inconsistent context stack"),
-                    };
-                    if !cfg!(feature = "report-allow-override") {
-                        assert!(prev.is_none());
-                    }
-
-                    self.current = CurrentReportingContext::Object(object);
-                } else {
-                    panic!("Inconsistent context stack");
-                }
-            }
-            ContextStackItem::Collection(mut collection) => {
-                let mut prev_current = CurrentReportingContext::Object(Default::default());
-                swap(&mut self.current, &mut prev_current);
-
-                match prev_current {
-                    CurrentReportingContext::Object(cur_object) => {
-                        collection.push(ReportingValue::Object(cur_object));
-                    }
-                    CurrentReportingContext::Collection(_) => {
-                        panic!("Cannot insert collection into collection");
-                    }
-                    CurrentReportingContext::Throwaway => panic!("Inconsistent context stack"),
-                    CurrentReportingContext::Capture(_) => panic!("Inconsistent context stack"),
-                };
-
-                self.current = CurrentReportingContext::Collection(collection);
-            }
-            _ => panic!("Inconsistent context stack"),
-        }
-    }
+thread_local! {
+    static REPORTER: RefCell<Reporter> = RefCell::new(Reporter::default());
+}
 
-    fn unblock_or_drop_capture(&mut self) {
-        if !matches!(self.current, CurrentReportingContext::Throwaway | CurrentReportingContext::Capture(_)) {
-            panic!("Inconsistent context stack");
-        }
-        match self.context_stack.pop().expect("tried to pop from empty context") {
-            ContextStackItem::Key(_) => panic!("Inconsistent context stack"),
-            ContextStackItem::Collection(collection) => {
-                self.current = CurrentReportingContext::Collection(collection);
-            }
-            ContextStackItem::Object(object) => {
-                self.current = CurrentReportingContext::Object(object);
-            }
-            ContextStackItem::Capture(object) => {
-                self.current = CurrentReportingContext::Capture(object);
-            }
-            ContextStackItem::Throwaway => {
-                self.current = CurrentReportingContext::Throwaway;
-            }
-        }
+fn with_reporter<O>(f: impl FnOnce(&mut Reporter) -> O) -> O {
+    REPORTER.with(|reporter| f(&mut reporter.borrow_mut()))
+}
+
+/// A handle to the innermost object context of this thread, `None` if reporting is disabled or blocked.
+pub fn current_recorder() -> Option<Recorder> {
+    with_reporter(|reporter| match reporter.stack.last() {
+        Some(Frame::Object(recorder)) | Some(Frame::Capture(_, recorder)) => Some(recorder.clone()),
+        _ => None,
+    })
+}
+
+impl Recorder {
+    /// Make this the innermost context of the current thread until the guard is dropped, so `report!` records into it.
+    /// This also enables reporting on threads without a reporter.
+    pub fn attach(self) -> AttachedRecorderGuard {
+        with_reporter(|reporter| reporter.stack.push(Frame::Object(self)));
+        AttachedRecorderGuard(())
     }
 }
 
-thread_local! {
-    static REPORTER: RefCell<Option<Reporter>> = RefCell::new(None);
+#[must_use]
+pub struct AttachedRecorderGuard(());
+
+impl Drop for AttachedRecorderGuard {
+    fn drop(&mut self) {
+        with_reporter(Reporter::pop);
+    }
 }
 
 #[must_use]
@@ -275,12 +158,12 @@ pub struct ContextGuard(());
 
 impl Drop for ContextGuard {
     fn drop(&mut self) {
-        REPORTER.with(|reporter| reporter.borrow_mut().as_mut().map(Reporter::pop_context));
+        with_reporter(Reporter::pop);
     }
 }
 
 pub fn push_context(key: &'static str) -> ContextGuard {
-    REPORTER.with(|reporter| reporter.borrow_mut().as_mut().map(|r| r.create_object_under_key(key)));
+    with_reporter(|reporter| reporter.push_with(|top| top.child(key)));
     ContextGuard(())
 }
 
@@ -289,18 +172,18 @@ pub struct CollectionContextGuard(());
 
 impl Drop for CollectionContextGuard {
     fn drop(&mut self) {
-        REPORTER.with(|reporter| reporter.borrow_mut().as_mut().map(Reporter::pop_context));
+        with_reporter(Reporter::pop);
     }
 }
 
 pub fn push_collection_context(key: &'static str) -> CollectionContextGuard {
-    REPORTER.with(|reporter| reporter.borrow_mut().as_mut().map(|r| r.create_collection_under_key(key)));
+    with_reporter(|reporter| reporter.push_with(|top| top.child_collection(key)));
     CollectionContextGuard(())
 }
 
 impl CollectionContextGuard {
     pub fn push_collection_item(&mut self) -> CollectionItemContextGuard {
-        REPORTER.with(|reporter| reporter.borrow_mut().as_mut().map(Reporter::create_collection_item));
+        with_reporter(|reporter| reporter.push_with(Frame::item));
         CollectionItemContextGuard(self)
     }
 }
@@ -310,7 +193,7 @@ pub struct CollectionItemContextGuard<'a>(&'a CollectionContextGuard);
 
 impl<'a> Drop for CollectionItemContextGuard<'a> {
     fn drop(&mut self) {
-        REPORTER.with(|reporter| reporter.borrow_mut().as_mut().map(Reporter::pop_context));
+        with_reporter(Reporter::pop);
     }
 }
 
@@ -319,12 +202,12 @@ pub struct BlockedReportingContextGuard();
 
 impl Drop for BlockedReportingContextGuard {
     fn drop(&mut self) {
-        REPORTER.with(|reporter| reporter.borrow_mut().as_mut().map(Reporter::unblock_or_drop_capture));
+        with_reporter(Reporter::pop);
     }
 }
 
 pub fn block_reporting() -> BlockedReportingContextGuard {
-    REPORTER.with(|reporter| reporter.borrow_mut().as_mut().map(Reporter::block_reporting));
+    with_reporter(|reporter| reporter.push_with(|_| Frame::Throwaway));
     BlockedReportingContextGuard()
 }
 
@@ -338,28 +221,37 @@ pub struct CaptureReportingContextGuard();
 
 impl Drop for CaptureReportingContextGuard {
     fn drop(&mut self) {
-        REPORTER.with(|reporter| reporter.borrow_mut().as_mut().map(Reporter::unblock_or_drop_capture));
+        with_reporter(Reporter::pop);
     }
 }
 
+/// Values reported until the guard is dropped are not part of the report but can be retrieved with `reported`.
 pub fn capture_reporting() -> CaptureReportingContextGuard {
-    REPORTER.with(|reporter| reporter.borrow_mut().as_mut().map(Reporter::capture_reporting));
+    with_reporter(|reporter| {
+        reporter.push_with(|_| {
+            let report = Report::new();
+            let recorder = report.recorder();
+            Frame::Capture(report, recorder)
+        })
+    });
     CaptureReportingContextGuard()
 }
 
 impl CaptureReportingContextGuard {
-    pub fn reported(self) -> Option<BTreeMap<&'static str, ReportingValue>> {
-        REPORTER.with(|reporter| {
-            if let Some(reporter) = reporter.borrow_mut().as_mut() {
-                if let CurrentReportingContext::Capture(obj) = &mut reporter.current {
-                    let mut tmp = BTreeMap::new();
-                    std::mem::swap(obj, &mut tmp);
-                    return Some(tmp);
-                } else {
-                    panic!("Inconsistent context stack");
+    /// Everything captured so far, `None` if reporting is disabled.
+    pub fn reported(self) -> Option<BTreeMap<String, ReportingValue>> {
+        with_reporter(|reporter| match reporter.stack.last_mut() {
+            Some(Frame::Capture(report, recorder)) => {
+                recorder.flush();
+                let captured = std::mem::take(report);
+                *recorder = report.recorder();
+                match ReportingValue::from(captured.tree()) {
+                    ReportingValue::Object(object) => Some(object),
+                    _ => panic!("Inconsistent context stack"),
                 }
             }
-            None
+            Some(_) => panic!("Inconsistent context stack"),
+            None => None,
         })
     }
 }
@@ -373,26 +265,19 @@ pub fn report(key: &'static str, val: Value) {
 
 #[inline(never)]
 pub fn report_silent(key: &'static str, val: Value) {
-    REPORTER.with(|reporter| reporter.borrow_mut().as_mut().map(|r| r.report(key, val)));
+    with_reporter(|reporter| reporter.report(key, val));
 }
 
 #[must_use]
-pub struct ReportingGuard(());
+pub struct ReportingGuard(Report, ReportFormat);
 
 impl Drop for ReportingGuard {
     fn drop(&mut self) {
-        REPORTER.with(|reporter| {
-            if let Some(r) = reporter.borrow_mut().as_mut() {
-                assert!(r.context_stack.is_empty());
-                let mut current = CurrentReportingContext::Object(Default::default());
-                swap(&mut current, &mut r.current);
-                if let CurrentReportingContext::Object(object) = current {
-                    println!("{}", serde_json::to_string(&object).unwrap());
-                } else {
-                    panic!("broken root object for reporting");
-                }
-            };
+        with_reporter(|reporter| {
+            assert_eq!(reporter.stack.len(), 1, "unclosed reporting contexts");
+            reporter.pop();
         });
+        println!("{}", self.0.format(&self.1));
     }
 }
 
@@ -407,7 +292,10 @@ macro_rules! report_silent {
 }
 
 pub fn enable_reporting(program: &str) -> ReportingGuard {
-    REPORTER.with(|reporter| reporter.replace(Some(Reporter::default())));
+    // parse before anything runs, so an invalid format does not waste an experiment
+    let format = std::env::var("REPORT_FORMAT").map_or(ReportFormat::Json, |format| format.parse().unwrap());
+    let report = Report::new();
+    with_reporter(|reporter| reporter.stack = vec![Frame::Object(report.recorder())]);
 
     report!("git_revision", built_info::GIT_VERSION.unwrap_or(""));
     report!("build_target", built_info::TARGET);
@@ -424,7 +312,7 @@ pub fn enable_reporting(program: &str) -> ReportingGuard {
     report!("start_time", chrono::prelude::Utc::now().to_rfc3339());
     report!("args", std::env::args().collect::<Vec<String>>());
 
-    ReportingGuard(())
+    ReportingGuard(report, format)
 }
 
 pub mod benchmark;
@@ -0,0 +1,537 @@
+//! Thread-safe recording of experimental results.
+//!
+//! A `Report` is a tree of objects, collections and values.
+//! Results are recorded through explicit `Recorder` handles, each pointing to one node of the tree.
+//! Handles are `Send`, so they can be moved into worker threads, and each buffers its records locally
+//! until it is flushed or dropped, so recording does not contend on a lock.
+//! Collection items are addressed by index, and objects keep their keys sorted,
+//! so the final tree does not depend on the order in which threads flush their buffers.
+//! Recording the same key twice is an error, unless the `report-allow-override` feature is enabled,
+//! in which case the value flushed last wins.
+//!
+//! Finished reports can be written as nested JSON, or a collection of the report can be flattened into CSV or a columnar JSON object,
+//! where nested keys are joined with `.`.
+//! `ReportFormat` selects one of these outputs.
+
+use serde_json::{Map, Value};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    error::Error,
+    fmt,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReportNode {
+    Object(BTreeMap<String, ReportNode>),
+    /// Items by index, indices may have gaps if items were not recorded.
+    Collection(BTreeMap<usize, ReportNode>),
+    Value(Value),
+}
+
+impl ReportNode {
+    pub fn to_value(&self) -> Value {
+        match self {
+            ReportNode::Object(object) => Value::Object(object.iter().map(|(key, node)| (key.clone(), node.to_value())).collect()),
+            ReportNode::Collection(items) => Value::Array(items.values().map(ReportNode::to_value).collect()),
+            ReportNode::Value(value) => value.clone(),
+        }
+    }
+
+    /// The node at the object keys in `path`.
+    pub fn get(&self, path: &[&str]) -> Option<&ReportNode> {
+        path.iter().try_fold(self, |node, key| match node {
+            ReportNode::Object(object) => object.get(*key),
+            _ => None,
+        })
+    }
+
+    fn child(&mut self, segment: &Segment) -> &mut ReportNode {
+        // items may be flushed before the handle which created their collection
+        if let (ReportNode::Object(object), Segment::Index(_)) = (&*self, segment) {
+            if object.is_empty() {
+                *self = ReportNode::Collection(BTreeMap::new());
+            }
+        }
+        match (self, segment) {
+            (ReportNode::Object(object), Segment::Key(key)) => object.entry(key.clone()).or_insert_with(|| ReportNode::Object(BTreeMap::new())),
+            (ReportNode::Collection(items), Segment::Index(idx)) => items.entry(*idx).or_insert_with(|| ReportNode::Object(BTreeMap::new())),
+            (node, segment) => panic!("cannot record {:?} in {}", segment, node.kind()),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            ReportNode::Object(_) => "object",
+            ReportNode::Collection(_) => "collection",
+            ReportNode::Value(_) => "value",
+        }
+    }
+
+    // Flattened scalar values with their joined keys.
+    fn flatten(&self, prefix: &str, out: &mut BTreeMap<String, Value>) {
+        let join = |key: &str| if prefix.is_empty() { key.to_string() } else { format!("{}.{}", prefix, key) };
+        match self {
+            ReportNode::Object(object) => {
+                for (key, node) in object {
+                    node.flatten(&join(key), out);
+                }
+            }
+            ReportNode::Collection(items) => {
+                for (idx, node) in items {
+                    node.flatten(&join(&idx.to_string()), out);
+                }
+            }
+            ReportNode::Value(value) => {
+                out.insert(prefix.to_string(), value.clone());
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Entry {
+    Object,
+    Collection,
+    Value(String, Value),
+}
+
+#[derive(Debug)]
+struct Shared {
+    root: Mutex<ReportNode>,
+}
+
+impl Shared {
+    // A failed consistency check poisons the lock, what was merged before is still valid and can be output.
+    fn lock(&self) -> std::sync::MutexGuard<'_, ReportNode> {
+        self.root.lock().unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn merge(&self, path: &[Segment], entries: Vec<Entry>) {
+        let mut root = self.lock();
+        let node = path.iter().fold(&mut *root, |node, segment| node.child(segment));
+        for entry in entries {
+            match entry {
+                Entry::Object => assert!(matches!(node, ReportNode::Object(_)), "expected object, found {}", node.kind()),
+                Entry::Collection => {
+                    if let ReportNode::Object(object) = node {
+                        // only empty placeholders can become collections
+                        if object.is_empty() {
+                            *node = ReportNode::Collection(BTreeMap::new());
+                        }
+                    }
+                    assert!(matches!(node, ReportNode::Collection(_)), "expected collection, found {}", node.kind());
+                }
+                Entry::Value(key, value) => match node {
+                    ReportNode::Object(object) => {
+                        let prev = object.insert(key.clone(), ReportNode::Value(value));
+                        if !cfg!(feature = "report-allow-override") {
+                            assert!(prev.is_none(), "{} reported twice", key);
+                        }
+                    }
+                    node => panic!("cannot report value {} in {}", key, node.kind()),
+                },
+            }
+        }
+    }
+}
+
+/// The tree all recorders of one experiment write to.
+#[derive(Debug, Clone)]
+pub struct Report {
+    shared: Arc<Shared>,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                root: Mutex::new(ReportNode::Object(BTreeMap::new())),
+            }),
+        }
+    }
+}
+
+impl Report {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A handle to the root object.
+    pub fn recorder(&self) -> Recorder {
+        Recorder {
+            shared: self.shared.clone(),
+            path: Vec::new(),
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Everything flushed so far.
+    pub fn tree(&self) -> ReportNode {
+        self.shared.lock().clone()
+    }
+
+    pub fn to_json(&self) -> Value {
+        self.tree().to_value()
+    }
+
+    /// The collection at `path` as rows of flattened items.
+    pub fn to_rows(&self, path: &[&str]) -> Vec<BTreeMap<String, Value>> {
+        match self.tree().get(path) {
+            Some(ReportNode::Collection(items)) => items
+                .values()
+                .map(|item| {
+                    let mut row = BTreeMap::new();
+                    item.flatten("", &mut row);
+                    row
+                })
+                .collect(),
+            None => Vec::new(),
+            Some(node) => panic!("{:?} is a {}, not a collection", path, node.kind()),
+        }
+    }
+
+    /// The collection at `path` as CSV, with the union of all flattened keys as columns in sorted order.
+    pub fn to_csv(&self, path: &[&str]) -> String {
+        let rows = self.to_rows(path);
+        let columns: BTreeSet<&String> = rows.iter().flat_map(|row| row.keys()).collect();
+        let mut out = columns.iter().map(|column| csv_field(column)).collect::<Vec<_>>().join(",");
+        out.push('\n');
+        for row in &rows {
+            let fields: Vec<String> = columns
+                .iter()
+                .map(|&column| match row.get(column) {
+                    None | Some(Value::Null) => String::new(),
+                    Some(Value::String(s)) => csv_field(s),
+                    Some(value) => csv_field(&value.to_string()),
+                })
+                .collect();
+            out.push_str(&fields.join(","));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// The collection at `path` as one array per flattened key, `null` where an item has no value.
+    pub fn to_columns(&self, path: &[&str]) -> Map<String, Value> {
+        let rows = self.to_rows(path);
+        let columns: BTreeSet<&String> = rows.iter().flat_map(|row| row.keys()).collect();
+        columns
+            .into_iter()
+            .map(|column| {
+                let values = rows.iter().map(|row| row.get(column).cloned().unwrap_or(Value::Null)).collect();
+                (column.clone(), Value::Array(values))
+            })
+            .collect()
+    }
+
+    /// The report in the given format.
+    pub fn format(&self, format: &ReportFormat) -> String {
+        match format {
+            ReportFormat::Json => serde_json::to_string(&self.to_json()).unwrap(),
+            ReportFormat::Csv(path) => self.to_csv(&keys(path)),
+            ReportFormat::Columns(path) => serde_json::to_string(&self.to_columns(&keys(path))).unwrap(),
+        }
+    }
+}
+
+/// Output format of a finished report.
+///
+/// Parsed from `json`, `csv:<path>` or `columns:<path>`, where `path` are the keys leading to the collection joined with `.`,
+/// e.g. `csv:experiments`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum ReportFormat {
+    /// The whole report as nested JSON.
+    #[default]
+    Json,
+    /// The collection at the path as CSV.
+    Csv(Vec<String>),
+    /// The collection at the path as a columnar JSON object.
+    Columns(Vec<String>),
+}
+
+#[derive(Debug)]
+pub struct ReportFormatErr(pub String);
+
+impl fmt::Display for ReportFormatErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "invalid report format: {}", self.0)
+    }
+}
+
+impl Error for ReportFormatErr {}
+
+impl FromStr for ReportFormat {
+    type Err = ReportFormatErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (format, path) = s.split_once(':').unwrap_or((s, ""));
+        let path: Vec<String> = if path.is_empty() {
+            Vec::new()
+        } else {
+            path.split('.').map(str::to_string).collect()
+        };
+        match format {
+            "json" if path.is_empty() => Ok(ReportFormat::Json),
+            "csv" | "columns" if path.is_empty() => Err(ReportFormatErr(format!("{} needs the path of a collection, e.g. {}:experiments", s, format))),
+            "csv" => Ok(ReportFormat::Csv(path)),
+            "columns" => Ok(ReportFormat::Columns(path)),
+            _ => Err(ReportFormatErr(format!("{}, expected json, csv:<path> or columns:<path>", s))),
+        }
+    }
+}
+
+fn keys(path: &[String]) -> Vec<&str> {
+    path.iter().map(String::as_str).collect()
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Handle to an object of a `Report`.
+///
+/// Records are buffered until `flush` or drop.
+/// Cloning creates a new handle to the same object with an empty buffer, for example to hand it to another thread.
+#[derive(Debug)]
+pub struct Recorder {
+    shared: Arc<Shared>,
+    path: Vec<Segment>,
+    buffer: Vec<Entry>,
+}
+
+impl Clone for Recorder {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            path: self.path.clone(),
+            buffer: Vec::new(),
+        }
+    }
+}
+
+impl Recorder {
+    pub fn record(&mut self, key: &str, value: Value) {
+        self.buffer.push(Entry::Value(key.to_string(), value));
+    }
+
+    /// Handle to the object under `key`, which is created even if nothing is recorded in it.
+    pub fn object(&self, key: &str) -> Recorder {
+        self.descend(Segment::Key(key.to_string()), Entry::Object)
+    }
+
+    /// Handle to the collection under `key`.
+    pub fn collection(&self, key: &str) -> CollectionRecorder {
+        CollectionRecorder {
+            base: self.descend(Segment::Key(key.to_string()), Entry::Collection),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn descend(&self, segment: Segment, marker: Entry) -> Recorder {
+        let mut path = self.path.clone();
+        path.push(segment);
+        Recorder {
+            shared: self.shared.clone(),
+            path,
+            buffer: vec![marker],
+        }
+    }
+
+    /// Merge the buffered records into the report.
+    pub fn flush(&mut self) {
+        if !self.buffer.is_empty() {
+            self.shared.merge(&self.path, std::mem::take(&mut self.buffer));
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // a failed consistency check should not turn a panic into an abort
+        if !std::thread::panicking() {
+            self.flush();
+        }
+    }
+}
+
+/// Handle to a collection of a `Report`.
+/// Items can be created from several threads, with explicit indices for a deterministic order.
+#[derive(Debug)]
+pub struct CollectionRecorder {
+    base: Recorder,
+    next: AtomicUsize,
+}
+
+impl CollectionRecorder {
+    /// Handle to the item at `index`.
+    pub fn item(&self, index: usize) -> Recorder {
+        self.next.fetch_max(index + 1, Ordering::Relaxed);
+        self.base.descend(Segment::Index(index), Entry::Object)
+    }
+
+    /// Handle to a new item after all items created so far.
+    pub fn push(&self) -> Recorder {
+        let index = self.next.fetch_add(1, Ordering::Relaxed);
+        self.base.descend(Segment::Index(index), Entry::Object)
+    }
+}
+
+/// Record a value in `json!` syntax with an explicit `Recorder`, the counterpart of `report!`.
+#[macro_export]
+macro_rules! record {
+    ($recorder:expr, $k:expr, $($json:tt)+) => { $recorder.record($k, $crate::report::json!($($json)+)) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rayon::prelude::*;
+    use serde_json::json;
+
+    #[test]
+    fn parallel_items_are_deterministic() {
+        let report = Report::new();
+        {
+            let mut root = report.recorder();
+            record!(root, "algo", "test");
+            let runs = root.collection("runs");
+            (0..100usize).into_par_iter().rev().for_each(|idx| {
+                let mut item = runs.item(idx);
+                record!(item, "idx", idx);
+                let mut stats = item.object("stats");
+                record!(stats, "square", idx * idx);
+            });
+            root.object("empty");
+        }
+        let json = report.to_json();
+        assert_eq!(json["algo"], "test");
+        assert_eq!(json["empty"], json!({}));
+        let runs = json["runs"].as_array().unwrap();
+        assert_eq!(runs.len(), 100);
+        for (idx, run) in runs.iter().enumerate() {
+            assert_eq!(run, &json!({ "idx": idx, "stats": { "square": idx * idx } }));
+        }
+    }
+
+    #[test]
+    fn csv_and_columns() {
+        let report = Report::new();
+        {
+            let root = report.recorder();
+            let queries = root.collection("queries");
+            let mut first = queries.push();
+            record!(first, "from", 1);
+            record!(first, "name", "a, \"b\"");
+            let mut second = queries.push();
+            record!(second, "from", 2);
+            record!(second.object("result"), "distance", 42);
+            // an empty collection
+            root.collection("none");
+        }
+        assert_eq!(report.to_csv(&["queries"]), "from,name,result.distance\n1,\"a, \"\"b\"\"\",\n2,,42\n");
+        assert_eq!(
+            Value::Object(report.to_columns(&["queries"])),
+            json!({ "from": [1, 2], "name": ["a, \"b\"", null], "result.distance": [null, 42] })
+        );
+        assert_eq!(report.to_json()["none"], json!([]));
+        assert_eq!(report.to_csv(&["missing"]), "\n");
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!("json".parse::<ReportFormat>().unwrap(), ReportFormat::Json);
+        assert_eq!("csv:queries".parse::<ReportFormat>().unwrap(), ReportFormat::Csv(vec!["queries".to_string()]));
+        assert_eq!(
+            "columns:run.queries".parse::<ReportFormat>().unwrap(),
+            ReportFormat::Columns(vec!["run".to_string(), "queries".to_string()])
+        );
+        assert!("csv".parse::<ReportFormat>().is_err());
+        assert!("json:queries".parse::<ReportFormat>().is_err());
+        assert!("xml".parse::<ReportFormat>().is_err());
+
+        let report = Report::new();
+        {
+            let mut root = report.recorder();
+            record!(root, "algo", "test");
+            let queries = root.object("run").collection("queries");
+            record!(queries.push(), "from", 1);
+            record!(queries.push(), "from", 2);
+        }
+        assert_eq!(
+            report.format(&ReportFormat::Json),
+            r#"{"algo":"test","run":{"queries":[{"from":1},{"from":2}]}}"#
+        );
+        assert_eq!(report.format(&"csv:run.queries".parse().unwrap()), "from\n1\n2\n");
+        assert_eq!(report.format(&"columns:run.queries".parse().unwrap()), r#"{"from":[1,2]}"#);
+    }
+
+    #[test]
+    fn attached_recorders_collect_report_macro() {
+        use crate::report::*;
+        let report = Report::new();
+        let root = report.recorder();
+        let threads: Vec<_> = ["first", "second"]
+            .into_iter()
+            .map(|name| {
+                let recorder = root.object(name);
+                std::thread::spawn(move || {
+                    let _recorder = recorder.attach();
+                    crate::report!("name", name);
+                    {
+                        let mut items = push_collection_context("items");
+                        assert!(current_recorder().is_none());
+                        for idx in 0..3 {
+                            let _item = items.push_collection_item();
+                            crate::report!("idx", idx);
+                        }
+                    }
+                    let capture = capture_reporting();
+                    crate::report!("captured", true);
+                    assert!(capture.reported().unwrap().contains_key("captured"));
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert!(current_recorder().is_none());
+        drop(root);
+        assert_eq!(
+            report.to_json(),
+            json!({
+                "first": { "name": "first", "items": [{ "idx": 0 }, { "idx": 1 }, { "idx": 2 }] },
+                "second": { "name": "second", "items": [{ "idx": 0 }, { "idx": 1 }, { "idx": 2 }] }
+            })
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn duplicate_keys() {
+        let report = Report::new();
+        let mut first = report.recorder();
+        let mut second = first.clone();
+        record!(first, "key", 1);
+        record!(second, "key", 2);
+        first.flush();
+        second.flush();
+        if cfg!(feature = "report-allow-override") {
+            panic!("overriding allowed");
+        }
+    }
+}
@@ -0,0 +1,1080 @@
+//! Consistency checks for graphs in RoutingKit format and repairs for common defects of imported datasets.
+//!
+//! Loading a graph does not check most invariants, violations usually surface much later as panics deep inside preprocessing.
+//! The checks here report precise diagnostics instead, with the file and the index of each offending entry.
+//! Only the first few diagnostics of each kind are kept, the rest are counted.
+//!
+//! The repair mode writes a fixed copy of a graph directory.
+//! It drops self-loops, unifies the weights of parallel arcs and makes travel time functions FIFO.
+
+use crate::{
+    algo::scc::strongly_connected_components,
+    datastr::graph::{
+        first_out_graph::degrees_to_first_out,
+        time_dependent::{period, Timestamp},
+        *,
+    },
+    io::*,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Display},
+    io::{Error, ErrorKind, Result},
+    path::Path,
+};
+
+// Diagnostics of each check after this many are only counted.
+const MAX_DIAGNOSTICS_PER_CHECK: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// Unusual but supported data, e.g. self-loops.
+    Warning,
+    /// Data which will break algorithms.
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// The file the problem was found in.
+    pub file: String,
+    /// The index of the offending entry in the file.
+    pub index: Option<usize>,
+    pub message: String,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        match self.index {
+            Some(index) => write!(f, "{}: {}[{}]: {}", severity, self.file, index, self.message),
+            None => write!(f, "{}: {}: {}", severity, self.file, self.message),
+        }
+    }
+}
+
+/// Collects the diagnostics of the checks.
+#[derive(Debug, Default)]
+pub struct Validation {
+    diagnostics: Vec<Diagnostic>,
+    counts: BTreeMap<(Severity, String, &'static str), usize>,
+}
+
+impl Validation {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Record a problem, `check` identifies the kind of problem for limiting the number of diagnostics.
+    pub fn add(&mut self, severity: Severity, file: &str, check: &'static str, index: Option<usize>, message: impl Display) {
+        let count = self.counts.entry((severity, file.to_string(), check)).or_insert(0);
+        *count += 1;
+        if *count <= MAX_DIAGNOSTICS_PER_CHECK {
+            self.diagnostics.push(Diagnostic {
+                severity,
+                file: file.to_string(),
+                index,
+                message: message.to_string(),
+            });
+        }
+    }
+
+    pub fn error(&mut self, file: &str, check: &'static str, index: Option<usize>, message: impl Display) {
+        self.add(Severity::Error, file, check, index, message)
+    }
+
+    pub fn warning(&mut self, file: &str, check: &'static str, index: Option<usize>, message: impl Display) {
+        self.add(Severity::Warning, file, check, index, message)
+    }
+
+    pub fn num_errors(&self) -> usize {
+        self.counts
+            .iter()
+            .filter(|((severity, _, _), _)| *severity == Severity::Error)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    pub fn num_warnings(&self) -> usize {
+        self.counts
+            .iter()
+            .filter(|((severity, _, _), _)| *severity == Severity::Warning)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.num_errors() == 0
+    }
+
+    /// Number of errors, not counting those of the given `checks`.
+    pub fn num_errors_except(&self, checks: &[&str]) -> usize {
+        self.counts
+            .iter()
+            .filter(|((severity, _, check), _)| *severity == Severity::Error && !checks.contains(check))
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    /// All kept diagnostics, followed by a summary for each check with omitted ones.
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.diagnostics.clone();
+        for ((severity, file, check), &count) in &self.counts {
+            if count > MAX_DIAGNOSTICS_PER_CHECK {
+                diagnostics.push(Diagnostic {
+                    severity: *severity,
+                    file: file.clone(),
+                    index: None,
+                    message: format!("{} more: {}", count - MAX_DIAGNOSTICS_PER_CHECK, check),
+                });
+            }
+        }
+        diagnostics
+    }
+}
+
+/// Check the adjacency array. Returns false if it is too broken for further checks.
+pub fn check_csr(first_out: &[EdgeId], head: &[NodeId], v: &mut Validation) -> bool {
+    if first_out.is_empty() {
+        v.error("first_out", "empty first_out", None, "must contain at least one entry");
+        return false;
+    }
+    let mut usable = true;
+    if first_out[0] != 0 {
+        v.error("first_out", "first entry not zero", Some(0), format_args!("must be 0 but is {}", first_out[0]));
+        usable = false;
+    }
+    for (node, &[begin, end]) in first_out.array_windows::<2>().enumerate() {
+        if begin > end {
+            v.error(
+                "first_out",
+                "decreasing first_out",
+                Some(node + 1),
+                format_args!("{} is smaller than the previous entry {}", end, begin),
+            );
+            usable = false;
+        }
+    }
+    let last = *first_out.last().unwrap() as usize;
+    if last != head.len() {
+        v.error(
+            "first_out",
+            "last entry does not match the number of arcs",
+            Some(first_out.len() - 1),
+            format_args!("is {} but head has {} entries", last, head.len()),
+        );
+        usable = false;
+    }
+
+    let n = first_out.len() - 1;
+    for (arc, &node) in head.iter().enumerate() {
+        if node as usize >= n {
+            v.error(
+                "head",
+                "head out of range",
+                Some(arc),
+                format_args!("node {} does not exist, there are {} nodes", node, n),
+            );
+            usable = false;
+        }
+    }
+    usable
+}
+
+/// Check that a file with one entry per arc has the right length.
+pub fn check_arc_attribute_len(file: &str, len: usize, num_arcs: usize, v: &mut Validation) -> bool {
+    if len != num_arcs {
+        v.error(
+            file,
+            "wrong number of entries",
+            None,
+            format_args!("has {} entries but there are {} arcs", len, num_arcs),
+        );
+    }
+    len == num_arcs
+}
+
+/// Weights must be smaller than `INFINITY`, zero weights are suspicious.
+pub fn check_weights(file: &str, weights: &[Weight], num_arcs: usize, v: &mut Validation) {
+    check_arc_attribute_len(file, weights.len(), num_arcs, v);
+    for (arc, &weight) in weights.iter().enumerate() {
+        if weight >= INFINITY {
+            v.error(
+                file,
+                "weight not below INFINITY",
+                Some(arc),
+                format_args!("weight {} is not smaller than INFINITY ({})", weight, INFINITY),
+            );
+        } else if weight == 0 {
+            v.warning(file, "zero weight", Some(arc), "zero weight");
+        }
+    }
+}
+
+/// Self-loops and parallel arcs are supported by most algorithms but often indicate import problems.
+/// Requires a valid adjacency array.
+pub fn check_loops_and_parallel_arcs(first_out: &[EdgeId], head: &[NodeId], v: &mut Validation) {
+    let mut last_arc_to = vec![None; first_out.len() - 1];
+    for (node, &[begin, end]) in first_out.array_windows::<2>().enumerate() {
+        for (arc, &target) in (begin as usize..).zip(&head[begin as usize..end as usize]) {
+            let target = target as usize;
+            if target == node {
+                v.warning("head", "self-loop", Some(arc), format_args!("self-loop at node {}", node));
+            }
+            match last_arc_to[target] {
+                Some((tail, prev_arc)) if tail == node => {
+                    v.warning(
+                        "head",
+                        "parallel arcs",
+                        Some(arc),
+                        format_args!("parallel to arc {} from node {} to node {}", prev_arc, node, target),
+                    );
+                }
+                _ => (),
+            }
+            last_arc_to[target] = Some((node, arc));
+        }
+    }
+}
+
+/// Coordinates in degrees, one per node.
+pub fn check_coordinates(lat: &[f32], lng: &[f32], num_nodes: usize, v: &mut Validation) {
+    for (file, values, bound) in [("latitude", lat, 90.0), ("longitude", lng, 180.0)] {
+        if values.len() != num_nodes {
+            v.error(
+                file,
+                "wrong number of entries",
+                None,
+                format_args!("has {} entries but there are {} nodes", values.len(), num_nodes),
+            );
+        }
+        for (node, &value) in values.iter().enumerate() {
+            if !value.is_finite() || value.abs() > bound {
+                v.error(
+                    file,
+                    "coordinate out of range",
+                    Some(node),
+                    format_args!("{} is not in [-{}, {}]", value, bound, bound),
+                );
+            }
+        }
+    }
+}
+
+/// Node orders must be permutations of all nodes.
+pub fn check_permutation(file: &str, order: &[NodeId], num_nodes: usize, v: &mut Validation) {
+    if order.len() != num_nodes {
+        v.error(
+            file,
+            "wrong number of entries",
+            None,
+            format_args!("has {} entries but there are {} nodes", order.len(), num_nodes),
+        );
+    }
+    let mut first_seen = vec![None; num_nodes];
+    for (rank, &node) in order.iter().enumerate() {
+        match first_seen.get_mut(node as usize) {
+            None => v.error(file, "node out of range", Some(rank), format_args!("node {} does not exist", node)),
+            Some(Some(prev)) => v.error(file, "duplicate node", Some(rank), format_args!("node {} already at {}", node, prev)),
+            Some(seen) => *seen = Some(rank),
+        }
+    }
+}
+
+/// Check the travel time functions of a time-dependent graph, in the format of the files `first_ipp_of_arc`, `ipp_departure_time` and `ipp_travel_time`.
+/// Departures must be strictly increasing within `[0, period()]` and functions have to fulfill the FIFO property, including the wrap around at the end of the period.
+pub fn check_travel_time_functions(first_ipp_of_arc: &[u32], departure: &[Timestamp], travel_time: &[Weight], num_arcs: usize, v: &mut Validation) {
+    let file = "first_ipp_of_arc";
+    check_arc_attribute_len(file, first_ipp_of_arc.len().saturating_sub(1), num_arcs, v);
+    if departure.len() != travel_time.len() {
+        v.error(
+            "ipp_travel_time",
+            "wrong number of entries",
+            None,
+            format_args!("has {} entries but ipp_departure_time has {}", travel_time.len(), departure.len()),
+        );
+    }
+    if first_ipp_of_arc.first() != Some(&0) {
+        v.error(file, "first entry not zero", Some(0), "must be 0");
+        return;
+    }
+    let num_ipps = std::cmp::min(departure.len(), travel_time.len());
+    if *first_ipp_of_arc.last().unwrap() as usize != num_ipps {
+        v.error(
+            file,
+            "last entry does not match the number of interpolation points",
+            Some(first_ipp_of_arc.len() - 1),
+            format_args!("is {} but there are {} interpolation points", first_ipp_of_arc.last().unwrap(), num_ipps),
+        );
+    }
+
+    for (arc, &[begin, end]) in first_ipp_of_arc.array_windows::<2>().enumerate() {
+        if begin >= end {
+            v.error(
+                file,
+                "arc without interpolation points",
+                Some(arc),
+                format_args!("arc {} has no interpolation points", arc),
+            );
+            continue;
+        }
+        if end as usize > num_ipps {
+            v.error(
+                file,
+                "interpolation points out of range",
+                Some(arc + 1),
+                format_args!("{} is beyond the last point", end),
+            );
+            continue;
+        }
+        let range = begin as usize..end as usize;
+        let (departure, travel_time) = (&departure[range.clone()], &travel_time[range.clone()]);
+
+        for (idx, (&at, &tt)) in departure.iter().zip(travel_time).enumerate() {
+            let ipp = range.start + idx;
+            if at > period() {
+                v.error(
+                    "ipp_departure_time",
+                    "departure after period",
+                    Some(ipp),
+                    format_args!("departure {} of arc {} is after the end of the period {}", at, arc, period()),
+                );
+            }
+            if tt >= INFINITY {
+                v.error(
+                    "ipp_travel_time",
+                    "travel time not below INFINITY",
+                    Some(ipp),
+                    format_args!("travel time {} of arc {} is not smaller than INFINITY", tt, arc),
+                );
+            } else if tt == 0 {
+                v.warning(
+                    "ipp_travel_time",
+                    "zero travel time",
+                    Some(ipp),
+                    format_args!("zero travel time on arc {}", arc),
+                );
+            }
+        }
+
+        for (idx, (&[at1, at2], &[tt1, tt2])) in departure.array_windows::<2>().zip(travel_time.array_windows::<2>()).enumerate() {
+            let ipp = range.start + idx + 1;
+            if at1 >= at2 {
+                v.error(
+                    "ipp_departure_time",
+                    "departures not increasing",
+                    Some(ipp),
+                    format_args!("departure {} of arc {} is not after the previous departure {}", at2, arc, at1),
+                );
+            } else if at1 as u64 + tt1 as u64 > at2 as u64 + tt2 as u64 {
+                v.error(
+                    "ipp_travel_time",
+                    "not FIFO",
+                    Some(ipp),
+                    format_args!("arc {} is not FIFO, departing at {} arrives before departing at {}", arc, at2, at1),
+                );
+            }
+        }
+
+        // the function continues periodically from its first point
+        let (first_at, first_tt) = (departure[0], travel_time[0]);
+        let (last_at, last_tt) = (*departure.last().unwrap(), *travel_time.last().unwrap());
+        if last_at < period() && last_at as u64 + last_tt as u64 > period() as u64 + first_at as u64 + first_tt as u64 {
+            v.error(
+                "ipp_travel_time",
+                "not FIFO",
+                Some(range.end - 1),
+                format_args!("arc {} is not FIFO across the end of the period", arc),
+            );
+        }
+    }
+}
+
+/// Turn restrictions as pairs of arc ids, sorted lexicographically, as expected by the turn expansion.
+/// Requires a valid adjacency array.
+pub fn check_turn_restrictions(first_out: &[EdgeId], head: &[NodeId], from_arc: &[EdgeId], to_arc: &[EdgeId], v: &mut Validation) {
+    let file = "forbidden_turn_from_arc";
+    if from_arc.len() != to_arc.len() {
+        v.error(
+            "forbidden_turn_to_arc",
+            "wrong number of entries",
+            None,
+            format_args!("has {} entries but forbidden_turn_from_arc has {}", to_arc.len(), from_arc.len()),
+        );
+    }
+    let m = head.len();
+    let tail = tails(first_out);
+    let mut prev = None;
+    for (idx, (&from, &to)) in from_arc.iter().zip(to_arc).enumerate() {
+        if from as usize >= m || to as usize >= m {
+            v.error(
+                file,
+                "arc out of range",
+                Some(idx),
+                format_args!("turn from arc {} to arc {} but there are {} arcs", from, to, m),
+            );
+            continue;
+        }
+        if head[from as usize] != tail[to as usize] {
+            v.warning(
+                file,
+                "turn between non adjacent arcs",
+                Some(idx),
+                format_args!(
+                    "arc {} ends at node {} but arc {} starts at node {}",
+                    from, head[from as usize], to, tail[to as usize]
+                ),
+            );
+        }
+        match prev {
+            Some(prev) if prev > (from, to) => v.error(file, "turn restrictions not sorted", Some(idx), "not sorted by from and to arc"),
+            Some(prev) if prev == (from, to) => v.warning(file, "duplicate turn restriction", Some(idx), "duplicate turn restriction"),
+            _ => (),
+        }
+        prev = Some((from, to));
+    }
+}
+
+/// Nodes outside the largest strongly connected component make many queries fail.
+/// Requires a valid adjacency array.
+pub fn check_connectivity(first_out: &[EdgeId], head: &[NodeId], v: &mut Validation) {
+    let components = strongly_connected_components(&UnweightedFirstOutGraph::new(first_out, head));
+    let sizes = components.sorted_sizes();
+    if sizes.len() > 1 {
+        v.warning(
+            "head",
+            "not strongly connected",
+            None,
+            format_args!(
+                "{} nodes in {} components are not strongly connected to the largest component with {} nodes",
+                first_out.len() - 1 - sizes[0],
+                sizes.len() - 1,
+                sizes[0]
+            ),
+        );
+    }
+}
+
+fn tails(first_out: &[EdgeId]) -> Vec<NodeId> {
+    let mut tail = Vec::with_capacity(*first_out.last().unwrap() as usize);
+    for (node, &[begin, end]) in first_out.array_windows::<2>().enumerate() {
+        tail.resize(tail.len() + (end - begin) as usize, node as NodeId);
+    }
+    tail
+}
+
+// Load a file of 4 byte values, `Ok(None)` if it does not exist. Broken files are diagnosed instead of panicking.
+fn load<T: Default + Copy>(dir: &Path, file: &str, v: &mut Validation) -> Result<Option<Vec<T>>> {
+    let path = dir.join(file);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let len = std::fs::metadata(&path)?.len();
+    if len % std::mem::size_of::<T>() as u64 != 0 {
+        v.error(
+            file,
+            "truncated file",
+            None,
+            format_args!("size {} is not a multiple of {} bytes", len, std::mem::size_of::<T>()),
+        );
+        return Ok(None);
+    }
+    Ok(Some(Vec::load_from(path)?))
+}
+
+/// Run all checks on the graph in `dir`.
+/// `first_out` and `head` are required as well as the given `metrics`.
+/// Coordinates, the node `order`, travel time functions and turn restrictions are checked if present.
+pub fn validate_dir(dir: &Path, metrics: &[String], order: Option<&str>) -> Result<Validation> {
+    let mut v = Validation::new();
+    let first_out = load::<EdgeId>(dir, "first_out", &mut v)?;
+    let head = load::<NodeId>(dir, "head", &mut v)?;
+    let (first_out, head) = match (first_out, head) {
+        (Some(first_out), Some(head)) => (first_out, head),
+        (first_out, _) => {
+            let missing = if first_out.is_none() { "first_out" } else { "head" };
+            v.error(missing, "missing file", None, "missing or unreadable");
+            return Ok(v);
+        }
+    };
+    if !check_csr(&first_out, &head, &mut v) {
+        return Ok(v);
+    }
+    let (n, m) = (first_out.len() - 1, head.len());
+    check_loops_and_parallel_arcs(&first_out, &head, &mut v);
+    check_connectivity(&first_out, &head, &mut v);
+
+    for metric in metrics {
+        match load::<Weight>(dir, metric, &mut v)? {
+            Some(weights) => check_weights(metric, &weights, m, &mut v),
+            None => v.error(metric, "missing file", None, "missing or unreadable"),
+        }
+    }
+
+    match (load::<f32>(dir, "latitude", &mut v)?, load::<f32>(dir, "longitude", &mut v)?) {
+        (Some(lat), Some(lng)) => check_coordinates(&lat, &lng, n, &mut v),
+        (None, None) => (),
+        (lat, _) => v.error(
+            if lat.is_none() { "latitude" } else { "longitude" },
+            "missing file",
+            None,
+            "coordinates are incomplete",
+        ),
+    }
+
+    if let Some(order) = order {
+        if let Some(perm) = load::<NodeId>(dir, order, &mut v)? {
+            check_permutation(order, &perm, n, &mut v);
+        }
+    }
+
+    if let Some(first_ipp_of_arc) = load::<u32>(dir, "first_ipp_of_arc", &mut v)? {
+        let departure = load::<Timestamp>(dir, "ipp_departure_time", &mut v)?.unwrap_or_default();
+        let travel_time = load::<Weight>(dir, "ipp_travel_time", &mut v)?.unwrap_or_default();
+        check_travel_time_functions(&first_ipp_of_arc, &departure, &travel_time, m, &mut v);
+    }
+
+    match (
+        load::<EdgeId>(dir, "forbidden_turn_from_arc", &mut v)?,
+        load::<EdgeId>(dir, "forbidden_turn_to_arc", &mut v)?,
+    ) {
+        (Some(from_arc), Some(to_arc)) => check_turn_restrictions(&first_out, &head, &from_arc, &to_arc, &mut v),
+        (None, None) => (),
+        (from_arc, _) => v.error(
+            if from_arc.is_none() {
+                "forbidden_turn_from_arc"
+            } else {
+                "forbidden_turn_to_arc"
+            },
+            "missing file",
+            None,
+            "turn restrictions are incomplete",
+        ),
+    }
+
+    Ok(v)
+}
+
+/// What `repair_dir` changed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RepairSummary {
+    pub removed_self_loops: usize,
+    /// Summed over all metrics.
+    pub unified_parallel_arc_weights: usize,
+    pub fifoized_arcs: usize,
+    pub raised_zero_travel_times: usize,
+    /// Restrictions involving removed arcs.
+    pub dropped_turn_restrictions: usize,
+}
+
+/// Make a piecewise linear travel time function FIFO by raising travel times where departing later would arrive earlier.
+/// The function is periodic, so the first point also continues the last one.
+/// Returns whether anything changed.
+pub fn fifoize_up(departure: &[Timestamp], travel_time: &mut [Weight]) -> bool {
+    let mut changed = false;
+    // two rounds, raising the first point can only affect the following ones once
+    for _ in 0..2 {
+        for i in 1..travel_time.len() {
+            let min_tt = (departure[i - 1] + travel_time[i - 1]).saturating_sub(departure[i]);
+            if travel_time[i] < min_tt {
+                travel_time[i] = min_tt;
+                changed = true;
+            }
+        }
+        let (last_at, last_tt) = (*departure.last().unwrap(), *travel_time.last().unwrap());
+        let wrap_tt = (last_at + last_tt).saturating_sub(period() + departure[0]);
+        if last_at < period() && travel_time[0] < wrap_tt {
+            travel_time[0] = wrap_tt;
+            changed = true;
+        } else {
+            break;
+        }
+    }
+    changed
+}
+
+// `first_ipp_of_arc`, `ipp_departure_time` and `ipp_travel_time`
+type TravelTimeFunctions = (Vec<u32>, Vec<Timestamp>, Vec<Weight>);
+
+// Load the travel time functions of `dir`, if there are any, and make sure they can be rewritten.
+// Errors of the `repairable` checks are left to the caller.
+fn load_travel_time_functions(dir: &Path, num_arcs: usize, repairable: &[&str]) -> Result<Option<TravelTimeFunctions>> {
+    if !dir.join("first_ipp_of_arc").exists() {
+        return Ok(None);
+    }
+    let first_ipp_of_arc: Vec<u32> = Vec::load_from(dir.join("first_ipp_of_arc"))?;
+    let departure: Vec<Timestamp> = Vec::load_from(dir.join("ipp_departure_time"))?;
+    let travel_time: Vec<Weight> = Vec::load_from(dir.join("ipp_travel_time"))?;
+    let mut v = Validation::new();
+    check_travel_time_functions(&first_ipp_of_arc, &departure, &travel_time, num_arcs, &mut v);
+    let num_errors = v.num_errors_except(repairable);
+    if num_errors > 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("the travel time functions have {} errors, see the diagnostics of validate", num_errors),
+        ));
+    }
+    Ok(Some((first_ipp_of_arc, departure, travel_time)))
+}
+
+// Write the turn restrictions of `dir` with new arc ids to `output`. Restrictions involving removed arcs are dropped, returns their number.
+fn remap_turn_restrictions(dir: &Path, output: &Path, new_arc_id: &[Option<EdgeId>]) -> Result<usize> {
+    if !dir.join("forbidden_turn_from_arc").exists() {
+        return Ok(0);
+    }
+    let from_arc: Vec<EdgeId> = Vec::load_from(dir.join("forbidden_turn_from_arc"))?;
+    let to_arc: Vec<EdgeId> = Vec::load_from(dir.join("forbidden_turn_to_arc"))?;
+    let mut turns: Vec<(EdgeId, EdgeId)> = from_arc
+        .iter()
+        .zip(&to_arc)
+        .filter_map(|(&from, &to)| Some((new_arc_id.get(from as usize).copied()??, new_arc_id.get(to as usize).copied()??)))
+        .collect();
+    let dropped = from_arc.len() - turns.len();
+    turns.sort_unstable();
+    turns.dedup();
+    turns
+        .iter()
+        .map(|&(from, _)| from)
+        .collect::<Vec<_>>()
+        .write_to(&output.join("forbidden_turn_from_arc"))?;
+    turns
+        .iter()
+        .map(|&(_, to)| to)
+        .collect::<Vec<_>>()
+        .write_to(&output.join("forbidden_turn_to_arc"))?;
+    Ok(dropped)
+}
+
+/// Write a repaired copy of the graph in `dir` to `output`.
+/// Arc ids change when self-loops are removed, `output` gets an `original_arc_id` file to map other arc based data.
+/// Node based files (coordinates and the given `order`) are copied unchanged.
+pub fn repair_dir(dir: &Path, output: &Path, metrics: &[String], order: Option<&str>) -> Result<RepairSummary> {
+    let first_out: Vec<EdgeId> = Vec::load_from(dir.join("first_out"))?;
+    let head: Vec<NodeId> = Vec::load_from(dir.join("head"))?;
+    let mut v = Validation::new();
+    if !check_csr(&first_out, &head, &mut v) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "the adjacency array is broken, the graph cannot be repaired",
+        ));
+    }
+    // broken functions would only be garbled further, FIFO violations are what the repair is for
+    let travel_time_functions = load_travel_time_functions(dir, head.len(), &["not FIFO"])?;
+    let mut summary = RepairSummary::default();
+
+    let tail = tails(&first_out);
+    let kept: Vec<usize> = (0..head.len()).filter(|&arc| tail[arc] != head[arc]).collect();
+    summary.removed_self_loops = head.len() - kept.len();
+    let mut new_arc_id = vec![None; head.len()];
+    for (new_id, &arc) in kept.iter().enumerate() {
+        new_arc_id[arc] = Some(new_id as EdgeId);
+    }
+
+    let new_first_out: Vec<EdgeId> = degrees_to_first_out(
+        first_out
+            .array_windows::<2>()
+            .map(|&[begin, end]| (begin as usize..end as usize).filter(|&arc| new_arc_id[arc].is_some()).count() as EdgeId),
+    )
+    .collect();
+    let new_head: Vec<NodeId> = kept.iter().map(|&arc| head[arc]).collect();
+
+    std::fs::create_dir_all(output)?;
+    new_first_out.write_to(&output.join("first_out"))?;
+    new_head.write_to(&output.join("head"))?;
+    kept.iter()
+        .map(|&arc| arc as EdgeId)
+        .collect::<Vec<_>>()
+        .write_to(&output.join("original_arc_id"))?;
+
+    for metric in metrics {
+        let weights: Vec<Weight> = Vec::load_from(dir.join(metric))?;
+        if weights.len() != head.len() {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{} does not have one entry per arc", metric)));
+        }
+        let mut graph = OwnedGraph::new(new_first_out.clone(), new_head.clone(), kept.iter().map(|&arc| weights[arc]).collect());
+        let before = graph.weight().to_vec();
+        unify_parallel_edges(&mut graph);
+        summary.unified_parallel_arc_weights += before.iter().zip(graph.weight()).filter(|(a, b)| a != b).count();
+        graph.weight().write_to(&output.join(metric))?;
+    }
+
+    if let Some((first_ipp_of_arc, departure, travel_time)) = travel_time_functions {
+        let mut new_first_ipp_of_arc = vec![0u32];
+        let mut new_departure = Vec::with_capacity(departure.len());
+        let mut new_travel_time = Vec::with_capacity(travel_time.len());
+        for &arc in &kept {
+            let range = first_ipp_of_arc[arc] as usize..first_ipp_of_arc[arc + 1] as usize;
+            let start = new_travel_time.len();
+            new_departure.extend_from_slice(&departure[range.clone()]);
+            new_travel_time.extend(travel_time[range].iter().map(|&tt| {
+                if tt == 0 {
+                    summary.raised_zero_travel_times += 1;
+                }
+                std::cmp::max(tt, 1)
+            }));
+            if new_travel_time.len() > start && fifoize_up(&new_departure[start..], &mut new_travel_time[start..]) {
+                summary.fifoized_arcs += 1;
+            }
+            new_first_ipp_of_arc.push(new_departure.len() as u32);
+        }
+        new_first_ipp_of_arc.write_to(&output.join("first_ipp_of_arc"))?;
+        new_departure.write_to(&output.join("ipp_departure_time"))?;
+        new_travel_time.write_to(&output.join("ipp_travel_time"))?;
+    }
+
+    summary.dropped_turn_restrictions = remap_turn_restrictions(dir, output, &new_arc_id)?;
+
+    for file in ["latitude", "longitude"].into_iter().chain(order) {
+        if dir.join(file).exists() {
+            std::fs::copy(dir.join(file), output.join(file))?;
+        }
+    }
+
+    Ok(summary)
+}
+
+/// What `restrict_dir_to_largest_scc` kept and removed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RestrictSummary {
+    pub kept_nodes: usize,
+    pub removed_nodes: usize,
+    pub kept_arcs: usize,
+    pub removed_arcs: usize,
+    /// Restrictions involving removed arcs.
+    pub dropped_turn_restrictions: usize,
+    /// Files and directories which are not known to belong to nodes or arcs and were not copied.
+    pub skipped_files: Vec<String>,
+}
+
+// Node orders which are restricted in addition to the given one.
+const ORDER_FILES: [&str; 2] = ["cch_perm", "cch_exp_perm"];
+
+// Files with one entry per node of the given size in bytes, which are no ids of this graph and can just be filtered.
+const NODE_ATTRIBUTE_FILES: [(&str, usize); 5] = [
+    ("latitude", 4),
+    ("longitude", 4),
+    ("elevation", 4),
+    ("osm_node_ids", 8),
+    ("original_node_id", 4),
+];
+
+// Files with one 4 byte entry per arc which can just be filtered, in addition to the given metrics.
+const ARC_ATTRIBUTE_FILES: [&str; 3] = ["travel_time", "geo_distance", "original_arc_id"];
+
+// Keep the entries of `entry_size` bytes at the `kept` indices.
+fn filter_entries(bytes: &[u8], entry_size: usize, kept: &[usize]) -> Vec<u8> {
+    kept.iter().flat_map(|&idx| &bytes[idx * entry_size..(idx + 1) * entry_size]).copied().collect()
+}
+
+/// Write the subgraph induced by the largest strongly connected component of the graph in `dir` to `output`.
+/// Orders, `tail`, travel time functions and turn restrictions are restricted and renumbered.
+/// The known node and arc attributes in `NODE_ATTRIBUTE_FILES`, `ARC_ATTRIBUTE_FILES` and the given `metrics` are filtered,
+/// all other files are skipped, as they might contain ids which would have to be renumbered.
+/// `original_node_id` and `original_arc_id` are created if they do not exist yet, so results can be mapped back.
+pub fn restrict_dir_to_largest_scc(dir: &Path, output: &Path, metrics: &[String], order: Option<&str>) -> Result<RestrictSummary> {
+    let first_out: Vec<EdgeId> = Vec::load_from(dir.join("first_out"))?;
+    let head: Vec<NodeId> = Vec::load_from(dir.join("head"))?;
+    let mut v = Validation::new();
+    if !check_csr(&first_out, &head, &mut v) {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "the adjacency array is broken, the graph cannot be restricted",
+        ));
+    }
+    let (n, m) = (first_out.len() - 1, head.len());
+    let travel_time_functions = load_travel_time_functions(dir, m, &[])?;
+
+    let in_component = strongly_connected_components(&UnweightedFirstOutGraph::new(&first_out[..], &head[..])).largest_mask();
+    let kept_nodes: Vec<usize> = (0..n).filter(|&node| in_component.get(node)).collect();
+    let mut new_node_id = vec![None; n];
+    for (new_id, &node) in kept_nodes.iter().enumerate() {
+        new_node_id[node] = Some(new_id as NodeId);
+    }
+    // arcs between two nodes of the component are always part of it
+    let tail = tails(&first_out);
+    let kept_arcs: Vec<usize> = (0..m)
+        .filter(|&arc| in_component.get(tail[arc] as usize) && in_component.get(head[arc] as usize))
+        .collect();
+    let mut new_arc_id = vec![None; m];
+    for (new_id, &arc) in kept_arcs.iter().enumerate() {
+        new_arc_id[arc] = Some(new_id as EdgeId);
+    }
+
+    let mut summary = RestrictSummary {
+        kept_nodes: kept_nodes.len(),
+        removed_nodes: n - kept_nodes.len(),
+        kept_arcs: kept_arcs.len(),
+        removed_arcs: m - kept_arcs.len(),
+        ..Default::default()
+    };
+
+    std::fs::create_dir_all(output)?;
+    degrees_to_first_out(
+        kept_nodes
+            .iter()
+            .map(|&node| (first_out[node]..first_out[node + 1]).filter(|&arc| new_arc_id[arc as usize].is_some()).count() as EdgeId),
+    )
+    .collect::<Vec<EdgeId>>()
+    .write_to(&output.join("first_out"))?;
+    kept_arcs
+        .iter()
+        .map(|&arc| new_node_id[head[arc] as usize].unwrap())
+        .collect::<Vec<NodeId>>()
+        .write_to(&output.join("head"))?;
+    let mut handled = vec!["first_out".to_string(), "head".to_string()];
+
+    if let Some((first_ipp_of_arc, departure, travel_time)) = travel_time_functions {
+        let mut new_first_ipp_of_arc = vec![0u32];
+        let mut new_departure = Vec::with_capacity(departure.len());
+        let mut new_travel_time = Vec::with_capacity(travel_time.len());
+        for &arc in &kept_arcs {
+            let range = first_ipp_of_arc[arc] as usize..first_ipp_of_arc[arc + 1] as usize;
+            new_departure.extend_from_slice(&departure[range.clone()]);
+            new_travel_time.extend_from_slice(&travel_time[range]);
+            new_first_ipp_of_arc.push(new_departure.len() as u32);
+        }
+        new_first_ipp_of_arc.write_to(&output.join("first_ipp_of_arc"))?;
+        new_departure.write_to(&output.join("ipp_departure_time"))?;
+        new_travel_time.write_to(&output.join("ipp_travel_time"))?;
+    }
+    handled.extend(["first_ipp_of_arc", "ipp_departure_time", "ipp_travel_time"].map(String::from));
+
+    summary.dropped_turn_restrictions = remap_turn_restrictions(dir, output, &new_arc_id)?;
+    handled.extend(["forbidden_turn_from_arc", "forbidden_turn_to_arc"].map(String::from));
+
+    for file in ORDER_FILES.into_iter().chain(order) {
+        if handled.iter().any(|handled| handled == file) || !dir.join(file).exists() {
+            continue;
+        }
+        let perm: Vec<NodeId> = Vec::load_from(dir.join(file))?;
+        if perm.len() != n || perm.iter().any(|&node| node as usize >= n) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{} is not a node permutation", file)));
+        }
+        perm.iter()
+            .filter_map(|&node| new_node_id[node as usize])
+            .collect::<Vec<_>>()
+            .write_to(&output.join(file))?;
+        handled.push(file.to_string());
+    }
+
+    if dir.join("tail").exists() {
+        let tail: Vec<NodeId> = Vec::load_from(dir.join("tail"))?;
+        if tail.len() != m {
+            return Err(Error::new(ErrorKind::InvalidData, "tail does not have one entry per arc"));
+        }
+        kept_arcs
+            .iter()
+            .map(|&arc| new_node_id[tail[arc] as usize].unwrap())
+            .collect::<Vec<NodeId>>()
+            .write_to(&output.join("tail"))?;
+    }
+    handled.push("tail".to_string());
+
+    let arc_files: Vec<&str> = metrics.iter().map(String::as_str).chain(ARC_ATTRIBUTE_FILES).collect();
+    let mut entries = std::fs::read_dir(dir)?.collect::<Result<Vec<_>>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if handled.contains(&name) {
+            continue;
+        }
+        let (kept, count, entry_size, element): (&[usize], usize, usize, &str) =
+            if let Some(&(_, entry_size)) = NODE_ATTRIBUTE_FILES.iter().find(|&&(file, _)| file == name) {
+                (&kept_nodes, n, entry_size, "node")
+            } else if arc_files.contains(&name.as_str()) {
+                (&kept_arcs, m, 4, "arc")
+            } else {
+                summary.skipped_files.push(name);
+                continue;
+            };
+        if !entry.file_type()?.is_file() || entry.metadata()?.len() as usize != count * entry_size {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("{} does not have one {} byte entry per {}", name, entry_size, element),
+            ));
+        }
+        std::fs::write(output.join(&name), filter_entries(&std::fs::read(entry.path())?, entry_size, kept))?;
+    }
+
+    if !dir.join("original_node_id").exists() {
+        kept_nodes
+            .iter()
+            .map(|&node| node as NodeId)
+            .collect::<Vec<_>>()
+            .write_to(&output.join("original_node_id"))?;
+    }
+    if !dir.join("original_arc_id").exists() {
+        kept_arcs
+            .iter()
+            .map(|&arc| arc as EdgeId)
+            .collect::<Vec<_>>()
+            .write_to(&output.join("original_arc_id"))?;
+    }
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastr::graph::time_dependent::run_test_with_periodicity;
+
+    fn messages(v: &Validation) -> Vec<String> {
+        v.diagnostics().iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn static_graph_diagnostics() {
+        let mut v = Validation::new();
+        assert!(!check_csr(&[0, 2, 1], &[1, 0, 3], &mut v));
+        assert_eq!(
+            messages(&v),
+            vec![
+                "error: first_out[2]: 1 is smaller than the previous entry 2",
+                "error: first_out[2]: is 1 but head has 3 entries",
+                "error: head[2]: node 3 does not exist, there are 2 nodes",
+            ]
+        );
+
+        let mut v = Validation::new();
+        let (first_out, head) = ([0, 3, 3, 4], [0, 2, 2, 1]);
+        assert!(check_csr(&first_out, &head, &mut v));
+        check_loops_and_parallel_arcs(&first_out, &head, &mut v);
+        check_weights("travel_time", &[1, 0, INFINITY, 2], 4, &mut v);
+        check_permutation("cch_perm", &[2, 0, 2, 5], 3, &mut v);
+        check_coordinates(&[49.0, 91.0, 48.0], &[8.4, 8.4, f32::NAN], 3, &mut v);
+        assert_eq!(v.num_errors(), 6);
+        assert_eq!(v.num_warnings(), 3);
+        assert!(messages(&v).contains(&"warning: head[2]: parallel to arc 1 from node 0 to node 2".to_string()));
+        assert!(messages(&v).contains(&"error: cch_perm[2]: node 2 already at 0".to_string()));
+        assert!(messages(&v).contains(&"error: cch_perm: has 4 entries but there are 3 nodes".to_string()));
+
+        let mut v = Validation::new();
+        check_weights("travel_time", &[0; 25], 25, &mut v);
+        let diagnostics = v.diagnostics();
+        assert_eq!(diagnostics.len(), MAX_DIAGNOSTICS_PER_CHECK + 1);
+        assert_eq!(diagnostics.last().unwrap().to_string(), "warning: travel_time: 15 more: zero weight");
+        assert_eq!(v.num_warnings(), 25);
+        assert!(v.is_valid());
+    }
+
+    #[test]
+    fn turn_restrictions() {
+        let mut v = Validation::new();
+        // 0 -> 1 -> 2
+        check_turn_restrictions(&[0, 1, 2, 2], &[1, 2], &[1, 0, 0, 2], &[0, 1, 1, 0], &mut v);
+        assert_eq!(
+            messages(&v),
+            vec![
+                "warning: forbidden_turn_from_arc[0]: arc 1 ends at node 2 but arc 0 starts at node 0",
+                "error: forbidden_turn_from_arc[1]: not sorted by from and to arc",
+                "warning: forbidden_turn_from_arc[2]: duplicate turn restriction",
+                "error: forbidden_turn_from_arc[3]: turn from arc 2 to arc 0 but there are 2 arcs",
+            ]
+        );
+    }
+
+    #[test]
+    fn travel_time_functions() {
+        run_test_with_periodicity(100, || {
+            let mut v = Validation::new();
+            check_travel_time_functions(&[0, 1, 4, 6], &[0, 0, 50, 40, 10, 90], &[5, 10, 0, 10, 5, 30], 3, &mut v);
+            assert_eq!(
+                messages(&v),
+                vec![
+                    "warning: ipp_travel_time[2]: zero travel time on arc 1",
+                    "error: ipp_departure_time[3]: departure 40 of arc 1 is not after the previous departure 50",
+                    "error: ipp_travel_time[5]: arc 2 is not FIFO across the end of the period",
+                ]
+            );
+
+            let departure = [10, 20, 90];
+            let mut travel_time = [5, 20, 30];
+            assert!(fifoize_up(&departure, &mut travel_time));
+            // 90 + 30 arrives at 20 of the next period, after 10 + 5
+            assert_eq!(travel_time, [10, 20, 30]);
+            let mut v = Validation::new();
+            check_travel_time_functions(&[0, 3], &departure, &travel_time, 1, &mut v);
+            assert!(v.is_valid());
+
+            let mut travel_time = [50, 5, 5];
+            assert!(fifoize_up(&[0, 20, 40], &mut travel_time));
+            assert_eq!(travel_time, [50, 30, 10]);
+        });
+    }
+
+    #[test]
+    fn restrict_to_largest_scc() {
+        run_test_with_periodicity(100, || {
+            // 0 <-> 1 <-> 2 with 4 -> 3 -> 0 leading into the component
+            let dir = std::env::temp_dir().join(format!("rrr_restrict_scc_{}", std::process::id()));
+            let output = dir.with_file_name(format!("rrr_restrict_scc_{}_output", std::process::id()));
+            std::fs::create_dir_all(dir.join("customized")).unwrap();
+            vec![0u32, 1, 3, 4, 5, 6].write_to(&dir.join("first_out")).unwrap();
+            vec![1u32, 0, 2, 1, 0, 3].write_to(&dir.join("head")).unwrap();
+            vec![1u32, 2, 3, 4, 5, 6].write_to(&dir.join("travel_time")).unwrap();
+            vec![10u32, 11, 12, 13, 14, 15].write_to(&dir.join("arc_category")).unwrap();
+            vec![0u32, 1, 1, 2, 3, 4].write_to(&dir.join("tail")).unwrap();
+            vec![100u64, 101, 102, 103, 104].write_to(&dir.join("osm_node_ids")).unwrap();
+            vec![49.0f32, 49.1, 49.2, 49.3, 49.4].write_to(&dir.join("latitude")).unwrap();
+            vec![8.0f32, 8.1, 8.2, 8.3, 8.4].write_to(&dir.join("longitude")).unwrap();
+            vec![4u32, 3, 2, 1, 0].write_to(&dir.join("cch_perm")).unwrap();
+            vec![0u32, 1, 2, 3, 4, 5, 6].write_to(&dir.join("first_ipp_of_arc")).unwrap();
+            vec![0u32; 6].write_to(&dir.join("ipp_departure_time")).unwrap();
+            vec![1u32, 2, 3, 4, 5, 6].write_to(&dir.join("ipp_travel_time")).unwrap();
+            vec![0u32, 0, 4].write_to(&dir.join("forbidden_turn_from_arc")).unwrap();
+            vec![1u32, 2, 0].write_to(&dir.join("forbidden_turn_to_arc")).unwrap();
+
+            let mut v = Validation::new();
+            check_connectivity(&[0, 1, 3, 4, 5, 6], &[1, 0, 2, 1, 0, 3], &mut v);
+            assert_eq!(
+                messages(&v),
+                vec!["warning: head: 2 nodes in 2 components are not strongly connected to the largest component with 3 nodes"]
+            );
+
+            let summary = restrict_dir_to_largest_scc(&dir, &output, &["travel_time".to_string()], None).unwrap();
+            assert_eq!(
+                (summary.kept_nodes, summary.removed_nodes, summary.kept_arcs, summary.removed_arcs),
+                (3, 2, 4, 2)
+            );
+            assert_eq!(summary.dropped_turn_restrictions, 1);
+            assert_eq!(summary.skipped_files, vec!["arc_category".to_string(), "customized".to_string()]);
+
+            let load = |file: &str| Vec::<u32>::load_from(output.join(file)).unwrap();
+            assert_eq!(load("first_out"), vec![0, 1, 3, 4]);
+            assert_eq!(load("head"), vec![1, 0, 2, 1]);
+            assert_eq!(load("travel_time"), vec![1, 2, 3, 4]);
+            assert!(!output.join("arc_category").exists());
+            assert_eq!(load("tail"), vec![0, 1, 1, 2]);
+            assert_eq!(Vec::<u64>::load_from(output.join("osm_node_ids")).unwrap(), vec![100, 101, 102]);
+            assert_eq!(load("cch_perm"), vec![2, 1, 0]);
+            assert_eq!(load("first_ipp_of_arc"), vec![0, 1, 2, 3, 4]);
+            assert_eq!(load("ipp_travel_time"), vec![1, 2, 3, 4]);
+            assert_eq!(load("forbidden_turn_from_arc"), vec![0, 0]);
+            assert_eq!(load("forbidden_turn_to_arc"), vec![1, 2]);
+            assert_eq!(load("original_node_id"), vec![0, 1, 2]);
+            assert_eq!(load("original_arc_id"), vec![0, 1, 2, 3]);
+            assert_eq!(Vec::<f32>::load_from(output.join("latitude")).unwrap(), vec![49.0, 49.1, 49.2]);
+            let v = validate_dir(&output, &["travel_time".to_string()], Some("cch_perm")).unwrap();
+            assert!(v.diagnostics().is_empty());
+
+            // arc 0 is not FIFO, which only the repair fixes
+            vec![0u32, 2, 3, 4, 5, 6, 7].write_to(&dir.join("first_ipp_of_arc")).unwrap();
+            vec![0u32, 10, 0, 0, 0, 0, 0].write_to(&dir.join("ipp_departure_time")).unwrap();
+            vec![20u32, 5, 2, 3, 4, 5, 6].write_to(&dir.join("ipp_travel_time")).unwrap();
+            let invalid_data = |result: Result<()>| result.unwrap_err().kind() == ErrorKind::InvalidData;
+            assert!(invalid_data(restrict_dir_to_largest_scc(&dir, &output, &[], None).map(|_| ())));
+            assert_eq!(repair_dir(&dir, &output, &[], None).unwrap().fifoized_arcs, 1);
+            // departures of arc 0 are not increasing
+            vec![10u32, 0, 0, 0, 0, 0, 0].write_to(&dir.join("ipp_departure_time")).unwrap();
+            assert!(invalid_data(repair_dir(&dir, &output, &[], None).map(|_| ())));
+
+            std::fs::remove_dir_all(&dir).unwrap();
+            std::fs::remove_dir_all(&output).unwrap();
+        });
+    }
+}
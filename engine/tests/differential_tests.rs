@@ -0,0 +1,518 @@
+// Differential tests of all query servers against Dijkstra on randomly generated graphs.
+// Each graph is generated from its own seed, which is included in all assertion messages, so failures can be reproduced.
+
+extern crate rust_road_router;
+
+use rand::{prelude::*, rngs::StdRng};
+use rust_road_router::{
+    algo::{
+        alt::ALTPotData,
+        catchup::Server as CATCHUpServer,
+        ch_potentials::CCHPotData,
+        contraction_hierarchy::{self, query::Server as CHServer},
+        customizable_contraction_hierarchy::{
+            query::{multi_metric::Server as MultiMetricCCHServer, Server as CCHServer},
+            *,
+        },
+        dijkstra::{
+            query::{bidirectional_dijkstra::Server as BiDijkServer, dijkstra::Server as DijkServer, floating_td_dijkstra::Server as FlTDDijkServer},
+            *,
+        },
+        ev_routing::{ConsumptionModel, Server as EvServer},
+        hl::HubLabels,
+        route_preferences::{LinkAttributes, LinkFlags, RoutePreferences, Server as PreferencesServer},
+        rphast::{RPHASTQuery, RPHAST},
+        *,
+    },
+    datastr::{graph::*, node_order::NodeOrder},
+};
+
+const NUM_GRAPHS: u64 = 20;
+const NUM_QUERIES: usize = 100;
+
+// A grid-like graph with some one-way streets and additionally
+// parallel edges, zero weights, INFINITY weights, a disconnected second component and isolated nodes.
+fn random_graph(rng: &mut StdRng) -> OwnedGraph {
+    let width = rng.gen_range(2..8);
+    let height = rng.gen_range(2..8);
+    let grid_nodes = width * height;
+    let second_component_nodes = rng.gen_range(0..6);
+    let isolated_nodes = rng.gen_range(0..3);
+    let n = grid_nodes + second_component_nodes + isolated_nodes;
+
+    let weight = |rng: &mut StdRng| match rng.gen_range(0..20) {
+        0 => 0,
+        1 => INFINITY,
+        _ => rng.gen_range(1..1000),
+    };
+
+    let mut arcs: Vec<(NodeId, NodeId, Weight)> = Vec::new();
+    let node = |x: u32, y: u32| y * width + x;
+    for y in 0..height {
+        for x in 0..width {
+            for (dx, dy) in [(1, 0), (0, 1)] {
+                if x + dx < width && y + dy < height {
+                    let (tail, head) = (node(x, y), node(x + dx, y + dy));
+                    arcs.push((tail, head, weight(rng)));
+                    if rng.gen_bool(0.8) {
+                        arcs.push((head, tail, weight(rng)));
+                    }
+                }
+            }
+        }
+    }
+    // some shortcuts across the grid
+    for _ in 0..rng.gen_range(0..grid_nodes) {
+        arcs.push((rng.gen_range(0..grid_nodes), rng.gen_range(0..grid_nodes), weight(rng)));
+    }
+    // a path in both directions, not connected to the grid
+    for offset in 1..second_component_nodes {
+        let (tail, head) = (grid_nodes + offset - 1, grid_nodes + offset);
+        arcs.push((tail, head, weight(rng)));
+        arcs.push((head, tail, weight(rng)));
+    }
+    // parallel edges
+    for _ in 0..rng.gen_range(0..=arcs.len() / 4) {
+        let (tail, head, _) = arcs[rng.gen_range(0..arcs.len())];
+        arcs.push((tail, head, weight(rng)));
+    }
+    arcs.retain(|&(tail, head, _)| tail != head);
+
+    arcs.sort_unstable_by_key(|&(tail, head, _)| (tail, head));
+    let mut first_out = vec![0; n as usize + 1];
+    for &(tail, _, _) in &arcs {
+        first_out[tail as usize + 1] += 1;
+    }
+    for node in 0..n as usize {
+        first_out[node + 1] += first_out[node];
+    }
+    OwnedGraph::new(
+        first_out,
+        arcs.iter().map(|&(_, head, _)| head).collect(),
+        arcs.iter().map(|&(_, _, weight)| weight).collect(),
+    )
+}
+
+fn random_order(n: usize, rng: &mut StdRng) -> NodeOrder {
+    let mut order: Vec<NodeId> = (0..n as NodeId).collect();
+    order.shuffle(rng);
+    NodeOrder::from_node_order(order)
+}
+
+fn random_queries(n: usize, rng: &mut StdRng) -> Vec<Query> {
+    (0..NUM_QUERIES)
+        .map(|_| Query {
+            from: rng.gen_range(0..n as NodeId),
+            to: rng.gen_range(0..n as NodeId),
+        })
+        .collect()
+}
+
+// Check that the path is a path in the graph from `from` to `to` with the given length.
+fn check_node_path(graph: &OwnedGraph, query: Query, path: &[NodeId], distance: Weight, seed: u64) {
+    assert_eq!(path.first(), Some(&query.from), "seed {} {:?} {:?}", seed, query, path);
+    assert_eq!(path.last(), Some(&query.to), "seed {} {:?} {:?}", seed, query, path);
+    let length: Weight = path
+        .windows(2)
+        .map(|arc| {
+            graph
+                .edge_indices(arc[0], arc[1])
+                .map(|EdgeIdT(edge_id)| graph.link(edge_id).weight)
+                .min()
+                .unwrap_or_else(|| panic!("seed {} {:?} path contains nonexisting arc {:?}", seed, query, arc))
+        })
+        .sum();
+    assert_eq!(length, distance, "seed {} {:?} {:?}", seed, query, path);
+}
+
+fn check_edge_path(graph: &OwnedGraph, query: Query, path: &[EdgeIdT], distance: Weight, seed: u64) {
+    let mut node = query.from;
+    let mut length = 0;
+    for &EdgeIdT(edge_id) in path {
+        assert!(graph.neighbor_edge_indices(node).contains(&edge_id), "seed {} {:?} {:?}", seed, query, path);
+        let link = graph.link(edge_id);
+        length += link.weight;
+        node = link.node;
+    }
+    assert_eq!(node, query.to, "seed {} {:?} {:?}", seed, query, path);
+    assert_eq!(length, distance, "seed {} {:?} {:?}", seed, query, path);
+}
+
+// Run all queries against a server and compare the distances to the ground truth.
+// Additionally, the node paths of all found routes have to be valid.
+// A macro rather than a function, because bounds on the path servers of all lifetimes would require the servers to be `'static`.
+macro_rules! check_server {
+    ($name:expr, $server:expr, $graph:expr, $queries:expr, $ground_truth:expr, $seed:expr $(,)?) => {{
+        let server = &mut $server;
+        for (&query, &expected) in $queries.iter().zip($ground_truth.iter()) {
+            let mut result = server.query(query);
+            assert_eq!(result.distance(), expected, "{} seed {} {:?}", $name, $seed, query);
+            if let Some(distance) = expected {
+                check_node_path($graph, query, &result.node_path().unwrap(), distance, $seed);
+            }
+        }
+    }};
+}
+
+#[test]
+fn dijkstra_variants_match_dijkstra() {
+    for seed in 0..NUM_GRAPHS {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let graph = random_graph(&mut rng);
+        let queries = random_queries(graph.num_nodes(), &mut rng);
+        let mut dijkstra = DijkServer::<_, DefaultOps>::new(graph.clone());
+        let ground_truth: Vec<_> = queries
+            .iter()
+            .map(|&query| {
+                let mut result = dijkstra.query(query);
+                let distance = result.distance();
+                if let Some(distance) = distance {
+                    check_node_path(&graph, query, &result.node_path().unwrap(), distance, seed);
+                }
+                distance
+            })
+            .collect();
+
+        check_server!("BiDijkstra", BiDijkServer::<_, _, _>::new(graph.clone()), &graph, &queries, &ground_truth, seed);
+
+        let alt = ALTPotData::new_with_avoid(&graph, 4, &mut rng);
+        check_server!(
+            "ALT",
+            DijkServer::<_, DefaultOps, _>::with_potential(graph.clone(), alt.forward_potential()),
+            &graph,
+            &queries,
+            &ground_truth,
+            seed,
+        );
+    }
+}
+
+#[test]
+fn cch_variants_match_dijkstra() {
+    for seed in 0..NUM_GRAPHS {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let graph = random_graph(&mut rng);
+        let queries = random_queries(graph.num_nodes(), &mut rng);
+        let mut dijkstra = DijkServer::<_, DefaultOps>::new(graph.clone());
+        let ground_truth: Vec<_> = queries.iter().map(|&query| dijkstra.query(query).distance()).collect();
+
+        let cch = CCH::fix_order_and_build(&graph, random_order(graph.num_nodes(), &mut rng));
+        check_server!("CCH", CCHServer::new(customize(&cch, &graph)), &graph, &queries, &ground_truth, seed);
+        check_server!(
+            "perfect CCH",
+            CCHServer::new(customize_perfect(customize(&cch, &graph))),
+            &graph,
+            &queries,
+            &ground_truth,
+            seed,
+        );
+
+        let directed_cch = cch.to_directed_cch();
+        check_server!(
+            "directed CCH",
+            CCHServer::new(customize_directed(&directed_cch, &graph)),
+            &graph,
+            &queries,
+            &ground_truth,
+            seed,
+        );
+        check_server!(
+            "perfect directed CCH",
+            CCHServer::new(customize_directed_perfect(customize_directed(&directed_cch, &graph))),
+            &graph,
+            &queries,
+            &ground_truth,
+            seed,
+        );
+
+        let cch_pot_data = CCHPotData::new(&cch, &graph);
+        check_server!(
+            "CCH potentials",
+            DijkServer::<_, DefaultOps, _>::with_potential(graph.clone(), cch_pot_data.forward_potential()),
+            &graph,
+            &queries,
+            &ground_truth,
+            seed,
+        );
+        check_server!(
+            "CH potentials",
+            DijkServer::<_, DefaultOps, _>::with_potential(graph.clone(), cch_pot_data.ch_forward_potential()),
+            &graph,
+            &queries,
+            &ground_truth,
+            seed,
+        );
+    }
+}
+
+#[test]
+fn multi_metric_cch_matches_dijkstra() {
+    for seed in 0..NUM_GRAPHS {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let graph = random_graph(&mut rng);
+        let queries = random_queries(graph.num_nodes(), &mut rng);
+
+        // the original weights, random weights in a different range and hop counts
+        let random_weights: Vec<Weight> = (0..graph.num_arcs()).map(|_| rng.gen_range(0..50)).collect();
+        let hops = vec![1; graph.num_arcs()];
+        let metrics = [graph.weight(), &random_weights[..], &hops[..]];
+        let graphs = metrics.map(|weights| OwnedGraph::new(graph.first_out().to_vec(), graph.head().to_vec(), weights.to_vec()));
+        let ground_truth = graphs.each_ref().map(|graph| {
+            let mut dijkstra = DijkServer::<_, DefaultOps>::new(graph.clone());
+            queries.iter().map(|&query| dijkstra.query(query).distance()).collect::<Vec<_>>()
+        });
+
+        let cch = CCH::fix_order_and_build(&graph, random_order(graph.num_nodes(), &mut rng));
+        let mut server = MultiMetricCCHServer::new(customize_multi_metric(&cch, metrics));
+        for (i, &query) in queries.iter().enumerate() {
+            let distances = server.query(query);
+            for metric in 0..metrics.len() {
+                assert_eq!(
+                    distances[metric], ground_truth[metric][i],
+                    "multi metric CCH, metric {metric}, seed {seed}, query {query:?}"
+                );
+            }
+        }
+
+        for ((customized, graph), ground_truth) in customize_multi_metric(&cch, metrics).into_metrics().into_iter().zip(&graphs).zip(&ground_truth) {
+            check_server!("split multi metric CCH", CCHServer::new(customized), graph, &queries, ground_truth, seed);
+        }
+
+        // five metrics take a full SIMD batch of four and a padded one
+        for (customized, metric) in customize_batched(&cch, &[metrics[0], metrics[1], metrics[2], metrics[1], metrics[0]])
+            .into_iter()
+            .zip([0, 1, 2, 1, 0])
+        {
+            check_server!(
+                "batched multi metric CCH",
+                CCHServer::new(customized),
+                &graphs[metric],
+                &queries,
+                &ground_truth[metric],
+                seed
+            );
+        }
+    }
+}
+
+#[test]
+fn route_preferences_match_dijkstra() {
+    for seed in 0..NUM_GRAPHS {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let graph = random_graph(&mut rng);
+        let m = graph.num_arcs();
+        let queries = random_queries(graph.num_nodes(), &mut rng);
+        let flags: Vec<u8> = (0..m).map(|_| if rng.gen_bool(0.7) { 0 } else { rng.gen_range(1..8) }).collect();
+        let road_classes: Vec<u8> = (0..m).map(|_| rng.gen_range(1..=5)).collect();
+        let attributes = LinkAttributes::new(flags, road_classes);
+
+        let mut penalized = RoutePreferences::avoiding(LinkFlags::FERRY);
+        penalized.set_road_class_factors(vec![1.0, 1.0, 1.5, 2.0, 3.0, 1.2]).unwrap();
+        assert!(penalized.clone().set_road_class_factors(vec![1.0, 0.5]).is_err());
+        assert!(penalized.clone().set_road_class_factors(vec![f64::NAN]).is_err());
+        for _ in 0..m / 4 {
+            penalized.edge_penalties.insert(rng.gen_range(0..m as EdgeId), rng.gen_range(0..500));
+        }
+        let all_preferences = [
+            RoutePreferences::default(),
+            RoutePreferences::avoiding(LinkFlags::TOLL),
+            RoutePreferences::avoiding(LinkFlags::FERRY | LinkFlags::HIGHWAY),
+            penalized,
+        ];
+        let modified_graphs: Vec<OwnedGraph> = all_preferences
+            .iter()
+            .map(|preferences| {
+                OwnedGraph::new(
+                    graph.first_out().to_vec(),
+                    graph.head().to_vec(),
+                    preferences.metric(&attributes, graph.weight()),
+                )
+            })
+            .collect();
+        let ground_truth: Vec<Vec<Option<Weight>>> = modified_graphs
+            .iter()
+            .map(|graph| {
+                let mut dijkstra = DijkServer::<_, DefaultOps>::new(graph.clone());
+                queries.iter().map(|&query| dijkstra.query(query).distance()).collect()
+            })
+            .collect();
+
+        let cch = CCH::fix_order_and_build(&graph, random_order(graph.num_nodes(), &mut rng));
+        let cch_pot_data = CCHPotData::new(&cch, &graph);
+        let mut server = PreferencesServer::new(graph.clone(), attributes, &cch_pot_data);
+
+        // first everything with A*, then with dedicated customizations where possible
+        for dedicated in [false, true] {
+            if dedicated {
+                server.add_dedicated_customization(LinkFlags::NONE);
+                server.add_dedicated_customization(LinkFlags::FERRY | LinkFlags::HIGHWAY);
+            }
+            for ((preferences, modified_graph), ground_truth) in all_preferences.iter().zip(&modified_graphs).zip(&ground_truth) {
+                for (&query, &expected) in queries.iter().zip(ground_truth) {
+                    let result = server.query(query, preferences);
+                    assert_eq!(
+                        result.as_ref().map(|(distance, _)| *distance),
+                        expected,
+                        "preferences {preferences:?}, seed {seed}, {query:?}"
+                    );
+                    if let Some((distance, path)) = result {
+                        check_node_path(modified_graph, query, &path, distance, seed);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn ev_routing_without_battery_limit_matches_dijkstra() {
+    for seed in 0..NUM_GRAPHS {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let graph = random_graph(&mut rng);
+        let queries = random_queries(graph.num_nodes(), &mut rng);
+        let geo_distance: Vec<Weight> = (0..graph.num_arcs()).map(|_| rng.gen_range(0..1000)).collect();
+        let elevation: Vec<f32> = (0..graph.num_nodes()).map(|_| rng.gen_range(0.0..100.0)).collect();
+        let consumption = ConsumptionModel::default().metric(&graph, &geo_distance, &elevation);
+
+        let cch = CCH::fix_order_and_build(&graph, random_order(graph.num_nodes(), &mut rng));
+        let time_pot_data = CCHPotData::new(&cch, &graph);
+        let consumption_pot_data = CCHPotData::new(&cch, &FirstOutGraph::new(graph.first_out(), graph.head(), consumption.reduced()));
+        let capacity = 1_000_000_000;
+        let mut server = EvServer::new(graph.clone(), consumption, capacity, Vec::new(), &time_pot_data, &consumption_pot_data);
+        let mut dijkstra = DijkServer::<_, DefaultOps>::new(graph.clone());
+
+        for &query in &queries {
+            let expected = dijkstra.query(query).distance();
+            let result = server.query(query, capacity);
+            assert_eq!(result.as_ref().map(|route| route.travel_time), expected, "seed {seed}, {query:?}");
+            if let Some(route) = result {
+                check_node_path(&graph, query, &route.path, route.travel_time, seed);
+                assert!(route.soc.iter().all(|&soc| (0..=capacity).contains(&soc)), "seed {seed}, {query:?}");
+            }
+        }
+    }
+}
+
+#[test]
+fn ch_variants_match_dijkstra() {
+    for seed in 0..NUM_GRAPHS {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let graph = random_graph(&mut rng);
+        let queries = random_queries(graph.num_nodes(), &mut rng);
+        let mut dijkstra = DijkServer::<_, DefaultOps>::new(graph.clone());
+        let ground_truth: Vec<_> = queries.iter().map(|&query| dijkstra.query(query).distance()).collect();
+
+        let order = random_order(graph.num_nodes(), &mut rng);
+        let ch = contraction_hierarchy::contract(&graph, order.clone());
+
+        let hub_labels = HubLabels::new(ch.forward(), ch.backward());
+        for (&query, &expected) in queries.iter().zip(&ground_truth) {
+            assert_eq!(
+                hub_labels.dist(order.rank(query.from), order.rank(query.to)),
+                expected,
+                "HL seed {} {:?}",
+                seed,
+                query
+            );
+        }
+
+        let mut rphast = RPHAST::new(ch.forward().clone(), ch.backward().clone(), order.clone());
+        let mut rphast_query = RPHASTQuery::new(&rphast);
+        let targets: Vec<NodeId> = queries.iter().map(|query| query.to).collect();
+        rphast.select(&targets);
+        for (&query, &expected) in queries.iter().zip(&ground_truth) {
+            let distance = rphast_query.query(query.from, &rphast).distance(query.to);
+            assert_eq!(Some(distance).filter(|&dist| dist < INFINITY), expected, "RPHAST seed {} {:?}", seed, query);
+        }
+
+        for ch in [ch, contraction_hierarchy::contract_parallel(&graph, order.clone())] {
+            let mut server = CHServer::new(ch, order.clone());
+            for (&query, &expected) in queries.iter().zip(&ground_truth) {
+                let mut result = server.query(query);
+                assert_eq!(result.distance(), expected, "CH seed {} {:?}", seed, query);
+                if let Some(distance) = expected {
+                    check_node_path(&graph, query, &result.node_path().unwrap(), distance, seed);
+                    check_edge_path(&graph, query, &result.edge_path().unwrap(), distance, seed);
+                }
+            }
+        }
+    }
+}
+
+mod time_dependent {
+    use super::*;
+    use rust_road_router::datastr::graph::floating_time_dependent::*;
+
+    const HOUR: u32 = 3_600_000;
+
+    // Random FIFO travel time functions on the topology of a random static graph.
+    // Breakpoints are at full hours and travel times change by less than an hour between them, so all functions are FIFO.
+    // Parallel edges are dropped, the CATCHUp customization does not support them.
+    fn random_td_graph(rng: &mut StdRng) -> TDGraph {
+        let topology = random_graph(rng);
+        let mut first_out = vec![0];
+        let mut head = Vec::new();
+        let mut first_ipp_of_arc = vec![0];
+        let mut ipp_departure_time = Vec::new();
+        let mut ipp_travel_time = Vec::new();
+
+        for node in 0..topology.num_nodes() as NodeId {
+            let mut heads: Vec<NodeId> = LinkIterable::<NodeIdT>::link_iter(&topology, node).map(|NodeIdT(head)| head).collect();
+            heads.dedup();
+            for h in heads {
+                head.push(h);
+                if rng.gen_bool(0.3) {
+                    ipp_departure_time.push(0);
+                    ipp_travel_time.push(rng.gen_range(1..HOUR / 2));
+                } else {
+                    let mut hours: Vec<u32> = (0..24).collect();
+                    hours.shuffle(rng);
+                    let mut hours = hours[..rng.gen_range(2..6)].to_vec();
+                    hours.sort_unstable();
+                    for hour in hours {
+                        ipp_departure_time.push(hour * HOUR);
+                        ipp_travel_time.push(rng.gen_range(1..HOUR / 2));
+                    }
+                }
+                first_ipp_of_arc.push(ipp_departure_time.len() as u32);
+            }
+            first_out.push(head.len() as EdgeId);
+        }
+
+        TDGraph::new(first_out, head, first_ipp_of_arc, ipp_departure_time, ipp_travel_time)
+    }
+
+    #[test]
+    fn catchup_matches_td_dijkstra() {
+        for seed in 0..NUM_GRAPHS / 2 {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let graph = random_td_graph(&mut rng);
+            let n = graph.num_nodes();
+            let cch = CCH::fix_order_and_build(&graph, random_order(n, &mut rng));
+            let customized = customization::ftd::customize(&cch, &graph);
+            let mut server = CATCHUpServer::new(&cch, &customized);
+            let mut dijkstra = FlTDDijkServer::new(graph.clone());
+
+            for _ in 0..NUM_QUERIES {
+                let query = TDQuery {
+                    from: rng.gen_range(0..n as NodeId),
+                    to: rng.gen_range(0..n as NodeId),
+                    departure: Timestamp::new(rng.gen_range(0.0..f64::from(period()))),
+                };
+                let expected = dijkstra.td_query(query).distance();
+                let mut result = server.td_query(query);
+                match (result.distance(), expected) {
+                    (Some(distance), Some(expected)) => {
+                        assert!(distance.fuzzy_eq(expected), "seed {} {:?} {:?} {:?}", seed, query, distance, expected);
+                        let path = result.node_path().unwrap();
+                        assert_eq!(path.first().unwrap().0, query.from, "seed {} {:?}", seed, query);
+                        assert_eq!(path.last().unwrap().0, query.to, "seed {} {:?}", seed, query);
+                        graph.check_path(&path);
+                    }
+                    (None, None) => (),
+                    (result, expected) => panic!("seed {} {:?} {:?} {:?}", seed, query, result, expected),
+                }
+            }
+        }
+    }
+}
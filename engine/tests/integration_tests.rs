@@ -1,14 +1,35 @@
 extern crate rust_road_router;
 
+use rand::{prelude::*, rngs::StdRng};
 use rust_road_router::{
     algo::{
+        a_star::{Potential, SymmetricBiDirPotential, ZeroPotential},
+        alt::ALTPotData,
+        ch_potentials::{query::MultiThreadedBiDirSkipLowDegServer, CHPotential},
+        contraction_hierarchy::{self, query::Server as CHServer, ContractionHierarchy},
+        customizable_contraction_hierarchy::{customize, customize_perfect, CCH},
         dijkstra::{
-            query::{bidirectional_dijkstra::Server as BiDijkServer, dijkstra::Server as DijkServer},
+            query::{
+                bidirectional_dijkstra::Server as BiDijkServer,
+                dijkstra::Server as DijkServer,
+                td_dijkstra::{ClosureAwareTDDijkstraOps, PessimisticLiveTDDijkstraOps, TDDijkstraOps},
+            },
             *,
         },
+        time_dependent_contraction_hierarchy::{self, query::Server as TCHServer, TDMetric},
+        time_dependent_sampling::{self, Aggregation, Samples, SamplingConfig},
+        topocore::VirtualTopocoreGraph,
         *,
     },
-    datastr::graph::*,
+    datastr::{
+        graph::{time_dependent::*, *},
+        live_traffic::{time_of_day, WallClock},
+        node_order::NodeOrder,
+        road_closures::{Closure, RoadClosures},
+    },
+    io::*,
+    report::Report,
+    util::in_range_option::InRangeOption,
 };
 
 fn graph() -> OwnedGraph {
@@ -64,3 +85,406 @@ fn bidir_dijkstra_stopping_crit() {
 
     assert_eq!(server.query(Query { from: 0, to: 4 }).distance(), Some(12));
 }
+
+#[test]
+fn ch_correct_distances_and_paths() {
+    let graph = graph();
+    let order = NodeOrder::from_node_order(vec![5, 4, 0, 2, 1, 3]);
+
+    for ch in [
+        contraction_hierarchy::contract(&graph, order.clone()),
+        contraction_hierarchy::contract_parallel(&graph, order.clone()),
+    ] {
+        let mut server = CHServer::new(ch, order.clone());
+
+        assert_eq!(server.query(Query { from: 0, to: 1 }).distance(), Some(1));
+        assert_eq!(server.query(Query { from: 3, to: 0 }).distance(), Some(7));
+        assert_eq!(server.query(Query { from: 4, to: 0 }).distance(), None);
+
+        let mut result = server.query(Query { from: 0, to: 4 }).found().unwrap();
+        assert_eq!(result.distance(), 5);
+        assert_eq!(result.node_path(), vec![0, 1, 3, 4]);
+        assert_eq!(result.edge_path(), vec![EdgeIdT(1), EdgeIdT(2), EdgeIdT(7)]);
+    }
+}
+
+#[test]
+fn ch_directories_load_as_routingkit_chs() {
+    let graph = graph();
+    let order = NodeOrder::from_node_order(vec![5, 4, 0, 2, 1, 3]);
+    let ch = contraction_hierarchy::contract(&graph, order.clone());
+
+    let dir = std::env::temp_dir().join(format!("ch_layout_test_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    ch.deconstruct_to(&dir).unwrap();
+    order.order().write_to(&dir.join("order")).unwrap();
+
+    let mut potential = CHPotential::reconstruct_from(&dir).unwrap();
+    let mut server = CHServer::new(ContractionHierarchy::reconstruct_from(&dir).unwrap(), order);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    potential.init(4);
+    assert_eq!(potential.potential(0), Some(5));
+    assert_eq!(potential.potential(3), Some(2));
+    let mut result = server.query(Query { from: 0, to: 4 }).found().unwrap();
+    assert_eq!(result.distance(), 5);
+    assert_eq!(result.node_path(), vec![0, 1, 3, 4]);
+}
+
+// A grid with random weights and coordinates, some arcs are one-way
+fn grid(size: u32, rng: &mut StdRng) -> (OwnedGraph, Vec<f32>, Vec<f32>) {
+    let node = |x: u32, y: u32| y * size + x;
+    let mut adjacency = vec![Vec::new(); (size * size) as usize];
+    let mut add_arc = |tail: NodeId, head: NodeId, rng: &mut StdRng| {
+        let weight = rng.gen_range(1..100);
+        adjacency[tail as usize].push((head, weight));
+        if rng.gen_bool(0.9) {
+            adjacency[head as usize].push((tail, weight));
+        }
+    };
+    for y in 0..size {
+        for x in 0..size {
+            if x + 1 < size {
+                add_arc(node(x, y), node(x + 1, y), rng);
+            }
+            if y + 1 < size {
+                add_arc(node(x, y), node(x, y + 1), rng);
+            }
+        }
+    }
+
+    let mut first_out = vec![0];
+    let mut head = Vec::new();
+    let mut weight = Vec::new();
+    for links in adjacency {
+        for (h, w) in links {
+            head.push(h);
+            weight.push(w);
+        }
+        first_out.push(head.len() as EdgeId);
+    }
+    let latitude = (0..size * size).map(|n| (n / size) as f32 * 0.01).collect();
+    let longitude = (0..size * size).map(|n| (n % size) as f32 * 0.01).collect();
+
+    (OwnedGraph::new(first_out, head, weight), latitude, longitude)
+}
+
+#[test]
+fn alt_landmark_strategies_correct_distances() {
+    let mut rng = StdRng::seed_from_u64(42);
+    let (graph, latitude, longitude) = grid(12, &mut rng);
+    let n = graph.num_nodes() as NodeId;
+    let mut dijkstra_server = DijkServer::<_, DefaultOps>::new(graph.clone());
+
+    let dir = std::env::temp_dir().join(format!("alt_pot_data_test_{}", std::process::id()));
+    let avoid = ALTPotData::new_with_avoid(&graph, 4, &mut rng);
+    avoid.deconstruct_to(&dir).unwrap();
+    let reconstructed = ALTPotData::reconstruct_from(&dir).unwrap();
+    // distances which do not match the landmarks are rejected
+    vec![0 as Weight; 5].write_to(&dir.join("landmark_forward_distances")).unwrap();
+    assert_eq!(ALTPotData::reconstruct_from(&dir).err().unwrap().kind(), std::io::ErrorKind::InvalidData);
+    Vec::<NodeId>::new().write_to(&dir.join("landmarks")).unwrap();
+    assert_eq!(ALTPotData::reconstruct_from(&dir).err().unwrap().kind(), std::io::ErrorKind::InvalidData);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(reconstructed.landmarks(), avoid.landmarks());
+
+    let all_pot_data = [
+        reconstructed,
+        ALTPotData::new_with_max_cover(&graph, 4, &mut rng),
+        ALTPotData::new(&graph, ALTPotData::planar_landmarks(&latitude, &longitude, 4)),
+        ALTPotData::new(&graph, ALTPotData::farthest_landmarks(&graph, 4, 0)),
+    ];
+
+    for pot_data in &all_pot_data {
+        assert_eq!(pot_data.landmarks().len(), 4);
+        let mut server = DijkServer::<_, DefaultOps, _>::with_potential(graph.clone(), pot_data.forward_potential());
+
+        for _ in 0..100 {
+            let query = Query {
+                from: rng.gen_range(0..n),
+                to: rng.gen_range(0..n),
+            };
+            assert_eq!(server.query(query).distance(), dijkstra_server.query(query).distance(), "{:?}", query);
+        }
+    }
+
+    // restricting the potential to a subset of landmarks yields worse bounds but still exact distances
+    for _ in 0..100 {
+        let query = Query {
+            from: rng.gen_range(0..n),
+            to: rng.gen_range(0..n),
+        };
+        let mut potential = all_pot_data[1].forward_potential();
+        potential.activate_best_landmarks(query.from, query.to, 2);
+        assert_eq!(potential.active_landmarks().len(), 2);
+        let mut server = DijkServer::<_, DefaultOps, _>::with_potential(graph.clone(), potential);
+        assert_eq!(server.query(query).distance(), dijkstra_server.query(query).distance(), "{:?}", query);
+    }
+}
+
+#[test]
+fn parallel_bidir_queries_report_both_directions() {
+    let mut rng = StdRng::seed_from_u64(42);
+    let (graph, _, _) = grid(12, &mut rng);
+    let n = graph.num_nodes() as NodeId;
+    let mut dijkstra_server = DijkServer::<_, DefaultOps>::new(graph.clone());
+
+    let (main_graph, _, topocore) = VirtualTopocoreGraph::<OwnedGraph>::new_topo_dijkstra_graphs(&graph);
+    let mut server = MultiThreadedBiDirSkipLowDegServer::new(main_graph, SymmetricBiDirPotential::<_, _>::new(ZeroPotential(), ZeroPotential()));
+
+    for _ in 0..50 {
+        let query = Query {
+            from: rng.gen_range(0..n),
+            to: rng.gen_range(0..n),
+        };
+        let report = Report::new();
+        let distance = {
+            // the searches run on the pool of the server, their stats have to end up in the report of this thread
+            let _recorder = report.recorder().attach();
+            server.distance(
+                Query {
+                    from: topocore.order.rank(query.from),
+                    to: topocore.order.rank(query.to),
+                },
+                INFINITY,
+                None,
+            )
+        };
+        assert_eq!(distance, dijkstra_server.query(query).distance(), "{:?}", query);
+
+        let report = report.to_json();
+        for key in ["num_queue_pops", "num_queue_pushs"] {
+            let forward = report["forward"][key].as_u64().unwrap();
+            let backward = report["backward"][key].as_u64().unwrap();
+            assert_eq!(report[key].as_u64().unwrap(), forward + backward, "{} {:?}", key, query);
+        }
+        assert!(report["forward"]["num_relaxed_arcs"].is_u64());
+        assert!(report["backward"]["num_relaxed_arcs"].is_u64());
+    }
+}
+
+#[test]
+fn parallel_customization_reports_all_subgraphs() {
+    let mut rng = StdRng::seed_from_u64(42);
+    let (graph, _, _) = grid(20, &mut rng);
+    let n = graph.num_nodes();
+    let mut order: Vec<NodeId> = (0..n as NodeId).collect();
+    order.shuffle(&mut rng);
+    let cch = CCH::fix_order_and_build(&graph, NodeOrder::from_node_order(order));
+
+    let report = Report::new();
+    {
+        let _recorder = report.recorder().attach();
+        customize_perfect(customize(&cch, &graph));
+    }
+    let report = report.to_json();
+
+    // every node is customized in exactly one cell or separator, items are ordered by their first node
+    for key in ["basic_customization_subgraphs", "perfect_customization_subgraphs"] {
+        let mut covered = vec![false; n];
+        let mut prev_first_node = None;
+        for item in report[key].as_array().unwrap() {
+            assert!(["cell", "separator"].contains(&item["kind"].as_str().unwrap()));
+            assert!(item["running_time_ms"].is_number());
+            let first_node = item["first_node"].as_u64().unwrap() as usize;
+            assert!(prev_first_node < Some(first_node));
+            prev_first_node = Some(first_node);
+            for node in first_node..first_node + item["num_nodes"].as_u64().unwrap() as usize {
+                assert!(!covered[node]);
+                covered[node] = true;
+            }
+        }
+        assert!(covered.iter().all(|&covered| covered), "{}", key);
+    }
+
+    let num_triangles: u64 = report["perfect_customization_subgraphs"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["num_triangles"].as_u64().unwrap())
+        .sum();
+    assert_eq!(report["num_triangles"].as_u64().unwrap(), num_triangles);
+}
+
+// A bidirected grid with random FIFO travel time functions
+fn td_grid(size: u32, rng: &mut StdRng) -> TDGraph {
+    let mut first_out = vec![0];
+    let mut head = Vec::new();
+    for row in 0..size {
+        for col in 0..size {
+            let node = row * size + col;
+            if col > 0 {
+                head.push(node - 1);
+            }
+            if col + 1 < size {
+                head.push(node + 1);
+            }
+            if row > 0 {
+                head.push(node - size);
+            }
+            if row + 1 < size {
+                head.push(node + size);
+            }
+            first_out.push(head.len() as EdgeId);
+        }
+    }
+
+    let mut first_ipp_of_arc = vec![0];
+    let mut ipp_departure_time = Vec::new();
+    let mut ipp_travel_time = Vec::new();
+    for _ in 0..head.len() {
+        // breakpoints are at least an hour apart and travel times vary by at most half an hour, so the functions are FIFO
+        let num_ipps = rng.gen_range(1..6);
+        for i in 0..num_ipps {
+            ipp_departure_time.push(i * 3_600_000 * 4 + rng.gen_range(0..3_600_000));
+            ipp_travel_time.push(rng.gen_range(60_000..1_800_000));
+        }
+        first_ipp_of_arc.push(ipp_departure_time.len() as u32);
+    }
+
+    TDGraph::new(first_out, head, first_ipp_of_arc, ipp_departure_time, ipp_travel_time)
+}
+
+#[test]
+fn tch_matches_td_dijkstra() {
+    let mut rng = StdRng::seed_from_u64(42);
+    let graph = td_grid(6, &mut rng);
+    let n = graph.num_nodes();
+    let mut order: Vec<NodeId> = (0..n as NodeId).collect();
+    order.shuffle(&mut rng);
+    let order = NodeOrder::from_node_order(order);
+
+    let tch = time_dependent_contraction_hierarchy::contract(&graph, order);
+    let mut tch_server = TCHServer::new(&tch, &graph);
+    let mut dijkstra_server = DijkServer::<_, TDDijkstraOps>::new(graph.clone());
+
+    for _ in 0..200 {
+        let from = rng.gen_range(0..n as NodeId);
+        let to = rng.gen_range(0..n as NodeId);
+        let departure = rng.gen_range(0..period());
+        let query = TDQuery { from, to, departure };
+
+        // plain Dijkstra yields the arrival time
+        let expected = dijkstra_server.td_query(query).distance().map(|arrival| arrival - departure);
+        let mut result = tch_server.td_query(query);
+        assert_eq!(result.distance(), expected, "{:?}", query);
+
+        if let Some(distance) = expected {
+            // the unpacked path has to have exactly the calculated travel time
+            let mut t = departure;
+            for EdgeIdT(edge_id) in result.edge_path().unwrap() {
+                t += TDMetric::eval(&graph, edge_id, t);
+            }
+            assert_eq!(t - departure, distance);
+            let node_path = result.node_path().unwrap();
+            assert_eq!(node_path.first().unwrap().0, from);
+            assert_eq!(node_path.last().unwrap(), &(to, departure + distance));
+        }
+    }
+}
+
+#[test]
+fn tds_paths_are_valid_and_persistable() {
+    let mut rng = StdRng::seed_from_u64(42);
+    let graph = td_grid(6, &mut rng);
+    let n = graph.num_nodes();
+    let mut order: Vec<NodeId> = (0..n as NodeId).collect();
+    order.shuffle(&mut rng);
+    let cch = CCH::fix_order_and_build(&graph, NodeOrder::from_node_order(order));
+
+    let config = SamplingConfig {
+        num_samples: Some(3),
+        ..SamplingConfig::uniform(
+            6,
+            Aggregation::Percentile {
+                percentile: 80,
+                num_points: 10,
+            },
+        )
+        .unwrap()
+    };
+    let samples = Samples::customize(&graph, &cch, &config);
+    assert_eq!(samples.customized().len(), 3);
+    assert_eq!(samples.windows().iter().map(Vec::len).sum::<usize>(), 6);
+
+    let dir = std::env::temp_dir().join(format!("tds_samples_test_{}", std::process::id()));
+    samples.deconstruct_to(&dir).unwrap();
+    let reconstructed: Samples = (&cch).reconstruct_from(&dir).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(reconstructed.windows(), samples.windows());
+
+    let mut server = time_dependent_sampling::Server::with_samples(graph.clone(), samples);
+    let mut reconstructed_server = time_dependent_sampling::Server::with_samples(graph.clone(), reconstructed);
+    let mut dijkstra_server = DijkServer::<_, TDDijkstraOps>::new(graph.clone());
+
+    for _ in 0..100 {
+        let from = rng.gen_range(0..n as NodeId);
+        let to = rng.gen_range(0..n as NodeId);
+        let departure = rng.gen_range(0..period());
+        let query = TDQuery { from, to, departure };
+
+        // TD-S is a heuristic, but its travel times belong to actual paths and the grid is strongly connected
+        let optimal = dijkstra_server.td_query(query).distance().unwrap() - departure;
+        let distance = server.td_query(query).distance().unwrap();
+        assert!(distance >= optimal, "{:?}", query);
+        assert_eq!(reconstructed_server.td_query(query).distance(), Some(distance), "{:?}", query);
+    }
+}
+
+#[test]
+fn road_closures_match_pessimistic_live_graph() {
+    let mut rng = StdRng::seed_from_u64(42);
+    let graph = td_grid(6, &mut rng);
+    let n = graph.num_nodes();
+    // some closures last past midnight
+    let now: WallClock = 3 * 86_400_000 + 20 * 3_600_000;
+    let t_now = time_of_day(now);
+
+    let mut closures = RoadClosures::new();
+    for _ in 0..graph.num_arcs() / 4 {
+        let edge = rng.gen_range(0..graph.num_arcs() as EdgeId);
+        let start = rng.gen_range(now - 3_600_000..=now);
+        let end = rng.gen_range(now + 1..now + 8 * 3_600_000);
+        closures.add(Closure {
+            edge,
+            start,
+            end,
+            reason: String::new(),
+        });
+    }
+
+    // all closures are already active, so they are fully covered by the live data at `now`
+    let mut live = vec![InRangeOption::NONE; graph.num_arcs()];
+    closures.apply_to_pessimistic_live(&mut live, now);
+    let live_graph = PessimisticLiveTDGraph::new(graph.clone(), live);
+
+    let mut data = DijkstraData::new(n);
+    let mut live_data = DijkstraData::new(n);
+    let mut unclosed_data = DijkstraData::new(n);
+    for _ in 0..100 {
+        let from = rng.gen_range(0..n as NodeId);
+        let mut ops = ClosureAwareTDDijkstraOps(&closures, now - WallClock::from(t_now));
+        let mut live_ops = PessimisticLiveTDDijkstraOps::default();
+        let mut unclosed_ops = TDDijkstraOps::default();
+        let init = || DijkstraInit {
+            source: NodeIdT(from),
+            initial_state: t_now,
+        };
+        let mut dijkstra = DijkstraRun::query(&graph, &mut data, &mut ops, init());
+        while dijkstra.next().is_some() {}
+        let mut live_dijkstra = DijkstraRun::query(&live_graph, &mut live_data, &mut live_ops, init());
+        while live_dijkstra.next().is_some() {}
+        let mut unclosed_dijkstra = DijkstraRun::query(&graph, &mut unclosed_data, &mut unclosed_ops, init());
+        while unclosed_dijkstra.next().is_some() {}
+
+        for node in 0..n as NodeId {
+            assert_eq!(dijkstra.tentative_distance(node), live_dijkstra.tentative_distance(node), "{} {}", from, node);
+            assert!(
+                dijkstra.tentative_distance(node) >= unclosed_dijkstra.tentative_distance(node),
+                "{} {}",
+                from,
+                node
+            );
+        }
+    }
+}
@@ -0,0 +1,566 @@
+// Versioned JSON schema for route queries and the profile configuration.
+//
+// A request has a list of waypoints, each a coordinate, a position on an edge or a node id.
+// Intermediate waypoints are stops with an optional dwell time, or pass-through points which only shape the route.
+// Stops split the route into legs, and their order can be optimized.
+// Queries are answered by the `Router` of the requested profile, so every algorithm with a `Router` can be offered through the config.
+// All times are milliseconds since midnight.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use rust_road_router::{
+    algo::{path_statistics::PathStatistics, route_preferences::LinkFlags, router::*, waypoints::optimize_order, Query},
+    datastr::{
+        graph::{link_id_to_tail_mapper::link_id_to_tail, time_dependent::Timestamp, *},
+        link_geometry::*,
+        road_attributes::RoadAttributes,
+    },
+};
+
+pub const VERSION: u32 = 1;
+
+// Ordering takes a query for each pair of waypoints.
+pub const MAX_OPTIMIZED_WAYPOINTS: usize = 50;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteRequest {
+    // has to be `VERSION`
+    pub version: u32,
+    // echoed in the response, so batch results can be matched to their requests
+    #[serde(default)]
+    pub id: Option<String>,
+    // at least two
+    pub waypoints: Vec<Waypoint>,
+    // defaults to now, at most one of departure and arrival may be given
+    #[serde(default)]
+    pub departure: Option<Timestamp>,
+    // only for profiles which are not time-dependent
+    #[serde(default)]
+    pub arrival: Option<Timestamp>,
+    // defaults to the live CCH profile
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub avoid: Vec<Avoid>,
+    // visit the intermediate waypoints in the order with the lowest travel time, first and last stay fixed
+    #[serde(default)]
+    pub optimize_order: bool,
+    #[serde(default)]
+    pub outputs: Outputs,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Waypoint {
+    #[serde(flatten)]
+    pub position: Position,
+    // only for intermediate waypoints, the first and last one are always stops
+    #[serde(default)]
+    pub pass_through: bool,
+    // waiting time at intermediate stops
+    #[serde(default)]
+    pub dwell: Weight,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(untagged)]
+pub enum Position {
+    // matched to the closest node
+    Coordinate {
+        lat: f32,
+        lng: f32,
+    },
+    // a position on an edge, `fraction` of its length from its tail
+    Edge {
+        edge: EdgeId,
+        #[serde(default)]
+        fraction: f32,
+    },
+    Node {
+        node: NodeId,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Avoid {
+    Toll,
+    Ferry,
+    Highway,
+}
+
+impl From<Avoid> for LinkFlags {
+    fn from(avoid: Avoid) -> Self {
+        match avoid {
+            Avoid::Toll => LinkFlags::TOLL,
+            Avoid::Ferry => LinkFlags::FERRY,
+            Avoid::Highway => LinkFlags::HIGHWAY,
+        }
+    }
+}
+
+// What to include in the response besides the times.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Outputs {
+    pub path: bool,
+    pub polyline: bool,
+    pub node_path: bool,
+    pub edge_path: bool,
+    pub attributes: bool,
+}
+
+impl Default for Outputs {
+    fn default() -> Self {
+        Self {
+            path: true,
+            polyline: false,
+            node_path: false,
+            edge_path: false,
+            attributes: false,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RouteResponse {
+    pub version: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub route: Option<Box<RouteBody>>,
+    // of the queries for the route, where the router keeps track of them, only for monitoring
+    #[serde(skip)]
+    pub search_spaces: Vec<SearchSpace>,
+}
+
+impl RouteResponse {
+    fn invalid(id: Option<String>, error: String) -> Self {
+        Self {
+            version: VERSION,
+            id,
+            status: Status::InvalidRequest,
+            error: Some(error),
+            route: None,
+            search_spaces: Vec::new(),
+        }
+    }
+
+    fn no_route(id: Option<String>) -> Self {
+        Self {
+            version: VERSION,
+            id,
+            status: Status::NoRoute,
+            error: None,
+            route: None,
+            search_spaces: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Ok,
+    NoRoute,
+    InvalidRequest,
+}
+
+impl Status {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::NoRoute => "no_route",
+            Status::InvalidRequest => "invalid_request",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RouteBody {
+    pub profile: String,
+    pub departure: Timestamp,
+    pub arrival: Timestamp,
+    // without dwell times
+    pub travel_time: Weight,
+    pub dwell_time: Weight,
+    // waypoint indices in the order they are visited, only with `optimize_order`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub waypoint_order: Option<Vec<usize>>,
+    // one leg between each pair of consecutive stops
+    pub legs: Vec<LegBody>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<Vec<(f32, f32)>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub polyline: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_path: Option<Vec<NodeId>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edge_path: Option<Vec<EdgeId>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<RouteAttributes>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LegBody {
+    // waypoint indices
+    pub from: usize,
+    pub to: usize,
+    pub departure: Timestamp,
+    pub arrival: Timestamp,
+}
+
+// Summary of the road attributes along a route, lengths in m.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RouteAttributes {
+    pub length: Weight,
+    pub toll_length: Weight,
+    pub motorway_share: f64,
+    pub street_names: Vec<String>,
+}
+
+impl RouteAttributes {
+    pub fn summarize(road_attributes: &RoadAttributes, edge_path: &[EdgeId]) -> Self {
+        let summary = road_attributes.summarize(edge_path);
+        Self {
+            length: summary.length,
+            toll_length: summary.toll_length,
+            motorway_share: summary.motorway_share(),
+            street_names: summary.street_names,
+        }
+    }
+}
+
+// Additional profiles, given as a JSON file on startup.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerConfig {
+    #[serde(default)]
+    pub profiles: Vec<ProfileConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProfileConfig {
+    pub name: String,
+    pub algorithm: Algorithm,
+    // weight file in the graph directory for static algorithms, defaults to `travel_time`
+    #[serde(default)]
+    pub metric: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Algorithm {
+    Cch,
+    Dijkstra,
+    // CCH potentials with support for avoid options
+    Preferences,
+    // the time-dependent algorithms use the travel time functions from the graph directory
+    TdDijkstra,
+    Tch,
+}
+
+impl Algorithm {
+    pub fn is_time_dependent(self) -> bool {
+        matches!(self, Algorithm::TdDijkstra | Algorithm::Tch)
+    }
+}
+
+pub const DEFAULT_PROFILE: &str = "default";
+
+// Everything needed to resolve waypoints and build responses.
+pub struct Context<'a, G> {
+    // topology for positions on edges and paths, travel times come from the router of each profile
+    pub graph: &'a G,
+    pub first_out: &'a [EdgeId],
+    pub lat: &'a [f32],
+    pub lng: &'a [f32],
+    pub closest_node: &'a dyn Fn((f32, f32)) -> NodeId,
+    pub link_geometry: &'a LinkGeometry,
+    pub road_attributes: Option<&'a RoadAttributes>,
+    pub now: Timestamp,
+}
+
+// Parse a single request of a batch, so malformed requests get their own error response.
+pub fn parse_request(value: Value) -> Result<RouteRequest, RouteResponse> {
+    let id = value.get("id").and_then(Value::as_str).map(str::to_string);
+    serde_json::from_value(value).map_err(|error| RouteResponse::invalid(id, error.to_string()))
+}
+
+// The live CCH server, shared with the asynchronous customization.
+pub struct SharedServer<'a, S>(pub &'a Arc<Mutex<S>>);
+
+impl<'a, S: NodePathServer> NodePathServer for SharedServer<'a, S> {
+    fn node_path_query(&mut self, query: Query) -> Option<Vec<NodeId>> {
+        self.0.lock().unwrap().node_path_query(query)
+    }
+
+    fn search_space(&self) -> Option<SearchSpace> {
+        self.0.lock().unwrap().search_space()
+    }
+}
+
+// Answer a request with the router of its profile, `live` for the default profile.
+pub fn dispatch<'p, G>(
+    request: Result<RouteRequest, RouteResponse>,
+    live: &mut dyn Router,
+    profiles: &mut HashMap<String, Box<dyn Router + 'p>>,
+    ctx: &Context<G>,
+) -> RouteResponse
+where
+    G: EdgeRandomAccessGraph<Link> + LinkIterable<(NodeIdT, EdgeIdT)>,
+{
+    let request = match request {
+        Ok(request) => request,
+        Err(response) => return response,
+    };
+    let profile = request.profile.clone().unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+    if profile == DEFAULT_PROFILE {
+        return answer(request, &profile, live, ctx);
+    }
+    match profiles.get_mut(&profile) {
+        Some(router) => answer(request, &profile, router.as_mut(), ctx),
+        None => RouteResponse::invalid(request.id, format!("unknown profile {}", profile)),
+    }
+}
+
+enum Location {
+    Node(NodeId),
+    Edge { edge: EdgeId, tail: NodeId, head: NodeId, fraction: f32 },
+}
+
+pub fn answer<G>(request: RouteRequest, profile: &str, router: &mut dyn Router, ctx: &Context<G>) -> RouteResponse
+where
+    G: EdgeRandomAccessGraph<Link> + LinkIterable<(NodeIdT, EdgeIdT)>,
+{
+    let RouteRequest {
+        version,
+        id,
+        waypoints,
+        departure,
+        arrival,
+        avoid,
+        optimize_order: optimize,
+        outputs,
+        ..
+    } = request;
+    if version != VERSION {
+        return RouteResponse::invalid(id, format!("unsupported version {}, expected {}", version, VERSION));
+    }
+    if waypoints.len() < 2 {
+        return RouteResponse::invalid(id, "at least two waypoints are required".to_string());
+    }
+    if optimize && waypoints.len() > MAX_OPTIMIZED_WAYPOINTS {
+        return RouteResponse::invalid(id, format!("at most {} waypoints can be reordered", MAX_OPTIMIZED_WAYPOINTS));
+    }
+    if waypoints.iter().any(|waypoint| waypoint.pass_through && waypoint.dwell > 0) {
+        return RouteResponse::invalid(id, "pass-through waypoints cannot have a dwell time".to_string());
+    }
+    if departure.is_some() && arrival.is_some() {
+        return RouteResponse::invalid(id, "only one of departure and arrival may be given".to_string());
+    }
+    if arrival.is_some() && router.is_time_dependent() {
+        return RouteResponse::invalid(id, format!("profile {} does not support arrival times", profile));
+    }
+    let avoid = avoid.into_iter().fold(LinkFlags::NONE, |flags, avoid| flags | avoid.into());
+    if !router.set_avoid(avoid) {
+        return RouteResponse::invalid(id, format!("profile {} does not support avoid options", profile));
+    }
+    let locations = match waypoints.iter().map(|waypoint| locate(waypoint.position, ctx)).collect::<Result<Vec<_>, _>>() {
+        Ok(locations) => locations,
+        Err(error) => return RouteResponse::invalid(id, error),
+    };
+
+    // arrival queries are static, so we can just shift the route
+    let start = departure.unwrap_or(if arrival.is_some() { 0 } else { ctx.now });
+    let order: Vec<usize> = if optimize {
+        // positions on edges are entered at the tail and left at the head, the edge itself is driven in any order
+        let exits: Vec<NodeId> = locations.iter().map(Location::exit).collect();
+        let entries: Vec<NodeId> = locations.iter().map(Location::entry).collect();
+        optimize_order(&router.distance_table(&exits, &entries, start))
+    } else {
+        (0..waypoints.len()).collect()
+    };
+    let Traversal {
+        from,
+        mut statistics,
+        mut legs,
+        arrival: end,
+        search_spaces,
+    } = match route_through(&locations, &waypoints, &order, start, router) {
+        Some(traversal) => traversal,
+        None => return RouteResponse::no_route(id),
+    };
+    let travel_time = statistics.total_weight() as Weight;
+    let duration = end - start;
+    let departure = match arrival {
+        Some(arrival) if arrival < duration => return RouteResponse::invalid(id, "arrival too early, the departure would be on the previous day".to_string()),
+        Some(arrival) => {
+            let departure = arrival - duration;
+            for t in &mut statistics.entry_times {
+                *t = *t - start + departure;
+            }
+            for leg in &mut legs {
+                leg.departure = leg.departure - start + departure;
+                leg.arrival = leg.arrival - start + departure;
+            }
+            departure
+        }
+        None => start,
+    };
+
+    let edge_path = &statistics.edges;
+    let path = if outputs.path || outputs.polyline {
+        Some(ctx.link_geometry.polyline(ctx.graph, from, edge_path, ctx.lat, ctx.lng))
+    } else {
+        None
+    };
+    let node_path = if outputs.node_path {
+        Some(std::iter::once(from).chain(edge_path.iter().map(|&edge| ctx.graph.link(edge).node)).collect())
+    } else {
+        None
+    };
+    RouteResponse {
+        version: VERSION,
+        id,
+        status: Status::Ok,
+        error: None,
+        route: Some(Box::new(RouteBody {
+            profile: profile.to_string(),
+            departure,
+            arrival: departure + duration,
+            travel_time,
+            dwell_time: duration - travel_time,
+            waypoint_order: if optimize { Some(order) } else { None },
+            legs,
+            polyline: if outputs.polyline { path.as_deref().map(encode_polyline) } else { None },
+            path: if outputs.path { path } else { None },
+            node_path,
+            edge_path: if outputs.edge_path { Some(edge_path.clone()) } else { None },
+            attributes: if outputs.attributes {
+                ctx.road_attributes
+                    .map(|road_attributes| RouteAttributes::summarize(road_attributes, edge_path))
+            } else {
+                None
+            },
+        })),
+        search_spaces,
+    }
+}
+
+impl Location {
+    fn entry(&self) -> NodeId {
+        match *self {
+            Location::Node(node) => node,
+            Location::Edge { tail, .. } => tail,
+        }
+    }
+
+    fn exit(&self) -> NodeId {
+        match *self {
+            Location::Node(node) => node,
+            Location::Edge { head, .. } => head,
+        }
+    }
+}
+
+fn locate<G: EdgeRandomAccessGraph<Link>>(position: Position, ctx: &Context<G>) -> Result<Location, String> {
+    match position {
+        Position::Coordinate { lat, lng } if lat.is_finite() && lng.is_finite() => Ok(Location::Node((ctx.closest_node)((lat, lng)))),
+        Position::Coordinate { .. } => Err("invalid coordinate".to_string()),
+        Position::Node { node } if (node as usize) < ctx.graph.num_nodes() => Ok(Location::Node(node)),
+        Position::Node { node } => Err(format!("no node {}", node)),
+        Position::Edge { edge, fraction } if (edge as usize) < ctx.graph.num_arcs() && (0.0..=1.0).contains(&fraction) => Ok(Location::Edge {
+            edge,
+            tail: link_id_to_tail(ctx.first_out, edge),
+            head: ctx.graph.link(edge).node,
+            fraction,
+        }),
+        Position::Edge { edge, fraction } => Err(format!("invalid position {} on edge {}", fraction, edge)),
+    }
+}
+
+struct Traversal {
+    // first node of the path
+    from: NodeId,
+    // edges with their entry times, entry times include dwell times
+    statistics: PathStatistics,
+    legs: Vec<LegBody>,
+    arrival: Timestamp,
+    search_spaces: Vec<SearchSpace>,
+}
+
+// Route through the locations in `order`, positions on edges force the route over the edge.
+// The first and last edge are only partially driven, but completely contained in the path.
+// At intermediate stops on an edge, the leg ends at the position on the edge.
+// Edge travel times are taken from the router when the edge is entered, so they match the profile.
+fn route_through(locations: &[Location], waypoints: &[Waypoint], order: &[usize], departure: Timestamp, router: &mut dyn Router) -> Option<Traversal> {
+    let mut statistics = PathStatistics::new(0);
+    let (from, mut node, mut t) = match locations[order[0]] {
+        Location::Node(node) => (node, node, departure),
+        Location::Edge { edge, tail, head, fraction } => {
+            let remaining = ((1.0 - fraction) * router.edge_weight(edge, departure) as f32) as Weight;
+            statistics.edges.push(edge);
+            statistics.entry_times.push(departure);
+            statistics.weights.push(remaining);
+            (tail, head, departure + remaining)
+        }
+    };
+    let mut legs = Vec::new();
+    let mut search_spaces = Vec::new();
+    let (mut leg_from, mut leg_departure) = (order[0], departure);
+
+    for (pos, &idx) in order.iter().enumerate().skip(1) {
+        let is_last = pos + 1 == order.len();
+        let is_stop = is_last || !waypoints[idx].pass_through;
+        let dwell = if is_last { 0 } else { waypoints[idx].dwell };
+        let target = locations[idx].entry();
+        let mut leg = router.route(node, target, t)?;
+        t = leg.arrival();
+        statistics.append(&mut leg.statistics);
+        search_spaces.extend(leg.search_space);
+        node = target;
+
+        // time at which the waypoint is reached
+        let mut reached = t;
+        if let Location::Edge { edge, head, fraction, .. } = locations[idx] {
+            let weight = router.edge_weight(edge, t);
+            let to_position = (fraction * weight as f32) as Weight;
+            let driven = if is_last { to_position } else { weight };
+            statistics.edges.push(edge);
+            statistics.entry_times.push(t);
+            statistics.weights.push(driven);
+            reached = t + to_position;
+            t += driven;
+            node = head;
+        }
+        if is_stop {
+            legs.push(LegBody {
+                from: leg_from,
+                to: idx,
+                departure: leg_departure,
+                arrival: reached,
+            });
+            leg_from = idx;
+            leg_departure = reached + dwell;
+            t += dwell;
+        }
+    }
+
+    Some(Traversal {
+        from,
+        statistics,
+        legs,
+        arrival: t,
+        search_spaces,
+    })
+}
@@ -7,34 +7,72 @@ extern crate rocket;
 use serde::{Deserialize, Serialize};
 
 use std::{
+    collections::HashMap,
     env,
     error::Error,
+    fs::File,
+    io::{self, Read},
     iter::once,
     path::{Path, PathBuf},
-    sync::mpsc::{self, Sender},
+    rc::Rc,
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender},
     sync::{Arc, Mutex},
     thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
-use rocket::{request::Form, response::NamedFile, State};
+use rocket::{
+    http::ContentType,
+    request::Form,
+    response::{content::Content, NamedFile, Stream},
+    State,
+};
 use rocket_contrib::json::Json;
+use serde_json::Value;
 
 use kdtree::kdtree::{Kdtree, KdtreePointTrait};
 
+mod api;
+use api::*;
+mod monitoring;
+use monitoring::*;
+
 use conversion::here::link_id_mapper::*;
+use rust_road_router::log_event;
 use rust_road_router::{
     algo::{
-        customizable_contraction_hierarchy::{customize as cch_customize, query::Server, CCH},
+        ch_potentials::CCHPotData,
+        customizable_contraction_hierarchy::{
+            customize as cch_customize, customize_perfect,
+            query::{nearest_neighbor::BCCHNearestNeighbor, Server},
+            CCH,
+        },
+        dijkstra::{
+            query::{dijkstra::Server as DijkServer, td_dijkstra::PessimisticLiveTDDijkstraOps},
+            DefaultOps,
+        },
+        route_preferences::{LinkAttributes, RoutePreferences, Server as PreferencesServer},
+        router::*,
+        time_dependent_contraction_hierarchy::{self, query::Server as TCHServer},
         *,
     },
     cli::CliErr,
     datastr::{
-        graph::{link_id_to_tail_mapper::*, *},
+        graph::{
+            link_id_to_tail_mapper::*,
+            time_dependent::{PessimisticLiveTDGraph, TDGraph},
+            *,
+        },
+        link_geometry::*,
+        live_traffic::{time_of_day, WallClock},
         node_order::NodeOrder,
         rank_select_map::*,
+        road_attributes::RoadAttributes,
+        road_closures::*,
     },
     io::*,
-    report::report_time,
+    logging::{self, log_time, Level},
+    util::in_range_option::InRangeOption,
 };
 
 #[derive(PartialEq, Clone, Copy)]
@@ -56,12 +94,18 @@ struct GeoQuery {
     from_lng: f32,
     to_lat: f32,
     to_lng: f32,
+    // additionally return the path as an encoded Google polyline
+    polyline: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize)]
 struct GeoResponse {
     distance: Weight,
     path: Vec<(f32, f32)>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    polyline: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attributes: Option<RouteAttributes>,
 }
 
 #[derive(Debug, FromForm, Copy, Clone)]
@@ -80,10 +124,43 @@ struct HereResponse {
     path: Vec<(u64, bool)>,
 }
 
+// A closure either references a local edge id or a HERE link id with direction.
+// Start and end are milliseconds since the unix epoch, so closures may span midnight or several days.
+#[derive(Debug, Deserialize)]
+struct ClosureRequest {
+    edge: Option<EdgeId>,
+    here_link_id: Option<u64>,
+    #[serde(default)]
+    from_ref: bool,
+    start: WallClock,
+    end: WallClock,
+    #[serde(default)]
+    reason: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ClosureResponse {
+    id: ClosureId,
+    edge: EdgeId,
+    start: WallClock,
+    end: WallClock,
+    reason: String,
+}
+
 enum Request {
     Geo((GeoQuery, Sender<Option<GeoResponse>>)),
     Here((HereQuery, Sender<Option<HereResponse>>)),
     Customize(Vec<(u64, bool, SerializedWeight)>),
+    // a new version of `live_travel_time`, replaces all earlier updates
+    LiveTravelTime(Vec<Weight>),
+    AddClosure((ClosureRequest, Sender<Option<ClosureId>>)),
+    ListClosures(Sender<Vec<ClosureResponse>>),
+    RemoveClosure((ClosureId, Sender<bool>)),
+    // requests of the JSON API, malformed ones are already answered
+    // batches are answered `BATCH_CHUNK_SIZE` requests at a time, see the engine loop
+    Routes((Vec<Result<RouteRequest, RouteResponse>>, Sender<RouteResponse>)),
+    // never sent through the queue, the engine switches to it when a closure starts or ends
+    ClosuresChanged,
 }
 
 #[get("/")]
@@ -97,42 +174,103 @@ fn files(file: PathBuf) -> Option<NamedFile> {
 }
 
 #[get("/query?<query_params..>", format = "application/json")]
-fn query(query_params: Form<GeoQuery>, state: State<Mutex<Sender<Request>>>) -> Json<Option<GeoResponse>> {
-    let result = report_time("Total Query Request Time", || {
-        println!("Received Query: {:?}", query_params);
+fn query(query_params: Form<GeoQuery>, queue: State<RequestQueue<Request>>, metrics: State<Arc<ServerMetrics>>) -> Json<Option<GeoResponse>> {
+    let result = metrics.query_seconds.time(|| {
+        log_event!(Level::Debug, "received query", { "query": format!("{:?}", *query_params) });
 
-        let tx_query = state.lock().unwrap();
         let (tx_result, rx_result) = mpsc::channel::<Option<GeoResponse>>();
 
-        tx_query.send(Request::Geo((*query_params, tx_result))).unwrap();
+        queue.send(Request::Geo((*query_params, tx_result))).unwrap();
         rx_result.recv().expect("routing engine crashed or hung up")
     });
 
-    println!();
     Json(result)
 }
 
 #[get("/here_query?<query_params..>", format = "application/json")]
-fn here_query(query_params: Form<HereQuery>, state: State<Mutex<Sender<Request>>>) -> Json<Option<HereResponse>> {
-    let result = report_time("Total Query Request Time", || {
-        println!("Received Query: {:?}", query_params);
+fn here_query(query_params: Form<HereQuery>, queue: State<RequestQueue<Request>>, metrics: State<Arc<ServerMetrics>>) -> Json<Option<HereResponse>> {
+    let result = metrics.here_query_seconds.time(|| {
+        log_event!(Level::Debug, "received query", { "query": format!("{:?}", *query_params) });
 
-        let tx_query = state.lock().unwrap();
         let (tx_result, rx_result) = mpsc::channel::<Option<HereResponse>>();
 
-        tx_query.send(Request::Here((*query_params, tx_result))).unwrap();
+        queue.send(Request::Here((*query_params, tx_result))).unwrap();
         rx_result.recv().expect("routing engine crashed or hung up")
     });
 
-    println!();
     Json(result)
 }
 
+#[post("/v1/route", format = "json", data = "<request>")]
+fn route(request: Json<Value>, queue: State<RequestQueue<Request>>, metrics: State<Arc<ServerMetrics>>) -> Json<RouteResponse> {
+    metrics.route_request_seconds.time(|| {
+        let (tx_result, rx_result) = mpsc::channel::<RouteResponse>();
+        queue.send(Request::Routes((vec![parse_request(request.0)], tx_result))).unwrap();
+        Json(rx_result.recv().expect("routing engine crashed or hung up"))
+    })
+}
+
+// Takes a JSON array of requests, responses are streamed back as newline delimited JSON as soon as they are done.
+#[post("/v1/batch", format = "json", data = "<requests>")]
+fn batch(requests: Json<Vec<Value>>, queue: State<RequestQueue<Request>>) -> Content<Stream<NdjsonReader>> {
+    let (tx_result, rx_result) = mpsc::channel::<RouteResponse>();
+    queue
+        .send(Request::Routes((requests.0.into_iter().map(parse_request).collect(), tx_result)))
+        .unwrap();
+    Content(ContentType::new("application", "x-ndjson"), Stream::from(NdjsonReader::new(rx_result)))
+}
+
+// Serializes responses one line each until the engine hangs up.
+// With the `sse` feature of rocket, a `WouldBlock` error makes rocket flush what it has so far, so we emit one after each line.
+struct NdjsonReader {
+    responses: Receiver<RouteResponse>,
+    line: Vec<u8>,
+    pos: usize,
+    flush: bool,
+}
+
+impl NdjsonReader {
+    fn new(responses: Receiver<RouteResponse>) -> Self {
+        Self {
+            responses,
+            line: Vec::new(),
+            pos: 0,
+            flush: false,
+        }
+    }
+}
+
+impl Read for NdjsonReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.line.len() {
+            if self.flush {
+                self.flush = false;
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "flush"));
+            }
+            match self.responses.recv() {
+                Ok(response) => {
+                    self.line = serde_json::to_vec(&response)?;
+                    self.line.push(b'\n');
+                    self.pos = 0;
+                }
+                // all responses sent
+                Err(_) => return Ok(0),
+            }
+        }
+        let n = std::cmp::min(buf.len(), self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        // if the buffer is full, rocket writes it out anyway and the next read starts a new chunk,
+        // flushing there would yield an empty chunk which ends the response
+        self.flush = self.pos == self.line.len() && n < buf.len();
+        Ok(n)
+    }
+}
+
 #[derive(Debug)]
 struct SerializedWeight(Weight);
 
 use serde::de::Deserializer;
-use serde_json::Value;
 
 impl<'de> Deserialize<'de> for SerializedWeight {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -164,14 +302,69 @@ impl<'de> Deserialize<'de> for SerializedWeight {
     }
 }
 
+#[get("/metrics")]
+fn prometheus_metrics(metrics: State<Arc<ServerMetrics>>) -> Content<String> {
+    Content(ContentType::Plain, metrics.registry.render())
+}
+
 #[post("/customize", data = "<updates>")]
-fn customize(updates: Json<Vec<(u64, bool, SerializedWeight)>>, state: State<Mutex<Sender<Request>>>) {
-    let tx_query = state.lock().unwrap();
-    tx_query.send(Request::Customize(updates.0)).expect("routing engine crashed or hung up");
+fn customize(updates: Json<Vec<(u64, bool, SerializedWeight)>>, queue: State<RequestQueue<Request>>) {
+    queue.send(Request::Customize(updates.0)).expect("routing engine crashed or hung up");
+}
+
+#[post("/closures", data = "<closure>")]
+fn add_closure(closure: Json<ClosureRequest>, queue: State<RequestQueue<Request>>) -> Option<Json<ClosureId>> {
+    let (tx_result, rx_result) = mpsc::channel::<Option<ClosureId>>();
+    queue.send(Request::AddClosure((closure.0, tx_result))).unwrap();
+    rx_result.recv().expect("routing engine crashed or hung up").map(Json)
+}
+
+#[get("/closures", format = "application/json")]
+fn list_closures(queue: State<RequestQueue<Request>>) -> Json<Vec<ClosureResponse>> {
+    let (tx_result, rx_result) = mpsc::channel::<Vec<ClosureResponse>>();
+    queue.send(Request::ListClosures(tx_result)).unwrap();
+    Json(rx_result.recv().expect("routing engine crashed or hung up"))
+}
+
+#[delete("/closures/<id>")]
+fn remove_closure(id: ClosureId, queue: State<RequestQueue<Request>>) -> Option<()> {
+    let (tx_result, rx_result) = mpsc::channel::<bool>();
+    queue.send(Request::RemoveClosure((id, tx_result))).unwrap();
+    if rx_result.recv().expect("routing engine crashed or hung up") {
+        Some(())
+    } else {
+        None
+    }
+}
+
+// Requests of a batch answered before the engine turns to other queued requests.
+const BATCH_CHUNK_SIZE: usize = 16;
+
+fn wall_clock() -> WallClock {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as WallClock
+}
+
+// Time-dependent Dijkstra sees the closures active at `now` as pessimistic live data.
+// The TCH preprocessing can not be updated, so TCH profiles ignore closures.
+fn td_dijkstra_router<'a>(td_graph: &TDGraph, closures: &RoadClosures, now: WallClock) -> Box<dyn Router + 'a> {
+    let mut live = vec![InRangeOption::NONE; td_graph.num_arcs()];
+    closures.apply_to_pessimistic_live(&mut live, now);
+    let live_graph = Rc::new(PessimisticLiveTDGraph::new(td_graph.clone(), live));
+    Box::new(TDRouter::new(
+        DijkServer::<PessimisticLiveTDGraph, PessimisticLiveTDDijkstraOps, _, _>::new(live_graph.clone()),
+        live_graph,
+    ))
 }
 
 fn main() -> Result<(), Box<dyn Error>> {
+    if let Ok(level) = env::var("LOG_LEVEL") {
+        logging::set_level(level.parse()?);
+    }
+    let metrics = Arc::new(ServerMetrics::default());
     let (tx_query, rx_query) = mpsc::channel::<Request>();
+    let queue = RequestQueue::new(tx_query.clone(), metrics.queue_depth.clone());
+    // the engine puts the remainder of large batches back into the queue
+    let requeue = RequestQueue::new(tx_query.clone(), metrics.queue_depth.clone());
 
     let mut args = env::args();
     args.next();
@@ -179,40 +372,192 @@ fn main() -> Result<(), Box<dyn Error>> {
     let arg = &args.next().ok_or(CliErr("No directory arg given"))?;
     let path = Path::new(arg);
 
-    let first_out = Vec::load_from(path.join("first_out"))?;
-    let head = Vec::load_from(path.join("head"))?;
+    let first_out: Vec<EdgeId> = Vec::load_from(path.join("first_out"))?;
+    let head: Vec<NodeId> = Vec::load_from(path.join("head"))?;
     let travel_time = Vec::load_from(path.join("travel_time"))?;
 
+    // `live_ingest` replaces `live_travel_time` atomically, so each new version can be loaded as soon as its modification time changes
+    let live_path = path.join("live_travel_time");
+    let live_poll_interval = Duration::from_secs(env::var("LIVE_POLL_SECONDS").ok().map(|s| s.parse()).transpose()?.unwrap_or(10));
+    let live_queue = RequestQueue::new(tx_query, metrics.queue_depth.clone());
+    thread::spawn(move || {
+        let mut last_modified = None;
+        loop {
+            let modified = std::fs::metadata(&live_path).and_then(|meta| meta.modified()).ok();
+            if modified.is_some() && modified != last_modified {
+                match Vec::<Weight>::load_from(&live_path) {
+                    Ok(live_travel_time) => {
+                        log_event!(Level::Info, "picked up live travel times");
+                        if live_queue.send(Request::LiveTravelTime(live_travel_time)).is_err() {
+                            break;
+                        }
+                        last_modified = modified;
+                    }
+                    Err(e) => log_event!(Level::Warn, "failed to load live travel times", { "error": e.to_string() }),
+                }
+            }
+            thread::sleep(live_poll_interval);
+        }
+    });
+
     let lat = Vec::load_from(path.join("latitude"))?;
     let lng = Vec::load_from(path.join("longitude"))?;
-
+    // graphs imported before shape points were kept get straight lines
+    let link_geometry = if path.join("first_geometry_point_of_arc").exists() {
+        LinkGeometry::reconstruct_from(&path)?
+    } else {
+        LinkGeometry::without_shapes(head.len())
+    };
+    let road_attributes = if path.join("street_name").exists() {
+        Some(RoadAttributes::reconstruct_from(&path)?)
+    } else {
+        None
+    };
+
+    // only snap to nodes from which every other snapped node can be reached
+    let main_component = log_time(Level::Info, "strongly connected components", || {
+        scc::strongly_connected_components(&UnweightedFirstOutGraph::new(&first_out[..], &head[..])).largest_mask()
+    });
     let mut coords: Vec<NodeCoord> = lat
         .iter()
         .zip(lng.iter())
         .enumerate()
+        .filter(|&(node_id, _)| main_component.get(node_id))
         .map(|(node_id, (&lat, &lng))| NodeCoord {
             node_id: node_id as NodeId,
             coords: [f64::from(lat), f64::from(lng)],
         })
         .collect();
-    let tree = report_time("build kd tree", || Kdtree::new(&mut coords));
+    let tree = log_time(Level::Info, "build kd tree", || Kdtree::new(&mut coords));
 
     let link_id_mapping = BitVec::load_from(path.join("link_id_mapping"))?;
     let link_id_mapping = InvertableRankSelectMap::new(RankSelectMap::new(link_id_mapping));
     let here_rank_to_link_id = Vec::load_from(path.join("here_rank_to_link_id"))?;
     let cch_order = NodeOrder::from_node_order(Vec::load_from(path.join("cch_perm"))?);
 
+    // additional profiles for the JSON API, the live CCH is always available as the default profile
+    let config: ServerConfig = match args.next() {
+        Some(config) => serde_json::from_reader(File::open(config)?)?,
+        None => ServerConfig::default(),
+    };
+    for (idx, profile) in config.profiles.iter().enumerate() {
+        if profile.name == DEFAULT_PROFILE || config.profiles[..idx].iter().any(|other| other.name == profile.name) {
+            return Err(format!("duplicate profile name {}", profile.name).into());
+        }
+    }
+    let mut profile_graphs = Vec::with_capacity(config.profiles.len());
+    let mut profile_attributes = Vec::with_capacity(config.profiles.len());
+    for profile in &config.profiles {
+        profile_graphs.push(if profile.algorithm.is_time_dependent() {
+            None
+        } else {
+            let metric = profile.metric.as_deref().unwrap_or("travel_time");
+            Some(OwnedGraph::new(first_out.clone(), head.clone(), Vec::load_from(path.join(metric))?))
+        });
+        profile_attributes.push(if profile.algorithm == Algorithm::Preferences {
+            Some(LinkAttributes::reconstruct_from(&path)?)
+        } else {
+            None
+        });
+    }
+    let td_graph = if config.profiles.iter().any(|profile| profile.algorithm.is_time_dependent()) {
+        Some(TDGraph::reconstruct_from(&path)?)
+    } else {
+        None
+    };
+
     // all further preprocessing happening asynchronous
+    let engine_metrics = metrics.clone();
     thread::spawn(move || {
+        let metrics = engine_metrics;
         let id_mapper = LinkIdMapper::new(link_id_mapping, here_rank_to_link_id, head.len());
 
         let graph = FirstOutGraph::new(&first_out[..], &head[..], travel_time.clone());
         let link_id_to_tail_mapper = LinkIdToTailMapper::new(&graph);
 
-        let cch = CCH::fix_order_and_build(&graph, cch_order);
+        let cch = CCH::fix_order_and_build(&graph, cch_order.clone());
         let server = Arc::new(Mutex::new(Server::new(cch_customize(&cch, &graph))));
 
-        let coords = |node: NodeId| -> (f32, f32) { (lat[node as usize], lng[node as usize]) };
+        let pot_data: Vec<Option<CCHPotData>> = config
+            .profiles
+            .iter()
+            .zip(&profile_graphs)
+            .map(|(profile, graph)| {
+                if profile.algorithm == Algorithm::Preferences {
+                    Some(CCHPotData::new(&cch, graph.as_ref().unwrap()))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        // perfect customizations for the bucket many-to-many distance tables of waypoint optimization
+        let perfect_customized: Vec<_> = config
+            .profiles
+            .iter()
+            .zip(&profile_graphs)
+            .map(|(profile, graph)| {
+                if profile.algorithm == Algorithm::Cch {
+                    Some(customize_perfect(cch_customize(&cch, graph.as_ref().unwrap())))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        let tch = td_graph
+            .as_ref()
+            .filter(|_| config.profiles.iter().any(|profile| profile.algorithm == Algorithm::Tch))
+            .map(|td_graph| {
+                log_time(Level::Info, "tch contraction", || {
+                    time_dependent_contraction_hierarchy::contract(td_graph, cch_order)
+                })
+            });
+        let mut profiles: HashMap<String, Box<dyn Router + '_>> = HashMap::new();
+        for ((((profile, graph), attributes), pot_data), perfect_customized) in config
+            .profiles
+            .iter()
+            .zip(&profile_graphs)
+            .zip(profile_attributes)
+            .zip(&pot_data)
+            .zip(&perfect_customized)
+        {
+            let router: Box<dyn Router + '_> = match profile.algorithm {
+                Algorithm::Cch => {
+                    let graph = graph.as_ref().unwrap();
+                    Box::new(BucketTableRouter::new(
+                        StaticRouter::new(Server::new(cch_customize(&cch, graph)), graph),
+                        BCCHNearestNeighbor::new(perfect_customized.as_ref().unwrap()),
+                    ))
+                }
+                Algorithm::Dijkstra => {
+                    let graph = graph.as_ref().unwrap();
+                    Box::new(StaticRouter::new(DijkServer::<_, DefaultOps>::new(graph.clone()), graph))
+                }
+                Algorithm::Preferences => Box::new(PreferenceRouter::new(
+                    PreferencesServer::new(graph.clone().unwrap(), attributes.unwrap(), pot_data.as_ref().unwrap()),
+                    RoutePreferences::default(),
+                )),
+                Algorithm::TdDijkstra => td_dijkstra_router(td_graph.as_ref().unwrap(), &RoadClosures::new(), wall_clock()),
+                Algorithm::Tch => {
+                    let td_graph = td_graph.as_ref().unwrap();
+                    Box::new(TDRouter::new(TCHServer::new(tch.as_ref().unwrap(), td_graph), td_graph))
+                }
+            };
+            profiles.insert(profile.name.clone(), router);
+        }
+
+        // only configured profiles become labels
+        let profile_metrics: HashMap<&str, ProfileMetrics> = std::iter::once(DEFAULT_PROFILE)
+            .chain(config.profiles.iter().map(|profile| &profile.name[..]))
+            .map(|profile| (profile, metrics.profile(profile)))
+            .collect();
+        let unknown_profile_metrics = metrics.profile("none");
+
+        // current weights including all updates, closures are applied on top of them for each customization
+        let mut travel_time = travel_time.clone();
+        let mut closures = RoadClosures::new();
+        // time-dependent profiles only need to be rebuilt when the active closures change, not for every static update
+        let mut closures_changed = false;
+
         let closest_node = |(p_lat, p_lng): (f32, f32)| -> NodeId {
             tree.nearest_search(&NodeCoord {
                 coords: [f64::from(p_lat), f64::from(p_lng)],
@@ -225,29 +570,67 @@ fn main() -> Result<(), Box<dyn Error>> {
         // Thus we create a scope here, so we can later spawn new threads
         // without the risk of data going out of scope.
         crossbeam_utils::thread::scope(|scope| {
-            for query_params in rx_query {
-                match query_params {
+            loop {
+                // closures change the metrics when they start or end, so wake up then even without requests
+                let now = wall_clock();
+                let received = match closures.next_change(now) {
+                    Some(change) => rx_query.recv_timeout(Duration::from_millis(change - now)),
+                    None => rx_query.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                let query_params = match received {
+                    Ok(query_params) => {
+                        metrics.queue_depth.dec();
+                        query_params
+                    }
+                    Err(RecvTimeoutError::Timeout) => Request::ClosuresChanged,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                let recustomize = match query_params {
                     Request::Geo((
                         GeoQuery {
                             from_lat,
                             from_lng,
                             to_lat,
                             to_lng,
+                            polyline,
                         },
                         tx_result,
                     )) => {
-                        let (from, to) = report_time("match nodes", || (closest_node((from_lat, from_lng)), closest_node((to_lat, to_lng))));
+                        let (from, to) = (closest_node((from_lat, from_lng)), closest_node((to_lat, to_lng)));
 
                         let mut server = server.lock().unwrap();
-                        let result = report_time("cch query", || {
-                            server.query(Query { from, to }).found().map(|mut result| {
+                        let result = {
+                            let mut result = server.query(Query { from, to });
+                            metrics.observe_cch_query(result.data().num_nodes_in_searchspace(), result.data().num_relaxed_edges());
+                            result.found().map(|mut result| {
                                 let distance = result.distance();
-                                let path = result.node_path().iter().map(|&node| coords(node)).collect();
-                                GeoResponse { distance, path }
+                                let edge_path: Vec<EdgeId> = result
+                                    .node_path()
+                                    .windows(2)
+                                    .map(|pair| {
+                                        let EdgeIdT(edge) = graph
+                                            .edge_indices(pair[0], pair[1])
+                                            .min_by_key(|&EdgeIdT(edge)| graph.weight()[edge as usize])
+                                            .unwrap();
+                                        edge
+                                    })
+                                    .collect();
+                                let path = link_geometry.polyline(&graph, from, &edge_path, &lat, &lng);
+                                let polyline = if polyline.unwrap_or(false) { Some(encode_polyline(&path)) } else { None };
+                                let attributes = road_attributes
+                                    .as_ref()
+                                    .map(|road_attributes| RouteAttributes::summarize(road_attributes, &edge_path));
+                                GeoResponse {
+                                    distance,
+                                    path,
+                                    polyline,
+                                    attributes,
+                                }
                             })
-                        });
+                        };
 
                         tx_result.send(result).unwrap();
+                        false
                     }
                     Request::Here((
                         HereQuery {
@@ -271,8 +654,10 @@ fn main() -> Result<(), Box<dyn Error>> {
                         let to = link_id_to_tail_mapper.link_id_to_tail(to_link_local_id);
 
                         let mut server = server.lock().unwrap();
-                        let result = report_time("cch query", || {
-                            server.query(Query { from, to }).found().map(|mut result| {
+                        let result = {
+                            let mut result = server.query(Query { from, to });
+                            metrics.observe_cch_query(result.data().num_nodes_in_searchspace(), result.data().num_relaxed_edges());
+                            result.found().map(|mut result| {
                                 let distance = result.distance()
                                     + (from_link_fraction * from_link.weight as f32) as u32
                                     + (to_link_fraction * to_link.weight as f32) as u32;
@@ -302,33 +687,151 @@ fn main() -> Result<(), Box<dyn Error>> {
 
                                 HereResponse { distance, path }
                             })
-                        });
+                        };
 
                         tx_result.send(result).unwrap();
+                        false
                     }
                     Request::Customize(updates) => {
-                        let server = server.clone();
-                        let mut travel_time = travel_time.clone();
-                        let id_mapper = &id_mapper;
-                        let cch = &cch;
-                        let first_out = &first_out;
-                        let head = &head;
-
-                        // asynchronous customization
-                        scope.spawn(move |_| {
-                            for (here_link_id, is_from_ref, weight) in updates.into_iter() {
-                                if is_from_ref {
-                                    if let Some(link_idx) = id_mapper.here_to_local_link_id(here_link_id, LinkDirection::FromRef) {
-                                        travel_time[link_idx as usize] = weight.0
-                                    }
-                                } else if let Some(link_idx) = id_mapper.here_to_local_link_id(here_link_id, LinkDirection::ToRef) {
+                        for (here_link_id, is_from_ref, weight) in updates.into_iter() {
+                            if is_from_ref {
+                                if let Some(link_idx) = id_mapper.here_to_local_link_id(here_link_id, LinkDirection::FromRef) {
                                     travel_time[link_idx as usize] = weight.0
                                 }
+                            } else if let Some(link_idx) = id_mapper.here_to_local_link_id(here_link_id, LinkDirection::ToRef) {
+                                travel_time[link_idx as usize] = weight.0
                             }
-                            let customized = cch_customize(&cch, &FirstOutGraph::new(&first_out[..], &head[..], travel_time));
-                            server.lock().unwrap().update(customized);
-                        });
+                        }
+                        true
+                    }
+                    Request::LiveTravelTime(live_travel_time) => {
+                        if live_travel_time.len() == travel_time.len() {
+                            travel_time = live_travel_time;
+                            true
+                        } else {
+                            log_event!(Level::Warn, "live travel times do not match the graph", { "num_arcs": travel_time.len(), "num_live": live_travel_time.len() });
+                            false
+                        }
+                    }
+                    Request::AddClosure((
+                        ClosureRequest {
+                            edge,
+                            here_link_id,
+                            from_ref,
+                            start,
+                            end,
+                            reason,
+                        },
+                        tx_result,
+                    )) => {
+                        let edge = match (edge, here_link_id) {
+                            (Some(edge), None) if (edge as usize) < head.len() => Some(edge),
+                            (None, Some(here_link_id)) => {
+                                id_mapper.here_to_local_link_id(here_link_id, if from_ref { LinkDirection::FromRef } else { LinkDirection::ToRef })
+                            }
+                            _ => None,
+                        };
+                        let id = edge.filter(|_| start < end).map(|edge| closures.add(Closure { edge, start, end, reason }));
+                        tx_result.send(id).unwrap();
+                        closures_changed |= id.is_some();
+                        id.is_some()
+                    }
+                    Request::ListClosures(tx_result) => {
+                        tx_result
+                            .send(
+                                closures
+                                    .iter()
+                                    .map(|(id, closure)| ClosureResponse {
+                                        id,
+                                        edge: closure.edge,
+                                        start: closure.start,
+                                        end: closure.end,
+                                        reason: closure.reason.clone(),
+                                    })
+                                    .collect(),
+                            )
+                            .unwrap();
+                        false
                     }
+                    Request::RemoveClosure((id, tx_result)) => {
+                        let removed = closures.remove(id).is_some();
+                        tx_result.send(removed).unwrap();
+                        closures_changed |= removed;
+                        removed
+                    }
+                    Request::Routes((mut requests, tx_result)) => {
+                        // large batches would block all other requests and updates, so the rest is queued behind them
+                        let rest = requests.split_off(std::cmp::min(requests.len(), BATCH_CHUNK_SIZE));
+                        let mut client_gone = false;
+                        let current = FirstOutGraph::new(&first_out[..], &head[..], &travel_time[..]);
+                        let mut live = StaticRouter::new(SharedServer(&server), &current);
+                        let ctx = Context {
+                            graph: &current,
+                            first_out: &first_out,
+                            lat: &lat,
+                            lng: &lng,
+                            closest_node: &closest_node,
+                            link_geometry: &link_geometry,
+                            road_attributes: road_attributes.as_ref(),
+                            now: time_of_day(wall_clock()),
+                        };
+                        for request in requests {
+                            let profile_metrics = request
+                                .as_ref()
+                                .ok()
+                                .and_then(|request| profile_metrics.get(request.profile.as_deref().unwrap_or(DEFAULT_PROFILE)))
+                                .unwrap_or(&unknown_profile_metrics);
+                            let response = profile_metrics.route_seconds.time(|| dispatch(request, &mut live, &mut profiles, &ctx));
+                            profile_metrics.responses(response.status).inc();
+                            for &search_space in &response.search_spaces {
+                                metrics.observe_search_space(search_space);
+                            }
+                            if response.status == Status::InvalidRequest {
+                                log_event!(Level::Debug, "invalid route request", { "id": response.id, "error": response.error });
+                            }
+                            // the client may have gone away
+                            if tx_result.send(response).is_err() {
+                                client_gone = true;
+                                break;
+                            }
+                        }
+                        if !rest.is_empty() && !client_gone {
+                            requeue.send(Request::Routes((rest, tx_result))).unwrap();
+                        }
+                        false
+                    }
+                    Request::ClosuresChanged => {
+                        closures_changed = true;
+                        true
+                    }
+                };
+
+                if closures_changed {
+                    let now = wall_clock();
+                    for profile in config.profiles.iter().filter(|profile| profile.algorithm == Algorithm::TdDijkstra) {
+                        profiles.insert(profile.name.clone(), td_dijkstra_router(td_graph.as_ref().unwrap(), &closures, now));
+                    }
+                    closures_changed = false;
+                }
+
+                if recustomize {
+                    let server = server.clone();
+                    let mut travel_time = travel_time.clone();
+                    closures.apply_to_weights(&mut travel_time, wall_clock());
+                    let cch = &cch;
+                    let first_out = &first_out;
+                    let head = &head;
+                    let metrics = &metrics;
+
+                    // asynchronous customization
+                    scope.spawn(move |_| {
+                        let customized = log_time(Level::Info, "customization", || {
+                            metrics
+                                .customization_seconds
+                                .time(|| cch_customize(&cch, &FirstOutGraph::new(&first_out[..], &head[..], travel_time)))
+                        });
+                        server.lock().unwrap().update(customized);
+                    });
                 }
             }
         })
@@ -336,8 +839,24 @@ fn main() -> Result<(), Box<dyn Error>> {
     });
 
     rocket::ignite()
-        .mount("/", routes![index, files, query, here_query, customize])
-        .manage(Mutex::new(tx_query))
+        .mount(
+            "/",
+            routes![
+                index,
+                files,
+                prometheus_metrics,
+                query,
+                here_query,
+                route,
+                batch,
+                customize,
+                add_closure,
+                list_closures,
+                remove_closure
+            ],
+        )
+        .manage(queue)
+        .manage(metrics)
         .launch();
 
     Ok(())
@@ -0,0 +1,136 @@
+// Metrics of the server, exposed on `/metrics` in the Prometheus text format.
+
+use std::sync::{
+    mpsc::{SendError, Sender},
+    Arc, Mutex,
+};
+
+use rust_road_router::{algo::router::SearchSpace, metrics::*};
+
+use crate::api::Status;
+
+pub struct ServerMetrics {
+    pub registry: Registry,
+    // requests sent to the engine thread but not yet picked up
+    pub queue_depth: Arc<Gauge>,
+    // latency of HTTP requests, including waiting for the engine
+    pub query_seconds: Arc<Histogram>,
+    pub here_query_seconds: Arc<Histogram>,
+    pub route_request_seconds: Arc<Histogram>,
+    pub customization_seconds: Arc<Histogram>,
+    pub searchspace_nodes: Arc<Histogram>,
+    pub relaxed_edges: Arc<Histogram>,
+}
+
+impl Default for ServerMetrics {
+    fn default() -> Self {
+        let registry = Registry::new();
+        Self {
+            queue_depth: registry.gauge("engine_queue_depth", "Requests waiting for the routing engine.", &[]),
+            query_seconds: request_seconds(&registry, "query"),
+            here_query_seconds: request_seconds(&registry, "here_query"),
+            route_request_seconds: request_seconds(&registry, "route"),
+            customization_seconds: registry.histogram(
+                "cch_customization_seconds",
+                "Duration of CCH customizations after weight updates.",
+                &[],
+                &exponential_buckets(0.1, 2.0, 12),
+            ),
+            searchspace_nodes: registry.histogram(
+                "cch_query_searchspace_nodes",
+                "Nodes in the search space of CCH queries.",
+                &[],
+                &exponential_buckets(16.0, 2.0, 14),
+            ),
+            relaxed_edges: registry.histogram(
+                "cch_query_relaxed_edges",
+                "Edges relaxed by CCH queries.",
+                &[],
+                &exponential_buckets(64.0, 2.0, 14),
+            ),
+            registry,
+        }
+    }
+}
+
+fn request_seconds(registry: &Registry, endpoint: &str) -> Arc<Histogram> {
+    registry.histogram(
+        "http_request_seconds",
+        "Latency of HTTP requests by endpoint.",
+        &[("endpoint", endpoint)],
+        LATENCY_BUCKETS,
+    )
+}
+
+impl ServerMetrics {
+    // Registers the metrics of a profile of the JSON API, only done on startup for the configured ones, to keep the number of labels bounded.
+    pub fn profile(&self, profile: &str) -> ProfileMetrics {
+        let responses = |status: Status| {
+            self.registry.counter(
+                "route_responses_total",
+                "Answered route requests by profile and status.",
+                &[("profile", profile), ("status", status.as_str())],
+            )
+        };
+        ProfileMetrics {
+            route_seconds: self.registry.histogram(
+                "route_request_seconds",
+                "Time to answer route requests by profile.",
+                &[("profile", profile)],
+                LATENCY_BUCKETS,
+            ),
+            ok: responses(Status::Ok),
+            no_route: responses(Status::NoRoute),
+            invalid_request: responses(Status::InvalidRequest),
+        }
+    }
+
+    pub fn observe_cch_query(&self, nodes_in_searchspace: usize, relaxed_edges: usize) {
+        self.searchspace_nodes.observe(nodes_in_searchspace as f64);
+        self.relaxed_edges.observe(relaxed_edges as f64);
+    }
+
+    pub fn observe_search_space(&self, search_space: SearchSpace) {
+        self.observe_cch_query(search_space.nodes, search_space.relaxed_edges);
+    }
+}
+
+// Time to answer single route requests of the JSON API and their responses by status.
+pub struct ProfileMetrics {
+    pub route_seconds: Arc<Histogram>,
+    ok: Arc<Counter>,
+    no_route: Arc<Counter>,
+    invalid_request: Arc<Counter>,
+}
+
+impl ProfileMetrics {
+    pub fn responses(&self, status: Status) -> &Counter {
+        match status {
+            Status::Ok => &self.ok,
+            Status::NoRoute => &self.no_route,
+            Status::InvalidRequest => &self.invalid_request,
+        }
+    }
+}
+
+// The channel to the engine thread, keeping track of the queue depth.
+// The engine thread decrements `depth` for each request it receives.
+pub struct RequestQueue<T> {
+    tx: Mutex<Sender<T>>,
+    depth: Arc<Gauge>,
+}
+
+impl<T> RequestQueue<T> {
+    pub fn new(tx: Sender<T>, depth: Arc<Gauge>) -> Self {
+        Self { tx: Mutex::new(tx), depth }
+    }
+
+    pub fn send(&self, request: T) -> Result<(), SendError<T>> {
+        self.depth.inc();
+        let res = self.tx.lock().unwrap().send(request);
+        if res.is_err() {
+            self.depth.dec();
+        }
+        res
+    }
+}
@@ -0,0 +1,211 @@
+// Long running live traffic ingestion.
+// Watches a directory (or listens on a unix socket) for feed files, maintains the current live state with expiry
+// and after every change writes `live_data`, `live_t`, the static `live_travel_time` metric and,
+// if `customized_corridor_mins` is available, a recustomized `interval_min_pot` into the graph directory.
+// Road closures from an optional `road_closures.csv` in the graph directory (a header line, then edge, start and end in ms since the unix epoch)
+// are applied on top of the live reports. The file is reloaded when it changes and outputs are rewritten whenever a closure starts or ends.
+//
+// The server watches `live_travel_time` and recustomizes whenever it is replaced.
+// Progress is logged as JSON lines on stderr, the level can be set with `LOG_LEVEL`.
+//
+// Usage: live_ingest <graph_dir> <feed_dir or socket path> [mapbox|ptv|here|tomtom] [poll interval in s]
+
+use rust_road_router::{
+    algo::{customizable_contraction_hierarchy::*, td_astar::*},
+    cli::CliErr,
+    datastr::{
+        graph::{time_dependent::*, *},
+        live_traffic::*,
+        node_order::*,
+        rank_select_map::BitVec,
+        road_closures::*,
+    },
+    io::*,
+    live_feed::*,
+    log_event,
+    logging::{self, log_time, Level},
+};
+use std::{
+    env,
+    error::Error,
+    fs::File,
+    io::BufReader,
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+enum Source {
+    Directory(FeedDirectory),
+    Socket(FeedSocket),
+}
+
+fn read_closures(path: &Path) -> Result<RoadClosures, Box<dyn Error>> {
+    let mut closures = RoadClosures::new();
+    for record in csv::Reader::from_path(path)?.records() {
+        let record = record?;
+        let field = |idx: usize| record.get(idx).ok_or_else(|| format!("missing column {} in {}", idx, path.display()));
+        let (edge, start, end): (EdgeId, WallClock, WallClock) = (field(0)?.parse()?, field(1)?.parse()?, field(2)?.parse()?);
+        if start >= end {
+            return Err(format!("empty closure interval for edge {} in {}", edge, path.display()).into());
+        }
+        closures.add(Closure {
+            edge,
+            start,
+            end,
+            reason: record.get(3).unwrap_or("").to_string(),
+        });
+    }
+    Ok(closures)
+}
+
+fn wall_clock() -> WallClock {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_millis() as WallClock
+}
+
+// Write to a hidden temporary name first, so consumers never see partially written data.
+fn replace_atomically(path: &Path, name: &str, write: impl FnOnce(&Path) -> std::io::Result<()>) -> std::io::Result<()> {
+    let tmp = path.join(format!(".{}.tmp", name));
+    let target = path.join(name);
+    write(&tmp)?;
+    if target.is_dir() {
+        std::fs::remove_dir_all(&target)?;
+    }
+    std::fs::rename(tmp, target)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    if let Ok(level) = env::var("LOG_LEVEL") {
+        logging::set_level(level.parse()?);
+    }
+    let mut args = env::args().skip(1);
+    let arg = &args.next().ok_or(CliErr("No directory arg given"))?;
+    let path = Path::new(arg);
+    let arg = &args.next().ok_or(CliErr("No feed directory or socket arg given"))?;
+    let feed_path = Path::new(arg);
+    let format: FeedFormat = args.next().as_deref().unwrap_or("mapbox").parse()?;
+    let poll_interval = Duration::from_secs(args.next().map(|s| s.parse()).transpose()?.unwrap_or(10));
+
+    let graph = UnweightedOwnedGraph::reconstruct_from(&path)?;
+    let m = graph.num_arcs();
+    let mapper = match format {
+        FeedFormat::Mapbox => FeedMapper::new(graph)
+            .with_osm_node_ids(&Vec::<u64>::load_from(path.join("osm_node_ids"))?)
+            .with_geo_distance(Vec::load_from(path.join("geo_distance"))?),
+        FeedFormat::Ptv => FeedMapper::new(graph),
+        FeedFormat::Here => FeedMapper::new(graph)
+            .with_here_link_ids(
+                BitVec::load_from(path.join("link_id_mapping"))?,
+                HereRankToLinkId::load_from(path.join("here_rank_to_link_id"))?,
+            )
+            .with_geo_distance(Vec::load_from(path.join("geo_distance"))?),
+        FeedFormat::TomTom => FeedMapper::new(graph).with_osm_node_ids(&Vec::<u64>::load_from(path.join("osm_node_ids"))?),
+    };
+    let travel_time = Vec::<Weight>::load_from(path.join("travel_time"))?;
+
+    let td_graph = TDGraph::reconstruct_from(&path)?;
+    let order = NodeOrder::from_node_order(Vec::load_from(path.join("cch_perm"))?);
+    let cch = log_time(Level::Info, "cch preprocessing", || CCH::fix_order_and_build(&td_graph, order));
+    let customized_folder = path.join("customized_corridor_mins");
+    let recustomize_pot = customized_folder.is_dir();
+    if !recustomize_pot {
+        log_event!(Level::Warn, "no customized_corridor_mins found, interval min potentials will not be updated");
+    }
+
+    let mut source = if feed_path.is_dir() {
+        Source::Directory(FeedDirectory::new(feed_path))
+    } else {
+        Source::Socket(FeedSocket::bind(feed_path)?)
+    };
+    let mut live = LiveTraffic::new();
+    let closures_path = path.join("road_closures.csv");
+    let mut closures = RoadClosures::new();
+    let mut closures_modified = None;
+    let mut next_closure_change = None;
+
+    loop {
+        let now = wall_clock();
+
+        let modified = std::fs::metadata(&closures_path).and_then(|meta| meta.modified()).ok();
+        let mut closures_changed = modified != closures_modified;
+        if closures_changed {
+            match modified.map(|_| read_closures(&closures_path)).transpose() {
+                Ok(reloaded) => closures = reloaded.unwrap_or_else(RoadClosures::new),
+                Err(e) => {
+                    log_event!(Level::Warn, "keeping previous road closures", { "error": e.to_string() });
+                    closures_changed = false;
+                }
+            }
+            // also on failure, so a broken file is only reported once until it is modified again
+            closures_modified = modified;
+        }
+        closures_changed |= next_closure_change.map(|change| change <= now).unwrap_or(false);
+
+        let mut records = Vec::new();
+        match &mut source {
+            Source::Directory(dir) => match dir.poll() {
+                Ok(files) => {
+                    for file in files {
+                        match File::open(&file).and_then(|f| read_feed(BufReader::new(f), format)) {
+                            Ok(file_records) => records.extend(file_records),
+                            Err(e) => log_event!(Level::Warn, "skipping feed file", { "file": file.display().to_string(), "error": e.to_string() }),
+                        }
+                    }
+                }
+                Err(e) => log_event!(Level::Warn, "polling feed directory failed", { "error": e.to_string() }),
+            },
+            Source::Socket(socket) => match socket.poll() {
+                Ok(received) => {
+                    for content in received {
+                        match read_feed(&content[..], format) {
+                            Ok(received) => records.extend(received),
+                            Err(e) => log_event!(Level::Warn, "skipping received feed", { "error": e.to_string() }),
+                        }
+                    }
+                }
+                Err(e) => log_event!(Level::Warn, "polling feed socket failed", { "error": e.to_string() }),
+            },
+        }
+
+        let updates = mapper.map_all(&records, now);
+        let num_unmapped = records.len() - updates.len();
+        let changed = live.ingest(updates);
+        let expired = live.expire(now);
+
+        if changed > 0 || expired > 0 || closures_changed {
+            let t_live = time_of_day(now);
+            next_closure_change = closures.next_change(now);
+            log_event!(Level::Info, "live state changed", {
+                "t_live": t_live,
+                "num_changed": changed,
+                "num_expired": expired,
+                "num_unmapped": num_unmapped,
+                "num_live_reports": live.len(),
+                "num_closures": closures.len(),
+            });
+
+            let mut live_data = live.live_data(now);
+            closures.apply_to_live_data(&mut live_data, now);
+            replace_atomically(path, "live_data", |tmp| live_data.write_to(&tmp))?;
+            replace_atomically(path, "live_t", |tmp| vec![t_live].write_to(&tmp))?;
+
+            let mut live_travel_time = travel_time.clone();
+            live.apply_to_weights(&mut live_travel_time, now);
+            closures.apply_to_weights(&mut live_travel_time, now);
+            replace_atomically(path, "live_travel_time", |tmp| live_travel_time.write_to(&tmp))?;
+
+            if recustomize_pot {
+                let mut pessimistic_live = live.pessimistic_live(m, now);
+                closures.apply_to_pessimistic_live(&mut pessimistic_live, now);
+                let live_graph = PessimisticLiveTDGraph::new(td_graph.clone(), pessimistic_live);
+                // the customization consumes the precomputed data, so reload it every time
+                let catchup = customization::ftd_for_pot::PotData::reconstruct_from(&customized_folder)?;
+                let pot = log_time(Level::Info, "interval min potential customization", || {
+                    IntervalMinPotential::new_for_live(&cch, catchup, &live_graph, t_live)
+                });
+                replace_atomically(path, "interval_min_pot", |tmp| pot.deconstruct_to(&tmp))?;
+            }
+        }
+
+        std::thread::sleep(poll_interval);
+    }
+}
@@ -1,6 +1,8 @@
+// Road attributes are carried over to `forward_road_attributes` and `backward_road_attributes` if the input graph has them.
+
 use rust_road_router::{
     cli::CliErr,
-    datastr::{graph::*, node_order::*},
+    datastr::{graph::*, node_order::*, road_attributes::RoadAttributes},
     io::*,
 };
 use std::{env, error::Error, path::Path};
@@ -13,16 +15,28 @@ fn main() -> Result<(), Box<dyn Error>> {
     let graph = WeightedGraphReconstructor("weight").reconstruct_from(&path)?;
     let order = NodeOrder::from_node_order(Vec::load_from(path.join("order"))?);
 
-    let (forward, backward) = graph.ch_split(&order);
+    // splitting and permutating the graph with edge ids as weights yields the original edge of each split edge,
+    // so per edge attributes can be mapped with `RoadAttributes::select`.
+    // Weights are mapped the same way, so parallel edges keep their weight and attributes together.
+    let edge_ids = OwnedGraph::new(graph.first_out().to_vec(), graph.head().to_vec(), (0..graph.num_arcs() as EdgeId).collect());
+    let (forward, backward) = edge_ids.ch_split(&order);
     let forward = OwnedGraph::permutated(&forward, &order);
     let backward = OwnedGraph::permutated(&backward, &order);
+    let weights = |origins: &[EdgeId]| -> Vec<Weight> { origins.iter().map(|&edge| graph.weight()[edge as usize]).collect() };
 
     forward.first_out().write_to(&path.join("forward_first_out"))?;
     forward.head().write_to(&path.join("forward_head"))?;
-    forward.weight().write_to(&path.join("forward_weight"))?;
+    weights(forward.weight()).write_to(&path.join("forward_weight"))?;
     backward.first_out().write_to(&path.join("backward_first_out"))?;
     backward.head().write_to(&path.join("backward_head"))?;
-    backward.weight().write_to(&path.join("backward_weight"))?;
+    weights(backward.weight()).write_to(&path.join("backward_weight"))?;
+    forward.weight().write_to(&path.join("forward_edge_origin"))?;
+    backward.weight().write_to(&path.join("backward_edge_origin"))?;
+    if path.join("street_name").exists() {
+        let attributes = RoadAttributes::reconstruct_from(&path)?;
+        attributes.select(forward.weight()).deconstruct_to(&path.join("forward_road_attributes"))?;
+        attributes.select(backward.weight()).deconstruct_to(&path.join("backward_road_attributes"))?;
+    }
 
     Ok(())
 }
@@ -1,8 +1,9 @@
 // Utility to extract a subgraph limited to a geographic bounding box.
+// Road attributes are carried over if the input graph has them.
 
 use rust_road_router::{
     cli::CliErr,
-    datastr::{graph::*, rank_select_map::*},
+    datastr::{graph::*, rank_select_map::*, road_attributes::RoadAttributes},
     io::*,
 };
 use std::{env, error::Error, path::Path};
@@ -29,6 +30,7 @@ fn main() -> Result<(), Box<dyn Error>> {
     let mut new_travel_time = Vec::new();
     let mut new_lat = Vec::new();
     let mut new_lng = Vec::new();
+    let mut edge_origins = Vec::new();
 
     new_first_out.push(0);
 
@@ -50,11 +52,13 @@ fn main() -> Result<(), Box<dyn Error>> {
             new_lat.push(lat[node]);
             new_lng.push(lng[node]);
 
-            for link in LinkIterable::<Link>::link_iter(&graph, node as NodeId) {
+            for edge in graph.neighbor_edge_indices(node as NodeId) {
+                let link = graph.link(edge);
                 if in_bounding_box(link.node as usize) {
                     *new_first_out.last_mut().unwrap() += 1;
                     new_head.push(id_map.get(link.node as usize).unwrap() as u32);
                     new_travel_time.push(link.weight);
+                    edge_origins.push(edge);
                 }
             }
         }
@@ -65,6 +69,9 @@ fn main() -> Result<(), Box<dyn Error>> {
     new_travel_time.write_to(&out_path.join("travel_time"))?;
     new_lat.write_to(&out_path.join("latitude"))?;
     new_lng.write_to(&out_path.join("longitude"))?;
+    if path.join("street_name").exists() {
+        RoadAttributes::reconstruct_from(&path)?.select(&edge_origins).deconstruct_to(&out_path)?;
+    }
 
     Ok(())
 }